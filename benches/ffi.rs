@@ -0,0 +1,625 @@
+//! Measures handshakes and stream throughput of a client and server endpoint, run with `cargo bench`.
+//! Also compares the receive path of `handle_datagram` and `handle_datagram_borrowed` for 1200-byte datagrams,
+//! and `handle_datagram` with `submit_datagram` when several reader threads pass datagrams to one endpoint.
+//!
+//! Everything goes through the exported functions and callbacks like a binding does, datagrams are exchanged in memory
+//! instead of over sockets. Performance regressions of the library show up as a lower rate than the previous run on the same machine.
+
+use quinn_ffi::{
+    ffi::{
+        FFIResult,
+        FFIResultKind,
+    },
+    proto_impl::{
+        EndpointStats,
+        IpAddr,
+    },
+};
+use std::{
+    ffi::c_void,
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        mpsc,
+        Mutex,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+type Handle = *mut c_void;
+
+/// The number of datagrams passed to each receive path.
+const DATAGRAMS: u32 = 200_000;
+
+/// The size of a datagram of a typical QUIC packet.
+const DATAGRAM_SIZE: usize = 1200;
+
+/// The number of threads passing datagrams to one endpoint in the reader benchmark.
+const READERS: u32 = 4;
+
+/// The number of connections established by the handshake benchmark.
+const HANDSHAKES: u32 = 100;
+
+/// The number of bytes transferred by the throughput benchmark.
+const TRANSFER_BYTES: u64 = 16 * 1024 * 1024;
+
+/// The size of the chunks written by the throughput benchmark.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long a benchmark waits for the next datagram or event before it fails.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The stream direction of `open_stream`.
+const UNI: u8 = 1;
+
+// The calling convention of the exported functions is `cdecl`, which is `C` on the benchmarked targets.
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "generate_self_signed_cert")]
+    fn generate_self_signed_cert(
+        subject_alt_names: *const u8,
+        subject_alt_names_len: u32,
+        validity_days: u32,
+        cert_buf: *mut u8,
+        cert_buf_len: usize,
+        actual_cert_len: *mut usize,
+        key_buf: *mut u8,
+        key_buf_len: usize,
+        actual_key_len: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_server_config")]
+    fn create_server_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_config")]
+    fn create_client_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_server_endpoint")]
+    fn create_server_endpoint(
+        config: Handle,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_endpoint")]
+    fn create_client_endpoint(
+        config: Handle,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connect_client")]
+    fn connect_client(
+        endpoint: Handle,
+        host: *const u8,
+        host_len: u32,
+        address: IpAddr,
+        out_connection: *mut Handle,
+        out_connection_id: *mut u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "open_stream")]
+    fn open_stream(connection: Handle, direction: u8, out_stream_id: *mut u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "write_stream")]
+    fn write_stream(
+        connection: Handle,
+        stream_id: u64,
+        buf: *const u8,
+        buf_len: usize,
+        out_written: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "read_stream_v3")]
+    fn read_stream_v3(
+        connection: Handle,
+        stream_id: u64,
+        buf: *mut u8,
+        buf_len: usize,
+        out_len: *mut usize,
+        out_reset_code: *mut u64,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "close_connection")]
+    fn close_connection(
+        connection: Handle,
+        reason: *const u8,
+        reason_len: u32,
+        error_code: u64,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_handle_release")]
+    fn connection_handle_release(connection: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_client_config")]
+    fn free_client_config(config: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_transmit_v2")]
+    fn set_on_transmit_v2(callback: extern "C" fn(u64, *const u8, usize, IpAddr)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_new_connection")]
+    fn set_on_new_connection(callback: extern "C" fn(Handle, u32, u64)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_connected")]
+    fn set_on_connected(callback: extern "C" fn(u32)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_stream_opened")]
+    fn set_on_stream_opened(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_stream_readable")]
+    fn set_on_stream_readable(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "handle_datagram")]
+    fn handle_datagram(
+        endpoint: Handle,
+        data: *const u8,
+        length: usize,
+        address: IpAddr,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "handle_datagram_borrowed")]
+    fn handle_datagram_borrowed(
+        endpoint: Handle,
+        data: *const u8,
+        length: usize,
+        address: IpAddr,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_datagram_ingress")]
+    fn endpoint_datagram_ingress(endpoint: Handle, out_ingress: *mut Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "submit_datagram")]
+    fn submit_datagram(
+        ingress: Handle,
+        data: *const u8,
+        length: usize,
+        address: IpAddr,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_datagram_ingress")]
+    fn free_datagram_ingress(ingress: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_stats")]
+    fn endpoint_stats(endpoint: Handle, out_stats: *mut EndpointStats) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "dispose_endpoint")]
+    fn dispose_endpoint(endpoint: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_server_config")]
+    fn free_server_config(config: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "last_error")]
+    fn last_error(
+        error_buf: *mut u8,
+        error_buf_len: usize,
+        actual_error_buf_len: *mut usize,
+    ) -> FFIResult;
+}
+
+fn check(result: FFIResult) {
+    if result.kind == FFIResultKind::Ok {
+        return;
+    }
+
+    let mut message = vec![0u8; result.message_len];
+    let mut len = 0;
+    unsafe { last_error(message.as_mut_ptr(), message.len(), &mut len) };
+    panic!(
+        "{:?}: {}",
+        result,
+        String::from_utf8_lossy(&message[..len.min(message.len())])
+    );
+}
+
+/// A datagram sent by an endpoint, with the id of the endpoint.
+type Datagram = (u64, Vec<u8>);
+
+/// The datagrams sent by the endpoints, delivered by the benchmark loop.
+static WIRE: Mutex<Option<mpsc::Sender<Datagram>>> = Mutex::new(None);
+/// The ids of the client connections whose handshake completed.
+static CONNECTED: Mutex<Option<mpsc::Sender<u32>>> = Mutex::new(None);
+/// The handles of the server connections by their id, callbacks only pass the id.
+static SERVER_CONNECTIONS: Mutex<Vec<(u32, usize)>> = Mutex::new(Vec::new());
+/// The stream bytes the server read.
+static RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+extern "C" fn on_transmit(endpoint_id: u64, data: *const u8, length: usize, _address: IpAddr) {
+    let data = unsafe { std::slice::from_raw_parts(data, length) };
+    if let Some(wire) = WIRE.lock().unwrap().as_ref() {
+        let _ = wire.send((endpoint_id, data.to_vec()));
+    }
+}
+
+extern "C" fn on_new_connection(connection: Handle, connection_id: u32, _endpoint_id: u64) {
+    SERVER_CONNECTIONS
+        .lock()
+        .unwrap()
+        .push((connection_id, connection as usize));
+}
+
+extern "C" fn on_connected(connection_id: u32) {
+    if let Some(connected) = CONNECTED.lock().unwrap().as_ref() {
+        let _ = connected.send(connection_id);
+    }
+}
+
+/// Reads the stream if it is one of the server, the data that opens a stream raises no readable event.
+extern "C" fn on_stream_data(connection_id: u32, stream_id: u64, _direction: u8) {
+    let connection = match SERVER_CONNECTIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(id, _)| *id == connection_id)
+    {
+        Some((_, connection)) => *connection as Handle,
+        None => return,
+    };
+
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let (mut length, mut code) = (0, 0);
+        let result = unsafe {
+            read_stream_v3(
+                connection,
+                stream_id,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut length,
+                &mut code,
+            )
+        };
+        if result.kind != FFIResultKind::Ok {
+            return;
+        }
+        RECEIVED.fetch_add(length as u64, Ordering::SeqCst);
+    }
+}
+
+fn self_signed_cert() -> (Vec<u8>, Vec<u8>) {
+    let names = b"localhost";
+    let (mut cert, mut key) = (vec![0u8; 4096], vec![0u8; 4096]);
+    let (mut cert_len, mut key_len) = (0, 0);
+
+    check(unsafe {
+        generate_self_signed_cert(
+            names.as_ptr(),
+            names.len() as u32,
+            1,
+            cert.as_mut_ptr(),
+            cert.len(),
+            &mut cert_len,
+            key.as_mut_ptr(),
+            key.len(),
+            &mut key_len,
+        )
+    });
+
+    cert.truncate(cert_len);
+    key.truncate(key_len);
+    (cert, key)
+}
+
+/// Creates a server endpoint with the certificate and key.
+fn server_endpoint(cert: &[u8], key: &[u8]) -> (Handle, u64, Handle) {
+    let (mut config, mut endpoint_id, mut endpoint) =
+        (std::ptr::null_mut(), 0, std::ptr::null_mut());
+
+    unsafe {
+        check(create_server_config(
+            &mut config,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        ));
+        check(create_server_endpoint(
+            config,
+            &mut endpoint_id,
+            &mut endpoint,
+        ));
+    }
+
+    (config, endpoint_id, endpoint)
+}
+
+/// A client and a server endpoint whose datagrams are exchanged in memory by `deliver`.
+struct Pair {
+    server_config: Handle,
+    server_id: u64,
+    server: Handle,
+    client_config: Handle,
+    client: Handle,
+    wire: mpsc::Receiver<Datagram>,
+    connected: mpsc::Receiver<u32>,
+}
+
+impl Pair {
+    const SERVER_ADDRESS: &'static str = "127.0.0.1:4433";
+    const CLIENT_ADDRESS: &'static str = "127.0.0.1:4434";
+
+    fn new() -> Self {
+        let (wire_tx, wire) = mpsc::channel();
+        let (connected_tx, connected) = mpsc::channel();
+        *WIRE.lock().unwrap() = Some(wire_tx);
+        *CONNECTED.lock().unwrap() = Some(connected_tx);
+
+        // The client trusts the certificate of the server.
+        let (cert, key) = self_signed_cert();
+        let (server_config, server_id, server) = server_endpoint(&cert, &key);
+        let (mut client_config, mut client_id, mut client) =
+            (std::ptr::null_mut(), 0, std::ptr::null_mut());
+        unsafe {
+            check(create_client_config(
+                &mut client_config,
+                cert.as_ptr(),
+                cert.len() as u32,
+                key.as_ptr(),
+                key.len() as u32,
+            ));
+            check(create_client_endpoint(
+                client_config,
+                &mut client_id,
+                &mut client,
+            ));
+        }
+
+        Pair {
+            server_config,
+            server_id,
+            server,
+            client_config,
+            client,
+            wire,
+            connected,
+        }
+    }
+
+    /// Passes the datagrams sent so far to the other endpoint, waits up to `wait` for the first one.
+    fn deliver(&self, wait: Duration) {
+        let mut next = self.wire.recv_timeout(wait).ok();
+        while let Some((endpoint_id, datagram)) = next {
+            let (endpoint, from) = if endpoint_id == self.server_id {
+                (self.client, Self::SERVER_ADDRESS)
+            } else {
+                (self.server, Self::CLIENT_ADDRESS)
+            };
+            let from = IpAddr::from(from.parse::<SocketAddr>().unwrap());
+            check(unsafe { handle_datagram(endpoint, datagram.as_ptr(), datagram.len(), from) });
+
+            next = self.wire.try_recv().ok();
+        }
+    }
+
+    /// Connects the client and exchanges datagrams until its handshake completed.
+    fn connect(&self) -> Handle {
+        let host = b"localhost";
+        let (mut connection, mut connection_id) = (std::ptr::null_mut(), 0);
+        check(unsafe {
+            connect_client(
+                self.client,
+                host.as_ptr(),
+                host.len() as u32,
+                IpAddr::from(Self::SERVER_ADDRESS.parse::<SocketAddr>().unwrap()),
+                &mut connection,
+                &mut connection_id,
+            )
+        });
+
+        let started = Instant::now();
+        loop {
+            if self.connected.try_iter().any(|id| id == connection_id) {
+                return connection;
+            }
+            assert!(
+                started.elapsed() < TIMEOUT,
+                "the handshake did not complete"
+            );
+            self.deliver(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Drop for Pair {
+    fn drop(&mut self) {
+        *WIRE.lock().unwrap() = None;
+        *CONNECTED.lock().unwrap() = None;
+
+        unsafe {
+            for (_, connection) in SERVER_CONNECTIONS.lock().unwrap().drain(..) {
+                check(connection_handle_release(connection as Handle));
+            }
+            check(dispose_endpoint(self.client));
+            check(dispose_endpoint(self.server));
+            check(free_client_config(self.client_config));
+            check(free_server_config(self.server_config));
+        }
+    }
+}
+
+/// Establishes `HANDSHAKES` connections one after another, returns the handshakes per second.
+fn handshake_rate() -> f64 {
+    let pair = Pair::new();
+
+    let start = Instant::now();
+    for _ in 0..HANDSHAKES {
+        let connection = pair.connect();
+        unsafe {
+            check(close_connection(connection, b"done".as_ptr(), 4, 0));
+            check(connection_handle_release(connection));
+        }
+    }
+
+    HANDSHAKES as f64 / start.elapsed().as_secs_f64()
+}
+
+/// Transfers `TRANSFER_BYTES` on a stream from the client to the server, returns the bytes per second.
+fn throughput() -> f64 {
+    let pair = Pair::new();
+    let connection = pair.connect();
+    RECEIVED.store(0, Ordering::SeqCst);
+
+    let mut stream_id = 0;
+    check(unsafe { open_stream(connection, UNI, &mut stream_id) });
+
+    let chunk = vec![0u8; CHUNK_SIZE];
+    let mut written = 0;
+    let start = Instant::now();
+    while RECEIVED.load(Ordering::SeqCst) < TRANSFER_BYTES {
+        while written < TRANSFER_BYTES {
+            let len = (TRANSFER_BYTES - written).min(CHUNK_SIZE as u64) as usize;
+            let mut count = 0;
+            let result =
+                unsafe { write_stream(connection, stream_id, chunk.as_ptr(), len, &mut count) };
+            // A write blocked by flow control fails, it is retried once the peer read the data.
+            if result.kind != FFIResultKind::Ok {
+                break;
+            }
+            written += count as u64;
+        }
+
+        assert!(
+            start.elapsed() < TIMEOUT * 6,
+            "the transfer did not complete"
+        );
+        pair.deliver(Duration::from_millis(1));
+    }
+    let elapsed = start.elapsed();
+
+    check(unsafe { connection_handle_release(connection) });
+    TRANSFER_BYTES as f64 / elapsed.as_secs_f64()
+}
+
+/// Passes `DATAGRAMS` datagrams to the receive path, returns the datagrams per second.
+///
+/// The datagrams lack the fixed bit of QUIC packets, such that the endpoint drops them without answering,
+/// and the rate reflects the cost of getting a datagram into quinn.
+fn receive_rate(
+    endpoint: Handle,
+    receive: unsafe extern "C" fn(Handle, *const u8, usize, IpAddr) -> FFIResult,
+) -> f64 {
+    let datagram = [0u8; DATAGRAM_SIZE];
+    let address = IpAddr::from("127.0.0.1:4433".parse::<SocketAddr>().unwrap());
+
+    let start = Instant::now();
+    for _ in 0..DATAGRAMS {
+        check(unsafe { receive(endpoint, datagram.as_ptr(), datagram.len(), address) });
+    }
+
+    DATAGRAMS as f64 / start.elapsed().as_secs_f64()
+}
+
+fn receive_paths() {
+    let (cert, key) = self_signed_cert();
+    let (config, _, endpoint) = server_endpoint(&cert, &key);
+
+    // Warms up the allocator and the caches with the first path.
+    receive_rate(endpoint, handle_datagram);
+    let copied = receive_rate(endpoint, handle_datagram);
+    let borrowed = receive_rate(endpoint, handle_datagram_borrowed);
+
+    println!(
+        "receive:    {:>10.1} datagrams/s copied, {:.1} datagrams/s borrowed ({:+.1}%)",
+        copied,
+        borrowed,
+        (borrowed / copied - 1.0) * 100.0
+    );
+
+    unsafe {
+        check(dispose_endpoint(endpoint));
+        check(free_server_config(config));
+    }
+}
+
+/// Passes `DATAGRAMS` datagrams from `READERS` threads to the endpoint, with `handle_datagram`
+/// or, given an ingress, with `submit_datagram`. Returns the datagrams per second until the endpoint handled all of them.
+fn readers_rate(endpoint: Handle, ingress: Option<Handle>) -> f64 {
+    let handled = || {
+        let mut stats = EndpointStats::default();
+        check(unsafe { endpoint_stats(endpoint, &mut stats) });
+        stats.handled_datagrams
+    };
+    let target = handled() + DATAGRAMS as u64;
+
+    let start = Instant::now();
+    let readers: Vec<_> = (0..READERS)
+        .map(|_| {
+            // Handles are passed to the threads as addresses.
+            let (endpoint, ingress) = (endpoint as usize, ingress.map(|ingress| ingress as usize));
+            thread::spawn(move || {
+                let datagram = [0u8; DATAGRAM_SIZE];
+                let address = IpAddr::from("127.0.0.1:4433".parse::<SocketAddr>().unwrap());
+                for _ in 0..DATAGRAMS / READERS {
+                    check(unsafe {
+                        match ingress {
+                            Some(ingress) => submit_datagram(
+                                ingress as Handle,
+                                datagram.as_ptr(),
+                                datagram.len(),
+                                address,
+                            ),
+                            None => handle_datagram(
+                                endpoint as Handle,
+                                datagram.as_ptr(),
+                                datagram.len(),
+                                address,
+                            ),
+                        }
+                    });
+                }
+            })
+        })
+        .collect();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+
+    // Submitted datagrams are handled by the poller of the endpoint, possibly after the readers returned.
+    while handled() < target {
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "the endpoint did not handle the datagrams"
+        );
+        thread::yield_now();
+    }
+
+    DATAGRAMS as f64 / start.elapsed().as_secs_f64()
+}
+
+fn reader_threads() {
+    let (cert, key) = self_signed_cert();
+    let (config, _, endpoint) = server_endpoint(&cert, &key);
+    let mut ingress = std::ptr::null_mut();
+    check(unsafe { endpoint_datagram_ingress(endpoint, &mut ingress) });
+
+    // Warms up the allocator and the caches with the first path.
+    readers_rate(endpoint, None);
+    let locked = readers_rate(endpoint, None);
+    let submitted = readers_rate(endpoint, Some(ingress));
+
+    println!(
+        "readers:    {:>10.1} datagrams/s handle_datagram, {:.1} datagrams/s submit_datagram ({:+.1}%) with {} threads",
+        locked,
+        submitted,
+        (submitted / locked - 1.0) * 100.0,
+        READERS
+    );
+
+    unsafe {
+        check(free_datagram_ingress(ingress));
+        check(dispose_endpoint(endpoint));
+        check(free_server_config(config));
+    }
+}
+
+fn main() {
+    // The callbacks are set before the endpoints are created.
+    unsafe {
+        check(set_on_transmit_v2(on_transmit));
+        check(set_on_new_connection(on_new_connection));
+        check(set_on_connected(on_connected));
+        check(set_on_stream_opened(on_stream_data));
+        check(set_on_stream_readable(on_stream_data));
+    }
+
+    println!("handshake:  {:>10.1} handshakes/s", handshake_rate());
+    println!(
+        "throughput: {:>10.1} MiB/s",
+        throughput() / (1024.0 * 1024.0)
+    );
+
+    receive_paths();
+    reader_threads();
+}
@@ -0,0 +1,78 @@
+//! Applies the `QUINN_FFI_SYMBOL_PREFIX` build option, the prefix of every exported FFI function.
+//!
+//! Two copies of the library built with different prefixes can be loaded into one process, e.g. by two plugins.
+//! The prefix is `quinn_` by default, such that `connect_client` is exported as `quinn_connect_client`.
+//!
+//! The symbol maps that export only the prefixed functions are written next to the built library:
+//! `quinn_ffi.map`, a version script for ELF linkers, and `quinn_ffi.exports`, an exported symbols list for the macOS linker.
+//! The `cdylib` is linked with them, hosts that link the `staticlib` into a shared library of their own pass them to the linker.
+
+use std::{
+    env,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+/// The prefix of the exported functions unless `QUINN_FFI_SYMBOL_PREFIX` is set.
+const DEFAULT_PREFIX: &str = "quinn_";
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=QUINN_FFI_SYMBOL_PREFIX");
+
+    let prefix = env::var("QUINN_FFI_SYMBOL_PREFIX").unwrap_or_else(|_| DEFAULT_PREFIX.to_owned());
+    if !is_valid_prefix(&prefix) {
+        panic!(
+            "QUINN_FFI_SYMBOL_PREFIX `{}` must start with `quinn_` and only contain ASCII letters, digits and underscores",
+            prefix
+        );
+    }
+
+    println!("cargo:rustc-env=QUINN_FFI_SYMBOL_PREFIX={}", prefix);
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let (version_script, exported_symbols) = write_symbol_maps(&out_dir, &prefix);
+
+    match env::var("CARGO_CFG_TARGET_OS").as_deref() {
+        Ok("macos") | Ok("ios") => println!(
+            "cargo:rustc-cdylib-link-arg=-Wl,-exported_symbols_list,{}",
+            exported_symbols.display()
+        ),
+        Ok("windows") => {}
+        _ => println!(
+            "cargo:rustc-cdylib-link-arg=-Wl,--version-script={}",
+            version_script.display()
+        ),
+    }
+
+    // `OUT_DIR` is `target/<profile>/build/<package>-<hash>/out`, the library is built into `target/<profile>`.
+    if let Some(profile_dir) = out_dir.ancestors().nth(3) {
+        fs::copy(&version_script, profile_dir.join("quinn_ffi.map")).unwrap();
+        fs::copy(&exported_symbols, profile_dir.join("quinn_ffi.exports")).unwrap();
+    }
+}
+
+/// A prefix in the `quinn_` namespace that is a valid start of a C identifier.
+fn is_valid_prefix(prefix: &str) -> bool {
+    prefix.starts_with("quinn_")
+        && prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Writes the version script and the exported symbols list into `dir`, returning their paths.
+fn write_symbol_maps(dir: &Path, prefix: &str) -> (PathBuf, PathBuf) {
+    let version_script = dir.join("quinn_ffi.map");
+    let exported_symbols = dir.join("quinn_ffi.exports");
+
+    fs::write(
+        &version_script,
+        format!("{{\n  global: {}*;\n  local: *;\n}};\n", prefix),
+    )
+    .unwrap();
+    fs::write(&exported_symbols, format!("_{}*\n", prefix)).unwrap();
+
+    (version_script, exported_symbols)
+}
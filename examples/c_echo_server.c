@@ -0,0 +1,255 @@
+/*
+ * An echo server hosting quinn-ffi from C, the plumbing every host implements:
+ * datagrams received on a UDP socket are passed to `handle_datagram`, transmits are sent from the `on_transmit_v2`
+ * callback and the data of every bidirectional stream is echoed from the stream callbacks.
+ *
+ * Usage: c_echo_server <certificate.der> <private key.der>
+ *
+ * Prints `listening <port>` once the server accepts connections on 127.0.0.1, and exits with 0 once the first
+ * connection is lost. Run by `tests/example_hosts.rs` with the `example-hosts` feature, such that ABI changes that
+ * break C hosts fail the tests of the crate.
+ *
+ * Build against the `cdylib`, e.g.: cc examples/c_echo_server.c -Ltarget/debug -lquinn_ffi -o c_echo_server
+ */
+
+#include <arpa/inet.h>
+#include <netinet/in.h>
+#include <stdatomic.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <sys/time.h>
+#include <unistd.h>
+
+/* The FFI types, see `FFIResult` and `IpAddr` in the Rust sources. */
+
+enum {
+    RESULT_OK = 0,
+    RESULT_BUFFER_BLOCKED = 3,
+    RESULT_STREAM_FINISHED = 15,
+    RESULT_STREAM_RESET = 16,
+};
+
+typedef struct {
+    int kind;
+    uint64_t code;
+    size_t message_len;
+} FFIResult;
+
+typedef struct {
+    uint16_t port;
+    uint8_t is_v6;
+    uint8_t address[16];
+    uint32_t flow_info;
+    uint32_t scope_id;
+} IpAddr;
+
+typedef void *Handle;
+
+/* The functions are exported with the default `QUINN_FFI_SYMBOL_PREFIX`, `quinn_`. */
+
+FFIResult quinn_create_server_config(Handle *out_handle, const uint8_t *cert, uint32_t cert_len, const uint8_t *key,
+                                     uint32_t key_len);
+FFIResult quinn_create_server_endpoint(Handle config, uint64_t *out_endpoint_id, Handle *out_endpoint);
+FFIResult quinn_handle_datagram(Handle endpoint, const uint8_t *data, size_t length, IpAddr address);
+FFIResult quinn_ip_addr_from_network_order(const uint8_t *address, uint32_t address_len, uint16_t network_port,
+                                           uint32_t network_flow_info, uint32_t scope_id, IpAddr *out_address);
+FFIResult quinn_read_stream_v3(Handle connection, uint64_t stream_id, uint8_t *buf, size_t buf_len, size_t *out_len,
+                               uint64_t *out_reset_code);
+FFIResult quinn_write_stream(Handle connection, uint64_t stream_id, const uint8_t *buf, size_t buf_len,
+                             size_t *out_written);
+FFIResult quinn_finish_stream(Handle connection, uint64_t stream_id);
+FFIResult quinn_connection_handle_release(Handle connection);
+FFIResult quinn_dispose_endpoint(Handle endpoint);
+FFIResult quinn_free_server_config(Handle config);
+
+FFIResult quinn_set_on_transmit_v2(void (*callback)(uint64_t, const uint8_t *, size_t, IpAddr));
+FFIResult quinn_set_on_new_connection(void (*callback)(Handle, uint32_t, uint64_t));
+FFIResult quinn_set_on_stream_opened(void (*callback)(uint32_t, uint64_t, uint8_t));
+FFIResult quinn_set_on_stream_readable(void (*callback)(uint32_t, uint64_t, uint8_t));
+FFIResult quinn_set_on_connection_lost(void (*callback)(uint32_t, const uint8_t *, uint32_t));
+
+#define MAX_CONNECTIONS 16
+#define STREAM_BI 0
+
+static int udp_socket = -1;
+static atomic_bool connection_lost;
+
+/* The handles of the connections by their id, callbacks only pass the id. */
+static struct {
+    uint32_t id;
+    Handle handle;
+} connections[MAX_CONNECTIONS];
+static int connection_count;
+
+static void check(FFIResult result, const char *call) {
+    if (result.kind != RESULT_OK) {
+        fprintf(stderr, "%s failed with result kind %d\n", call, result.kind);
+        exit(1);
+    }
+}
+
+static Handle connection_handle(uint32_t id) {
+    for (int i = 0; i < connection_count; i++) {
+        if (connections[i].id == id) {
+            return connections[i].handle;
+        }
+    }
+    return NULL;
+}
+
+static void on_transmit(uint64_t endpoint_id, const uint8_t *data, size_t length, IpAddr address) {
+    (void)endpoint_id;
+
+    struct sockaddr_in peer = {0};
+    peer.sin_family = AF_INET;
+    peer.sin_port = htons(address.port);
+    memcpy(&peer.sin_addr, address.address, 4);
+
+    sendto(udp_socket, data, length, 0, (struct sockaddr *)&peer, sizeof(peer));
+}
+
+static void on_new_connection(Handle connection, uint32_t connection_id, uint64_t endpoint_id) {
+    (void)endpoint_id;
+
+    if (connection_count == MAX_CONNECTIONS) {
+        fprintf(stderr, "too many connections\n");
+        exit(1);
+    }
+    connections[connection_count].id = connection_id;
+    connections[connection_count].handle = connection;
+    connection_count++;
+}
+
+/* Echoes the data read from the stream, and finishes the stream once the peer finished it. */
+static void echo(uint32_t connection_id, uint64_t stream_id, uint8_t direction) {
+    Handle connection = connection_handle(connection_id);
+    if (connection == NULL || direction != STREAM_BI) {
+        return;
+    }
+
+    uint8_t buf[4096];
+    for (;;) {
+        size_t length = 0;
+        uint64_t reset_code = 0;
+        FFIResult result = quinn_read_stream_v3(connection, stream_id, buf, sizeof(buf), &length, &reset_code);
+
+        if (result.kind == RESULT_BUFFER_BLOCKED || result.kind == RESULT_STREAM_RESET) {
+            return;
+        }
+        if (result.kind == RESULT_STREAM_FINISHED) {
+            check(quinn_finish_stream(connection, stream_id), "finish_stream");
+            return;
+        }
+        check(result, "read_stream_v3");
+
+        /* The echoed messages are small, flow control never blocks them. */
+        size_t written = 0;
+        check(quinn_write_stream(connection, stream_id, buf, length, &written), "write_stream");
+        if (written != length) {
+            fprintf(stderr, "write_stream wrote %zu of %zu bytes\n", written, length);
+            exit(1);
+        }
+    }
+}
+
+/* The data that opens a stream is read on the open event, it raises no readable event. */
+static void on_stream_opened(uint32_t connection_id, uint64_t stream_id, uint8_t direction) {
+    echo(connection_id, stream_id, direction);
+}
+
+static void on_stream_readable(uint32_t connection_id, uint64_t stream_id, uint8_t direction) {
+    echo(connection_id, stream_id, direction);
+}
+
+static void on_connection_lost(uint32_t connection_id, const uint8_t *message, uint32_t message_len) {
+    (void)connection_id;
+    (void)message;
+    (void)message_len;
+
+    atomic_store(&connection_lost, true);
+}
+
+static uint8_t *read_file(const char *path, uint32_t *out_len) {
+    FILE *file = fopen(path, "rb");
+    if (file == NULL) {
+        perror(path);
+        exit(1);
+    }
+
+    static uint8_t buffers[2][8192];
+    static int next;
+    uint8_t *buf = buffers[next++];
+    *out_len = (uint32_t)fread(buf, 1, sizeof(buffers[0]), file);
+    fclose(file);
+    return buf;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 3) {
+        fprintf(stderr, "usage: %s <certificate.der> <private key.der>\n", argv[0]);
+        return 2;
+    }
+
+    uint32_t cert_len, key_len;
+    uint8_t *cert = read_file(argv[1], &cert_len);
+    uint8_t *key = read_file(argv[2], &key_len);
+
+    udp_socket = socket(AF_INET, SOCK_DGRAM, 0);
+    struct sockaddr_in local = {0};
+    local.sin_family = AF_INET;
+    local.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+    if (bind(udp_socket, (struct sockaddr *)&local, sizeof(local)) != 0) {
+        perror("bind");
+        return 1;
+    }
+    socklen_t local_len = sizeof(local);
+    getsockname(udp_socket, (struct sockaddr *)&local, &local_len);
+
+    /* Wakes the receive loop up regularly, such that it notices the lost connection. */
+    struct timeval timeout = {0, 50 * 1000};
+    setsockopt(udp_socket, SOL_SOCKET, SO_RCVTIMEO, &timeout, sizeof(timeout));
+
+    /* The callbacks are set before the endpoint is created. */
+    check(quinn_set_on_transmit_v2(on_transmit), "set_on_transmit_v2");
+    check(quinn_set_on_new_connection(on_new_connection), "set_on_new_connection");
+    check(quinn_set_on_stream_opened(on_stream_opened), "set_on_stream_opened");
+    check(quinn_set_on_stream_readable(on_stream_readable), "set_on_stream_readable");
+    check(quinn_set_on_connection_lost(on_connection_lost), "set_on_connection_lost");
+
+    Handle config = NULL, endpoint = NULL;
+    uint64_t endpoint_id = 0;
+    check(quinn_create_server_config(&config, cert, cert_len, key, key_len), "create_server_config");
+    check(quinn_create_server_endpoint(config, &endpoint_id, &endpoint), "create_server_endpoint");
+
+    printf("listening %u\n", ntohs(local.sin_port));
+    fflush(stdout);
+
+    uint8_t buf[65536];
+    while (!atomic_load(&connection_lost)) {
+        struct sockaddr_in peer;
+        socklen_t peer_len = sizeof(peer);
+        ssize_t length = recvfrom(udp_socket, buf, sizeof(buf), 0, (struct sockaddr *)&peer, &peer_len);
+        if (length < 0) {
+            continue;
+        }
+
+        IpAddr address;
+        check(quinn_ip_addr_from_network_order((const uint8_t *)&peer.sin_addr, 4, peer.sin_port, 0, 0, &address),
+              "ip_addr_from_network_order");
+        check(quinn_handle_datagram(endpoint, buf, (size_t)length, address), "handle_datagram");
+    }
+
+    for (int i = 0; i < connection_count; i++) {
+        check(quinn_connection_handle_release(connections[i].handle), "connection_handle_release");
+    }
+    check(quinn_dispose_endpoint(endpoint), "dispose_endpoint");
+    check(quinn_free_server_config(config), "free_server_config");
+    close(udp_socket);
+
+    printf("closed\n");
+    return 0;
+}
@@ -0,0 +1,265 @@
+// An echo server hosting quinn-ffi from .NET, the counterpart of `examples/c_echo_server.c`:
+// datagrams received on a UDP socket are passed to `handle_datagram`, transmits are sent from the `on_transmit_v2`
+// callback and the data of every bidirectional stream is echoed from the stream callbacks.
+//
+// Usage: dotnet run -- <certificate.der> <private key.der>
+//
+// Loads the library from the directory in `QUINN_FFI_LIBRARY_DIR`. Prints `listening <port>` once the server accepts
+// connections on 127.0.0.1, and exits with 0 once the first connection is lost. Run by `tests/example_hosts.rs` with
+// the `example-hosts` feature if the .NET SDK is installed, such that ABI changes that break .NET hosts fail the tests of the crate.
+
+using System;
+using System.Collections.Concurrent;
+using System.IO;
+using System.Net;
+using System.Net.Sockets;
+using System.Reflection;
+using System.Runtime.CompilerServices;
+using System.Runtime.InteropServices;
+using System.Threading;
+
+namespace QuinnFfi.Examples
+{
+    enum FFIResultKind
+    {
+        Ok = 0,
+        BufferBlocked = 3,
+        StreamFinished = 15,
+        StreamReset = 16,
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    struct FFIResult
+    {
+        public FFIResultKind Kind;
+        public ulong Code;
+        public nuint MessageLen;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    unsafe struct IpAddr
+    {
+        public ushort Port;
+        public byte IsV6;
+        public fixed byte Address[16];
+        public uint FlowInfo;
+        public uint ScopeId;
+    }
+
+    static unsafe class Native
+    {
+        const string Library = "quinn_ffi";
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_create_server_config")]
+        public static extern FFIResult create_server_config(out IntPtr out_handle, byte* cert, uint cert_len, byte* key, uint key_len);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_create_server_endpoint")]
+        public static extern FFIResult create_server_endpoint(IntPtr config, out ulong out_endpoint_id, out IntPtr out_endpoint);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_handle_datagram")]
+        public static extern FFIResult handle_datagram(IntPtr endpoint, byte* data, nuint length, IpAddr address);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_ip_addr_from_network_order")]
+        public static extern FFIResult ip_addr_from_network_order(byte* address, uint address_len, ushort network_port, uint network_flow_info, uint scope_id, out IpAddr out_address);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_read_stream_v3")]
+        public static extern FFIResult read_stream_v3(IntPtr connection, ulong stream_id, byte* buf, nuint buf_len, out nuint out_len, out ulong out_reset_code);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_write_stream")]
+        public static extern FFIResult write_stream(IntPtr connection, ulong stream_id, byte* buf, nuint buf_len, out nuint out_written);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_finish_stream")]
+        public static extern FFIResult finish_stream(IntPtr connection, ulong stream_id);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_connection_handle_release")]
+        public static extern FFIResult connection_handle_release(IntPtr connection);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_dispose_endpoint")]
+        public static extern FFIResult dispose_endpoint(IntPtr endpoint);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_free_server_config")]
+        public static extern FFIResult free_server_config(IntPtr config);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_set_on_transmit_v2")]
+        public static extern FFIResult set_on_transmit_v2(delegate* unmanaged[Cdecl]<ulong, byte*, nuint, IpAddr, void> callback);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_set_on_new_connection")]
+        public static extern FFIResult set_on_new_connection(delegate* unmanaged[Cdecl]<IntPtr, uint, ulong, void> callback);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_set_on_stream_opened")]
+        public static extern FFIResult set_on_stream_opened(delegate* unmanaged[Cdecl]<uint, ulong, byte, void> callback);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_set_on_stream_readable")]
+        public static extern FFIResult set_on_stream_readable(delegate* unmanaged[Cdecl]<uint, ulong, byte, void> callback);
+
+        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = "quinn_set_on_connection_lost")]
+        public static extern FFIResult set_on_connection_lost(delegate* unmanaged[Cdecl]<uint, byte*, uint, void> callback);
+    }
+
+    static unsafe class Program
+    {
+        const byte StreamBi = 0;
+
+        static readonly Socket Socket = new Socket(AddressFamily.InterNetwork, SocketType.Dgram, ProtocolType.Udp);
+        // The handles of the connections by their id, callbacks only pass the id.
+        static readonly ConcurrentDictionary<uint, IntPtr> Connections = new ConcurrentDictionary<uint, IntPtr>();
+        static readonly ManualResetEventSlim ConnectionLost = new ManualResetEventSlim();
+
+        static void Check(FFIResult result, string call)
+        {
+            if (result.Kind != FFIResultKind.Ok)
+            {
+                Console.Error.WriteLine($"{call} failed with result kind {(int)result.Kind}");
+                Environment.Exit(1);
+            }
+        }
+
+        [UnmanagedCallersOnly(CallConvs = new[] { typeof(CallConvCdecl) })]
+        static void OnTransmit(ulong endpointId, byte* data, nuint length, IpAddr address)
+        {
+            var bytes = new ReadOnlySpan<byte>(data, (int)length).ToArray();
+            var peer = new IPEndPoint(new IPAddress(new ReadOnlySpan<byte>(address.Address, 4)), address.Port);
+            Socket.SendTo(bytes, peer);
+        }
+
+        [UnmanagedCallersOnly(CallConvs = new[] { typeof(CallConvCdecl) })]
+        static void OnNewConnection(IntPtr connection, uint connectionId, ulong endpointId)
+        {
+            Connections[connectionId] = connection;
+        }
+
+        // The data that opens a stream is read on the open event, it raises no readable event.
+        [UnmanagedCallersOnly(CallConvs = new[] { typeof(CallConvCdecl) })]
+        static void OnStreamOpened(uint connectionId, ulong streamId, byte direction)
+        {
+            Echo(connectionId, streamId, direction);
+        }
+
+        [UnmanagedCallersOnly(CallConvs = new[] { typeof(CallConvCdecl) })]
+        static void OnStreamReadable(uint connectionId, ulong streamId, byte direction)
+        {
+            Echo(connectionId, streamId, direction);
+        }
+
+        [UnmanagedCallersOnly(CallConvs = new[] { typeof(CallConvCdecl) })]
+        static void OnConnectionLost(uint connectionId, byte* message, uint messageLen)
+        {
+            ConnectionLost.Set();
+        }
+
+        // Echoes the data read from the stream, and finishes the stream once the peer finished it.
+        static void Echo(uint connectionId, ulong streamId, byte direction)
+        {
+            if (direction != StreamBi || !Connections.TryGetValue(connectionId, out var connection))
+            {
+                return;
+            }
+
+            var buf = stackalloc byte[4096];
+            while (true)
+            {
+                var result = Native.read_stream_v3(connection, streamId, buf, 4096, out var length, out _);
+                if (result.Kind == FFIResultKind.BufferBlocked || result.Kind == FFIResultKind.StreamReset)
+                {
+                    return;
+                }
+                if (result.Kind == FFIResultKind.StreamFinished)
+                {
+                    Check(Native.finish_stream(connection, streamId), "finish_stream");
+                    return;
+                }
+                Check(result, "read_stream_v3");
+
+                // The echoed messages are small, flow control never blocks them.
+                Check(Native.write_stream(connection, streamId, buf, length, out var written), "write_stream");
+                if (written != length)
+                {
+                    Console.Error.WriteLine($"write_stream wrote {written} of {length} bytes");
+                    Environment.Exit(1);
+                }
+            }
+        }
+
+        static int Main(string[] args)
+        {
+            if (args.Length != 2)
+            {
+                Console.Error.WriteLine("usage: DotnetEchoServer <certificate.der> <private key.der>");
+                return 2;
+            }
+
+            var libraryDir = Environment.GetEnvironmentVariable("QUINN_FFI_LIBRARY_DIR");
+            if (libraryDir != null)
+            {
+                NativeLibrary.SetDllImportResolver(Assembly.GetExecutingAssembly(), (name, assembly, path) =>
+                    NativeLibrary.Load(Path.Combine(libraryDir, $"{LibraryPrefix()}{name}{LibrarySuffix()}")));
+            }
+
+            var cert = File.ReadAllBytes(args[0]);
+            var key = File.ReadAllBytes(args[1]);
+
+            Socket.Bind(new IPEndPoint(IPAddress.Loopback, 0));
+            // Wakes the receive loop up regularly, such that it notices the lost connection.
+            Socket.ReceiveTimeout = 50;
+
+            // The callbacks are set before the endpoint is created.
+            Check(Native.set_on_transmit_v2(&OnTransmit), "set_on_transmit_v2");
+            Check(Native.set_on_new_connection(&OnNewConnection), "set_on_new_connection");
+            Check(Native.set_on_stream_opened(&OnStreamOpened), "set_on_stream_opened");
+            Check(Native.set_on_stream_readable(&OnStreamReadable), "set_on_stream_readable");
+            Check(Native.set_on_connection_lost(&OnConnectionLost), "set_on_connection_lost");
+
+            IntPtr config, endpoint;
+            fixed (byte* certPtr = cert, keyPtr = key)
+            {
+                Check(Native.create_server_config(out config, certPtr, (uint)cert.Length, keyPtr, (uint)key.Length), "create_server_config");
+            }
+            Check(Native.create_server_endpoint(config, out _, out endpoint), "create_server_endpoint");
+
+            Console.WriteLine($"listening {((IPEndPoint)Socket.LocalEndPoint!).Port}");
+            Console.Out.Flush();
+
+            var buf = new byte[65536];
+            EndPoint peer = new IPEndPoint(IPAddress.Any, 0);
+            while (!ConnectionLost.IsSet)
+            {
+                int length;
+                try
+                {
+                    length = Socket.ReceiveFrom(buf, ref peer);
+                }
+                catch (SocketException e) when (e.SocketErrorCode == SocketError.TimedOut || e.SocketErrorCode == SocketError.WouldBlock)
+                {
+                    continue;
+                }
+
+                var from = (IPEndPoint)peer;
+                var addressBytes = from.Address.GetAddressBytes();
+                // The port of an `IPEndPoint` is in host byte order, the function expects it as stored in a `sockaddr_in`.
+                var networkPort = (ushort)IPAddress.HostToNetworkOrder((short)from.Port);
+
+                fixed (byte* addressPtr = addressBytes, data = buf)
+                {
+                    Check(Native.ip_addr_from_network_order(addressPtr, (uint)addressBytes.Length, networkPort, 0, 0, out var address), "ip_addr_from_network_order");
+                    Check(Native.handle_datagram(endpoint, data, (nuint)length, address), "handle_datagram");
+                }
+            }
+
+            foreach (var connection in Connections.Values)
+            {
+                Check(Native.connection_handle_release(connection), "connection_handle_release");
+            }
+            Check(Native.dispose_endpoint(endpoint), "dispose_endpoint");
+            Check(Native.free_server_config(config), "free_server_config");
+            Socket.Close();
+
+            Console.WriteLine("closed");
+            return 0;
+        }
+
+        static string LibraryPrefix() => OperatingSystem.IsWindows() ? "" : "lib";
+
+        static string LibrarySuffix() =>
+            OperatingSystem.IsWindows() ? ".dll" : OperatingSystem.IsMacOS() ? ".dylib" : ".so";
+    }
+}
@@ -0,0 +1,495 @@
+//! Drives quinn-ffi through its exported functions and raw pointers, like bindings in other languages do.
+//!
+//! Everything runs on the fuzzing thread: endpoints are polled manually, the clock is simulated and
+//! transmits are queued in memory instead of being sent over sockets.
+
+#![allow(dead_code)]
+
+use quinn_ffi::{
+    ffi::FFIResult,
+    proto_impl,
+};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    net::SocketAddr,
+    os::raw::c_void,
+    ptr,
+    slice,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        OnceLock,
+    },
+};
+
+/// A handle returned by the FFI.
+pub type Handle = *mut c_void;
+
+/// The layout of `IpAddr`, whose fields are not public to Rust code.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct IpAddr {
+    pub port: u16,
+    pub is_v6: u8,
+    pub address: [u8; 16],
+    pub flow_info: u32,
+    pub scope_id: u32,
+}
+
+/// The layout of `RecvDatagram`.
+#[repr(C)]
+pub struct RecvDatagram {
+    pub data: *const u8,
+    pub length: usize,
+    pub address: IpAddr,
+    pub ecn: u8,
+}
+
+pub const CLIENT_ADDRESS: &str = "127.0.0.1:4433";
+pub const SERVER_ADDRESS: &str = "127.0.0.1:4434";
+const SERVER_NAME: &str = "localhost";
+
+// The calling convention of the exported functions is `cdecl`, which is `C` on the fuzzed targets.
+// The functions are exported with the default `quinn_` prefix, the fuzz targets don't set `QUINN_FFI_SYMBOL_PREFIX`.
+extern "C" {
+    #[link_name = "quinn_endpoint_config_set_manual_polling"]
+    fn endpoint_config_set_manual_polling(enabled: bool) -> FFIResult;
+    #[link_name = "quinn_generate_self_signed_cert"]
+    fn generate_self_signed_cert(
+        subject_alt_names: *const u8,
+        subject_alt_names_len: u32,
+        validity_days: u32,
+        cert_buf: *mut u8,
+        cert_buf_len: usize,
+        actual_cert_len: *mut usize,
+        key_buf: *mut u8,
+        key_buf_len: usize,
+        actual_key_len: *mut usize,
+    ) -> FFIResult;
+    #[link_name = "quinn_create_server_config"]
+    fn create_server_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = "quinn_create_client_config"]
+    fn create_client_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = "quinn_create_server_endpoint"]
+    fn create_server_endpoint(
+        config: Handle,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = "quinn_create_client_endpoint"]
+    fn create_client_endpoint(
+        config: Handle,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = "quinn_connect_client"]
+    fn connect_client(
+        endpoint: Handle,
+        host: *const u8,
+        host_len: u32,
+        address: IpAddr,
+        out_connection: *mut Handle,
+        out_connection_id: *mut u32,
+    ) -> FFIResult;
+    #[link_name = "quinn_dispose_endpoint"]
+    fn dispose_endpoint(endpoint: Handle) -> FFIResult;
+    #[link_name = "quinn_free_connection_handle"]
+    fn free_connection_handle(connection: Handle) -> FFIResult;
+
+    #[link_name = "quinn_handle_datagram"]
+    pub fn handle_datagram(
+        endpoint: Handle,
+        data: *const u8,
+        length: usize,
+        address: IpAddr,
+    ) -> FFIResult;
+    #[link_name = "quinn_handle_datagram_v2"]
+    pub fn handle_datagram_v2(
+        endpoint: Handle,
+        data: *const u8,
+        length: usize,
+        address: IpAddr,
+        out_outcome: *mut u8,
+    ) -> FFIResult;
+    #[link_name = "quinn_handle_datagrams"]
+    pub fn handle_datagrams(
+        endpoint: Handle,
+        datagrams: *const RecvDatagram,
+        count: usize,
+    ) -> FFIResult;
+    #[link_name = "quinn_poll_endpoint"]
+    pub fn poll_endpoint(endpoint: Handle, now_micros: u64) -> FFIResult;
+    #[link_name = "quinn_open_stream"]
+    pub fn open_stream(
+        connection: Handle,
+        stream_direction: u8,
+        out_stream_id: *mut u64,
+    ) -> FFIResult;
+    #[link_name = "quinn_write_stream"]
+    pub fn write_stream(
+        connection: Handle,
+        stream_id: u64,
+        buffer: *const u8,
+        buf_len: usize,
+        written_bytes: *mut usize,
+    ) -> FFIResult;
+    #[link_name = "quinn_read_stream_v2"]
+    pub fn read_stream_v2(
+        connection: Handle,
+        stream_id: u64,
+        message_buf: *mut u8,
+        message_buf_len: usize,
+        actual_message_len: *mut usize,
+        out_finished: *mut bool,
+    ) -> FFIResult;
+    #[link_name = "quinn_finish_stream"]
+    pub fn finish_stream(connection: Handle, stream_id: u64) -> FFIResult;
+    #[link_name = "quinn_close_connection"]
+    pub fn close_connection(
+        connection: Handle,
+        reason: *const u8,
+        reason_len: u32,
+        error_code: u64,
+    ) -> FFIResult;
+    #[link_name = "quinn_last_error"]
+    pub fn last_error(
+        error_buf: *mut u8,
+        error_buf_len: usize,
+        actual_error_len: *mut usize,
+    ) -> FFIResult;
+
+    #[link_name = "quinn_set_clock_callback"]
+    fn set_clock_callback(callback: extern "C" fn() -> u64) -> FFIResult;
+    #[link_name = "quinn_set_on_new_connection"]
+    fn set_on_new_connection(callback: extern "C" fn(Handle, u32, u64)) -> FFIResult;
+    #[link_name = "quinn_set_on_connected"]
+    fn set_on_connected(callback: extern "C" fn(u32)) -> FFIResult;
+    #[link_name = "quinn_set_on_connection_lost"]
+    fn set_on_connection_lost(callback: extern "C" fn(u32, *const u8, u32)) -> FFIResult;
+    #[link_name = "quinn_set_on_stream_writable"]
+    fn set_on_stream_writable(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = "quinn_set_on_stream_readable"]
+    fn set_on_stream_readable(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = "quinn_set_on_stream_finished"]
+    fn set_on_stream_finished(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = "quinn_set_on_stream_stopped"]
+    fn set_on_stream_stopped(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = "quinn_set_on_stream_available"]
+    fn set_on_stream_available(callback: extern "C" fn(u32, u8)) -> FFIResult;
+    #[link_name = "quinn_set_on_stream_opened"]
+    fn set_on_stream_opened(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = "quinn_set_on_datagram_received"]
+    fn set_on_datagram_received(callback: extern "C" fn(u32)) -> FFIResult;
+    #[link_name = "quinn_set_on_pollable_connection"]
+    fn set_on_pollable_connection(callback: extern "C" fn(u32)) -> FFIResult;
+    #[link_name = "quinn_set_on_transmit_v2"]
+    fn set_on_transmit_v2(callback: extern "C" fn(u64, *const u8, usize, IpAddr)) -> FFIResult;
+}
+
+static NOW_MICROS: AtomicU64 = AtomicU64::new(0);
+// The server and client configuration, created once per process as generating the certificate is slow.
+static CONFIGS: OnceLock<(usize, usize)> = OnceLock::new();
+
+thread_local! {
+    // transmits that were not delivered yet, with the id of the sending endpoint.
+    static TRANSMITS: RefCell<VecDeque<(u64, Vec<u8>)>> = RefCell::new(VecDeque::new());
+    // connection handles passed to `on_new_connection`.
+    static ACCEPTED: RefCell<Vec<Handle>> = RefCell::new(Vec::new());
+    // streams opened by the peer, by whether the client or the server opened them.
+    static OPENED_STREAMS: RefCell<[Vec<u64>; 2]> = RefCell::new([Vec::new(), Vec::new()]);
+}
+
+extern "C" fn clock() -> u64 {
+    NOW_MICROS.load(Ordering::Relaxed)
+}
+
+extern "C" fn on_transmit(endpoint_id: u64, contents: *const u8, len: usize, _destination: IpAddr) {
+    let contents = unsafe { slice::from_raw_parts(contents, len) }.to_vec();
+    TRANSMITS.with(|transmits| transmits.borrow_mut().push_back((endpoint_id, contents)));
+}
+
+extern "C" fn on_new_connection(handle: Handle, _con: u32, _endpoint_id: u64) {
+    ACCEPTED.with(|accepted| accepted.borrow_mut().push(handle));
+}
+
+extern "C" fn on_stream_opened(_con: u32, stream_id: u64, _dir: u8) {
+    // The lowest bit of a stream id is set for streams opened by the server.
+    let opened_by_server = (stream_id & 1) as usize;
+    OPENED_STREAMS.with(|streams| streams.borrow_mut()[opened_by_server].push(stream_id));
+}
+
+extern "C" fn ignore_connection(_con: u32) {}
+
+extern "C" fn ignore_connection_lost(_con: u32, _reason: *const u8, _reason_len: u32) {}
+
+extern "C" fn ignore_stream(_con: u32, _stream_id: u64, _dir: u8) {}
+
+extern "C" fn ignore_stream_available(_con: u32, _dir: u8) {}
+
+pub fn address(address: &str) -> IpAddr {
+    let address = proto_impl::IpAddr::from(address.parse::<SocketAddr>().unwrap());
+    // Both types have the same layout.
+    unsafe { std::mem::transmute(address) }
+}
+
+/// Returns the current time of the simulated clock.
+pub fn now() -> u64 {
+    NOW_MICROS.load(Ordering::Relaxed)
+}
+
+/// Advances the simulated clock.
+pub fn advance(micros: u64) {
+    NOW_MICROS.fetch_add(micros, Ordering::Relaxed);
+}
+
+/// Sets the callbacks and creates the configurations, once per process.
+fn configs() -> (Handle, Handle) {
+    let (server_config, client_config) = *CONFIGS.get_or_init(|| unsafe {
+        endpoint_config_set_manual_polling(true);
+
+        set_clock_callback(clock);
+        set_on_new_connection(on_new_connection);
+        set_on_connected(ignore_connection);
+        set_on_connection_lost(ignore_connection_lost);
+        set_on_stream_writable(ignore_stream);
+        set_on_stream_readable(ignore_stream);
+        set_on_stream_finished(ignore_stream);
+        set_on_stream_stopped(ignore_stream);
+        set_on_stream_available(ignore_stream_available);
+        set_on_stream_opened(on_stream_opened);
+        set_on_datagram_received(ignore_connection);
+        set_on_pollable_connection(ignore_connection);
+        set_on_transmit_v2(on_transmit);
+
+        let mut cert = vec![0u8; 1024];
+        let mut key = vec![0u8; 1024];
+        let (mut cert_len, mut key_len) = (0, 0);
+        generate_self_signed_cert(
+            SERVER_NAME.as_ptr(),
+            SERVER_NAME.len() as u32,
+            1,
+            cert.as_mut_ptr(),
+            cert.len(),
+            &mut cert_len,
+            key.as_mut_ptr(),
+            key.len(),
+            &mut key_len,
+        );
+
+        let mut server_config = ptr::null_mut();
+        create_server_config(
+            &mut server_config,
+            cert.as_ptr(),
+            cert_len as u32,
+            key.as_ptr(),
+            key_len as u32,
+        );
+        let mut client_config = ptr::null_mut();
+        create_client_config(
+            &mut client_config,
+            cert.as_ptr(),
+            cert_len as u32,
+            key.as_ptr(),
+            key_len as u32,
+        );
+
+        (server_config as usize, client_config as usize)
+    });
+
+    (server_config as Handle, client_config as Handle)
+}
+
+/// A server endpoint, without a client.
+pub struct Server {
+    pub endpoint: Handle,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        let (server_config, _) = configs();
+        let mut endpoint = ptr::null_mut();
+        let mut endpoint_id = 0;
+        unsafe { create_server_endpoint(server_config, &mut endpoint_id, &mut endpoint) };
+
+        Server { endpoint }
+    }
+
+    pub fn poll(&self) {
+        unsafe { poll_endpoint(self.endpoint, now()) };
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        for connection in ACCEPTED.with(|accepted| accepted.take()) {
+            unsafe { free_connection_handle(connection) };
+        }
+        TRANSMITS.with(|transmits| transmits.borrow_mut().clear());
+
+        unsafe { dispose_endpoint(self.endpoint) };
+    }
+}
+
+/// A client endpoint connected to a server endpoint, packets are exchanged in memory.
+pub struct Pair {
+    pub server: Handle,
+    pub client: Handle,
+    server_id: u64,
+    client_id: u64,
+    pub client_connection: Handle,
+    pub server_connection: Option<Handle>,
+}
+
+impl Pair {
+    /// Creates the endpoints and starts connecting the client, see `handshake` to complete the connection.
+    pub fn new() -> Self {
+        let (server_config, client_config) = configs();
+
+        let mut pair = Pair {
+            server: ptr::null_mut(),
+            client: ptr::null_mut(),
+            server_id: 0,
+            client_id: 0,
+            client_connection: ptr::null_mut(),
+            server_connection: None,
+        };
+
+        unsafe {
+            create_server_endpoint(server_config, &mut pair.server_id, &mut pair.server);
+            create_client_endpoint(client_config, &mut pair.client_id, &mut pair.client);
+
+            let mut connection_id = 0;
+            connect_client(
+                pair.client,
+                SERVER_NAME.as_ptr(),
+                SERVER_NAME.len() as u32,
+                address(SERVER_ADDRESS),
+                &mut pair.client_connection,
+                &mut connection_id,
+            );
+        }
+
+        pair.poll();
+        pair
+    }
+
+    /// Exchanges packets until no more are sent, at most `rounds` times.
+    pub fn handshake(&mut self, rounds: usize) {
+        for _ in 0..rounds {
+            if self.deliver(usize::MAX) == 0 {
+                break;
+            }
+        }
+    }
+
+    /// The connection of the client or the server side, `None` if the server did not accept it yet.
+    pub fn connection(&self, client: bool) -> Option<Handle> {
+        match client {
+            true => Some(self.client_connection),
+            false => self.server_connection,
+        }
+    }
+
+    /// Returns the streams opened by the peer of the given side.
+    pub fn opened_streams(&self, client: bool) -> Vec<u64> {
+        // The client receives the streams opened by the server.
+        OPENED_STREAMS.with(|streams| streams.borrow()[client as usize].clone())
+    }
+
+    /// Delivers up to `count` queued transmits to the other endpoint and polls, returns the number delivered.
+    pub fn deliver(&mut self, count: usize) -> usize {
+        let mut delivered = 0;
+
+        while delivered < count {
+            let (endpoint_id, contents) =
+                match TRANSMITS.with(|transmits| transmits.borrow_mut().pop_front()) {
+                    Some(transmit) => transmit,
+                    None => break,
+                };
+
+            let (destination, source) = match endpoint_id == self.client_id {
+                true => (self.server, CLIENT_ADDRESS),
+                false => (self.client, SERVER_ADDRESS),
+            };
+            unsafe {
+                handle_datagram(
+                    destination,
+                    contents.as_ptr(),
+                    contents.len(),
+                    address(source),
+                )
+            };
+            delivered += 1;
+        }
+
+        self.poll();
+        delivered
+    }
+
+    /// Drops up to `count` queued transmits, as if they were lost.
+    pub fn drop_transmits(&mut self, count: usize) {
+        TRANSMITS.with(|transmits| {
+            let mut transmits = transmits.borrow_mut();
+            let count = count.min(transmits.len());
+            transmits.drain(..count);
+        });
+    }
+
+    /// Flips bits of a queued transmit, as if it was corrupted on the way.
+    pub fn corrupt_transmit(&mut self, index: usize, offset: usize, mask: u8) {
+        TRANSMITS.with(|transmits| {
+            let mut transmits = transmits.borrow_mut();
+            let len = transmits.len();
+            if let Some((_, contents)) = transmits.get_mut(index % len.max(1)) {
+                let len = contents.len();
+                if let Some(byte) = contents.get_mut(offset % len.max(1)) {
+                    *byte ^= mask;
+                }
+            }
+        });
+    }
+
+    /// Polls both endpoints, and picks up the connection accepted by the server.
+    pub fn poll(&mut self) {
+        unsafe {
+            poll_endpoint(self.client, now());
+            poll_endpoint(self.server, now());
+        }
+
+        if self.server_connection.is_none() {
+            self.server_connection = ACCEPTED.with(|accepted| accepted.borrow().first().copied());
+        }
+    }
+}
+
+impl Drop for Pair {
+    fn drop(&mut self) {
+        unsafe {
+            free_connection_handle(self.client_connection);
+            for connection in ACCEPTED.with(|accepted| accepted.take()) {
+                free_connection_handle(connection);
+            }
+
+            dispose_endpoint(self.client);
+            dispose_endpoint(self.server);
+        }
+
+        TRANSMITS.with(|transmits| transmits.borrow_mut().clear());
+        OPENED_STREAMS.with(|streams| *streams.borrow_mut() = [Vec::new(), Vec::new()]);
+    }
+}
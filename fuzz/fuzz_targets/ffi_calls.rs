@@ -0,0 +1,195 @@
+//! Runs arbitrary sequences of FFI calls against a connected client and server endpoint.
+//!
+//! The packets between the endpoints are delivered, dropped, corrupted and delayed by the input.
+
+#![no_main]
+
+mod common;
+
+use arbitrary::Arbitrary;
+use common::Pair;
+use libfuzzer_sys::fuzz_target;
+use std::ptr;
+
+/// The number of packet exchanges allowed to complete the handshake before the operations run.
+const HANDSHAKE_ROUNDS: usize = 10;
+
+#[derive(Arbitrary, Debug)]
+enum Stream {
+    /// A stream opened by this side, by the index of the open operation.
+    Opened(usize),
+    /// A stream opened by the peer, by the index of its `on_stream_opened` callback.
+    Accepted(usize),
+    /// Any stream id, which need not exist.
+    Raw(u64),
+}
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    OpenStream {
+        client: bool,
+        direction: u8,
+    },
+    Write {
+        client: bool,
+        stream: Stream,
+        data: Vec<u8>,
+    },
+    Read {
+        client: bool,
+        stream: Stream,
+        buf_len: u16,
+    },
+    Finish {
+        client: bool,
+        stream: Stream,
+    },
+    Close {
+        client: bool,
+        error_code: u64,
+        reason: Vec<u8>,
+    },
+    Deliver(u8),
+    Drop(u8),
+    Corrupt {
+        index: u8,
+        offset: u16,
+        mask: u8,
+    },
+    Advance(u32),
+    LastError,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    complete_handshake: bool,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut pair = Pair::new();
+    if input.complete_handshake {
+        pair.handshake(HANDSHAKE_ROUNDS);
+    }
+
+    // streams opened by the client and by the server.
+    let mut opened: [Vec<u64>; 2] = [Vec::new(), Vec::new()];
+
+    let stream_id =
+        |pair: &Pair, opened: &[Vec<u64>; 2], client: bool, stream: &Stream| match *stream {
+            Stream::Opened(index) => {
+                let streams = &opened[client as usize];
+                streams.get(index % streams.len().max(1)).copied()
+            }
+            Stream::Accepted(index) => {
+                let streams = pair.opened_streams(client);
+                streams.get(index % streams.len().max(1)).copied()
+            }
+            Stream::Raw(id) => Some(id),
+        };
+
+    for op in input.ops {
+        match op {
+            Op::OpenStream { client, direction } => {
+                if let Some(connection) = pair.connection(client) {
+                    let mut id = 0;
+                    let result = unsafe { common::open_stream(connection, direction, &mut id) };
+                    if !result.is_err() {
+                        opened[client as usize].push(id);
+                    }
+                }
+            }
+            Op::Write {
+                client,
+                stream,
+                data,
+            } => {
+                if let (Some(connection), Some(id)) = (
+                    pair.connection(client),
+                    stream_id(&pair, &opened, client, &stream),
+                ) {
+                    let mut written = 0;
+                    unsafe {
+                        common::write_stream(
+                            connection,
+                            id,
+                            data.as_ptr(),
+                            data.len(),
+                            &mut written,
+                        )
+                    };
+                }
+            }
+            Op::Read {
+                client,
+                stream,
+                buf_len,
+            } => {
+                if let (Some(connection), Some(id)) = (
+                    pair.connection(client),
+                    stream_id(&pair, &opened, client, &stream),
+                ) {
+                    let mut buf = vec![0u8; buf_len as usize];
+                    let mut actual_len = 0;
+                    let mut finished = false;
+                    unsafe {
+                        common::read_stream_v2(
+                            connection,
+                            id,
+                            buf.as_mut_ptr(),
+                            buf.len(),
+                            &mut actual_len,
+                            &mut finished,
+                        )
+                    };
+                    assert!(actual_len <= buf.len());
+                }
+            }
+            Op::Finish { client, stream } => {
+                if let (Some(connection), Some(id)) = (
+                    pair.connection(client),
+                    stream_id(&pair, &opened, client, &stream),
+                ) {
+                    unsafe { common::finish_stream(connection, id) };
+                }
+            }
+            Op::Close {
+                client,
+                error_code,
+                reason,
+            } => {
+                if let Some(connection) = pair.connection(client) {
+                    // Error codes are variable-length integers of at most 62 bits.
+                    let error_code = error_code & ((1 << 62) - 1);
+                    unsafe {
+                        common::close_connection(
+                            connection,
+                            reason.as_ptr(),
+                            reason.len() as u32,
+                            error_code,
+                        )
+                    };
+                }
+            }
+            Op::Deliver(count) => {
+                pair.deliver(count as usize);
+            }
+            Op::Drop(count) => pair.drop_transmits(count as usize),
+            Op::Corrupt {
+                index,
+                offset,
+                mask,
+            } => pair.corrupt_transmit(index as usize, offset as usize, mask),
+            Op::Advance(micros) => {
+                common::advance(micros as u64);
+                pair.poll();
+            }
+            Op::LastError => {
+                let mut len = 0;
+                unsafe { common::last_error(ptr::null_mut(), 0, &mut len) };
+                let mut buf = vec![0u8; len];
+                unsafe { common::last_error(buf.as_mut_ptr(), buf.len(), &mut len) };
+            }
+        }
+    }
+});
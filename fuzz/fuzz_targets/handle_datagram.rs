@@ -0,0 +1,89 @@
+//! Feeds arbitrary datagrams from arbitrary addresses to a server endpoint.
+
+#![no_main]
+
+mod common;
+
+use arbitrary::Arbitrary;
+use common::{
+    IpAddr,
+    RecvDatagram,
+    Server,
+};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    datagrams: Vec<Datagram>,
+    // whether the datagrams are handled in one batch by `handle_datagrams`.
+    batched: bool,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Datagram {
+    data: Vec<u8>,
+    port: u16,
+    // not restricted to 0 and 1, such that invalid addresses are rejected.
+    is_v6: u8,
+    address: [u8; 16],
+    flow_info: u32,
+    scope_id: u32,
+    ecn: u8,
+    // whether the outcome is reported by `handle_datagram_v2`.
+    v2: bool,
+}
+
+impl Datagram {
+    fn address(&self) -> IpAddr {
+        IpAddr {
+            port: self.port,
+            is_v6: self.is_v6,
+            address: self.address,
+            flow_info: self.flow_info,
+            scope_id: self.scope_id,
+        }
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let server = Server::new();
+
+    if input.batched {
+        let datagrams: Vec<_> = input
+            .datagrams
+            .iter()
+            .map(|datagram| RecvDatagram {
+                data: datagram.data.as_ptr(),
+                length: datagram.data.len(),
+                address: datagram.address(),
+                ecn: datagram.ecn,
+            })
+            .collect();
+
+        unsafe { common::handle_datagrams(server.endpoint, datagrams.as_ptr(), datagrams.len()) };
+    } else {
+        for datagram in &input.datagrams {
+            unsafe {
+                if datagram.v2 {
+                    let mut outcome = 0u8;
+                    common::handle_datagram_v2(
+                        server.endpoint,
+                        datagram.data.as_ptr(),
+                        datagram.data.len(),
+                        datagram.address(),
+                        &mut outcome,
+                    );
+                } else {
+                    common::handle_datagram(
+                        server.endpoint,
+                        datagram.data.as_ptr(),
+                        datagram.data.len(),
+                        datagram.address(),
+                    );
+                }
+            }
+        }
+    }
+
+    server.poll();
+});
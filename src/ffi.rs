@@ -12,18 +12,56 @@ pub use safe_api::*;
 #[cfg(feature = "unsafe-api")]
 pub use unsafe_api::*;
 
+mod abi;
 mod bindings;
+mod callback_slot;
+mod dispatch;
+mod endpoint_options;
 mod ffi_result;
 mod handle_mut;
 mod handle_shared;
+mod handle_slots;
+mod library;
+mod logger;
 mod null;
 mod out;
+pub(crate) mod poison;
 mod reference;
+mod type_info;
+mod wakeup;
 
+pub use abi::{
+    ABI_VERSION_MAJOR,
+    ABI_VERSION_MINOR,
+};
+pub use dispatch::CallbackDispatchMode;
+pub(crate) use dispatch::{
+    defer,
+    dispatch_mode,
+    LockScope,
+};
+pub use endpoint_options::{
+    EndpointOptions,
+    EndpointRole,
+    PollerMode,
+};
+pub use library::{
+    init_library,
+    LibraryOptions,
+};
+pub use logger::{
+    set_log_callback,
+    LogLevel,
+};
 pub use null::IsNull;
-pub use out::Out;
+pub use out::{
+    Out,
+    OutBuffer,
+};
 
 pub use ffi_result::{
+    result_is_fatal,
+    ErrorDetails,
     FFIResult,
     FFIResultKind,
     QuinnError,
@@ -36,20 +74,167 @@ pub use reference::{
 
 pub use bindings::{
     accept_stream,
+    acquire_recv_buffer,
+    cancellation_cancel,
+    claim_stream,
+    client_config_set_h3_alpn,
+    close_connection_with_app_error,
+    commit_recv_buffer,
     connect_client,
+    connect_client_with_config,
+    connection_close_reason,
+    connection_diagnostics,
+    connection_drain_deadline,
+    connection_enable_qlog,
+    connection_export_keying_material,
+    connection_handle_clone,
+    connection_handle_release,
+    connection_handshake_data,
+    connection_is_handshaking,
+    connection_last_activity,
+    connection_max_datagram_size,
+    connection_open_streams,
+    connection_pacing_rate,
+    connection_peer_params,
+    connection_ping,
+    connection_quic_version,
+    connection_rtt_micros,
+    connection_sender_handle,
+    connection_set_auth_token,
+    connection_set_diagnostics_enabled,
+    connection_set_max_send_rate,
+    connection_set_stream_data_tap,
+    connection_set_stream_orphan_policy,
+    connection_set_stream_scheduling,
+    connection_state,
+    create_cancellation,
     create_client_config,
     create_client_endpoint,
+    create_client_endpoint_ex,
+    create_endpoint_config,
+    create_endpoint_ex,
     create_server_config,
     create_server_endpoint,
+    create_server_endpoint_ex,
+    create_transport_config,
+    describe_types,
+    dispatch_callbacks,
+    endpoint_add_local_address,
+    endpoint_config_handle_set_cid_generator_enabled,
+    endpoint_config_handle_set_cid_length,
+    endpoint_config_handle_set_handshake_timeout,
+    endpoint_config_handle_set_manual_polling,
+    endpoint_config_handle_set_max_udp_payload_size,
+    endpoint_config_handle_set_reset_key,
+    endpoint_config_handle_set_supported_versions,
+    endpoint_config_set_cid_length,
+    endpoint_config_set_handshake_timeout,
+    endpoint_config_set_manual_polling,
+    endpoint_config_set_max_udp_payload_size,
+    endpoint_config_set_reset_key,
+    endpoint_config_set_supported_versions,
+    endpoint_datagram_ingress,
+    endpoint_event_fd,
+    endpoint_from_id,
+    endpoint_get_or_connect,
+    endpoint_list_connections,
+    endpoint_pending_handshakes,
+    endpoint_poller_stats,
+    endpoint_require_auth_token,
+    endpoint_set_connection_idle_policy,
+    endpoint_set_event_polling_enabled,
+    endpoint_set_max_pending_handshakes,
+    endpoint_set_max_transmit_burst,
+    endpoint_set_pollable_coalescing,
+    endpoint_set_recv_buffer_pool,
+    endpoint_set_server_config,
+    endpoint_set_transmit_pull_mode,
+    endpoint_stats,
+    endpoint_transmit_burst_stats,
+    ffi_abi_require,
+    ffi_abi_version,
+    ffi_selftest,
+    format_socket_addr,
+    free_cancellation,
+    free_connection_handle,
+    free_connection_sender,
+    free_datagram_ingress,
+    free_endpoint_config,
+    free_endpoint_handle,
+    free_transport_config,
+    generate_self_signed_cert,
+    get_connection_handle,
+    h3_open_control_stream,
+    h3_parse_settings,
     handle_datagram,
+    handle_datagram_borrowed,
+    handle_datagram_local,
+    handle_datagram_v2,
+    handle_datagrams,
+    init_runtime,
+    ip_addr_from_network_order,
+    ip_addr_network_order,
     last_error,
+    last_error_details,
     open_stream,
+    open_stream_and_write,
+    parse_socket_addr,
     poll_connection,
+    poll_connection_events,
+    poll_endpoint,
+    poll_transmit,
+    read_message,
     read_stream,
+    read_stream_v2,
+    read_stream_v3,
+    receive_to_file,
+    receive_to_file_fd,
+    report_transmit_failed,
+    report_transmit_writable,
+    run_benchmark,
+    send_datagram_expiring,
+    send_file,
+    send_file_fd,
+    sender_send_datagram,
+    sender_take_failure,
+    sender_write_stream,
+    server_config_set_h3_alpn,
+    server_config_set_retry_enabled,
+    server_config_set_token_key,
+    set_callback_dispatch_mode,
+    set_event_polling_enabled,
+    shutdown_all,
+    stream_id_direction,
+    stream_id_index,
+    stream_id_initiator,
+    stream_set_framed,
+    stream_set_priority,
+    stream_set_user_data,
+    stream_stats,
+    stream_user_data,
+    submit_datagram,
+    transport_config_handle_set_crypto_buffer_size,
+    transport_config_handle_set_datagram_receive_buffer_size,
+    transport_config_handle_set_datagram_send_buffer_size,
+    transport_config_handle_set_initial_window,
+    transport_config_set_crypto_buffer_size,
+    transport_config_set_datagram_receive_buffer_size,
+    transport_config_set_datagram_send_buffer_size,
+    transport_config_set_initial_window,
+    validate_client_config,
+    validate_server_config,
+    wait_closed,
+    wait_closed_v2,
+    wait_connected,
+    wait_connected_v2,
+    write_message,
     write_stream,
+    write_stream_all,
+    write_stream_owned,
 };
 
 pub use bindings::callbacks;
+pub(crate) use bindings::drain_datagram_ingress;
 
 use crate::proto_impl::FFIErrorKind;
 
@@ -0,0 +1,24 @@
+//! The version of the binary interface, such that prebuilt hosts detect an incompatible native library
+//! instead of corrupting memory on mismatching struct layouts or signatures.
+//!
+//! The major version changes with incompatible changes, e.g. a changed struct layout or function signature.
+//! The minor version changes with additions, e.g. new functions, `_v2` functions or callbacks.
+//!
+//! Version 2.0 rejects stream directions other than 0 and 1, returns `StreamFinished` and `StreamReset` from
+//! `read_stream` instead of an empty successful read, and adds the `ErrorDetails` struct of `last_error_details`.
+//!
+//! Version 3.0 exports every function with the `QUINN_FFI_SYMBOL_PREFIX`, `quinn_` by default, e.g. `connect_client`
+//! as `quinn_connect_client`. `ffi_abi_version`, `ffi_abi_require` and `ffi_selftest` keep their exported names
+//! `quinn_ffi_abi_version`, `quinn_ffi_abi_require` and `quinn_ffi_selftest` with the default prefix.
+
+/// The major version of the binary interface.
+pub const ABI_VERSION_MAJOR: u32 = 3;
+/// The minor version of the binary interface.
+pub const ABI_VERSION_MINOR: u32 = 0;
+
+/// Returns whether a host built against the given version can use this library.
+// The comparison is absurd while the minor version is 0.
+#[allow(clippy::absurd_extreme_comparisons)]
+pub(crate) const fn is_compatible(major: u32, minor: u32) -> bool {
+    major == ABI_VERSION_MAJOR && minor <= ABI_VERSION_MINOR
+}
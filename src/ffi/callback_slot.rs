@@ -0,0 +1,124 @@
+use std::{
+    cell::Cell,
+    marker::PhantomData,
+    mem,
+    ptr,
+    sync::atomic::{
+        AtomicBool,
+        AtomicPtr,
+        AtomicUsize,
+        Ordering,
+    },
+    thread,
+};
+
+/// The number of callbacks being invoked by all threads.
+static INVOKING: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The number of callbacks being invoked by this thread, which are nested if more than one.
+    static INVOKING_HERE: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Stores a callback of the client application such that it can be set and read from any thread.
+///
+/// `F` is an `extern "C"` function pointer type, which is stored as an atomic pointer.
+/// Invoke the callback with `invoke`, such that `wait_for_invocations` can tell when a cleared callback is no longer running.
+pub(crate) struct CallbackSlot<F> {
+    callback: AtomicPtr<()>,
+    // whether the callback is invoked, disabled callbacks stay set.
+    enabled: AtomicBool,
+    _callback_type: PhantomData<F>,
+}
+
+impl<F: Copy> CallbackSlot<F> {
+    /// Creates a slot without a callback.
+    ///
+    /// # Safety
+    ///
+    /// `F` must be a function pointer type.
+    pub(crate) const unsafe fn new() -> Self {
+        assert!(mem::size_of::<F>() == mem::size_of::<*mut ()>());
+
+        CallbackSlot {
+            callback: AtomicPtr::new(ptr::null_mut()),
+            enabled: AtomicBool::new(true),
+            _callback_type: PhantomData,
+        }
+    }
+
+    /// Sets the callback, replacing the previous one.
+    pub(crate) fn set(&self, callback: F) {
+        // `F` is a function pointer as required by `new`.
+        let callback = unsafe { mem::transmute_copy::<F, *mut ()>(&callback) };
+        self.callback.store(callback, Ordering::SeqCst);
+    }
+
+    /// Removes the callback, invocations from now on do nothing.
+    pub(crate) fn clear(&self) {
+        self.callback.store(ptr::null_mut(), Ordering::SeqCst);
+    }
+
+    /// Enables or disables the callback, a disabled callback is not invoked but stays set.
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Invokes `f` with the callback if it is set and enabled, returns `None` otherwise.
+    pub(crate) fn invoke<R>(&self, f: impl FnOnce(F) -> R) -> Option<R> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let _invocation = Invocation::enter();
+        // Read after the invocation is counted, such that `wait_for_invocations` either waits for it or it reads a cleared slot.
+        let callback = self.get()?;
+        Some(f(callback))
+    }
+
+    /// Returns the callback, `None` if it is not set.
+    fn get(&self) -> Option<F> {
+        let callback = self.callback.load(Ordering::SeqCst);
+
+        if callback.is_null() {
+            None
+        } else {
+            // Only pointers converted from `F` by `set` are stored.
+            Some(unsafe { mem::transmute_copy::<*mut (), F>(&callback) })
+        }
+    }
+
+    /// Returns whether the callback is set.
+    pub(crate) fn is_set(&self) -> bool {
+        !self.callback.load(Ordering::SeqCst).is_null()
+    }
+}
+
+/// Blocks until no other thread invokes a callback, callbacks the current thread is invoking are not waited for.
+///
+/// Once it returns, callbacks that were cleared before the call are not running and won't run again.
+pub(crate) fn wait_for_invocations() {
+    let own = INVOKING_HERE.with(|invoking| invoking.get());
+
+    while INVOKING.load(Ordering::SeqCst) > own {
+        thread::yield_now();
+    }
+}
+
+/// Counts a callback invocation for as long as it is alive.
+struct Invocation(());
+
+impl Invocation {
+    fn enter() -> Self {
+        INVOKING.fetch_add(1, Ordering::SeqCst);
+        INVOKING_HERE.with(|invoking| invoking.set(invoking.get() + 1));
+        Invocation(())
+    }
+}
+
+impl Drop for Invocation {
+    fn drop(&mut self) {
+        INVOKING_HERE.with(|invoking| invoking.set(invoking.get() - 1));
+        INVOKING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,189 @@
+//! Defers callbacks to the client application until no lock is held by the invoking thread.
+//!
+//! Callbacks are raised while an endpoint or connection is locked.
+//! Invoking them right away would deadlock when the client application calls back into the FFI from the callback.
+//! In the queued dispatch mode callbacks are instead delivered on the thread that calls `dispatch_callbacks`.
+
+use crate::{
+    ffi::{
+        poison::lock_recovering,
+        wakeup,
+        FFIResultKind,
+    },
+    proto_impl::FFIErrorKind,
+};
+use std::{
+    cell::{
+        Cell,
+        RefCell,
+    },
+    collections::VecDeque,
+    convert::TryFrom,
+    io,
+    sync::{
+        atomic::{
+            AtomicU8,
+            Ordering,
+        },
+        Mutex,
+    },
+};
+
+/// On which thread callbacks are invoked.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CallbackDispatchMode {
+    /// On the thread that raised them, e.g. a poller thread, once it released its locks.
+    Inline,
+    /// On the thread that calls `dispatch_callbacks`, e.g. the main thread of a single-threaded runtime.
+    Queued,
+}
+
+impl TryFrom<u8> for CallbackDispatchMode {
+    type Error = FFIErrorKind;
+
+    /// Fails with `InvalidArgument` for values other than those of the variants.
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(CallbackDispatchMode::Inline),
+            1 => Ok(CallbackDispatchMode::Queued),
+            _ => Err(FFIErrorKind::FFIResultKind(FFIResultKind::InvalidArgument)),
+        }
+    }
+}
+
+static DISPATCH_MODE: AtomicU8 = AtomicU8::new(CallbackDispatchMode::Inline as u8);
+
+/// Callbacks raised in the queued dispatch mode, in the order they were raised.
+static QUEUED: Mutex<VecDeque<Box<dyn FnOnce() + Send>>> = Mutex::new(VecDeque::new());
+
+thread_local! {
+    /// The number of locks held by this thread.
+    static LOCK_DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// Callbacks raised by this thread while it held a lock, in the order they were raised.
+    static DEFERRED: RefCell<VecDeque<Box<dyn FnOnce()>>> = const { RefCell::new(VecDeque::new()) };
+    /// Whether this thread runs a callback that is invoked inline while an endpoint or connection is locked.
+    static IN_LOCKED_CALLBACK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks that the current thread holds a lock for as long as the scope is alive.
+///
+/// Create the scope before acquiring the lock, such that it is dropped after the lock is released.
+/// Dropping the outermost scope of a thread invokes the callbacks deferred in the meantime.
+pub(crate) struct LockScope(());
+
+impl LockScope {
+    pub(crate) fn enter() -> Self {
+        LOCK_DEPTH.with(|depth| depth.set(depth.get() + 1));
+        LockScope(())
+    }
+}
+
+impl Drop for LockScope {
+    fn drop(&mut self) {
+        let depth = LOCK_DEPTH.with(|depth| {
+            depth.set(depth.get() - 1);
+            depth.get()
+        });
+
+        if depth == 0 {
+            dispatch_deferred();
+        }
+    }
+}
+
+/// Marks that the current thread runs a callback while an endpoint or connection is locked, e.g. the SNI config selector
+/// or `on_auth_token`.
+///
+/// Such callbacks must return a value and cannot be deferred, calls of endpoint and connection functions from them
+/// would deadlock and fail with `reject_locked_callback` instead.
+pub(crate) struct LockedCallbackScope(());
+
+impl LockedCallbackScope {
+    pub(crate) fn enter() -> Self {
+        IN_LOCKED_CALLBACK.with(|inside| inside.set(true));
+        LockedCallbackScope(())
+    }
+}
+
+impl Drop for LockedCallbackScope {
+    fn drop(&mut self) {
+        IN_LOCKED_CALLBACK.with(|inside| inside.set(false));
+    }
+}
+
+/// Fails if the current thread runs a callback while an endpoint or connection is locked, called before either is locked.
+pub(crate) fn reject_locked_callback() -> Result<(), FFIErrorKind> {
+    if IN_LOCKED_CALLBACK.with(|inside| inside.get()) {
+        return Err(FFIErrorKind::io_error(
+            "Endpoint and connection functions must not be called from the SNI config selector or `on_auth_token`, \
+             the endpoint or connection is locked",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Invokes the callback right away if the current thread holds no lock, and after the last lock is released otherwise.
+///
+/// In the queued dispatch mode the callback is queued for `dispatch_callbacks` instead.
+pub(crate) fn defer(callback: impl FnOnce() + Send + 'static) {
+    if DISPATCH_MODE.load(Ordering::Acquire) == CallbackDispatchMode::Queued as u8 {
+        let mut queued = lock_recovering(&QUEUED);
+        queued.push_back(Box::new(callback));
+        if queued.len() == 1 {
+            wakeup::signal();
+        }
+    } else if LOCK_DEPTH.with(|depth| depth.get()) == 0 {
+        callback();
+    } else {
+        DEFERRED.with(|deferred| deferred.borrow_mut().push_back(Box::new(callback)));
+    }
+}
+
+fn dispatch_deferred() {
+    // The queue is not borrowed while a callback runs, since the callback may raise callbacks itself.
+    while let Some(callback) = DEFERRED.with(|deferred| deferred.borrow_mut().pop_front()) {
+        callback();
+    }
+}
+
+/// Sets on which thread callbacks raised from now on are invoked, see `set_callback_dispatch_mode`.
+pub(crate) fn set_dispatch_mode(mode: CallbackDispatchMode) {
+    DISPATCH_MODE.store(mode as u8, Ordering::Release);
+}
+
+/// Returns the dispatch mode set with `set_callback_dispatch_mode`.
+pub(crate) fn dispatch_mode() -> CallbackDispatchMode {
+    CallbackDispatchMode::try_from(DISPATCH_MODE.load(Ordering::Acquire))
+        .unwrap_or(CallbackDispatchMode::Inline)
+}
+
+/// Invokes the queued callbacks on the calling thread, returns how many were invoked, see `dispatch_callbacks`.
+pub(crate) fn dispatch_queued() -> u64 {
+    let mut dispatched = 0;
+
+    // The queue is not locked while a callback runs, since the callback may raise callbacks itself.
+    loop {
+        let mut queued = lock_recovering(&QUEUED);
+        let callback = match queued.pop_front() {
+            Some(callback) => callback,
+            None => {
+                // Cleared under the lock, such that a callback queued meanwhile signals again.
+                wakeup::clear();
+                return dispatched;
+            }
+        };
+        drop(queued);
+
+        callback();
+        dispatched += 1;
+    }
+}
+
+/// Returns the descriptor that is readable while callbacks are queued, see `endpoint_event_fd`.
+pub(crate) fn queued_event_fd() -> io::Result<i64> {
+    // Locked first, like when callbacks are queued, such that the descriptor is signaled if callbacks are pending.
+    let queued = lock_recovering(&QUEUED);
+    wakeup::raw_event(!queued.is_empty())
+}
@@ -0,0 +1,130 @@
+//! The options of `create_endpoint_ex`, a single struct such that new options don't change the function signature.
+
+use crate::{
+    ffi::{
+        CallbackDispatchMode,
+        EndpointConfigHandle,
+        FFIResultKind,
+        RustlsClientConfigHandle,
+        RustlsServerConfigHandle,
+        TransportConfigHandle,
+    },
+    proto_impl::FFIErrorKind,
+};
+use std::{
+    convert::TryFrom,
+    mem,
+    ptr,
+};
+
+/// Whether `create_endpoint_ex` creates a client or a server endpoint.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EndpointRole {
+    /// Connects to servers with `connect_client`, uses `client_crypto`.
+    Client,
+    /// Accepts incoming connections, uses `server_crypto`.
+    Server,
+}
+
+impl TryFrom<u8> for EndpointRole {
+    type Error = FFIErrorKind;
+
+    /// Fails with `InvalidArgument` for values other than those of the variants.
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(EndpointRole::Client),
+            1 => Ok(EndpointRole::Server),
+            _ => Err(FFIErrorKind::FFIResultKind(FFIResultKind::InvalidArgument)),
+        }
+    }
+}
+
+/// Who polls an endpoint created by `create_endpoint_ex`.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PollerMode {
+    /// As set by the endpoint configuration, see `endpoint_config_handle_set_manual_polling`.
+    Default,
+    /// The client application, with `poll_endpoint` and `poll_connection`.
+    Manual,
+    /// The shared poller pool if it is running, see `init_runtime`, otherwise a thread of the endpoint.
+    Automatic,
+}
+
+impl TryFrom<u8> for PollerMode {
+    type Error = FFIErrorKind;
+
+    /// Fails with `InvalidArgument` for values other than those of the variants.
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(PollerMode::Default),
+            1 => Ok(PollerMode::Manual),
+            2 => Ok(PollerMode::Automatic),
+            _ => Err(FFIErrorKind::FFIResultKind(FFIResultKind::InvalidArgument)),
+        }
+    }
+}
+
+/// The options of `create_endpoint_ex`.
+///
+/// Handles are only accessed during the call, null handles select the defaults of `create_server_endpoint` and
+/// `create_client_endpoint`. Options are only ever appended, `struct_size` tells the library which the host knows.
+/// The enums are passed as `u8`, such that unknown values are rejected instead of being undefined behavior.
+#[repr(C)]
+pub struct EndpointOptions<'a> {
+    /// The size of the struct the host was compiled with, `sizeof(EndpointOptions)`.
+    pub struct_size: u32,
+    /// Whether a client or a server endpoint is created, an `EndpointRole`.
+    pub role: u8,
+    /// Who polls the endpoint, a `PollerMode`.
+    pub poller_mode: u8,
+    /// The `CallbackDispatchMode`, which applies to all endpoints, see `set_callback_dispatch_mode`.
+    pub dispatch_mode: u8,
+    /// The crypto configuration of a server endpoint, null for clients.
+    pub server_crypto: RustlsServerConfigHandle<'a>,
+    /// The crypto configuration of a client endpoint, null for servers.
+    pub client_crypto: RustlsClientConfigHandle<'a>,
+    /// The endpoint configuration, null for the defaults.
+    pub endpoint_config: EndpointConfigHandle<'a>,
+    /// The transport configuration replacing the transport settings of the crypto configuration, null to keep them.
+    pub transport_config: TransportConfigHandle<'a>,
+}
+
+impl<'a> EndpointOptions<'a> {
+    /// Copies the options of the host, which may have been compiled against an older or newer layout.
+    ///
+    /// `struct_size` is read first and only the bytes both layouts have are copied, options the host does not know
+    /// keep their defaults, zero or null. Fails with `InvalidArgument` if `struct_size` does not even cover itself.
+    ///
+    /// # Safety
+    ///
+    /// `options` must point to at least `struct_size` readable bytes.
+    pub(crate) unsafe fn read(options: *const Self) -> Result<Self, FFIErrorKind> {
+        let struct_size = ptr::addr_of!((*options).struct_size).read_unaligned() as usize;
+        if struct_size < mem::size_of::<u32>() {
+            return Err(FFIErrorKind::FFIResultKind(FFIResultKind::InvalidArgument));
+        }
+
+        // All fields are integers or handles, for which zero is the default or null.
+        let mut copied: Self = mem::zeroed();
+        ptr::copy_nonoverlapping(
+            options as *const u8,
+            &mut copied as *mut Self as *mut u8,
+            struct_size.min(mem::size_of::<Self>()),
+        );
+        Ok(copied)
+    }
+
+    pub(crate) fn role(&self) -> Result<EndpointRole, FFIErrorKind> {
+        EndpointRole::try_from(self.role)
+    }
+
+    pub(crate) fn poller_mode(&self) -> Result<PollerMode, FFIErrorKind> {
+        PollerMode::try_from(self.poller_mode)
+    }
+
+    pub(crate) fn dispatch_mode(&self) -> Result<CallbackDispatchMode, FFIErrorKind> {
+        CallbackDispatchMode::try_from(self.dispatch_mode)
+    }
+}
@@ -1,5 +1,6 @@
 use crate::proto_impl::FFIErrorKind;
 
+use libc::size_t;
 use std::{
     any::Any,
     cell::RefCell,
@@ -29,13 +30,75 @@ pub struct LastResult {
 #[repr(C)]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FFIResult {
-    // FFi result only contains a enum kind.
     pub kind: FFIResultKind,
+    /// The code of the error, e.g. the QUIC or OS error code, 0 if the error has no code.
+    pub code: u64,
+    /// The buffer size `last_error` requires for the error message, 0 if there is no message.
+    pub message_len: size_t,
+}
+
+/// The structured context of the last error, retrieved with `last_error_details`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ErrorDetails {
+    /// The code of the error, like `FFIResult::code`.
+    pub code: u64,
+    /// The innermost stream id of the contexts, valid if `has_stream_id` is set.
+    pub stream_id: u64,
+    /// The outermost operation, e.g. `poll_connection`, UTF-8 without a terminator, null if the error has no context.
+    ///
+    /// Points to static memory, valid for the lifetime of the process.
+    pub operation: *const u8,
+    /// The length of `operation`.
+    pub operation_len: u32,
+    /// The innermost connection id of the contexts, valid if `has_connection_id` is set.
+    pub connection_id: u32,
+    /// The number of contexts the error was passed through.
+    pub depth: u32,
+    pub has_connection_id: bool,
+    pub has_stream_id: bool,
+}
+
+impl ErrorDetails {
+    pub(crate) fn new(error: &FFIErrorKind) -> Self {
+        let mut details = ErrorDetails {
+            code: error.code(),
+            stream_id: 0,
+            operation: std::ptr::null(),
+            operation_len: 0,
+            connection_id: 0,
+            depth: 0,
+            has_connection_id: false,
+            has_stream_id: false,
+        };
+
+        for context in error.contexts() {
+            if details.depth == 0 {
+                details.operation = context.operation.as_ptr();
+                details.operation_len = context.operation.len() as u32;
+            }
+            if let Some(connection_id) = context.connection_id {
+                details.connection_id = connection_id;
+                details.has_connection_id = true;
+            }
+            if let Some(stream_id) = context.stream_id {
+                details.stream_id = stream_id;
+                details.has_stream_id = true;
+            }
+            details.depth += 1;
+        }
+
+        details
+    }
 }
 
 impl FFIResult {
     pub fn new(kind: FFIResultKind) -> FFIResult {
-        FFIResult { kind }
+        FFIResult {
+            kind,
+            code: 0,
+            message_len: 0,
+        }
     }
 
     /// Result is successful.
@@ -60,13 +123,68 @@ impl FFIResult {
         FFIResult::new(FFIResultKind::ArgumentNull)
     }
 
+    pub fn streams_exhausted() -> Self {
+        FFIResult::new(FFIResultKind::StreamsExhausted)
+    }
+
+    pub fn connection_lost() -> Self {
+        FFIResult::new(FFIResultKind::ConnectionLost)
+    }
+
+    pub fn invalid_handle() -> Self {
+        FFIResult::new(FFIResultKind::InvalidHandle)
+    }
+
+    pub fn unknown_stream() -> Self {
+        FFIResult::new(FFIResultKind::UnknownStream)
+    }
+
+    pub fn unknown_connection() -> Self {
+        FFIResult::new(FFIResultKind::UnknownConnection)
+    }
+
+    pub fn timed_out() -> Self {
+        FFIResult::new(FFIResultKind::TimedOut)
+    }
+
+    pub fn datagram_too_large() -> Self {
+        FFIResult::new(FFIResultKind::DatagramTooLarge)
+    }
+
+    pub fn poisoned() -> Self {
+        FFIResult::new(FFIResultKind::Poisoned)
+    }
+
+    pub fn unsupported() -> Self {
+        FFIResult::new(FFIResultKind::Unsupported)
+    }
+
+    pub fn invalid_argument() -> Self {
+        FFIResult::new(FFIResultKind::InvalidArgument)
+    }
+
+    pub fn stream_finished() -> Self {
+        FFIResult::new(FFIResultKind::StreamFinished)
+    }
+
+    pub fn stream_reset() -> Self {
+        FFIResult::new(FFIResultKind::StreamReset)
+    }
+
+    pub fn cancelled() -> Self {
+        FFIResult::new(FFIResultKind::Cancelled)
+    }
+
     pub fn is_err(&self) -> bool {
         self.kind != FFIResultKind::Ok
     }
 
-    /// Sets the `LAST_RESULT` context to the given `FFIErrorKind`.
-    pub fn context(self, e: FFIErrorKind) -> Self {
+    /// Sets the `LAST_RESULT` context to the given `FFIErrorKind`, and its code and message length on this result.
+    pub fn context(mut self, e: FFIErrorKind) -> Self {
         tracing::error!("with context {:?}", e);
+        self.code = e.code();
+        self.message_len = e.to_string().len();
+
         LAST_RESULT.with(|last_result| {
             let result = LastResult { err: Some(e) };
             last_result.replace(Some(result));
@@ -88,37 +206,24 @@ impl FFIResult {
                 }
             }
 
-            tracing::error!("NONE {:?}", last_result);
+            tracing::trace!("Last result {:?}", last_result);
 
             return f(message);
         })
     }
 
-    /// Calls a function catching any panic and on panic sets the `LAST_RESULT`.
+    /// Calls a function catching any panic, a panic is returned as error with the panic message as context.
     pub(super) fn catch(f: impl FnOnce() -> Self + UnwindSafe) -> Self {
-        LAST_RESULT.with(|last_result| {
-            {
-                *last_result.borrow_mut() = None;
+        match catch_unwind(f) {
+            Ok(result) => result,
+            Err(e) => {
+                let message = extract_panic(&e)
+                    .map(|s| format!("internal panic with '{}'", s))
+                    .unwrap_or_else(|| "internal panic".to_owned());
+
+                FFIResult::err().context(FFIErrorKind::io_error(&message))
             }
-            return match catch_unwind(f) {
-                Ok(result) => result,
-                Err(e) => {
-                    let extract_panic =
-                        || extract_panic(&e).map(|s| format!("internal panic with '{}'", s));
-
-                    // Set the last error to the panic message if it's not already set
-                    let mut ref_mut = last_result.borrow_mut();
-
-                    ref_mut.as_mut().map(|a| {
-                        *a = LastResult {
-                            err: Some(FFIErrorKind::io_error(&extract_panic().unwrap())),
-                        };
-                    });
-
-                    FFIResult::err()
-                }
-            };
-        })
+        }
     }
 }
 
@@ -142,6 +247,39 @@ impl Display for FFIResult {
                 write!(f, "There is no data in the buffer to be read.")?
             }
             FFIResultKind::ArgumentNull => write!(f, "An argument was null.")?,
+            FFIResultKind::StreamsExhausted => {
+                write!(f, "No more streams can be opened in the given direction.")?
+            }
+            FFIResultKind::ConnectionLost => write!(f, "The connection is lost.")?,
+            FFIResultKind::InvalidHandle => {
+                write!(f, "The handle is invalid or was already freed.")?
+            }
+            FFIResultKind::UnknownStream => write!(
+                f,
+                "The stream is not open or was already finished or reset."
+            )?,
+            FFIResultKind::UnknownConnection => {
+                write!(f, "No connection with the given id exists.")?
+            }
+            FFIResultKind::TimedOut => write!(f, "The timeout elapsed before the wait completed.")?,
+            FFIResultKind::DatagramTooLarge => write!(
+                f,
+                "The datagram exceeds the maximum UDP payload size of the endpoint."
+            )?,
+            FFIResultKind::Poisoned => write!(
+                f,
+                "The call succeeded on a handle recovered from a panic in an earlier call."
+            )?,
+            FFIResultKind::Unsupported => write!(
+                f,
+                "The function is not available in this build of the library."
+            )?,
+            FFIResultKind::InvalidArgument => {
+                write!(f, "An argument is not a valid value of its type.")?
+            }
+            FFIResultKind::StreamFinished => write!(f, "All data of the stream was read.")?,
+            FFIResultKind::StreamReset => write!(f, "The peer reset the stream.")?,
+            FFIResultKind::Cancelled => write!(f, "The wait was cancelled.")?,
         }
         Ok(())
     }
@@ -158,7 +296,30 @@ impl<T> From<Result<T, FFIErrorKind>> for FFIResult {
                     FFIResultKind::BufferToSmall => FFIResult::buffer_too_small(),
                     FFIResultKind::BufferBlocked => FFIResult::buffer_blocked(),
                     FFIResultKind::ArgumentNull => FFIResult::argument_null(),
+                    FFIResultKind::StreamsExhausted => FFIResult::streams_exhausted(),
+                    FFIResultKind::ConnectionLost => FFIResult::connection_lost(),
+                    FFIResultKind::InvalidHandle => FFIResult::invalid_handle(),
+                    FFIResultKind::UnknownStream => FFIResult::unknown_stream(),
+                    FFIResultKind::UnknownConnection => FFIResult::unknown_connection(),
+                    FFIResultKind::TimedOut => FFIResult::timed_out(),
+                    FFIResultKind::DatagramTooLarge => FFIResult::datagram_too_large(),
+                    FFIResultKind::Poisoned => FFIResult::poisoned(),
+                    FFIResultKind::Unsupported => FFIResult::unsupported(),
+                    FFIResultKind::InvalidArgument => FFIResult::invalid_argument(),
+                    FFIResultKind::StreamFinished => FFIResult::stream_finished(),
+                    FFIResultKind::StreamReset => FFIResult::stream_reset(),
+                    FFIResultKind::Cancelled => FFIResult::cancelled(),
                 },
+                e @ FFIErrorKind::Unsupported { .. } => FFIResult::unsupported().context(e),
+                // The kind of the original error is kept, the message names the contexts.
+                e @ FFIErrorKind::Context { .. } => {
+                    let kind = match e.root() {
+                        FFIErrorKind::FFIResultKind(kind) => kind.clone(),
+                        FFIErrorKind::Unsupported { .. } => FFIResultKind::Unsupported,
+                        _ => FFIResultKind::Error,
+                    };
+                    FFIResult::new(kind).context(e)
+                }
                 e => FFIResult::err().context(e),
             },
         }
@@ -185,6 +346,64 @@ pub enum FFIResultKind {
     BufferBlocked,
     /// A argument to the FFI function was not initialized.
     ArgumentNull,
+    /// Stream credit is exhausted, retry after `on_stream_available` is invoked for that direction.
+    StreamsExhausted,
+    /// The connection is lost, the reason can be retrieved with `connection_close_reason`.
+    ConnectionLost,
+    /// A handle argument does not point to a live handle of the expected type.
+    InvalidHandle,
+    /// The stream id is not a stream of the connection, or the stream was already finished, reset or stopped.
+    UnknownStream,
+    /// No connection with the connection id exists, e.g. because it was closed and removed from its endpoint.
+    UnknownConnection,
+    /// A blocking wait returned because its timeout elapsed, wait again to keep waiting.
+    TimedOut,
+    /// A datagram passed to the endpoint exceeds its maximum UDP payload size, see `endpoint_config_set_max_udp_payload_size`.
+    DatagramTooLarge,
+    /// A warning that the call succeeded, but an earlier call panicked while it accessed the handle.
+    ///
+    /// Reported once per panic, the handle is used in the state the panicking call left it in.
+    Poisoned,
+    /// The function needs a cargo feature the library was built without, the message names the feature.
+    Unsupported,
+    /// An argument is out of the range of its type, e.g. a `StreamDirection` other than 0 or 1.
+    InvalidArgument,
+    /// The peer finished the stream and all its data was read.
+    StreamFinished,
+    /// The peer reset the stream, data that was not read yet is discarded. `read_stream_v3` returns the error code.
+    StreamReset,
+    /// A blocking wait returned because its `CancellationHandle` was cancelled.
+    Cancelled,
+}
+
+impl FFIResultKind {
+    /// Returns whether retrying the call can not succeed.
+    ///
+    /// `Error` is fatal since its cause is unknown, the other kinds except `ConnectionLost`, `InvalidHandle`,
+    /// `UnknownStream`, `UnknownConnection`, `Unsupported`, `InvalidArgument`, `StreamFinished` and `StreamReset`
+    /// can be retried. `DatagramTooLarge` only concerns the one datagram, the endpoint keeps handling others.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            FFIResultKind::Error
+                | FFIResultKind::ConnectionLost
+                | FFIResultKind::InvalidHandle
+                | FFIResultKind::UnknownStream
+                | FFIResultKind::UnknownConnection
+                | FFIResultKind::Unsupported
+                | FFIResultKind::InvalidArgument
+                | FFIResultKind::StreamFinished
+                | FFIResultKind::StreamReset
+        )
+    }
+}
+
+/// Returns whether the result is an error after which retrying the call can not succeed, e.g. because the connection is lost.
+///
+/// Results like `BufferBlocked` or `BufferToSmall` are not fatal, retry after the condition is resolved.
+#[export_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "result_is_fatal")]
+pub extern "cdecl" fn result_is_fatal(result: FFIResult) -> bool {
+    result.kind.is_fatal()
 }
 
 /// Error with code and reason.
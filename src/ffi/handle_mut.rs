@@ -1,10 +1,16 @@
-use crate::ffi::IsNull;
+use crate::{
+    ffi::{
+        handle_slots::{
+            self,
+            HandleAccess,
+        },
+        IsNull,
+    },
+    proto_impl::FFIErrorKind,
+};
 use std::{
+    ffi::c_void,
     marker::PhantomData,
-    ops::{
-        Deref,
-        DerefMut,
-    },
     panic::{
         RefUnwindSafe,
         UnwindSafe,
@@ -14,50 +20,40 @@ use std::{
 /// A handle that can be read/write-accessed concurrently by multiple threads.
 ///
 /// Can only contain types that are `Sync` + `Send` semantically.
+/// Refers to a slot of the handle table such that invalid and freed handles can be detected, see `handle_slots`.
 #[repr(transparent)]
-pub struct FFIHandleMut<'a, T>(*mut T, PhantomData<&'a T>)
+pub struct FFIHandleMut<'a, T>(*mut c_void, PhantomData<&'a T>)
 where
     T: ?Sized + Send + Sync;
 
 impl<'a, T> UnwindSafe for FFIHandleMut<'a, T> where T: ?Sized + Send + Sync + RefUnwindSafe {}
 
+// The handle only gives access to a `Send + Sync` value, so it can be shared like `&T`.
+unsafe impl<'a, T> Send for FFIHandleMut<'a, T> where T: ?Sized + Send + Sync {}
+unsafe impl<'a, T> Sync for FFIHandleMut<'a, T> where T: ?Sized + Send + Sync {}
+
 impl<'a, T> FFIHandleMut<'a, T>
 where
     T: Send + Sync,
 {
     /// Allocates and initializes memory for the passed type.
     pub fn alloc(value: T) -> Self {
-        FFIHandleMut(Box::into_raw(Box::new(value)), PhantomData)
+        FFIHandleMut(handle_slots::alloc(value), PhantomData)
     }
 
     /// Deallocates and initializes memory for the passed type.
     ///
     /// There are no other live references and the handle won't be used again
-    pub unsafe fn dealloc<R>(handle: Self, f: impl FnOnce(T) -> R) -> R {
-        let v = Box::into_inner(Box::from_raw(handle.0));
-        f(v)
-    }
-}
-
-impl<'a, T> Deref for FFIHandleMut<'a, T>
-where
-    T: ?Sized + Send + Sync,
-{
-    type Target = T;
-
-    fn deref(&self) -> &T {
-        // We own the interior value
-        unsafe { &*self.0 }
+    pub unsafe fn dealloc<R>(handle: Self, f: impl FnOnce(T) -> R) -> Result<R, FFIErrorKind> {
+        Ok(f(handle_slots::free(handle.0)?))
     }
-}
 
-impl<'a, T> DerefMut for FFIHandleMut<'a, T>
-where
-    T: ?Sized + Send + Sync,
-{
-    fn deref_mut(&mut self) -> &mut T {
-        // We own the interior value
-        unsafe { &mut *self.0 }
+    /// Returns the value, or an `InvalidHandle` error if the handle does not refer to a live allocation of `T`.
+    ///
+    /// The handle cannot be freed until the returned access is dropped. Take it after entering a `LockScope`, such
+    /// that callbacks deferred meanwhile run once it is dropped and may free the handle.
+    pub(crate) fn access(&self) -> Result<HandleAccess<T>, FFIErrorKind> {
+        handle_slots::access(self.0)
     }
 }
 
@@ -1,7 +1,16 @@
-use crate::ffi::IsNull;
+use crate::{
+    ffi::{
+        handle_slots::{
+            self,
+            HandleAccess,
+        },
+        IsNull,
+    },
+    proto_impl::FFIErrorKind,
+};
 use std::{
+    ffi::c_void,
     marker::PhantomData,
-    ops::Deref,
     panic::{
         RefUnwindSafe,
         UnwindSafe,
@@ -11,13 +20,18 @@ use std::{
 /// A handle that can be read-accessed concurrently by multiple threads.
 ///
 /// The interior value can be treated like `&T`.
+/// Refers to a slot of the handle table such that invalid and freed handles can be detected, see `handle_slots`.
 #[repr(transparent)]
-pub struct FFIHandleRef<'a, T>(*const T, PhantomData<&'a T>)
+pub struct FFIHandleRef<'a, T>(*mut c_void, PhantomData<&'a T>)
 where
     T: ?Sized + Send + Sync;
 
 impl<'a, T> UnwindSafe for FFIHandleRef<'a, T> where T: ?Sized + RefUnwindSafe + Send + Sync {}
 
+// The handle only gives access to a `Send + Sync` value, so it can be shared like `&T`.
+unsafe impl<'a, T> Send for FFIHandleRef<'a, T> where T: ?Sized + Send + Sync {}
+unsafe impl<'a, T> Sync for FFIHandleRef<'a, T> where T: ?Sized + Send + Sync {}
+
 impl<'a, T> FFIHandleRef<'a, T>
 where
     T: Send + Sync,
@@ -27,9 +41,7 @@ where
     where
         T: 'static,
     {
-        let v = Box::new(value);
-
-        FFIHandleRef(Box::into_raw(v), PhantomData)
+        FFIHandleRef(handle_slots::alloc(value), PhantomData)
     }
 }
 
@@ -40,21 +52,16 @@ where
     /// Deallocates and initializes memory for the passed type.
     ///
     /// There are no other live references and the handle won't be used again
-    pub unsafe fn dealloc<R>(handle: Self, f: impl FnOnce(T) -> R) -> R {
-        let v = Box::from_raw(handle.0 as *mut T);
-        f(*v)
+    pub unsafe fn dealloc<R>(handle: Self, f: impl FnOnce(T) -> R) -> Result<R, FFIErrorKind> {
+        Ok(f(handle_slots::free(handle.0)?))
     }
-}
 
-impl<'a, T> Deref for FFIHandleRef<'a, T>
-where
-    T: ?Sized + Send + Sync,
-{
-    type Target = T;
-
-    // We own the interior value
-    fn deref(&self) -> &T {
-        unsafe { &*self.0 }
+    /// Returns the value, or an `InvalidHandle` error if the handle does not refer to a live allocation of `T`.
+    ///
+    /// The handle cannot be freed until the returned access is dropped. Take it after entering a `LockScope`, such
+    /// that callbacks deferred meanwhile run once it is dropped and may free the handle.
+    pub(crate) fn access(&self) -> Result<HandleAccess<T>, FFIErrorKind> {
+        handle_slots::access(self.0)
     }
 }
 
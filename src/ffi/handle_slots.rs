@@ -0,0 +1,198 @@
+//! The table of live handles, used to detect invalid and freed handles.
+//!
+//! With the `handle-validation` feature a handle is not a pointer but the index of a slot in the table together
+//! with the generation of the slot, both packed into a pointer-sized value. Freeing a handle bumps the generation of
+//! its slot, such that a stale handle is rejected even after the slot is reused.
+//! An access holds the read lock of the slot until it is done, freeing waits for the accesses of other threads.
+//! Without the feature a handle is a pointer to the boxed value and is not checked.
+
+use crate::{
+    ffi::{
+        poison::lock_recovering,
+        FFIResultKind,
+    },
+    proto_impl::FFIErrorKind,
+};
+use std::{
+    any,
+    ffi::c_void,
+    marker::PhantomData,
+    ops::Deref,
+    sync::{
+        Mutex,
+        PoisonError,
+        RwLock,
+        RwLockReadGuard,
+    },
+};
+
+/// The number of low bits of a handle holding the slot index plus one, the high bits hold the generation.
+///
+/// On 32 bit targets at most 65535 handles are live at a time.
+const INDEX_BITS: u32 = usize::BITS / 2;
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+
+/// The slots by index, a slot is never freed but reused once its handle is freed.
+static SLOTS: RwLock<Vec<&'static HandleSlot>> = RwLock::new(Vec::new());
+
+/// The indices of the slots whose handle was freed.
+static FREE_SLOTS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+/// A slot of the table, locked for reading while its value is accessed.
+struct HandleSlot(RwLock<SlotEntry>);
+
+struct SlotEntry {
+    /// Bumped whenever the handle of the slot is freed, masked to the bits above `INDEX_BITS`.
+    generation: usize,
+    /// The tag of the value type and the boxed value, `None` while the slot is free.
+    value: Option<(u64, *mut c_void)>,
+}
+
+// The value is only accessed as the type of its tag, whose handles are `Send + Sync`.
+unsafe impl Send for SlotEntry {}
+unsafe impl Sync for SlotEntry {}
+
+/// The value of a live handle, the slot stays locked for reading until it is dropped.
+pub(crate) struct HandleAccess<T> {
+    value: *const T,
+    _slot: Option<RwLockReadGuard<'static, SlotEntry>>,
+    _value: PhantomData<T>,
+}
+
+impl<T> Deref for HandleAccess<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // The slot is locked, so the value is not freed meanwhile.
+        unsafe { &*self.value }
+    }
+}
+
+/// Allocates the value and returns its handle.
+///
+/// Panics if the table is full, which only happens on 32 bit targets.
+pub(crate) fn alloc<T>(value: T) -> *mut c_void {
+    let value = Box::into_raw(Box::new(value)) as *mut c_void;
+    if !cfg!(feature = "handle-validation") {
+        return value;
+    }
+
+    let (index, slot) = free_slot();
+    let mut entry = slot.0.write().unwrap_or_else(PoisonError::into_inner);
+    entry.value = Some((type_tag::<T>(), value));
+
+    (entry.generation | (index + 1)) as *mut c_void
+}
+
+/// Returns the value of the handle, or an `InvalidHandle` error if it is not a live handle of `T`.
+///
+/// Without the `handle-validation` feature the handle is dereferenced unchecked.
+pub(crate) fn access<T>(handle: *const c_void) -> Result<HandleAccess<T>, FFIErrorKind> {
+    if !cfg!(feature = "handle-validation") {
+        return Ok(HandleAccess {
+            value: handle as *const T,
+            _slot: None,
+            _value: PhantomData,
+        });
+    }
+
+    let entry = slot(handle)?
+        .0
+        .read()
+        .unwrap_or_else(PoisonError::into_inner);
+    let value = live_value::<T>(&entry, handle)?;
+
+    Ok(HandleAccess {
+        value: value as *const T,
+        _slot: Some(entry),
+        _value: PhantomData,
+    })
+}
+
+/// Frees the handle and returns its value, waiting until other threads finished accessing it.
+///
+/// Fails with `InvalidHandle` if the handle is not a live handle of `T`, such that a handle is freed once.
+///
+/// # Safety
+/// Without the `handle-validation` feature the handle must be a live handle of `T`.
+/// With it the current thread must not access the handle meanwhile, freeing would wait for itself.
+pub(crate) unsafe fn free<T>(handle: *mut c_void) -> Result<T, FFIErrorKind> {
+    if !cfg!(feature = "handle-validation") {
+        return Ok(*Box::from_raw(handle as *mut T));
+    }
+
+    let slot = slot(handle)?;
+    let mut entry = slot.0.write().unwrap_or_else(PoisonError::into_inner);
+    let value = live_value::<T>(&entry, handle)?;
+    entry.value = None;
+    entry.generation = entry.generation.wrapping_add(1 << INDEX_BITS);
+    drop(entry);
+
+    lock_recovering(&FREE_SLOTS).push((handle as usize & INDEX_MASK) - 1);
+    Ok(*Box::from_raw(value as *mut T))
+}
+
+/// Returns the slot the handle refers to, whether it is live or not.
+fn slot(handle: *const c_void) -> Result<&'static HandleSlot, FFIErrorKind> {
+    let index = (handle as usize & INDEX_MASK).checked_sub(1);
+    let slots = SLOTS.read().unwrap_or_else(PoisonError::into_inner);
+
+    match index.and_then(|index| slots.get(index)) {
+        Some(slot) => Ok(slot),
+        None => {
+            tracing::error!("Handle {:?} was never allocated", handle);
+            Err(FFIErrorKind::FFIResultKind(FFIResultKind::InvalidHandle))
+        }
+    }
+}
+
+/// Checks the generation of the handle against the slot, then the type of the value in the slot.
+fn live_value<T>(entry: &SlotEntry, handle: *const c_void) -> Result<*mut c_void, FFIErrorKind> {
+    match entry.value {
+        Some((tag, value)) if entry.generation == handle as usize & !INDEX_MASK => {
+            if tag == type_tag::<T>() {
+                return Ok(value);
+            }
+
+            tracing::error!(
+                "Handle {:?} is not a handle of {}",
+                handle,
+                any::type_name::<T>()
+            );
+        }
+        _ => {
+            tracing::error!("Handle {:?} of {} was freed", handle, any::type_name::<T>());
+        }
+    }
+
+    Err(FFIErrorKind::FFIResultKind(FFIResultKind::InvalidHandle))
+}
+
+/// Takes a free slot, or appends a new one to the table.
+fn free_slot() -> (usize, &'static HandleSlot) {
+    if let Some(index) = lock_recovering(&FREE_SLOTS).pop() {
+        let slots = SLOTS.read().unwrap_or_else(PoisonError::into_inner);
+        return (index, slots[index]);
+    }
+
+    let mut slots = SLOTS.write().unwrap_or_else(PoisonError::into_inner);
+    let index = slots.len();
+    assert!(index < INDEX_MASK, "Too many live handles");
+    let slot = Box::leak(Box::new(HandleSlot(RwLock::new(SlotEntry {
+        generation: 1 << INDEX_BITS,
+        value: None,
+    }))));
+    slots.push(slot);
+
+    (index, slot)
+}
+
+/// Returns a tag derived from the name of the type, distinct for each handle type.
+fn type_tag<T>() -> u64 {
+    // 64 bit FNV-1a.
+    any::type_name::<T>()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
@@ -0,0 +1,84 @@
+//! One-time initialization of the library by the bindings, see `init_library`.
+
+use crate::{
+    ffi::{
+        dispatch::set_dispatch_mode,
+        CallbackDispatchMode,
+        FFIResult,
+    },
+    proto_impl::poller_pool,
+};
+use std::{
+    panic,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Mutex,
+    },
+};
+
+/// Global defaults passed to `init_library`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LibraryOptions {
+    /// On which thread callbacks are invoked, see `set_callback_dispatch_mode`.
+    pub dispatch_mode: CallbackDispatchMode,
+    /// Whether the library prints nothing to stdout or stderr, panics are then only reported through the log callback.
+    pub suppress_output: bool,
+    /// The worker threads of the shared poller pool, see `init_runtime`, 0 polls each endpoint on its own thread.
+    pub worker_threads: u32,
+}
+
+/// The options of the first `init_library` call.
+static OPTIONS: Mutex<Option<LibraryOptions>> = Mutex::new(None);
+
+static SUPPRESS_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Returns whether `init_library` was called with `suppress_output`.
+pub(crate) fn output_suppressed() -> bool {
+    SUPPRESS_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Initializes the library, call it once before any other function.
+///
+/// Installs a panic hook that reports panics through the log callback, see `set_log_callback`,
+/// sets the callback dispatch mode and starts the shared poller pool if `worker_threads` is not 0.
+/// With `suppress_output` the default hook, which prints panics to stderr, is not called and `enable_log` fails.
+/// The library uses the system allocator, which can't be changed at runtime.
+///
+/// Further calls with the same options succeed without effect, calls with other options return an error.
+#[export_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "init_library")]
+pub extern "cdecl" fn init_library(options: LibraryOptions) -> FFIResult {
+    let mut initialized = OPTIONS.lock().unwrap();
+
+    match *initialized {
+        Some(previous) if previous == options => return FFIResult::ok(),
+        Some(_) => {
+            return FFIResult::from("The library was already initialized with other options")
+        }
+        None => {}
+    }
+
+    if options.worker_threads > 0 {
+        let result = FFIResult::from(poller_pool::start(options.worker_threads as usize));
+        if result.is_err() {
+            return result;
+        }
+    }
+
+    set_dispatch_mode(options.dispatch_mode);
+    SUPPRESS_OUTPUT.store(options.suppress_output, Ordering::Relaxed);
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        tracing::error!(target: "quinn_ffi::panic", "{}", info);
+        if !output_suppressed() {
+            default_hook(info);
+        }
+    }));
+
+    *initialized = Some(options);
+    FFIResult::ok()
+}
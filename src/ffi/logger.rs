@@ -0,0 +1,163 @@
+//! Forwards `tracing` events, including those of quinn-proto, to a log callback set by the client application.
+
+use crate::ffi::{
+    callback_slot::CallbackSlot,
+    FFIResult,
+};
+use std::{
+    fmt,
+    fmt::Write,
+    sync::{
+        atomic::{
+            AtomicU8,
+            Ordering,
+        },
+        Once,
+    },
+};
+use tracing::{
+    field::{
+        Field,
+        Visit,
+    },
+    span,
+    subscriber::Interest,
+    Event,
+    Level,
+    Metadata,
+    Subscriber,
+};
+
+/// The severity of a log message, a higher level is more verbose.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// No messages are logged.
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<&Level> for LogLevel {
+    fn from(level: &Level) -> Self {
+        match *level {
+            Level::ERROR => LogLevel::Error,
+            Level::WARN => LogLevel::Warn,
+            Level::INFO => LogLevel::Info,
+            Level::DEBUG => LogLevel::Debug,
+            Level::TRACE => LogLevel::Trace,
+        }
+    }
+}
+
+/// The log callback with the level, target and target length, message and message length.
+type LogCallback = extern "C" fn(LogLevel, *const u8, u32, *const u8, u32);
+
+// A static slot with external function pointer.
+static LOG_CALLBACK: CallbackSlot<LogCallback> = unsafe { CallbackSlot::new() };
+
+/// The most verbose level that is forwarded to the log callback.
+static MAX_LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Off as u8);
+
+static INSTALL_SUBSCRIBER: Once = Once::new();
+
+/// Set a callback that will be invoked for every log message up to the given level.
+///
+/// Installs a global `tracing` subscriber the first time it is called, further calls replace the callback and level.
+/// Returns an error if another global subscriber, e.g. by `enable_log`, was installed before.
+#[export_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_log_callback")]
+pub extern "cdecl" fn set_log_callback(
+    callback: extern "C" fn(LogLevel, *const u8, u32, *const u8, u32),
+    max_level: LogLevel,
+) -> FFIResult {
+    let mut result = FFIResult::ok();
+
+    INSTALL_SUBSCRIBER.call_once(|| {
+        if tracing::subscriber::set_global_default(CallbackSubscriber).is_err() {
+            result = FFIResult::from("A global logger is already installed");
+        }
+    });
+
+    LOG_CALLBACK.set(callback);
+    MAX_LOG_LEVEL.store(max_level as u8, Ordering::Relaxed);
+
+    result
+}
+
+/// Removes the log callback, the subscriber stays installed and drops messages until a callback is set again.
+pub(crate) fn clear_log_callback() {
+    LOG_CALLBACK.clear();
+}
+
+/// A subscriber that formats events and passes them to the log callback.
+///
+/// Spans are not tracked, only events are forwarded.
+struct CallbackSubscriber;
+
+impl Subscriber for CallbackSubscriber {
+    fn register_callsite(&self, _metadata: &'static Metadata<'static>) -> Interest {
+        // The level can change at runtime so the interest can not be cached.
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        LogLevel::from(metadata.level()) as u8 <= MAX_LOG_LEVEL.load(Ordering::Relaxed)
+    }
+
+    fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(1)
+    }
+
+    fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        if !LOG_CALLBACK.is_set() {
+            return;
+        }
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        let metadata = event.metadata();
+        let target = metadata.target();
+
+        LOG_CALLBACK.invoke(|callback| {
+            callback(
+                metadata.level().into(),
+                target.as_ptr(),
+                target.len() as u32,
+                visitor.message.as_ptr(),
+                visitor.message.len() as u32,
+            )
+        });
+    }
+
+    fn enter(&self, _span: &span::Id) {}
+
+    fn exit(&self, _span: &span::Id) {}
+}
+
+/// Formats the message and the other fields of an event as `message key=value`.
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.message.is_empty() {
+            self.message.push(' ');
+        }
+
+        let _ = if field.name() == "message" {
+            write!(self.message, "{:?}", value)
+        } else {
+            write!(self.message, "{}={:?}", field.name(), value)
+        };
+    }
+}
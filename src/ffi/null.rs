@@ -1,4 +1,8 @@
-use crate::proto_impl::IpAddr;
+use crate::proto_impl::{
+    BenchmarkKind,
+    FreeFn,
+    IpAddr,
+};
 
 macro_rules! never_null {
     ($($t:ty),*) => {
@@ -32,4 +36,21 @@ impl<T: ?Sized> IsNull for *mut T {
     }
 }
 
-never_null!(usize, isize, u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, bool, IpAddr);
+never_null!(
+    usize,
+    isize,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    bool,
+    IpAddr,
+    FreeFn,
+    BenchmarkKind
+);
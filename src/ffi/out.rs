@@ -1,6 +1,13 @@
-use crate::ffi::IsNull;
+use crate::{
+    ffi::{
+        FFIResultKind,
+        IsNull,
+    },
+    proto_impl::FFIErrorKind,
+};
 use std::{
     marker::PhantomData,
+    mem::MaybeUninit,
     panic::{
         RefUnwindSafe,
         UnwindSafe,
@@ -25,6 +32,15 @@ impl<'a, T> Out<'a, T> {
     pub unsafe fn init(&mut self, value: T) {
         ptr::write(self.0, value);
     }
+
+    /// Returns an uninitialized slice of `len` elements starting at the out parameter.
+    ///
+    /// # Safety
+    /// The pointer must be nonnull, aligned and valid for writes of `len` elements, which are not aliased elsewhere
+    /// while the slice is alive.
+    pub unsafe fn as_uninit_slice_mut(&mut self, len: usize) -> &mut [MaybeUninit<T>] {
+        slice::from_raw_parts_mut(self.0 as *mut MaybeUninit<T>, len)
+    }
 }
 
 impl<'a> Out<'a, u8> {
@@ -48,3 +64,57 @@ impl<'a, T: ?Sized> IsNull for Out<'a, T> {
         self.0.is_null()
     }
 }
+
+/// Allocated but uninitialized memory for a byte buffer, or null to only query the length of the bytes.
+///
+/// Unlike `Out<u8>` a null buffer is not rejected with `ArgumentNull`.
+#[repr(transparent)]
+pub struct OutBuffer<'a>(Out<'a, u8>);
+
+impl<'a> OutBuffer<'a> {
+    /// Returns whether the buffer is null, such that only the length is queried.
+    pub fn is_query(&self) -> bool {
+        self.0.is_null()
+    }
+
+    /// Returns the buffer as uninitialized byte slice of `len` bytes.
+    ///
+    /// # Safety
+    /// The buffer must not be null, the slice must never be read from and must be valid for writes of `len` bytes.
+    pub unsafe fn as_uninit_bytes_mut(&mut self, len: usize) -> &mut [u8] {
+        self.0.as_uninit_bytes_mut(len)
+    }
+
+    /// Writes the length of the bytes to `actual_len`, and the bytes to the buffer of `buf_len` bytes unless it is null.
+    ///
+    /// Returns `BufferToSmall` if the buffer is not null and the bytes don't fit.
+    ///
+    /// # Safety
+    /// The buffer must be null or valid for writes of `buf_len` bytes, `actual_len` must be nonnull and valid for writes.
+    pub unsafe fn write(
+        &mut self,
+        value: &[u8],
+        buf_len: usize,
+        actual_len: &mut Out<usize>,
+    ) -> Result<(), FFIErrorKind> {
+        actual_len.init(value.len());
+
+        if self.is_query() {
+            return Ok(());
+        }
+
+        if buf_len < value.len() {
+            return Err(FFIErrorKind::FFIResultKind(FFIResultKind::BufferToSmall));
+        }
+
+        self.0.init_bytes(value);
+        Ok(())
+    }
+}
+
+impl<'a> IsNull for OutBuffer<'a> {
+    /// Returns false since a null buffer queries the length.
+    fn is_null(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,46 @@
+//! Recovers handles whose mutex was poisoned by a panic, such that one panicking call does not fail every later call.
+//!
+//! The panicking call returns an error with the panic message, the first call that succeeds on the recovered
+//! handle returns the `Poisoned` warning.
+
+use crate::ffi::FFIResult;
+use std::{
+    cell::Cell,
+    sync::{
+        Mutex,
+        MutexGuard,
+    },
+};
+
+thread_local! {
+    /// Whether this thread recovered a poisoned mutex since the current FFI call started.
+    static RECOVERED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Locks a mutex, recovering it if a panic in an earlier call poisoned it.
+///
+/// The value is used in the state the panicking call left it in.
+pub(crate) fn lock_recovering<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            tracing::warn!("Recovered a handle poisoned by a panic in an earlier call");
+            mutex.clear_poison();
+            RECOVERED.with(|recovered| recovered.set(true));
+            poisoned.into_inner()
+        }
+    }
+}
+
+/// Forgets recoveries of earlier calls on this thread, called when an FFI call starts.
+pub(crate) fn reset_recovered() {
+    RECOVERED.with(|recovered| recovered.set(false));
+}
+
+/// Turns the successful result of an FFI call that recovered a poisoned mutex into the `Poisoned` warning.
+pub(crate) fn report_recovered(result: FFIResult) -> FFIResult {
+    match RECOVERED.with(|recovered| recovered.replace(false)) {
+        true if !result.is_err() => FFIResult::poisoned(),
+        _ => result,
+    }
+}
@@ -27,6 +27,24 @@ where
     pub unsafe fn as_ref(&self) -> &T {
         &*self.0
     }
+
+    pub fn as_ptr(&self) -> *const T {
+        self.0
+    }
+}
+
+impl<'a, T> Ref<'a, T>
+where
+    T: Send,
+{
+    /// Returns the `len` elements starting at the parameter.
+    ///
+    /// # Safety
+    /// The pointer must be nonnull, aligned and valid for reads of `len` initialized elements, which must not be
+    /// mutated while the slice is alive.
+    pub unsafe fn as_slice(&self, len: usize) -> &[T] {
+        slice::from_raw_parts(self.0, len)
+    }
 }
 
 impl<'a> Ref<'a, u8> {
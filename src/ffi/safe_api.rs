@@ -5,14 +5,28 @@
 //! It is more safe then the unsafe api however it introduces some extra logic to keep this safe which could come at a little performance cost.
 
 use crate::proto_impl::{
+    CancellationToken,
+    ClientConfigImpl,
     ConnectionImpl,
+    ConnectionSender,
+    DatagramIngress,
+    EndpointConfigImpl,
     EndpointImpl,
     FFIErrorKind,
+    ServerConfigImpl,
+    TransportSettings,
 };
 
 use crate::ffi::{
+    dispatch::{
+        reject_locked_callback,
+        LockScope,
+    },
     handle_mut::FFIHandleMut,
+    handle_shared::FFIHandleRef,
+    poison::lock_recovering,
     HandleMut,
+    HandleRef,
 };
 
 use std::sync::{
@@ -21,31 +35,44 @@ use std::sync::{
 };
 
 // Mutex required for unwind safeness due to possible interior mutability.
-pub type RustlsClientConfigHandle<'a> = FFIHandleMut<'a, Mutex<quinn_proto::ClientConfig>>;
+pub type RustlsClientConfigHandle<'a> = FFIHandleMut<'a, Mutex<ClientConfigImpl>>;
 // Mutex required for unwind safeness due to possible interior mutability.
-pub type RustlsServerConfigHandle<'a> = FFIHandleMut<'a, Mutex<quinn_proto::ServerConfig>>;
+pub type RustlsServerConfigHandle<'a> = FFIHandleMut<'a, Mutex<ServerConfigImpl>>;
 // Mutex require d for unwind safeness due to possible interior mutability.
 pub type EndpointHandle<'a> = FFIHandleMut<'a, Arc<Mutex<EndpointImpl>>>;
 // Mutex required for unwind safeness due to possible interior mutability.
 pub type ConnectionHandle<'a> = FFIHandleMut<'a, Arc<Mutex<ConnectionImpl>>>;
+// Mutex required for unwind safeness due to possible interior mutability.
+pub type EndpointConfigHandle<'a> = FFIHandleMut<'a, Mutex<EndpointConfigImpl>>;
+// Mutex required for unwind safeness due to possible interior mutability.
+pub type TransportConfigHandle<'a> = FFIHandleMut<'a, Mutex<TransportSettings>>;
+// No mutex, the ingress queue is shared by the reader threads without locking.
+pub type DatagramIngressHandle<'a> = FFIHandleRef<'a, DatagramIngress>;
+// No mutex, the send queue is shared by the sending threads without locking.
+pub type ConnectionSenderHandle<'a> = FFIHandleRef<'a, ConnectionSender>;
+// No mutex, the token is cancelled and checked without locking.
+pub type CancellationHandle<'a> = FFIHandleRef<'a, CancellationToken>;
 
 impl<'a> HandleMut for RustlsClientConfigHandle<'a> {
-    type Inner = quinn_proto::ClientConfig;
+    type Inner = ClientConfigImpl;
 
     fn ref_access(
         &self,
         cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let lock = &self.lock().unwrap();
-        cb(lock)
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let lock = lock_recovering(&value);
+        cb(&lock)
     }
 
     fn mut_access(
         &mut self,
         cb: &mut dyn FnMut(&mut Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let mut lock = self.lock().unwrap();
-
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let mut lock = lock_recovering(&value);
         cb(&mut lock)
     }
 
@@ -55,13 +82,43 @@ impl<'a> HandleMut for RustlsClientConfigHandle<'a> {
 }
 
 impl<'a> HandleMut for RustlsServerConfigHandle<'a> {
-    type Inner = quinn_proto::ServerConfig;
+    type Inner = ServerConfigImpl;
+
+    fn ref_access(
+        &self,
+        cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let lock = lock_recovering(&value);
+        cb(&lock)
+    }
+
+    fn mut_access(
+        &mut self,
+        cb: &mut dyn FnMut(&mut Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let mut lock = lock_recovering(&value);
+        cb(&mut lock)
+    }
+
+    fn new(instance: Self::Inner) -> Self {
+        Self::alloc(Mutex::new(instance))
+    }
+}
+
+impl<'a> HandleMut for EndpointConfigHandle<'a> {
+    type Inner = EndpointConfigImpl;
 
     fn ref_access(
         &self,
         cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let lock = self.lock().unwrap();
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let lock = lock_recovering(&value);
         cb(&lock)
     }
 
@@ -69,7 +126,37 @@ impl<'a> HandleMut for RustlsServerConfigHandle<'a> {
         &mut self,
         cb: &mut dyn FnMut(&mut Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let mut lock = self.lock().unwrap();
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let mut lock = lock_recovering(&value);
+        cb(&mut lock)
+    }
+
+    fn new(instance: Self::Inner) -> Self {
+        Self::alloc(Mutex::new(instance))
+    }
+}
+
+impl<'a> HandleMut for TransportConfigHandle<'a> {
+    type Inner = TransportSettings;
+
+    fn ref_access(
+        &self,
+        cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let lock = lock_recovering(&value);
+        cb(&lock)
+    }
+
+    fn mut_access(
+        &mut self,
+        cb: &mut dyn FnMut(&mut Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let mut lock = lock_recovering(&value);
         cb(&mut lock)
     }
 
@@ -85,7 +172,10 @@ impl<'a> HandleMut for EndpointHandle<'a> {
         &self,
         cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let lock = self.lock().unwrap();
+        reject_locked_callback()?;
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let lock = lock_recovering(&value);
         cb(&lock)
     }
 
@@ -93,11 +183,11 @@ impl<'a> HandleMut for EndpointHandle<'a> {
         &mut self,
         cb: &mut dyn FnMut(&mut Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        //println!(" ++ endpoint lock");
-        let mut lock = self.lock().unwrap();
-        let a = cb(&mut lock);
-        //println!(" ++ end endpoint lock");
-        a
+        reject_locked_callback()?;
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let mut lock = lock_recovering(&value);
+        cb(&mut lock)
     }
 
     fn new(instance: Self::Inner) -> Self {
@@ -112,7 +202,11 @@ impl<'a> HandleMut for ConnectionHandle<'a> {
         &self,
         cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let lock = self.lock().unwrap();
+        reject_locked_callback()?;
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let lock = lock_recovering(&value);
+        lock.ensure_not_freed()?;
         cb(&lock)
     }
 
@@ -120,11 +214,12 @@ impl<'a> HandleMut for ConnectionHandle<'a> {
         &mut self,
         cb: &mut dyn FnMut(&mut Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        //println!("\t++ connection lock");
-        let mut lock = self.lock().unwrap();
-        let a = cb(&mut lock);
-        //println!("\t-- end connection lock");
-        a
+        reject_locked_callback()?;
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let mut lock = lock_recovering(&value);
+        lock.ensure_not_freed()?;
+        cb(&mut lock)
     }
 
     fn new(instance: Self::Inner) -> Self {
@@ -132,10 +227,62 @@ impl<'a> HandleMut for ConnectionHandle<'a> {
     }
 }
 
+impl<'a> HandleRef for DatagramIngressHandle<'a> {
+    type Inner = DatagramIngress;
+
+    fn new(instance: Self::Inner) -> Self {
+        Self::alloc(instance)
+    }
+
+    fn ref_access(
+        &self,
+        cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        cb(&value)
+    }
+}
+
+impl<'a> HandleRef for ConnectionSenderHandle<'a> {
+    type Inner = ConnectionSender;
+
+    fn new(instance: Self::Inner) -> Self {
+        Self::alloc(instance)
+    }
+
+    fn ref_access(
+        &self,
+        cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        cb(&value)
+    }
+}
+
+impl<'a> HandleRef for CancellationHandle<'a> {
+    type Inner = CancellationToken;
+
+    fn new(instance: Self::Inner) -> Self {
+        Self::alloc(instance)
+    }
+
+    fn ref_access(
+        &self,
+        cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        cb(&value)
+    }
+}
+
 /**
 Wrap an FFI function.
 
-This macro ensures all arguments satisfy `NotNull::not_null`. It's also a simple way to work
+This macro ensures all arguments satisfy `NotNull::not_null`, a null `Ref`, `Out` or handle argument returns
+`ArgumentNull` with the argument name as last error. A null `OutBuffer` is passed on, it queries the length of the buffer. It's also a simple way to work
 around not having a stable catch expression yet so we can handle early returns from ffi functions.
 The macro doesn't support generics or argument patterns that are more complex than simple identifiers.
 */
@@ -148,22 +295,25 @@ macro_rules! ffi {
         $(
             $(#[$meta])*
             #[allow(unsafe_code, unused_attributes)]
-            #[no_mangle]
+            #[export_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), stringify!($name))]
             pub unsafe extern "cdecl" fn $name( $($arg_ident : $arg_ty),* ) -> FFIResult {
                 tracing::trace!("FFI invoke: {:?}", stringify!($name));
+                $crate::ffi::poison::reset_recovered();
 
                 #[allow(unused_mut)]
                 fn call( $(mut $arg_ident: $arg_ty),* ) -> FFIResult {
                     $(
                         if $crate::ffi::IsNull::is_null(&$arg_ident) {
-                            return FFIResult::argument_null().context(FFIErrorKind::io_error(&stringify!($arg_ident)));
+                            return FFIResult::argument_null().context(FFIErrorKind::io_error(
+                                concat!("Argument `", stringify!($arg_ident), "` is null"),
+                            ));
                         }
                     )*
 
                     $body
                 }
 
-                FFIResult::catch(move || call( $($arg_ident),* ))
+                $crate::ffi::poison::report_recovered(FFIResult::catch(move || call( $($arg_ident),* )))
             }
         )*
     };
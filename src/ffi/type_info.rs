@@ -0,0 +1,488 @@
+//! Describes the `#[repr(C)]` types and the callback signatures as JSON, see `describe_types`.
+//!
+//! Script-language bindings, e.g. Python ctypes or LuaJIT FFI, can declare the types at runtime from the description
+//! instead of by hand. The declarations below fail to compile if a field or variant is missing or has another type.
+
+use crate::{
+    ffi::{
+        bindings::{
+            callbacks,
+            callbacks::CallbackKind,
+        },
+        CallbackDispatchMode,
+        EndpointConfigHandle,
+        EndpointOptions,
+        EndpointRole,
+        ErrorDetails,
+        FFIResult,
+        FFIResultKind,
+        LibraryOptions,
+        LogLevel,
+        PollerMode,
+        RustlsClientConfigHandle,
+        RustlsServerConfigHandle,
+        TransportConfigHandle,
+    },
+    proto_impl::{
+        AppEvent,
+        AppEventData,
+        AppEventKind,
+        BenchmarkKind,
+        BenchmarkReport,
+        ClosedEventData,
+        ConfigIssue,
+        CongestionEventKind,
+        ConnectionErrorKind,
+        ConnectionInfo,
+        ConnectionState,
+        DatagramOutcome,
+        DiagnosticsEvent,
+        DiagnosticsEventKind,
+        EndpointPollerStats,
+        EndpointStats,
+        EndpointTransmitKind,
+        H3Setting,
+        IpAddr,
+        PeerParams,
+        RecvDatagram,
+        SelfTestReport,
+        SendFailure,
+        StreamDataDirection,
+        StreamDirection,
+        StreamEventData,
+        StreamScheduling,
+        StreamStats,
+        TransmitBurstStats,
+    },
+};
+use libc::size_t;
+use std::{
+    fmt::Write,
+    mem,
+};
+
+struct Field {
+    name: &'static str,
+    ty: &'static str,
+    offset: usize,
+    size: usize,
+}
+
+struct Composite {
+    name: &'static str,
+    /// `struct` or `union`.
+    kind: &'static str,
+    size: usize,
+    align: usize,
+    fields: Vec<Field>,
+}
+
+struct Enum {
+    name: &'static str,
+    size: usize,
+    variants: Vec<(&'static str, i64)>,
+}
+
+macro_rules! describe_composite {
+    ($kind:literal $name:ident { $($field:ident: $ty:ty),* $(,)? }) => {{
+        $(
+            #[allow(unused_unsafe)]
+            let _ = |value: &$name| {
+                let _: &$ty = unsafe { &value.$field };
+            };
+        )*
+
+        Composite {
+            name: stringify!($name),
+            kind: $kind,
+            size: mem::size_of::<$name>(),
+            align: mem::align_of::<$name>(),
+            fields: vec![$(Field {
+                name: stringify!($field),
+                ty: stringify!($ty),
+                offset: mem::offset_of!($name, $field),
+                size: mem::size_of::<$ty>(),
+            }),*],
+        }
+    }};
+}
+
+macro_rules! describe_enum {
+    ($name:ident { $($variant:ident),* $(,)? }) => {{
+        let _ = |value: &$name| match value {
+            $($name::$variant => (),)*
+        };
+
+        Enum {
+            name: stringify!($name),
+            size: mem::size_of::<$name>(),
+            variants: vec![$((stringify!($variant), $name::$variant as i64)),*],
+        }
+    }};
+}
+
+fn composites() -> Vec<Composite> {
+    vec![
+        describe_composite!("struct" FFIResult {
+            kind: FFIResultKind,
+            code: u64,
+            message_len: size_t,
+        }),
+        describe_composite!("struct" LibraryOptions {
+            dispatch_mode: CallbackDispatchMode,
+            suppress_output: bool,
+            worker_threads: u32,
+        }),
+        describe_composite!("struct" EndpointOptions {
+            struct_size: u32,
+            role: u8,
+            poller_mode: u8,
+            dispatch_mode: u8,
+            server_crypto: RustlsServerConfigHandle,
+            client_crypto: RustlsClientConfigHandle,
+            endpoint_config: EndpointConfigHandle,
+            transport_config: TransportConfigHandle,
+        }),
+        describe_composite!("struct" ErrorDetails {
+            code: u64,
+            stream_id: u64,
+            operation: *const u8,
+            operation_len: u32,
+            connection_id: u32,
+            depth: u32,
+            has_connection_id: bool,
+            has_stream_id: bool,
+        }),
+        describe_composite!("struct" IpAddr {
+            port: u16,
+            is_v6: u8,
+            address: [u8; 16],
+            flow_info: u32,
+            scope_id: u32,
+        }),
+        describe_composite!("struct" RecvDatagram {
+            data: *const u8,
+            length: usize,
+            address: IpAddr,
+            ecn: u8,
+        }),
+        describe_composite!("struct" EndpointStats {
+            accepted_connections: u64,
+            refused_connections: u64,
+            handshaking_connections: u64,
+            handled_datagrams: u64,
+            emitted_transmits: u64,
+            version_negotiation_packets: u64,
+            retry_packets: u64,
+            stateless_resets: u64,
+            dropped_datagrams: u64,
+        }),
+        describe_composite!("struct" EndpointPollerStats {
+            wakeups: u64,
+            timer_wakeups: u64,
+            work_items: u64,
+            average_work_per_wakeup: f64,
+        }),
+        describe_composite!("struct" TransmitBurstStats {
+            bursts: u64,
+            transmits: u64,
+            largest_burst: u64,
+            capped_bursts: u64,
+            average_burst: f64,
+        }),
+        describe_composite!("struct" PeerParams {
+            datagrams_supported: bool,
+            max_datagram_size: u64,
+        }),
+        describe_composite!("struct" StreamStats {
+            bytes_sent: u64,
+            bytes_received: u64,
+        }),
+        describe_composite!("struct" StreamEventData {
+            stream_id: u64,
+            dir: StreamDirection,
+        }),
+        describe_composite!("struct" ConnectionInfo {
+            connection_id: u32,
+            state: ConnectionState,
+        }),
+        describe_composite!("struct" DiagnosticsEvent {
+            micros: u64,
+            value: u64,
+            kind: DiagnosticsEventKind,
+        }),
+        describe_composite!("struct" SendFailure {
+            stream_id: u64,
+            datagram: bool,
+            kind: FFIResultKind,
+        }),
+        describe_composite!("struct" H3Setting {
+            id: u64,
+            value: u64,
+        }),
+        describe_composite!("struct" ClosedEventData {
+            kind: ConnectionErrorKind,
+            code: u64,
+        }),
+        describe_composite!("union" AppEventData {
+            stream: StreamEventData,
+            closed: ClosedEventData,
+            none: u8,
+        }),
+        describe_composite!("struct" AppEvent {
+            kind: AppEventKind,
+            data: AppEventData,
+        }),
+        describe_composite!("struct" SelfTestReport {
+            report_size: u32,
+            ffi_result_size: u32,
+            ip_addr_size: u32,
+            recv_datagram_size: u32,
+            server_address: IpAddr,
+            echoed_bytes: u64,
+            exchanged_datagrams: u32,
+        }),
+        describe_composite!("struct" BenchmarkReport {
+            kind: BenchmarkKind,
+            iterations: u64,
+            bytes: u64,
+            elapsed_micros: u64,
+            rate: f64,
+        }),
+    ]
+}
+
+fn enums() -> Vec<Enum> {
+    vec![
+        describe_enum!(FFIResultKind {
+            Ok,
+            Error,
+            BufferToSmall,
+            BufferBlocked,
+            ArgumentNull,
+            StreamsExhausted,
+            ConnectionLost,
+            InvalidHandle,
+            UnknownStream,
+            UnknownConnection,
+            TimedOut,
+            DatagramTooLarge,
+            Poisoned,
+            Unsupported,
+            InvalidArgument,
+            StreamFinished,
+            StreamReset,
+            Cancelled,
+        }),
+        describe_enum!(ConnectionErrorKind {
+            VersionMismatch,
+            TransportError,
+            ConnectionClosed,
+            ApplicationClosed,
+            Reset,
+            TimedOut,
+            LocallyClosed,
+            TlsAlertSent,
+            TlsAlertReceived,
+        }),
+        describe_enum!(ConfigIssue {
+            None,
+            InvalidCertificate,
+            UnsupportedKey,
+            KeyMismatch,
+            MissingAlpn,
+            InvalidTransport,
+        }),
+        describe_enum!(ConnectionState {
+            Handshaking,
+            Established,
+            Closing,
+            Draining,
+            Closed,
+        }),
+        describe_enum!(DiagnosticsEventKind {
+            StateChanged,
+            HandshakeTimedOut,
+            HandshakeDataReady,
+            Connected,
+            ConnectionLost,
+            StreamOpened,
+            StreamReadable,
+            StreamWritable,
+            StreamFinished,
+            StreamStopped,
+            StreamAvailable,
+            DatagramReceived,
+            OrphanStopped,
+            PathMigrated,
+            Congestion,
+            DatagramSizeChanged,
+        }),
+        describe_enum!(CallbackKind {
+            StreamReadable,
+            StreamWritable,
+            StreamFinished,
+            StreamStopped,
+            StreamOpened,
+            StreamAvailable,
+            DatagramReceived,
+            MessageReceived,
+            FileTransferProgress,
+            CongestionEvent,
+            PathMigrated,
+        }),
+        describe_enum!(BenchmarkKind {
+            Handshake,
+            Throughput,
+        }),
+        describe_enum!(CongestionEventKind {
+            Congestion,
+            PersistentCongestion,
+        }),
+        describe_enum!(StreamDataDirection { Received, Sent }),
+        describe_enum!(StreamDirection { Bi, Uni }),
+        describe_enum!(StreamScheduling {
+            StrictPriority,
+            RoundRobin,
+        }),
+        describe_enum!(AppEventKind {
+            Connected,
+            Closed,
+            StreamReadable,
+            StreamWritable,
+            StreamFinished,
+            StreamStopped,
+            StreamOpened,
+            StreamAvailable,
+            DatagramReceived,
+            HandshakeDataReady,
+            MessageReceived,
+            EventsDropped,
+        }),
+        describe_enum!(DatagramOutcome {
+            ConnectionEvent,
+            NewConnection,
+            VersionNegotiationSent,
+            StatelessResetSent,
+            RetrySent,
+            ConnectionRefused,
+            Dropped,
+        }),
+        describe_enum!(EndpointTransmitKind {
+            VersionNegotiation,
+            Retry,
+            StatelessReset,
+            ConnectionRefused,
+        }),
+        describe_enum!(CallbackDispatchMode { Inline, Queued }),
+        describe_enum!(EndpointRole { Client, Server }),
+        describe_enum!(PollerMode {
+            Default,
+            Manual,
+            Automatic,
+        }),
+        describe_enum!(LogLevel {
+            Off,
+            Error,
+            Warn,
+            Info,
+            Debug,
+            Trace,
+        }),
+    ]
+}
+
+/// The setters of the callbacks that are not declared with `set_callbacks!`, with their argument and return types.
+const OTHER_CALLBACKS: &[(&str, &[&str], &str)] = &[
+    ("set_clock_callback", &[], "u64"),
+    (
+        "set_sni_config_selector",
+        &["u64", "*const u8", "u32"],
+        "RustlsServerConfigHandle",
+    ),
+    (
+        "set_log_callback",
+        &["LogLevel", "*const u8", "u32", "*const u8", "u32"],
+        "void",
+    ),
+];
+
+/// Returns the description of the types and callbacks as JSON.
+///
+/// Handles, e.g. `ConnectionHandle`, are pointers. Type names are the Rust ones, e.g. `u64`, `*const u8` or `[u8; 16]`.
+pub(crate) fn describe() -> String {
+    let mut json = String::from("{\"pointer_size\":");
+    let _ = write!(json, "{},\"structs\":[", mem::size_of::<*const u8>());
+
+    for (i, composite) in composites().iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            "{{\"name\":\"{}\",\"kind\":\"{}\",\"size\":{},\"align\":{},\"fields\":[",
+            composite.name, composite.kind, composite.size, composite.align
+        );
+        for (i, field) in composite.fields.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"name\":\"{}\",\"type\":\"{}\",\"offset\":{},\"size\":{}}}",
+                field.name,
+                type_name(field.ty),
+                field.offset,
+                field.size
+            );
+        }
+        json.push_str("]}");
+    }
+
+    json.push_str("],\"enums\":[");
+    for (i, described) in enums().iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            "{{\"name\":\"{}\",\"size\":{},\"variants\":[",
+            described.name, described.size
+        );
+        for (i, (name, value)) in described.variants.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "{{\"name\":\"{}\",\"value\":{}}}", name, value);
+        }
+        json.push_str("]}");
+    }
+
+    json.push_str("],\"callbacks\":[");
+    let callbacks = callbacks::CALLBACK_SIGNATURES
+        .iter()
+        .map(|(setter, args)| (*setter, *args, "void"))
+        .chain(OTHER_CALLBACKS.iter().copied());
+    for (i, (setter, args, returns)) in callbacks.enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(json, "{{\"setter\":\"{}\",\"args\":[", setter);
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "\"{}\"", type_name(arg));
+        }
+        let _ = write!(json, "],\"returns\":\"{}\"}}", returns);
+    }
+    json.push_str("]}");
+
+    json
+}
+
+/// Normalizes a stringified type, e.g. `super :: ConnectionHandle` to `ConnectionHandle`.
+fn type_name(ty: &str) -> String {
+    let ty = ty.replace(" :: ", "::").replace("super::", "");
+    ty.rsplit("::").next().unwrap_or(&ty).to_owned()
+}
@@ -3,14 +3,26 @@
 use crate::{
     ffi::HandleMut,
     proto_impl::{
+        CancellationToken,
+        ClientConfigImpl,
         ConnectionImpl,
+        ConnectionSender,
+        DatagramIngress,
+        EndpointConfigImpl,
         EndpointImpl,
         FFIErrorKind,
+        ServerConfigImpl,
+        TransportSettings,
     },
 };
 
 use crate::ffi::{
+    dispatch::{
+        reject_locked_callback,
+        LockScope,
+    },
     handle_mut::FFIHandleMut,
+    handle_shared::FFIHandleRef,
     HandleRef,
 };
 use std::sync::{
@@ -19,22 +31,34 @@ use std::sync::{
 };
 
 // Mutex required for unwind safeness due to possible interior mutability.
-pub type RustlsClientConfigHandle<'a> = FFIHandleMut<'a, Mutex<quinn_proto::ClientConfig>>;
+pub type RustlsClientConfigHandle<'a> = FFIHandleMut<'a, Mutex<ClientConfigImpl>>;
 // Mutex required for unwind safeness due to possible interior mutability.
-pub type RustlsServerConfigHandle<'a> = FFIHandleMut<'a, Mutex<quinn_proto::ServerConfig>>;
+pub type RustlsServerConfigHandle<'a> = FFIHandleMut<'a, Mutex<ServerConfigImpl>>;
 // Mutex required for unwind safeness due to possible interior mutability.
 pub type EndpointHandle<'a> = FFIHandleMut<'a, Arc<Mutex<EndpointImpl>>>;
 // Mutex required for unwind safeness due to possible interior mutability.
 pub type ConnectionHandle<'a> = FFIHandleMut<'a, Arc<Mutex<ConnectionImpl>>>;
+// Mutex required for unwind safeness due to possible interior mutability.
+pub type EndpointConfigHandle<'a> = FFIHandleMut<'a, Mutex<EndpointConfigImpl>>;
+// Mutex required for unwind safeness due to possible interior mutability.
+pub type TransportConfigHandle<'a> = FFIHandleMut<'a, Mutex<TransportSettings>>;
+// No mutex, the ingress queue is shared by the reader threads without locking.
+pub type DatagramIngressHandle<'a> = FFIHandleRef<'a, DatagramIngress>;
+// No mutex, the send queue is shared by the sending threads without locking.
+pub type ConnectionSenderHandle<'a> = FFIHandleRef<'a, ConnectionSender>;
+// No mutex, the token is cancelled and checked without locking.
+pub type CancellationHandle<'a> = FFIHandleRef<'a, CancellationToken>;
 
 impl<'a> HandleMut for RustlsClientConfigHandle<'a> {
-    type Inner = quinn_proto::ClientConfig;
+    type Inner = ClientConfigImpl;
 
     fn ref_access(
         &self,
         cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let lock = &self.lock().unwrap();
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let lock = &value.lock().unwrap();
         cb(lock)
     }
 
@@ -42,8 +66,66 @@ impl<'a> HandleMut for RustlsClientConfigHandle<'a> {
         &mut self,
         cb: &mut dyn FnMut(&mut Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let mut lock = self.lock().unwrap();
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let mut lock = value.lock().unwrap();
+
+        cb(&mut lock)
+    }
+
+    fn new(instance: Self::Inner) -> Self {
+        Self::alloc(Mutex::new(instance))
+    }
+}
+
+impl<'a> HandleMut for EndpointConfigHandle<'a> {
+    type Inner = EndpointConfigImpl;
+
+    fn ref_access(
+        &self,
+        cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let lock = value.lock().unwrap();
+        cb(&lock)
+    }
+
+    fn mut_access(
+        &mut self,
+        cb: &mut dyn FnMut(&mut Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let mut lock = value.lock().unwrap();
+        cb(&mut lock)
+    }
+
+    fn new(instance: Self::Inner) -> Self {
+        Self::alloc(Mutex::new(instance))
+    }
+}
+
+impl<'a> HandleMut for TransportConfigHandle<'a> {
+    type Inner = TransportSettings;
+
+    fn ref_access(
+        &self,
+        cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let lock = value.lock().unwrap();
+        cb(&lock)
+    }
 
+    fn mut_access(
+        &mut self,
+        cb: &mut dyn FnMut(&mut Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let mut lock = value.lock().unwrap();
         cb(&mut lock)
     }
 
@@ -59,7 +141,10 @@ impl<'a> HandleMut for EndpointHandle<'a> {
         &self,
         cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let mut lock = self.lock().unwrap();
+        reject_locked_callback()?;
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let mut lock = value.lock().unwrap();
 
         cb(&mut lock)
     }
@@ -68,7 +153,10 @@ impl<'a> HandleMut for EndpointHandle<'a> {
         &mut self,
         cb: &mut dyn FnMut(&mut Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let mut lock = self.lock().unwrap();
+        reject_locked_callback()?;
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let mut lock = value.lock().unwrap();
 
         cb(&mut lock)
     }
@@ -79,13 +167,15 @@ impl<'a> HandleMut for EndpointHandle<'a> {
 }
 
 impl<'a> HandleMut for RustlsServerConfigHandle<'a> {
-    type Inner = quinn_proto::ServerConfig;
+    type Inner = ServerConfigImpl;
 
     fn ref_access(
         &self,
         cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let lock = &self.lock().unwrap();
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let lock = &value.lock().unwrap();
 
         cb(lock)
     }
@@ -94,7 +184,9 @@ impl<'a> HandleMut for RustlsServerConfigHandle<'a> {
         &mut self,
         cb: &mut dyn FnMut(&mut Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let mut lock = self.lock().unwrap();
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let mut lock = value.lock().unwrap();
 
         cb(&mut lock)
     }
@@ -111,7 +203,11 @@ impl<'a> HandleMut for ConnectionHandle<'a> {
         &self,
         cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let lock = &self.lock().unwrap();
+        reject_locked_callback()?;
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let lock = &value.lock().unwrap();
+        lock.ensure_not_freed()?;
 
         cb(lock)
     }
@@ -120,7 +216,11 @@ impl<'a> HandleMut for ConnectionHandle<'a> {
         &mut self,
         cb: &mut dyn FnMut(&mut Self::Inner) -> Result<(), FFIErrorKind>,
     ) -> Result<(), FFIErrorKind> {
-        let mut lock = self.lock().unwrap();
+        reject_locked_callback()?;
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        let mut lock = value.lock().unwrap();
+        lock.ensure_not_freed()?;
 
         let a = cb(&mut lock);
         drop(lock);
@@ -132,6 +232,57 @@ impl<'a> HandleMut for ConnectionHandle<'a> {
     }
 }
 
+impl<'a> HandleRef for DatagramIngressHandle<'a> {
+    type Inner = DatagramIngress;
+
+    fn new(instance: Self::Inner) -> Self {
+        Self::alloc(instance)
+    }
+
+    fn ref_access(
+        &self,
+        cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        cb(&value)
+    }
+}
+
+impl<'a> HandleRef for ConnectionSenderHandle<'a> {
+    type Inner = ConnectionSender;
+
+    fn new(instance: Self::Inner) -> Self {
+        Self::alloc(instance)
+    }
+
+    fn ref_access(
+        &self,
+        cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        cb(&value)
+    }
+}
+
+impl<'a> HandleRef for CancellationHandle<'a> {
+    type Inner = CancellationToken;
+
+    fn new(instance: Self::Inner) -> Self {
+        Self::alloc(instance)
+    }
+
+    fn ref_access(
+        &self,
+        cb: &mut dyn FnMut(&Self::Inner) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let _scope = LockScope::enter();
+        let value = self.access()?;
+        cb(&value)
+    }
+}
+
 /**
 Wrap an FFI function.
 
@@ -141,7 +292,7 @@ macro_rules! ffi {
     ($(fn $name:ident ( $( $arg_ident:ident : $arg_ty:ty),* ) -> FFIResult $body:expr)*) => {
         $(
             #[allow(unsafe_code, unused_attributes)]
-            #[no_mangle]
+            #[export_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), stringify!($name))]
             pub unsafe extern "cdecl" fn $name( $(mut $arg_ident : $arg_ty),* ) -> FFIResult {
                 $body
             }
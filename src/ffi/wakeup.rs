@@ -0,0 +1,197 @@
+//! A file descriptor, or an event object on Windows, that is readable while callbacks are queued for `dispatch_callbacks`.
+//!
+//! Lets hosts wait for queued callbacks in their epoll, kqueue or IOCP based event loop instead of on a dedicated thread.
+//! The descriptor is created on first use and stays open for the lifetime of the process.
+
+use std::{
+    io,
+    sync::Mutex,
+};
+
+static EVENT: Mutex<Option<imp::Event>> = Mutex::new(None);
+
+/// Returns the raw descriptor, created and signaled if callbacks are already pending.
+pub(crate) fn raw_event(pending: bool) -> io::Result<i64> {
+    let mut event = EVENT.lock().unwrap();
+
+    if event.is_none() {
+        let created = imp::Event::new()?;
+        if pending {
+            created.signal();
+        }
+        *event = Some(created);
+    }
+
+    Ok(event.as_ref().map_or(-1, imp::Event::raw))
+}
+
+/// Makes the descriptor readable, if it was created.
+pub(crate) fn signal() {
+    if let Some(event) = EVENT.lock().unwrap().as_ref() {
+        event.signal();
+    }
+}
+
+/// Makes the descriptor not readable, if it was created.
+pub(crate) fn clear() {
+    if let Some(event) = EVENT.lock().unwrap().as_ref() {
+        event.clear();
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::io;
+
+    /// An `eventfd`, whose counter is non-zero while it is signaled.
+    pub(super) struct Event(libc::c_int);
+
+    impl Event {
+        pub(super) fn new() -> io::Result<Self> {
+            match unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) } {
+                -1 => Err(io::Error::last_os_error()),
+                fd => Ok(Event(fd)),
+            }
+        }
+
+        pub(super) fn raw(&self) -> i64 {
+            self.0 as i64
+        }
+
+        pub(super) fn signal(&self) {
+            let one = 1u64;
+            unsafe { libc::write(self.0, &one as *const u64 as *const libc::c_void, 8) };
+        }
+
+        pub(super) fn clear(&self) {
+            // Fails with `EAGAIN` if the counter is already zero.
+            let mut counter = 0u64;
+            unsafe { libc::read(self.0, &mut counter as *mut u64 as *mut libc::c_void, 8) };
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+mod imp {
+    use std::io;
+
+    /// A pipe, whose read end holds a byte while it is signaled.
+    pub(super) struct Event {
+        read: libc::c_int,
+        write: libc::c_int,
+    }
+
+    impl Event {
+        pub(super) fn new() -> io::Result<Self> {
+            let mut fds = [0; 2];
+            if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            for fd in fds {
+                unsafe {
+                    libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK);
+                    libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                }
+            }
+
+            Ok(Event {
+                read: fds[0],
+                write: fds[1],
+            })
+        }
+
+        pub(super) fn raw(&self) -> i64 {
+            self.read as i64
+        }
+
+        pub(super) fn signal(&self) {
+            unsafe { libc::write(self.write, [1u8].as_ptr() as *const libc::c_void, 1) };
+        }
+
+        pub(super) fn clear(&self) {
+            let mut buf = [0u8; 64];
+            while unsafe { libc::read(self.read, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) }
+                > 0
+            {}
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::{
+        ffi::c_void,
+        io,
+    };
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn CreateEventW(
+            attributes: *mut c_void,
+            manual_reset: i32,
+            initial_state: i32,
+            name: *const u16,
+        ) -> *mut c_void;
+        fn SetEvent(event: *mut c_void) -> i32;
+        fn ResetEvent(event: *mut c_void) -> i32;
+    }
+
+    /// A manual-reset event object, which is signaled until it is reset.
+    pub(super) struct Event(*mut c_void);
+
+    // The handle is only passed to the thread safe event functions.
+    unsafe impl Send for Event {}
+
+    impl Event {
+        pub(super) fn new() -> io::Result<Self> {
+            let event = unsafe { CreateEventW(std::ptr::null_mut(), 1, 0, std::ptr::null()) };
+            if event.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Event(event))
+        }
+
+        pub(super) fn raw(&self) -> i64 {
+            self.0 as i64
+        }
+
+        pub(super) fn signal(&self) {
+            unsafe { SetEvent(self.0) };
+        }
+
+        pub(super) fn clear(&self) {
+            unsafe { ResetEvent(self.0) };
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use std::io;
+
+    /// Targets without descriptors, like wasm, have no event loop to wake.
+    pub(super) enum Event {}
+
+    impl Event {
+        pub(super) fn new() -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Event descriptors are not supported on this target",
+            ))
+        }
+
+        pub(super) fn raw(&self) -> i64 {
+            match *self {}
+        }
+
+        pub(super) fn signal(&self) {
+            match *self {}
+        }
+
+        pub(super) fn clear(&self) {
+            match *self {}
+        }
+    }
+}
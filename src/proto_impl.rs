@@ -1,18 +1,123 @@
 //! Quinn-proto implementation, similar to `quinn` but without the async runtime and some differences to make it fit with FFi applications.
 
 pub use addr::IpAddr;
+pub use app_events::{
+    AppEvent,
+    AppEventData,
+    AppEventKind,
+    ClosedEventData,
+    StreamEventData,
+};
+pub use benchmark::{
+    BenchmarkKind,
+    BenchmarkReport,
+};
+pub use cancellation::CancellationToken;
+pub use close_reason::{
+    CloseReason,
+    ConnectionErrorKind,
+};
+pub use config::{
+    ClientConfigImpl,
+    ServerConfigImpl,
+};
+pub use congestion::CongestionEventKind;
+pub(crate) use connection::RecvStreamEnd;
 pub use connection::{
     ConnectionEvent,
     ConnectionImpl,
+    ConnectionInfo,
+    ConnectionState,
+    StreamDataDirection,
+};
+pub use connection_sender::{
+    ConnectionSender,
+    SendFailure,
+};
+pub use diagnostics::{
+    DiagnosticsEvent,
+    DiagnosticsEventKind,
 };
 pub use endpoint::{
     EndpointEvent,
     EndpointImpl,
     EndpointPoller,
+    PollNotifier,
+};
+pub use endpoint_config::EndpointConfigImpl;
+pub use h3::H3Setting;
+pub use host_buffer::FreeFn;
+pub use ingress::DatagramIngress;
+pub use peer_params::PeerParams;
+pub use recv_buffers::RecvDatagram;
+pub use result::{
+    ErrorContext,
+    FFIErrorKind,
+};
+pub use selftest::SelfTestReport;
+pub use stats::{
+    DatagramOutcome,
+    EndpointPollerStats,
+    EndpointStats,
+    EndpointTransmitKind,
+    StreamStats,
+    TransmitBurstStats,
 };
-pub use result::FFIErrorKind;
+pub use stream_direction::StreamDirection;
+pub use stream_scheduling::StreamScheduling;
+pub use transport_config::TransportSettings;
+pub use validation::ConfigIssue;
 
 mod addr;
+pub(crate) mod app_events;
+pub(crate) mod auth_token;
+pub(crate) mod benchmark;
+mod cancellation;
+pub(crate) mod cid_generator;
+pub(crate) mod clock;
+mod close_reason;
+pub(crate) mod config;
+mod congestion;
 mod connection;
-mod endpoint;
+pub(crate) mod connection_sender;
+pub(crate) mod diagnostics;
+pub(crate) mod endpoint;
+pub(crate) mod endpoint_config;
+pub(crate) mod entropy;
+pub(crate) mod file_transfer;
+pub(crate) mod framing;
+pub(crate) mod h3;
+pub(crate) mod host_buffer;
+pub(crate) mod ingress;
+mod peer_params;
+pub(crate) mod poller_pool;
+mod qlog;
+mod recv_buffers;
+pub(crate) mod registry;
+pub(crate) mod reset_key;
 mod result;
+#[cfg(feature = "self-signed")]
+pub(crate) mod self_signed;
+/// Stands in for the certificate generator in builds without the `self-signed` feature.
+#[cfg(not(feature = "self-signed"))]
+pub(crate) mod self_signed {
+    use crate::proto_impl::FFIErrorKind;
+
+    pub struct SelfSignedCert {
+        pub cert: Vec<u8>,
+        pub key: Vec<u8>,
+    }
+
+    pub fn generate(_: &[&str], _: u32) -> Result<SelfSignedCert, FFIErrorKind> {
+        Err(FFIErrorKind::unsupported("self-signed"))
+    }
+}
+pub(crate) mod selftest;
+mod send_rate;
+pub(crate) mod state_watch;
+mod stats;
+mod stream_direction;
+mod stream_scheduling;
+pub(crate) mod transport_config;
+pub(crate) mod validation;
+pub(crate) mod versions;
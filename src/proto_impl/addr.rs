@@ -1,49 +1,130 @@
+use crate::proto_impl::FFIErrorKind;
 use std::{
-    net,
+    convert::TryFrom,
     net::{
         Ipv4Addr,
+        Ipv6Addr,
         SocketAddr,
         SocketAddrV4,
+        SocketAddrV6,
     },
 };
 
 /// IpAddress that is FFI safe.
+///
+/// IPv4-mapped IPv6 addresses, as reported by dual-stack sockets, are kept as IPv6 such that replies leave through the same socket.
+///
+/// The address bytes are in network byte order, the numbers in the byte order of the host, unlike `sockaddr_in` and
+/// `sockaddr_in6` which store the port and flow info in network byte order. Convert those fields with
+/// `ip_addr_from_network_order` and `ip_addr_network_order` instead of copying them.
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct IpAddr {
-    /// The port
-    port: u16,
-    // The address bytes
-    // Right now only ip4 is supported
-    address: [u8; 4],
+    /// The port in host byte order, e.g. `ntohs(sin_port)`.
+    pub(crate) port: u16,
+    /// 1 if the address is IPv6, 0 if it is IPv4, other values are rejected.
+    pub(crate) is_v6: u8,
+    /// The address bytes in network byte order, as in `in_addr` and `in6_addr`, IPv4 addresses only use the first 4 bytes.
+    pub(crate) address: [u8; 16],
+    /// The IPv6 flow info in host byte order, e.g. `ntohl(sin6_flowinfo)`, 0 for IPv4.
+    pub(crate) flow_info: u32,
+    /// The IPv6 scope id, e.g. the interface index of link-local addresses, 0 for IPv4.
+    pub(crate) scope_id: u32,
+}
+
+impl IpAddr {
+    /// Creates an address from the fields of a `sockaddr_in` or `sockaddr_in6`.
+    ///
+    /// `network_port` and `network_flow_info` are the values as stored in the socket address, in network byte order,
+    /// the scope id is stored in host byte order. The address is IPv4 if it has 4 bytes, IPv6 if it has 16 bytes.
+    pub fn from_network_order(
+        address: &[u8],
+        network_port: u16,
+        network_flow_info: u32,
+        scope_id: u32,
+    ) -> Result<Self, FFIErrorKind> {
+        let port = u16::from_be(network_port);
+
+        match address.len() {
+            4 => {
+                let mut octets = [0; 4];
+                octets.copy_from_slice(address);
+                Ok(IpAddr::from(SocketAddr::from((octets, port))))
+            }
+            16 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(address);
+                Ok(IpAddr::from(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(octets),
+                    port,
+                    u32::from_be(network_flow_info),
+                    scope_id,
+                ))))
+            }
+            _ => Err(FFIErrorKind::io_error(
+                "An address has 4 bytes for IPv4 or 16 bytes for IPv6",
+            )),
+        }
+    }
+
+    /// Returns the port and flow info in network byte order, as stored in a `sockaddr_in` or `sockaddr_in6`.
+    pub fn network_order(&self) -> (u16, u32) {
+        (self.port.to_be(), self.flow_info.to_be())
+    }
 }
 
 impl From<SocketAddr> for IpAddr {
     /// From `SocketAddr` to FFI-safe `IpAddr`
     fn from(addr: SocketAddr) -> Self {
-        let address_bytes = match addr.ip() {
-            net::IpAddr::V4(ip) => ip.octets(),
-            net::IpAddr::V6(_ip) => panic!("not supported"),
-        };
-
-        IpAddr {
-            port: addr.port(),
-            address: address_bytes,
+        let mut address = [0; 16];
+
+        match addr {
+            SocketAddr::V4(addr) => {
+                address[..4].copy_from_slice(&addr.ip().octets());
+
+                IpAddr {
+                    port: addr.port(),
+                    is_v6: 0,
+                    address,
+                    flow_info: 0,
+                    scope_id: 0,
+                }
+            }
+            SocketAddr::V6(addr) => IpAddr {
+                port: addr.port(),
+                is_v6: 1,
+                address: addr.ip().octets(),
+                flow_info: addr.flowinfo(),
+                scope_id: addr.scope_id(),
+            },
         }
     }
 }
 
-impl Into<SocketAddr> for IpAddr {
-    /// From FFI-safe `IpAddr` to `SocketAddr`
-    fn into(self) -> SocketAddr {
-        SocketAddr::V4(SocketAddrV4::new(
-            Ipv4Addr::new(
-                self.address[0],
-                self.address[1],
-                self.address[2],
-                self.address[3],
-            ),
-            self.port,
-        ))
+impl TryFrom<IpAddr> for SocketAddr {
+    type Error = FFIErrorKind;
+
+    /// From FFI-safe `IpAddr` to `SocketAddr`, fails if `is_v6` is neither 0 nor 1.
+    fn try_from(addr: IpAddr) -> Result<Self, Self::Error> {
+        match addr.is_v6 {
+            0 => Ok(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::new(
+                    addr.address[0],
+                    addr.address[1],
+                    addr.address[2],
+                    addr.address[3],
+                ),
+                addr.port,
+            ))),
+            1 => Ok(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(addr.address),
+                addr.port,
+                addr.flow_info,
+                addr.scope_id,
+            ))),
+            _ => Err(FFIErrorKind::io_error(
+                "Invalid address family, `is_v6` must be 0 or 1",
+            )),
+        }
     }
 }
@@ -0,0 +1,176 @@
+//! Connection events that are queued for the client application to poll, instead of invoking a callback for each event.
+
+use crate::{
+    proto::{
+        StreamId,
+        VarInt,
+    },
+    proto_impl::{
+        CloseReason,
+        ConnectionErrorKind,
+        StreamDirection,
+    },
+};
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+
+/// The number of events a connection queues, once reached further events are dropped until events are polled.
+pub(crate) const MAX_QUEUED_EVENTS: usize = 4096;
+
+/// Whether connections created from now on queue their events rather than invoking callbacks.
+static EVENT_POLLING: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether connections created from now on queue their events rather than invoking callbacks.
+pub(crate) fn set_event_polling_enabled(enabled: bool) {
+    EVENT_POLLING.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether new connections queue their events.
+pub(crate) fn event_polling_enabled() -> bool {
+    EVENT_POLLING.load(Ordering::Relaxed)
+}
+
+/// The kind of an `AppEvent`, which determines the valid field of `AppEvent::data`.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AppEventKind {
+    /// The handshake completed, no data.
+    Connected,
+    /// The connection was lost, `data.closed` is valid.
+    Closed,
+    /// A stream can be read from, `data.stream` is valid.
+    StreamReadable,
+    /// A stream can be written to, `data.stream` is valid.
+    StreamWritable,
+    /// A stream was finished by the peer, `data.stream` is valid.
+    StreamFinished,
+    /// A stream was stopped by the peer, `data.stream` is valid.
+    StreamStopped,
+    /// The peer opened a stream, `data.stream` is valid.
+    StreamOpened,
+    /// A stream can be opened in a direction, `data.stream.dir` is valid.
+    StreamAvailable,
+    /// A datagram was received, no data.
+    DatagramReceived,
+    /// The server name and application protocol can be read with `connection_handshake_data`, no data.
+    HandshakeDataReady,
+    /// A message can be read from a stream in framing mode with `read_message`, `data.stream` is valid.
+    MessageReceived,
+    /// The queue was full and events were dropped, the streams and datagrams have to be checked without events, no data.
+    EventsDropped,
+}
+
+/// The stream of a stream event.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct StreamEventData {
+    pub stream_id: u64,
+    pub dir: StreamDirection,
+}
+
+/// The reason of a `Closed` event, the reason phrase can be retrieved with `connection_close_reason`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ClosedEventData {
+    pub kind: ConnectionErrorKind,
+    pub code: u64,
+}
+
+/// The data of an `AppEvent`, tagged by `AppEvent::kind`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union AppEventData {
+    pub stream: StreamEventData,
+    pub closed: ClosedEventData,
+    pub none: u8,
+}
+
+/// A connection event that is FFI safe.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct AppEvent {
+    pub kind: AppEventKind,
+    pub data: AppEventData,
+}
+
+impl AppEvent {
+    /// An event without data.
+    pub(crate) fn new(kind: AppEventKind) -> Self {
+        AppEvent {
+            kind,
+            data: AppEventData { none: 0 },
+        }
+    }
+
+    /// An event of the given stream.
+    pub(crate) fn stream(kind: AppEventKind, stream_id: StreamId) -> Self {
+        AppEvent {
+            kind,
+            data: AppEventData {
+                stream: StreamEventData {
+                    stream_id: VarInt::from(stream_id).into_inner(),
+                    dir: stream_id.dir().into(),
+                },
+            },
+        }
+    }
+
+    /// A `StreamAvailable` event of the given direction.
+    pub(crate) fn stream_available(dir: StreamDirection) -> Self {
+        AppEvent {
+            kind: AppEventKind::StreamAvailable,
+            data: AppEventData {
+                stream: StreamEventData { stream_id: 0, dir },
+            },
+        }
+    }
+
+    /// An `EventsDropped` event.
+    pub(crate) fn events_dropped() -> Self {
+        AppEvent::new(AppEventKind::EventsDropped)
+    }
+
+    /// Whether the event only tells that a stream, a direction or the datagrams can be used, such that an equal event
+    /// that is still queued already tells it.
+    pub(crate) fn is_coalesced(&self) -> bool {
+        matches!(
+            self.kind,
+            AppEventKind::StreamReadable
+                | AppEventKind::StreamWritable
+                | AppEventKind::StreamAvailable
+                | AppEventKind::DatagramReceived
+                | AppEventKind::MessageReceived
+        )
+    }
+
+    /// Whether both events are of the same kind and the same stream or direction, if any.
+    pub(crate) fn same_as(&self, other: &AppEvent) -> bool {
+        if self.kind != other.kind {
+            return false;
+        }
+
+        match self.kind {
+            AppEventKind::DatagramReceived => true,
+            // `data.stream` is valid for the kinds of coalesced events except `DatagramReceived`.
+            _ => unsafe {
+                self.data.stream.stream_id == other.data.stream.stream_id
+                    && self.data.stream.dir == other.data.stream.dir
+            },
+        }
+    }
+
+    /// A `Closed` event with the given reason.
+    pub(crate) fn closed(reason: &CloseReason) -> Self {
+        AppEvent {
+            kind: AppEventKind::Closed,
+            data: AppEventData {
+                closed: ClosedEventData {
+                    kind: reason.kind,
+                    code: reason.code,
+                },
+            },
+        }
+    }
+}
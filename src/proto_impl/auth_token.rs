@@ -0,0 +1,134 @@
+//! Authenticates client connections with an opaque token, sent by the crate on the first unidirectional stream of the client.
+//!
+//! quinn does not support custom transport parameters, the stream is consumed by the crate on both sides such that
+//! the server application only sees the connection and its streams once `on_auth_token` accepted the token.
+
+use crate::{
+    proto,
+    proto::{
+        Dir,
+        ReadError,
+        Side,
+        StreamId,
+        VarInt,
+    },
+    proto_impl::FFIErrorKind,
+};
+
+/// The largest token a client may send, larger tokens are rejected without invoking `on_auth_token`.
+pub(crate) const MAX_AUTH_TOKEN_LEN: usize = 4096;
+
+/// Returns the stream carrying the token, the first unidirectional stream opened by the client.
+pub(crate) fn auth_stream() -> StreamId {
+    StreamId::new(Side::Client, Dir::Uni, 0)
+}
+
+/// Opens the stream carrying the token and writes and finishes it, once the handshake of the client completed.
+pub(crate) fn send_token(
+    connection: &mut proto::Connection,
+    token: &[u8],
+) -> Result<(), FFIErrorKind> {
+    let id = connection.streams().open(Dir::Uni).ok_or_else(|| {
+        FFIErrorKind::io_error(
+            "The peer allows no unidirectional stream for the authentication token",
+        )
+    })?;
+
+    if id != auth_stream() {
+        return Err(FFIErrorKind::io_error(
+            "A unidirectional stream was opened before the authentication token was sent",
+        ));
+    }
+
+    let mut stream = connection.send_stream(id);
+    if stream.write(token)? != token.len() {
+        return Err(FFIErrorKind::io_error(
+            "The authentication token exceeds the stream flow control window of the peer",
+        ));
+    }
+
+    stream.finish()?;
+    Ok(())
+}
+
+/// Withholds a server connection from the client application until the token of the client is accepted.
+#[derive(Debug)]
+pub(crate) struct AuthGate {
+    reject_code: VarInt,
+    received: Vec<u8>,
+    // streams of the peer opened before the token was accepted, announced once it is.
+    held_streams: Vec<(StreamId, Dir)>,
+    // whether the handshake completed, `on_connected` is invoked once the token is accepted.
+    connected: bool,
+}
+
+impl AuthGate {
+    pub(crate) fn new(reject_code: VarInt) -> Self {
+        AuthGate {
+            reject_code,
+            received: Vec::new(),
+            held_streams: Vec::new(),
+            connected: false,
+        }
+    }
+
+    /// The application error code the connection is closed with if the token is rejected.
+    pub(crate) fn reject_code(&self) -> VarInt {
+        self.reject_code
+    }
+
+    /// The token received so far.
+    pub(crate) fn token(&self) -> &[u8] {
+        &self.received
+    }
+
+    pub(crate) fn hold_stream(&mut self, id: StreamId, dir: Dir) {
+        self.held_streams.push((id, dir));
+    }
+
+    pub(crate) fn set_connected(&mut self) {
+        self.connected = true;
+    }
+
+    /// Returns whether the handshake completed and the streams opened by the peer, once the token is accepted.
+    pub(crate) fn into_released(self) -> (bool, Vec<(StreamId, Dir)>) {
+        (self.connected, self.held_streams)
+    }
+
+    /// Reads the received part of the token, returns whether the client finished the stream.
+    pub(crate) fn receive(
+        &mut self,
+        connection: &mut proto::Connection,
+    ) -> Result<bool, FFIErrorKind> {
+        let mut stream = connection.recv_stream(auth_stream());
+        let mut chunks = stream.read(true)?;
+        let mut result = Ok(false);
+
+        loop {
+            match chunks.next(usize::MAX) {
+                Ok(Some(chunk)) => {
+                    self.received.extend_from_slice(&chunk.bytes);
+                    if self.received.len() > MAX_AUTH_TOKEN_LEN {
+                        result = Err(FFIErrorKind::io_error(
+                            "The peer sent an authentication token exceeding the maximum size",
+                        ));
+                        break;
+                    }
+                }
+                Ok(None) => {
+                    result = Ok(true);
+                    break;
+                }
+                Err(ReadError::Blocked) => break,
+                Err(e) => {
+                    result = Err(e.into());
+                    break;
+                }
+            }
+        }
+
+        // The connection transmits flow control updates when it is polled after the stream event.
+        let _ = chunks.finalize();
+        result
+    }
+}
@@ -0,0 +1,410 @@
+//! Measures handshakes and stream throughput of a client and server exchanging packets in memory, see `run_benchmark`.
+//!
+//! No sockets are used and no callbacks are invoked, such that only the protocol logic is measured.
+
+use crate::{
+    ffi::FFIResultKind,
+    proto,
+    proto::{
+        ConnectionHandle,
+        DatagramEvent,
+        Dir,
+        Endpoint,
+        EndpointConfig,
+        ReadError,
+        StreamId,
+        WriteError,
+    },
+    proto_impl::{
+        cid_generator,
+        reset_key,
+        self_signed,
+        transport_config,
+        versions,
+        FFIErrorKind,
+    },
+};
+use bytes::BytesMut;
+use rustls::{
+    client::WebPkiVerifier,
+    Certificate,
+    PrivateKey,
+    RootCertStore,
+};
+use std::{
+    convert::TryFrom,
+    net::SocketAddr,
+    sync::Arc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+const SERVER_NAME: &str = "localhost";
+
+/// The number of connections established by the handshake benchmark.
+const HANDSHAKES: u64 = 100;
+
+/// The number of bytes transferred by the throughput benchmark.
+const TRANSFER_BYTES: u64 = 16 * 1024 * 1024;
+
+/// The size of the chunks written by the throughput benchmark.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The number of packet exchanges, or timeouts, after which the throughput benchmark fails.
+const MAX_STEPS: usize = 1_000_000;
+
+/// The number of packet exchanges, or timeouts, after which a handshake fails.
+const HANDSHAKE_STEPS: usize = 1000;
+
+/// What `run_benchmark` measures.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BenchmarkKind {
+    /// Establishes connections one after another, the rate is in handshakes per second.
+    Handshake,
+    /// Transfers data on a single stream, the rate is in bytes per second.
+    Throughput,
+}
+
+impl TryFrom<u8> for BenchmarkKind {
+    type Error = FFIErrorKind;
+
+    /// Fails with `InvalidArgument` for values other than those of the variants.
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(BenchmarkKind::Handshake),
+            1 => Ok(BenchmarkKind::Throughput),
+            _ => Err(FFIErrorKind::FFIResultKind(FFIResultKind::InvalidArgument)),
+        }
+    }
+}
+
+/// The result of `run_benchmark`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct BenchmarkReport {
+    /// The benchmark that was run.
+    pub kind: BenchmarkKind,
+    /// The number of handshakes, or 1 for the throughput benchmark.
+    pub iterations: u64,
+    /// The number of bytes transferred on streams.
+    pub bytes: u64,
+    /// The wall-clock time the benchmark took.
+    pub elapsed_micros: u64,
+    /// Handshakes or bytes per second.
+    pub rate: f64,
+}
+
+/// Runs the benchmark and reports its rate.
+pub(crate) fn run(kind: BenchmarkKind) -> Result<BenchmarkReport, FFIErrorKind> {
+    let start = Instant::now();
+
+    let (iterations, bytes) = match kind {
+        BenchmarkKind::Handshake => (handshakes()?, 0),
+        BenchmarkKind::Throughput => (1, transfer()?),
+    };
+
+    let elapsed = start.elapsed();
+    let count = match kind {
+        BenchmarkKind::Handshake => iterations,
+        BenchmarkKind::Throughput => bytes,
+    };
+
+    Ok(BenchmarkReport {
+        kind,
+        iterations,
+        bytes,
+        elapsed_micros: elapsed.as_micros() as u64,
+        rate: count as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+    })
+}
+
+/// Establishes `HANDSHAKES` connections, returns the number of handshakes.
+fn handshakes() -> Result<u64, FFIErrorKind> {
+    for _ in 0..HANDSHAKES {
+        let mut pair = connected_pair()?;
+        pair.client
+            .connection()
+            .close(pair.now, 0u32.into(), Default::default());
+    }
+
+    Ok(HANDSHAKES)
+}
+
+/// Transfers `TRANSFER_BYTES` from the client to the server, returns the number of bytes received.
+fn transfer() -> Result<u64, FFIErrorKind> {
+    let mut pair = connected_pair()?;
+    let stream = pair
+        .client
+        .connection()
+        .streams()
+        .open(Dir::Uni)
+        .ok_or_else(|| failure("the client could not open a stream"))?;
+
+    let chunk = vec![0u8; CHUNK_SIZE];
+    let mut written = 0;
+    let mut received = Vec::with_capacity(CHUNK_SIZE);
+    let mut received_bytes = 0;
+
+    for _ in 0..MAX_STEPS {
+        while written < TRANSFER_BYTES {
+            let len = (TRANSFER_BYTES - written).min(CHUNK_SIZE as u64) as usize;
+            match pair
+                .client
+                .connection()
+                .send_stream(stream)
+                .write(&chunk[..len])
+            {
+                Ok(n) => written += n as u64,
+                Err(WriteError::Blocked) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // Reading fails until the stream is opened on the server.
+        let server = pair.server.connection();
+        while server.streams().accept(Dir::Uni).is_some() {}
+        let _ = read_available(server, stream, &mut received);
+        received_bytes += received.len() as u64;
+        received.clear();
+
+        if received_bytes >= TRANSFER_BYTES {
+            return Ok(received_bytes);
+        }
+
+        if !pair.exchange() {
+            pair.advance()?;
+        }
+    }
+
+    Err(failure("the transfer did not complete"))
+}
+
+fn connected_pair() -> Result<Pair, FFIErrorKind> {
+    let server_address = SocketAddr::from(([127, 0, 0, 1], 4433));
+    let client_address = SocketAddr::from(([127, 0, 0, 1], 4434));
+
+    let mut pair = Pair::new(server_address, client_address)?;
+    pair.run_until("handshake", |pair| {
+        pair.server.connection.is_some() && !pair.client.connection().is_handshaking()
+    })?;
+
+    Ok(pair)
+}
+
+/// An endpoint with at most one connection.
+struct Peer {
+    endpoint: Endpoint,
+    connection: Option<(ConnectionHandle, proto::Connection)>,
+    address: SocketAddr,
+}
+
+impl Peer {
+    fn connection(&mut self) -> &mut proto::Connection {
+        &mut self.connection.as_mut().unwrap().1
+    }
+
+    /// Collects the datagrams of the endpoint and its connection.
+    fn poll_transmits(&mut self, now: Instant, transmits: &mut Vec<Vec<u8>>) {
+        while let Some(transmit) = self.endpoint.poll_transmit() {
+            transmits.push(transmit.contents);
+        }
+
+        if let Some((handle, connection)) = &mut self.connection {
+            while let Some(event) = connection.poll_endpoint_events() {
+                if let Some(event) = self.endpoint.handle_event(*handle, event) {
+                    connection.handle_event(event);
+                }
+            }
+            while let Some(transmit) = connection.poll_transmit(now, 1) {
+                transmits.push(transmit.contents);
+            }
+            // Events are not used, stream and datagram data is polled instead.
+            while connection.poll().is_some() {}
+        }
+    }
+
+    fn handle_datagram(&mut self, now: Instant, remote: SocketAddr, data: &[u8]) {
+        match self
+            .endpoint
+            .handle(now, remote, None, None, BytesMut::from(data))
+        {
+            Some((handle, DatagramEvent::NewConnection(connection))) => {
+                self.connection = Some((handle, connection));
+            }
+            Some((_, DatagramEvent::ConnectionEvent(event))) => {
+                if let Some((_, connection)) = &mut self.connection {
+                    connection.handle_event(event);
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+/// A client connected to a server, packets are exchanged in memory on a simulated clock.
+///
+/// The quinn-proto endpoints are driven directly rather than through `EndpointImpl`, such that no callbacks are invoked.
+/// The global settings, like the supported versions and the connection id length, do apply.
+struct Pair {
+    client: Peer,
+    server: Peer,
+    now: Instant,
+}
+
+impl Pair {
+    fn new(server_address: SocketAddr, client_address: SocketAddr) -> Result<Self, FFIErrorKind> {
+        if cid_generator::cid_length() == 0 {
+            return Err(failure(
+                "server endpoints require a non-zero connection id length",
+            ));
+        }
+
+        let generated = self_signed::generate(&[SERVER_NAME], 1)?;
+        let (cert, key) = (Certificate(generated.cert), PrivateKey(generated.key));
+        let mut store = RootCertStore::empty();
+        store
+            .add(&cert)
+            .map_err(|e| failure(&format!("the certificate is not accepted: {}", e)))?;
+
+        let server_crypto = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert], key)?;
+        let mut server_config = proto::ServerConfig::with_crypto(Arc::new(server_crypto));
+        server_config.transport = transport_config::transport_config();
+
+        let client_crypto = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(WebPkiVerifier::new(store, None)))
+            .with_no_client_auth();
+        let mut client_config = proto::ClientConfig::new(Arc::new(client_crypto));
+        client_config.transport = transport_config::transport_config();
+
+        let mut client = Peer {
+            endpoint: Endpoint::new(endpoint_config(), None),
+            connection: None,
+            address: client_address,
+        };
+        let server = Peer {
+            endpoint: Endpoint::new(endpoint_config(), Some(Arc::new(server_config))),
+            connection: None,
+            address: server_address,
+        };
+
+        client.connection = Some(
+            client
+                .endpoint
+                .connect(client_config, server_address, SERVER_NAME)
+                .map_err(|e| failure(&format!("the client could not connect: {}", e)))?,
+        );
+
+        Ok(Pair {
+            client,
+            server,
+            now: Instant::now(),
+        })
+    }
+
+    /// Exchanges packets, and advances the clock when none are sent, until `done` returns `true`.
+    fn run_until(
+        &mut self,
+        step: &str,
+        mut done: impl FnMut(&mut Pair) -> bool,
+    ) -> Result<(), FFIErrorKind> {
+        for _ in 0..HANDSHAKE_STEPS {
+            if self.server.connection.is_some() && done(self) {
+                return Ok(());
+            }
+
+            if !self.exchange() {
+                self.advance()?;
+            }
+        }
+
+        Err(failure(&format!("the {} did not complete", step)))
+    }
+
+    /// Delivers the pending datagrams of both endpoints, returns whether any were sent.
+    fn exchange(&mut self) -> bool {
+        let mut to_server = Vec::new();
+        let mut to_client = Vec::new();
+        self.client.poll_transmits(self.now, &mut to_server);
+        self.server.poll_transmits(self.now, &mut to_client);
+
+        for datagram in &to_server {
+            self.server
+                .handle_datagram(self.now, self.client.address, datagram);
+        }
+        for datagram in &to_client {
+            self.client
+                .handle_datagram(self.now, self.server.address, datagram);
+        }
+
+        !to_server.is_empty() || !to_client.is_empty()
+    }
+
+    /// Advances the clock to the earliest timer of the connections and handles the timeout.
+    fn advance(&mut self) -> Result<(), FFIErrorKind> {
+        let timeout = [&mut self.client, &mut self.server]
+            .iter_mut()
+            .filter_map(|peer| peer.connection.as_mut())
+            .filter_map(|(_, connection)| connection.poll_timeout())
+            .min()
+            .ok_or_else(|| failure("no packets are sent and no timers are pending"))?;
+
+        self.now = self.now.max(timeout) + Duration::from_micros(1);
+        for peer in [&mut self.client, &mut self.server].iter_mut() {
+            if let Some((_, connection)) = &mut peer.connection {
+                connection.handle_timeout(self.now);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn endpoint_config() -> Arc<EndpointConfig> {
+    let mut config = EndpointConfig::default();
+    config.supported_versions(versions::supported_versions());
+    config.cid_generator(cid_generator::new_generator);
+    if let Some(key) = reset_key::reset_key() {
+        config.reset_key(key);
+    }
+    Arc::new(config)
+}
+
+/// Appends the available data of the stream to `buffer`, returns whether the stream is finished.
+fn read_available(
+    connection: &mut proto::Connection,
+    id: StreamId,
+    buffer: &mut Vec<u8>,
+) -> Result<bool, FFIErrorKind> {
+    let mut stream = connection.recv_stream(id);
+    let mut chunks = stream.read(true)?;
+    let mut finished = false;
+
+    loop {
+        match chunks.next(usize::MAX) {
+            Ok(Some(chunk)) => buffer.extend_from_slice(&chunk.bytes),
+            Ok(None) => {
+                finished = true;
+                break;
+            }
+            Err(ReadError::Blocked) => break,
+            Err(e) => {
+                let _ = chunks.finalize();
+                return Err(e.into());
+            }
+        }
+    }
+
+    let _ = chunks.finalize();
+    Ok(finished)
+}
+
+fn failure(reason: &str) -> FFIErrorKind {
+    FFIErrorKind::io_error(&format!("Benchmark failed: {}", reason))
+}
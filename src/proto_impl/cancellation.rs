@@ -0,0 +1,56 @@
+//! Interrupts the blocking waits of `wait_connected_v2` and `wait_closed_v2` from any thread, see `create_cancellation`.
+
+use crate::proto_impl::state_watch::StateWatch;
+use std::sync::{
+    atomic::{
+        AtomicBool,
+        Ordering,
+    },
+    Arc,
+    Mutex,
+};
+
+/// A cancellation shared by the host thread that cancels and the threads that wait, cancelling is permanent.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    // the state watches of the connections waited on, woken up when the token is cancelled.
+    waiters: Mutex<Vec<StateWatch>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the token and wakes up the threads waiting with it, waits started later return at once.
+    pub(crate) fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+
+        let waiters = std::mem::take(&mut *self.0.waiters.lock().unwrap());
+        for watch in waiters {
+            watch.wake();
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Registers a watch that is woken up on cancellation, until the wait on it returned.
+    ///
+    /// Register before waiting, the wait must check `is_cancelled` while holding the lock of the watch.
+    pub(crate) fn register(&self, watch: &StateWatch) {
+        self.0.waiters.lock().unwrap().push(watch.clone());
+    }
+
+    pub(crate) fn unregister(&self, watch: &StateWatch) {
+        let mut waiters = self.0.waiters.lock().unwrap();
+        if let Some(index) = waiters.iter().position(|waiter| waiter.same(watch)) {
+            waiters.swap_remove(index);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Connection id generation for new endpoints, optionally delegated to the client application.
+
+use crate::{
+    ffi::callbacks,
+    proto::{
+        ConnectionId,
+        ConnectionIdGenerator,
+        RandomConnectionIdGenerator,
+    },
+    proto_impl::{
+        entropy,
+        FFIErrorKind,
+    },
+};
+use std::{
+    sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    },
+    time::Duration,
+};
+
+/// The maximum length of a connection id in QUIC version 1.
+pub const MAX_CID_LENGTH: usize = 20;
+
+/// The connection id length used by quinn by default.
+const DEFAULT_CID_LENGTH: usize = 8;
+
+/// The length of the connection ids generated by endpoints created from now on.
+static CID_LENGTH: AtomicUsize = AtomicUsize::new(DEFAULT_CID_LENGTH);
+
+/// Sets the length of the connection ids generated by endpoints created from now on.
+pub(crate) fn set_cid_length(length: usize) -> Result<(), FFIErrorKind> {
+    check_cid_length(length)?;
+    CID_LENGTH.store(length, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Fails if connection ids of the length are not allowed by QUIC version 1.
+pub(crate) fn check_cid_length(length: usize) -> Result<(), FFIErrorKind> {
+    if length > MAX_CID_LENGTH {
+        return Err(FFIErrorKind::io_error(&format!(
+            "Connection id length can not exceed {} bytes",
+            MAX_CID_LENGTH
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns the length of the connection ids generated by new endpoints.
+pub(crate) fn cid_length() -> usize {
+    CID_LENGTH.load(Ordering::Relaxed)
+}
+
+/// Creates the connection id generator of a new endpoint.
+///
+/// Uses the generator callback if it is set when the endpoint is created, then the entropy callback, and random connection ids otherwise.
+pub(crate) fn new_generator() -> Box<dyn ConnectionIdGenerator> {
+    generator(cid_length(), None)
+}
+
+/// Creates the connection id generator of a new endpoint with connection ids of a certain length.
+///
+/// `use_callback` selects whether the generator callback fills in the connection ids, `None` uses it if it is set.
+pub(crate) fn generator(
+    cid_len: usize,
+    use_callback: Option<bool>,
+) -> Box<dyn ConnectionIdGenerator> {
+    let use_callback = use_callback.unwrap_or_else(callbacks::has_cid_generator);
+
+    if use_callback || entropy::is_host_provided() {
+        Box::new(HostConnectionIdGenerator {
+            cid_len,
+            use_callback,
+        })
+    } else {
+        Box::new(RandomConnectionIdGenerator::new(cid_len))
+    }
+}
+
+/// Lets the client application fill in the connection ids, e.g. to encode routing information for a load balancer.
+///
+/// Without a generator callback the connection ids are random bytes of the entropy callback.
+struct HostConnectionIdGenerator {
+    cid_len: usize,
+    use_callback: bool,
+}
+
+impl ConnectionIdGenerator for HostConnectionIdGenerator {
+    fn generate_cid(&mut self) -> ConnectionId {
+        let mut bytes = [0; MAX_CID_LENGTH];
+        if !(self.use_callback && callbacks::on_generate_cid(&mut bytes[..self.cid_len])) {
+            // The callback is not set or was cleared, e.g. before the library is unloaded.
+            entropy::fill(&mut bytes[..self.cid_len]);
+        }
+
+        ConnectionId::new(&bytes[..self.cid_len])
+    }
+
+    fn cid_len(&self) -> usize {
+        self.cid_len
+    }
+
+    fn cid_lifetime(&self) -> Option<Duration> {
+        None
+    }
+}
@@ -0,0 +1,33 @@
+//! The time used by the protocol logic, which can be supplied by the client application.
+
+use crate::ffi::callbacks;
+use std::{
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// The first host timestamp in microseconds and the instant it was converted at.
+///
+/// Host timestamps are relative to this pair such that the host clock can have any origin.
+static EPOCH: Mutex<Option<(u64, Instant)>> = Mutex::new(None);
+
+/// Returns the current time of the clock callback, or of the system when the callback is not set.
+pub(crate) fn now() -> Instant {
+    match callbacks::clock_micros() {
+        Some(now_micros) => instant_from_micros(now_micros),
+        None => Instant::now(),
+    }
+}
+
+/// Converts a timestamp of a monotonic host clock, in microseconds, into an instant.
+///
+/// Timestamps before the first converted timestamp are clamped to it.
+pub(crate) fn instant_from_micros(now_micros: u64) -> Instant {
+    let mut epoch = EPOCH.lock().unwrap();
+    let (epoch_micros, epoch_instant) = *epoch.get_or_insert_with(|| (now_micros, Instant::now()));
+
+    epoch_instant + Duration::from_micros(now_micros.saturating_sub(epoch_micros))
+}
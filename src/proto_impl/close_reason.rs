@@ -0,0 +1,85 @@
+use crate::proto::ConnectionError;
+
+/// The transport error codes that carry a TLS alert in their lowest byte.
+const CRYPTO_ERROR_CODES: std::ops::RangeInclusive<u64> = 0x100..=0x1ff;
+
+/// The class of error that caused a connection to be lost.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConnectionErrorKind {
+    /// The peer doesn't implement any supported version.
+    VersionMismatch,
+    /// The peer violated the QUIC specification.
+    TransportError,
+    /// The peer's QUIC stack aborted the connection with a transport error code.
+    ConnectionClosed,
+    /// The peer application closed the connection with an application error code.
+    ApplicationClosed,
+    /// The peer is unable to continue processing this connection.
+    Reset,
+    /// Communication with the peer has lapsed for longer than the negotiated idle timeout.
+    TimedOut,
+    /// The local application closed the connection.
+    LocallyClosed,
+    /// The local TLS stack aborted the handshake, e.g. on a bad certificate of the peer.
+    ///
+    /// The code is the TLS alert sent to the peer, e.g. 42 for `bad_certificate`, and the reason describes the rustls error.
+    TlsAlertSent,
+    /// The peer's TLS stack aborted the handshake, e.g. on a protocol version or ALPN mismatch.
+    ///
+    /// The code is the TLS alert received from the peer, e.g. 70 for `protocol_version` or 120 for `no_application_protocol`.
+    TlsAlertReceived,
+}
+
+/// The reason a connection was lost.
+#[derive(Debug, Clone)]
+pub struct CloseReason {
+    /// The class of error.
+    pub kind: ConnectionErrorKind,
+    /// The transport or application error code or TLS alert, zero if the error class has no code.
+    pub code: u64,
+    /// The reason phrase, empty if the error class has no reason phrase.
+    pub reason: Vec<u8>,
+}
+
+impl From<&ConnectionError> for CloseReason {
+    fn from(error: &ConnectionError) -> Self {
+        let (kind, code, reason) = match error {
+            ConnectionError::VersionMismatch => (ConnectionErrorKind::VersionMismatch, 0, vec![]),
+            ConnectionError::TransportError(error) => {
+                let (kind, code) = match tls_alert(error.code.into()) {
+                    Some(alert) => (ConnectionErrorKind::TlsAlertSent, alert),
+                    None => (ConnectionErrorKind::TransportError, error.code.into()),
+                };
+                (kind, code, error.reason.as_bytes().to_vec())
+            }
+            ConnectionError::ConnectionClosed(close) => {
+                let (kind, code) = match tls_alert(close.error_code.into()) {
+                    Some(alert) => (ConnectionErrorKind::TlsAlertReceived, alert),
+                    None => (
+                        ConnectionErrorKind::ConnectionClosed,
+                        close.error_code.into(),
+                    ),
+                };
+                (kind, code, close.reason.to_vec())
+            }
+            ConnectionError::ApplicationClosed(close) => (
+                ConnectionErrorKind::ApplicationClosed,
+                close.error_code.into_inner(),
+                close.reason.to_vec(),
+            ),
+            ConnectionError::Reset => (ConnectionErrorKind::Reset, 0, vec![]),
+            ConnectionError::TimedOut => (ConnectionErrorKind::TimedOut, 0, vec![]),
+            ConnectionError::LocallyClosed => (ConnectionErrorKind::LocallyClosed, 0, vec![]),
+        };
+
+        CloseReason { kind, code, reason }
+    }
+}
+
+/// Returns the TLS alert of a crypto error code, `None` for other transport error codes.
+fn tls_alert(code: u64) -> Option<u64> {
+    CRYPTO_ERROR_CODES
+        .contains(&code)
+        .then(|| code - CRYPTO_ERROR_CODES.start())
+}
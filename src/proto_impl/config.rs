@@ -0,0 +1,386 @@
+use crate::{
+    ffi::callbacks,
+    proto,
+    proto_impl::{
+        transport_config,
+        validation,
+        validation::ConfigError,
+        FFIErrorKind,
+    },
+};
+use ring::hkdf;
+#[cfg(feature = "key-log")]
+use rustls::KeyLogFile;
+use rustls::{
+    client::ServerCertVerifier,
+    server::{
+        ClientHello,
+        ResolvesServerCert,
+    },
+    sign::CertifiedKey,
+    Certificate,
+    KeyLog,
+    NoKeyLog,
+    PrivateKey,
+    SupportedCipherSuite,
+    SupportedKxGroup,
+    SupportedProtocolVersion,
+    ALL_CIPHER_SUITES,
+    ALL_KX_GROUPS,
+    ALL_VERSIONS,
+    DEFAULT_CIPHER_SUITES,
+    DEFAULT_VERSIONS,
+};
+use std::sync::Arc;
+
+/// The minimum length in bytes of the secret retry tokens are derived from, the output length of HKDF-SHA256.
+pub(crate) const MIN_TOKEN_KEY_LENGTH: usize = 32;
+
+/// TLS parameters that rustls only accepts while building a configuration.
+#[derive(Clone)]
+pub struct TlsParameters {
+    cipher_suites: Vec<SupportedCipherSuite>,
+    kx_groups: Vec<&'static SupportedKxGroup>,
+    versions: Vec<&'static SupportedProtocolVersion>,
+}
+
+impl Default for TlsParameters {
+    /// The safe defaults of rustls.
+    fn default() -> Self {
+        TlsParameters {
+            cipher_suites: DEFAULT_CIPHER_SUITES.to_vec(),
+            kx_groups: ALL_KX_GROUPS.to_vec(),
+            versions: DEFAULT_VERSIONS.to_vec(),
+        }
+    }
+}
+
+impl TlsParameters {
+    /// Selects the cipher suites by their IANA identifiers, in order of preference.
+    pub fn set_cipher_suites(&mut self, ids: &[u16]) -> Result<(), FFIErrorKind> {
+        self.cipher_suites = select(ids, ALL_CIPHER_SUITES, |suite| suite.suite().get_u16())
+            .map_err(|id| {
+                FFIErrorKind::io_error(&format!("Unsupported cipher suite {:#06x}", id))
+            })?;
+        Ok(())
+    }
+
+    /// Selects the key exchange groups by their IANA identifiers, in order of preference.
+    pub fn set_kx_groups(&mut self, ids: &[u16]) -> Result<(), FFIErrorKind> {
+        self.kx_groups =
+            select(ids, &ALL_KX_GROUPS, |group| group.name.get_u16()).map_err(|id| {
+                FFIErrorKind::io_error(&format!("Unsupported key exchange group {:#06x}", id))
+            })?;
+        Ok(())
+    }
+
+    /// Selects the protocol versions by their IANA identifiers.
+    pub fn set_versions(&mut self, ids: &[u16]) -> Result<(), FFIErrorKind> {
+        self.versions =
+            select(ids, ALL_VERSIONS, |version| version.version.get_u16()).map_err(|id| {
+                FFIErrorKind::io_error(&format!("Unsupported TLS version {:#06x}", id))
+            })?;
+        Ok(())
+    }
+}
+
+/// Selects the supported items with the given identifiers, returns the first identifier that is not supported.
+fn select<T: Clone>(ids: &[u16], supported: &[T], id: impl Fn(&T) -> u16) -> Result<Vec<T>, u16> {
+    ids.iter()
+        .map(|&wanted| {
+            supported
+                .iter()
+                .find(|item| id(item) == wanted)
+                .cloned()
+                .ok_or(wanted)
+        })
+        .collect()
+}
+
+/// A client configuration that keeps its rustls configuration such that it can be changed after creation.
+pub struct ClientConfigImpl {
+    crypto: rustls::ClientConfig,
+    config: proto::ClientConfig,
+    tls: TlsParameters,
+    verifier: Arc<dyn ServerCertVerifier>,
+    // the client certificate chain and key, checked by `validate`.
+    certified_key: Option<(Vec<Certificate>, PrivateKey)>,
+    // the problem of the transport settings at the time of creation.
+    transport_error: Option<ConfigError>,
+}
+
+impl ClientConfigImpl {
+    /// Creates a configuration from a rustls configuration built with the safe defaults and the given verifier.
+    ///
+    /// Uses the transport settings set at the time of creation.
+    pub fn new(crypto: rustls::ClientConfig, verifier: Arc<dyn ServerCertVerifier>) -> Self {
+        let mut config = proto::ClientConfig::new(Arc::new(crypto.clone()));
+        config.transport = transport_config::transport_config();
+        ClientConfigImpl {
+            crypto,
+            config,
+            tls: TlsParameters::default(),
+            verifier,
+            certified_key: None,
+            transport_error: transport_config::validate().err(),
+        }
+    }
+
+    /// Keeps the certificate chain and key the rustls configuration was built with, such that `validate` can check them.
+    pub fn with_certified_key(mut self, chain: Vec<Certificate>, key: PrivateKey) -> Self {
+        self.certified_key = Some((chain, key));
+        self
+    }
+
+    /// Returns the first problem of the certificate and key, the ALPN protocols or the transport settings.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some((chain, key)) = self.certified_key.as_ref() {
+            validation::check_certified_key(chain, key)?;
+        }
+        validation::check_alpn(&self.crypto.alpn_protocols)?;
+
+        match self.transport_error.as_ref() {
+            Some(e) => Err(ConfigError::new(e.issue, e.message.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the quinn configuration used for new connections.
+    pub fn config(&self) -> &proto::ClientConfig {
+        &self.config
+    }
+
+    /// Changes the rustls configuration, this only affects new connections.
+    pub fn update_crypto(&mut self, f: impl FnOnce(&mut rustls::ClientConfig)) {
+        f(&mut self.crypto);
+        self.config.crypto = Arc::new(self.crypto.clone());
+    }
+
+    /// Enables or disables logging of TLS secrets.
+    pub fn set_key_log_enabled(&mut self, enabled: bool) -> Result<(), FFIErrorKind> {
+        let key_log = key_log(enabled)?;
+        self.update_crypto(|crypto| crypto.key_log = key_log);
+        Ok(())
+    }
+
+    /// Changes the TLS parameters, this only affects new connections.
+    ///
+    /// Rebuilds the rustls configuration, keeping all other settings.
+    pub fn update_tls(
+        &mut self,
+        f: impl FnOnce(&mut TlsParameters) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let mut tls = self.tls.clone();
+        f(&mut tls)?;
+
+        let mut crypto = rustls::ClientConfig::builder()
+            .with_cipher_suites(&tls.cipher_suites)
+            .with_kx_groups(&tls.kx_groups)
+            .with_protocol_versions(&tls.versions)?
+            .with_custom_certificate_verifier(self.verifier.clone())
+            .with_no_client_auth();
+
+        crypto.alpn_protocols = self.crypto.alpn_protocols.clone();
+        crypto.session_storage = self.crypto.session_storage.clone();
+        crypto.max_fragment_size = self.crypto.max_fragment_size;
+        crypto.client_auth_cert_resolver = self.crypto.client_auth_cert_resolver.clone();
+        crypto.enable_tickets = self.crypto.enable_tickets;
+        crypto.enable_sni = self.crypto.enable_sni;
+        crypto.key_log = self.crypto.key_log.clone();
+        crypto.enable_early_data = self.crypto.enable_early_data;
+
+        self.tls = tls;
+        self.update_crypto(|old| *old = crypto);
+
+        Ok(())
+    }
+}
+
+/// A server configuration that keeps its rustls configuration such that it can be changed after creation.
+pub struct ServerConfigImpl {
+    crypto: rustls::ServerConfig,
+    config: proto::ServerConfig,
+    tls: TlsParameters,
+    // the certificate chain and key, checked by `validate`.
+    certified_key: Option<(Vec<Certificate>, PrivateKey)>,
+    // the problem of the transport settings at the time of creation.
+    transport_error: Option<ConfigError>,
+}
+
+impl ServerConfigImpl {
+    /// Creates a configuration from a rustls configuration built with the safe defaults and without client authentication.
+    ///
+    /// Uses the transport settings set at the time of creation.
+    pub fn new(crypto: rustls::ServerConfig) -> Self {
+        let mut config = proto::ServerConfig::with_crypto(Arc::new(crypto.clone()));
+        config.transport = transport_config::transport_config();
+        ServerConfigImpl {
+            crypto,
+            config,
+            tls: TlsParameters::default(),
+            certified_key: None,
+            transport_error: transport_config::validate().err(),
+        }
+    }
+
+    /// Keeps the certificate chain and key the rustls configuration was built with, such that `validate` can check them.
+    pub fn with_certified_key(mut self, chain: Vec<Certificate>, key: PrivateKey) -> Self {
+        self.certified_key = Some((chain, key));
+        self
+    }
+
+    /// Returns the first problem of the certificate and key, the ALPN protocols or the transport settings.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some((chain, key)) = self.certified_key.as_ref() {
+            validation::check_certified_key(chain, key)?;
+        }
+        validation::check_alpn(&self.crypto.alpn_protocols)?;
+
+        match self.transport_error.as_ref() {
+            Some(e) => Err(ConfigError::new(e.issue, e.message.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the quinn configuration used for incoming connections.
+    pub fn config(&self) -> &proto::ServerConfig {
+        &self.config
+    }
+
+    /// Returns the quinn configuration for incoming connections of the given endpoint.
+    ///
+    /// The certificate is selected by the SNI config selector callback if it is set, see `set_sni_config_selector`.
+    pub fn config_for_endpoint(&self, endpoint_id: u64) -> proto::ServerConfig {
+        let mut crypto = self.crypto.clone();
+        crypto.cert_resolver = Arc::new(SniResolver {
+            endpoint_id,
+            fallback: self.crypto.cert_resolver.clone(),
+        });
+
+        let mut config = self.config.clone();
+        config.crypto = Arc::new(crypto);
+        config
+    }
+
+    /// Returns the resolver that selects the certificate of this configuration.
+    pub fn cert_resolver(&self) -> Arc<dyn ResolvesServerCert> {
+        self.crypto.cert_resolver.clone()
+    }
+
+    /// Changes the rustls configuration, this only affects new connections.
+    pub fn update_crypto(&mut self, f: impl FnOnce(&mut rustls::ServerConfig)) {
+        f(&mut self.crypto);
+        self.config.crypto = Arc::new(self.crypto.clone());
+    }
+
+    /// Enables or disables logging of TLS secrets.
+    pub fn set_key_log_enabled(&mut self, enabled: bool) -> Result<(), FFIErrorKind> {
+        let key_log = key_log(enabled)?;
+        self.update_crypto(|crypto| crypto.key_log = key_log);
+        Ok(())
+    }
+
+    /// Sets whether new connections must first validate their address with a retry token.
+    pub fn set_retry_enabled(&mut self, enabled: bool) {
+        self.config.use_retry(enabled);
+    }
+
+    /// Derives the key of retry tokens from the secret, such that a restarted server accepts the tokens of its previous incarnation.
+    pub fn set_token_key(&mut self, secret: &[u8]) {
+        let key = hkdf::Salt::new(hkdf::HKDF_SHA256, &[]).extract(secret);
+        self.config
+            .token_key(Arc::new(key))
+            .expect("quinn accepts any token key");
+    }
+
+    /// Sets whether clients may migrate to new addresses, this only affects new connections.
+    pub fn set_migration_enabled(&mut self, enabled: bool) {
+        self.config.migration(enabled);
+    }
+
+    /// Changes the TLS parameters, this only affects new connections.
+    ///
+    /// Rebuilds the rustls configuration, keeping all other settings.
+    pub fn update_tls(
+        &mut self,
+        f: impl FnOnce(&mut TlsParameters) -> Result<(), FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let mut tls = self.tls.clone();
+        f(&mut tls)?;
+
+        let mut crypto = rustls::ServerConfig::builder()
+            .with_cipher_suites(&tls.cipher_suites)
+            .with_kx_groups(&tls.kx_groups)
+            .with_protocol_versions(&tls.versions)?
+            .with_no_client_auth()
+            .with_cert_resolver(self.crypto.cert_resolver.clone());
+
+        crypto.ignore_client_order = self.crypto.ignore_client_order;
+        crypto.max_fragment_size = self.crypto.max_fragment_size;
+        crypto.session_storage = self.crypto.session_storage.clone();
+        crypto.ticketer = self.crypto.ticketer.clone();
+        crypto.alpn_protocols = self.crypto.alpn_protocols.clone();
+        crypto.key_log = self.crypto.key_log.clone();
+        crypto.max_early_data_size = self.crypto.max_early_data_size;
+
+        self.tls = tls;
+        self.update_crypto(|old| *old = crypto);
+
+        Ok(())
+    }
+}
+
+/// Resolves the certificate of the configuration selected by the SNI config selector callback, if any.
+struct SniResolver {
+    endpoint_id: u64,
+    // the resolver of the endpoint's configuration, used if no configuration is selected.
+    fallback: Arc<dyn ResolvesServerCert>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let selected = client_hello
+            .server_name()
+            .and_then(|server_name| callbacks::select_sni_config(self.endpoint_id, server_name));
+
+        match selected {
+            Some(resolver) => resolver.resolve(client_hello),
+            None => self.fallback.resolve(client_hello),
+        }
+    }
+}
+
+fn key_log(enabled: bool) -> Result<Arc<dyn KeyLog>, FFIErrorKind> {
+    match enabled {
+        #[cfg(feature = "key-log")]
+        true => Ok(Arc::new(HostKeyLog(KeyLogFile::new()))),
+        #[cfg(not(feature = "key-log"))]
+        true => Err(FFIErrorKind::unsupported("key-log")),
+        false => Ok(Arc::new(NoKeyLog)),
+    }
+}
+
+/// Loads the DER encoded root certificates of the platform.
+#[cfg(feature = "native-roots")]
+pub(crate) fn native_root_certs() -> Result<Vec<Vec<u8>>, FFIErrorKind> {
+    let native_certs = rustls_native_certs::load_native_certs()?;
+    Ok(native_certs.into_iter().map(|cert| cert.0).collect())
+}
+
+#[cfg(not(feature = "native-roots"))]
+pub(crate) fn native_root_certs() -> Result<Vec<Vec<u8>>, FFIErrorKind> {
+    Err(FFIErrorKind::unsupported("native-roots"))
+}
+
+/// Passes TLS secrets to the key log callback, or to the file at `SSLKEYLOGFILE` when no callback is set.
+#[cfg(feature = "key-log")]
+struct HostKeyLog(KeyLogFile);
+
+#[cfg(feature = "key-log")]
+impl KeyLog for HostKeyLog {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        if !callbacks::on_key_log(label, client_random, secret) {
+            self.0.log(label, client_random, secret);
+        }
+    }
+}
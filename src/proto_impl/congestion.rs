@@ -0,0 +1,49 @@
+use quinn_proto::ConnectionStats;
+
+/// The kind of a congestion event, which reduced the congestion window.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CongestionEventKind {
+    /// Packets were lost or marked as congestion experienced by ECN.
+    Congestion,
+    /// Packets were lost over a longer period, the window collapsed to its minimum.
+    PersistentCongestion,
+}
+
+/// Detects reductions of the congestion window between polls of a connection.
+///
+/// quinn-proto only counts congestion events, it does not tell loss and ECN marks apart.
+#[derive(Debug)]
+pub(crate) struct CongestionMonitor {
+    congestion_events: u64,
+    window: u64,
+}
+
+impl CongestionMonitor {
+    pub(crate) fn new(window: u64) -> Self {
+        CongestionMonitor {
+            congestion_events: 0,
+            window,
+        }
+    }
+
+    /// Returns the kind of congestion event if the window was reduced since the last update.
+    ///
+    /// Events during a recovery period don't reduce the window and are not reported.
+    pub(crate) fn update(&mut self, stats: &ConnectionStats) -> Option<CongestionEventKind> {
+        let previous_window = std::mem::replace(&mut self.window, stats.path.cwnd);
+        let congested = stats.path.congestion_events > self.congestion_events;
+        self.congestion_events = stats.path.congestion_events;
+
+        if !congested || stats.path.cwnd >= previous_window {
+            return None;
+        }
+
+        // Cubic reduces the window to 7/10 on congestion, only persistent congestion reduces it further.
+        if stats.path.cwnd * 10 < previous_window * 7 {
+            Some(CongestionEventKind::PersistentCongestion)
+        } else {
+            Some(CongestionEventKind::Congestion)
+        }
+    }
+}
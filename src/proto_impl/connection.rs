@@ -1,22 +1,94 @@
 use crate::{
-    ffi::callbacks,
+    ffi::{
+        callbacks,
+        FFIResultKind,
+    },
     proto,
-    proto::VarInt,
+    proto::{
+        Dir,
+        StreamId,
+        VarInt,
+    },
     proto_impl::{
-        endpoint::EndpointEvent,
+        app_events,
+        app_events::{
+            AppEvent,
+            AppEventKind,
+        },
+        auth_token,
+        auth_token::AuthGate,
+        clock,
+        congestion::CongestionMonitor,
+        connection_sender::{
+            ConnectionSender,
+            PollRequest,
+            QueuedSend,
+            SendFailures,
+            SEND_QUEUE_CAPACITY,
+        },
+        diagnostics::{
+            DiagnosticsEvent,
+            DiagnosticsEventKind,
+            DiagnosticsRing,
+        },
+        endpoint::{
+            EndpointEvent,
+            PollNotifier,
+        },
+        file_transfer::{
+            FileTransfer,
+            TransferState,
+        },
+        framing::FramedStream,
+        qlog::QlogWriter,
         result::FFIErrorKind,
+        send_rate::SendRateLimiter,
+        state_watch::StateWatch,
+        CloseReason,
+        ConnectionErrorKind,
+        StreamScheduling,
+        StreamStats,
     },
 };
-use bytes::Bytes;
-use quinn_proto::StreamEvent;
+use bytes::{
+    Buf,
+    Bytes,
+};
+use quinn_proto::{
+    crypto::rustls::HandshakeData,
+    SendDatagramError,
+    StreamEvent,
+    WriteError,
+};
 use std::{
+    collections::{
+        BTreeSet,
+        HashMap,
+        VecDeque,
+    },
+    fs::File,
+    io,
+    mem::MaybeUninit,
+    net::SocketAddr,
+    path::Path,
     sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
         mpsc,
         mpsc::Sender,
+        Arc,
+    },
+    time::{
+        Duration,
+        Instant,
     },
-    time::Instant,
 };
 
+/// The longest close reason that fits a packet of the minimum size with the frame and the largest headers.
+const MAX_APPLICATION_CLOSE_REASON: usize = 1024;
+
 /// Events for the connection.
 #[derive(Debug)]
 pub enum ConnectionEvent {
@@ -28,33 +100,246 @@ pub enum ConnectionEvent {
     Ping,
 }
 
+/// The lifecycle state of a connection.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The handshake did not complete yet, streams can't be used before it does.
+    Handshaking,
+    /// The handshake completed, the connection is usable.
+    Established,
+    /// The connection was closed locally or by an error, the close is retransmitted until the connection is drained.
+    Closing,
+    /// The peer closed or reset the connection, packets still in flight are absorbed until the connection is drained.
+    Draining,
+    /// The connection is drained and can be freed.
+    Closed,
+}
+
+/// A connection of an endpoint and its state, listed by `endpoint_list_connections`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ConnectionInfo {
+    /// The id passed to the connection callbacks.
+    pub connection_id: u32,
+    /// The state of the connection at the time of the call.
+    pub state: ConnectionState,
+}
+
 /// A QUIC connection using quinn-proto.
 pub struct ConnectionImpl {
     pub(crate) inner: proto::Connection,
     pub(crate) connection_handle: proto::ConnectionHandle,
+    version: u32,
+    remote_address: SocketAddr,
     connection_events: mpsc::Receiver<ConnectionEvent>,
     endpoint_events: Sender<(proto::ConnectionHandle, EndpointEvent)>,
     timer_deadline: Option<Instant>,
     last_poll: Instant,
-    endpoint_poll_notifier: Option<Sender<i8>>,
+    endpoint_poll_notifier: Option<PollNotifier>,
+    close_reason: Option<CloseReason>,
+    qlog: Option<QlogWriter>,
+    // detects reductions of the congestion window to invoke `on_congestion_event`.
+    congestion: CongestionMonitor,
+    // events for the client application to poll, `None` if callbacks are invoked instead.
+    app_events: Option<VecDeque<AppEvent>>,
+    // directions, indexed by `Dir`, in which opening a stream failed since the last `on_stream_available`.
+    exhausted_streams: [bool; 2],
+    // number of streams opened by this side, indexed by `Dir`.
+    opened_streams: [u64; 2],
+    // file transfers by the stream they use, their stream events are not passed to the client application.
+    file_transfers: HashMap<StreamId, FileTransfer>,
+    // streams in framing mode, their readable events are replaced by message events.
+    framed_streams: HashMap<StreamId, FramedStream>,
+    // tags of the client application by stream, passed to the `_v2` stream callbacks.
+    stream_user_data: HashMap<StreamId, StreamTag>,
+    // buffers of the client application not yet written to their stream, the stream is reported writable once they are.
+    owned_writes: HashMap<StreamId, VecDeque<Bytes>>,
+    // whether `on_connection_pollable` is only invoked again after the connection was polled.
+    coalesce_pollable: bool,
+    // whether `on_connection_pollable` was invoked since the last poll.
+    pollable_signaled: bool,
+    // datagrams with the deadline they must be sent by, handed to quinn one at a time.
+    expiring_datagrams: VecDeque<(Bytes, Instant)>,
+    // the expiring datagram quinn may still queue, `None` once it was sent or expired.
+    handed_expiring: Option<HandedDatagram>,
+    // datagrams of `ConnectionSender`s handed to quinn that were not seen sent yet, quinn sends datagrams in order.
+    unsent_datagrams: u64,
+    // the DATAGRAM frames sent as of the last check of the expiring datagrams.
+    seen_datagram_frames: u64,
+    // the state at the last poll, transitions are recorded in the diagnostics ring.
+    state: ConnectionState,
+    // the recent events of the connection, `None` unless diagnostics are enabled.
+    diagnostics: Option<Box<DiagnosticsRing>>,
+    // stops streams that stay readable without being read, `None` if streams are never stopped.
+    orphan_policy: Option<OrphanPolicy>,
+    // readable streams by when they became readable without being read since.
+    unread_streams: HashMap<StreamId, Instant>,
+    // whether stream data read and written by the client application is passed to the stream data tap.
+    tap_stream_data: bool,
+    // streams of the peer announced by `on_stream_opened`, until they are claimed, accepted or read.
+    unclaimed_streams: BTreeSet<StreamId>,
+    // the state at the last poll, shared with threads blocked in `wait_connected` or `wait_closed`.
+    state_watch: StateWatch,
+    // the largest datagram at the last poll, changes are reported with `on_datagram_size_changed`.
+    max_datagram_size: u64,
+    // bytes written to and read from each stream used by this connection, kept until the connection is freed.
+    stream_stats: HashMap<StreamId, StreamStats>,
+    // stream writes and datagrams of `ConnectionSender`s, applied when the connection is polled.
+    queued_sends_tx: mpsc::SyncSender<(QueuedSend, SendFailures)>,
+    queued_sends: mpsc::Receiver<(QueuedSend, SendFailures)>,
+    // whether a sender or a file transfer worker requested a poll since the connection was last polled.
+    poll_requested: Arc<AtomicBool>,
+    // caps the payload bytes per second written to streams and datagrams, `None` if only the pacer limits the rate.
+    send_rate: Option<SendRateLimiter>,
+    // streams a write to was refused by the send rate cap, reported writable once it admits payload again.
+    rate_blocked_streams: BTreeSet<StreamId>,
+    // the token a client sends once its handshake completes, see `connection_set_auth_token`.
+    auth_token: Option<Bytes>,
+    // withholds a server connection from the client application until the token of the client is accepted.
+    auth_gate: Option<AuthGate>,
+    // how send capacity is shared between streams, priorities are only passed to quinn with strict priority.
+    stream_scheduling: StreamScheduling,
+    // priorities set by the client application other than 0, kept while round-robin scheduling ignores them.
+    stream_priorities: HashMap<StreamId, i32>,
+    // set by the endpoint once it removed the drained connection, FFI calls on the connection then fail.
+    freed: Arc<AtomicBool>,
+    // the end of streams reached by a read that returned data, reported by the next read of the stream.
+    pending_stream_ends: HashMap<StreamId, RecvStreamEnd>,
+    // the chunks read by a length query of `read_stream`, read before the data quinn still buffers.
+    retained_chunks: HashMap<StreamId, Bytes>,
+    // when the connection is closed if its handshake did not complete, `None` without a handshake timeout.
+    handshake_deadline: Option<Instant>,
+    // when a packet was last received or sent, see `connection_last_activity`.
+    last_activity: Instant,
+    // datagrams received and sent at the last poll, a change is recorded as activity.
+    activity_datagrams: u64,
+}
+
+/// How a receive stream ended, reported by reads once all data before the end was read.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum RecvStreamEnd {
+    /// The peer finished the stream.
+    Finished,
+    /// The peer reset the stream with the error code.
+    Reset(u64),
+}
+
+impl From<RecvStreamEnd> for FFIErrorKind {
+    fn from(end: RecvStreamEnd) -> Self {
+        match end {
+            RecvStreamEnd::Finished => FFIErrorKind::FFIResultKind(FFIResultKind::StreamFinished),
+            RecvStreamEnd::Reset(_) => FFIErrorKind::FFIResultKind(FFIResultKind::StreamReset),
+        }
+    }
+}
+
+/// Whether the data passed to the stream data tap was received or sent.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StreamDataDirection {
+    Received,
+    Sent,
+}
+
+/// Stops streams the client application does not read, such that their data does not hold flow control credit forever.
+#[derive(Debug, Copy, Clone)]
+struct OrphanPolicy {
+    timeout: Duration,
+    error_code: VarInt,
+}
+
+/// An expiring datagram handed to quinn, which can't drop it once it is queued.
+#[derive(Debug, Copy, Clone)]
+struct HandedDatagram {
+    deadline: Instant,
+    // the number of DATAGRAM frames sent once the datagrams queued before it and it were sent.
+    sent_frames: u64,
+}
+
+/// The tag of a stream set by the client application, removed once the halves of the stream this side uses are closed.
+#[derive(Debug, Copy, Clone)]
+struct StreamTag {
+    user_data: u64,
+    send_open: bool,
+    recv_open: bool,
+}
+
+/// Kept by the endpoint to mark the connection freed once it removed it, without locking the connection.
+pub(crate) struct FreedSignal {
+    freed: Arc<AtomicBool>,
+    state_watch: StateWatch,
+}
+
+impl FreedSignal {
+    /// Sets the freed flag and wakes up the threads waiting for a state, a freed connection counts as closed.
+    pub(crate) fn signal(self) {
+        self.freed.store(true, Ordering::SeqCst);
+        self.state_watch.set(ConnectionState::Closed);
+    }
 }
 
 impl ConnectionImpl {
     pub(crate) fn new(
         inner: proto::Connection,
         handle: proto::ConnectionHandle,
+        version: u32,
         recv: mpsc::Receiver<ConnectionEvent>,
         endpoint_events_tx: Sender<(proto::ConnectionHandle, EndpointEvent)>,
-        endpoint_poll_notifier: Option<Sender<i8>>,
+        endpoint_poll_notifier: Option<PollNotifier>,
     ) -> ConnectionImpl {
+        let (queued_sends_tx, queued_sends) = mpsc::sync_channel(SEND_QUEUE_CAPACITY);
+
         ConnectionImpl {
+            remote_address: inner.remote_address(),
+            congestion: CongestionMonitor::new(inner.congestion_state().window()),
             inner,
             connection_events: recv,
             endpoint_events: endpoint_events_tx,
             connection_handle: handle,
+            version,
             timer_deadline: None,
-            last_poll: Instant::now(),
+            last_poll: clock::now(),
             endpoint_poll_notifier,
+            close_reason: None,
+            qlog: None,
+            app_events: app_events::event_polling_enabled().then(VecDeque::new),
+            exhausted_streams: [false; 2],
+            opened_streams: [0; 2],
+            file_transfers: HashMap::new(),
+            framed_streams: HashMap::new(),
+            owned_writes: HashMap::new(),
+            stream_user_data: HashMap::new(),
+            coalesce_pollable: false,
+            pollable_signaled: false,
+            expiring_datagrams: VecDeque::new(),
+            handed_expiring: None,
+            unsent_datagrams: 0,
+            seen_datagram_frames: 0,
+            state: ConnectionState::Handshaking,
+            diagnostics: None,
+            orphan_policy: None,
+            unread_streams: HashMap::new(),
+            tap_stream_data: false,
+            unclaimed_streams: BTreeSet::new(),
+            state_watch: StateWatch::new(ConnectionState::Handshaking),
+            max_datagram_size: 0,
+            stream_stats: HashMap::new(),
+            queued_sends_tx,
+            queued_sends,
+            poll_requested: Arc::new(AtomicBool::new(false)),
+            send_rate: None,
+            rate_blocked_streams: BTreeSet::new(),
+            auth_token: None,
+            auth_gate: None,
+            stream_scheduling: StreamScheduling::StrictPriority,
+            stream_priorities: HashMap::new(),
+            freed: Arc::new(AtomicBool::new(false)),
+            pending_stream_ends: HashMap::new(),
+            retained_chunks: HashMap::new(),
+            handshake_deadline: None,
+            last_activity: clock::now(),
+            activity_datagrams: 0,
         }
     }
 }
@@ -70,12 +355,40 @@ impl ConnectionImpl {
     ///
     /// Polling the connection might result in callbacks to the client application.
     pub fn poll(&mut self) -> Result<(), FFIErrorKind> {
-        let _ = self.handle_connection_events();
+        self.poll_at(clock::now())
+    }
+
+    /// Polls the connection at the given time.
+    ///
+    /// See [`poll()`] for details.
+    ///
+    /// [`poll()`]: ConnectionImpl::poll
+    pub fn poll_at(&mut self, now: Instant) -> Result<(), FFIErrorKind> {
+        self.pollable_signaled = false;
+        // Cleared before applying the requests, such that requests meanwhile request another poll.
+        self.poll_requested.store(false, Ordering::SeqCst);
+        self.handle_connection_events();
+        self.handle_queued_sends();
+        self.advance_file_transfers();
 
-        let mut poll_again = self.handle_timer();
+        let mut poll_again = self.handle_timer(now);
+        self.handle_handshake_timeout(now);
         let _ = self.handle_endpoint_events();
         self.handle_app_events();
-        poll_again |= self.handle_transmits()?;
+        self.stop_orphaned_streams(now);
+        self.handle_migration();
+        poll_again |= self.handle_transmits(now)?;
+
+        self.record_state_transition();
+        self.handle_datagram_size();
+
+        let stats = self.inner.stats();
+        self.record_activity(&stats, now);
+        self.handle_congestion(&stats);
+        if let Some(qlog) = self.qlog.as_mut() {
+            qlog.update_metrics(&stats);
+            qlog.flush();
+        }
 
         Ok(())
     }
@@ -84,24 +397,829 @@ impl ConnectionImpl {
     /// Connection should be polled when IO operations are performed, and timeout happened.
     ///
     /// This will poll the connection if `auto-poll` feature is enabled, else it will invoke the client application set callback.
+    /// With pollable coalescing the callback is skipped until the connection was polled.
+    /// Connections of manually polled endpoints are left to `poll_endpoint`.
     pub fn mark_pollable(&mut self) -> Result<(), FFIErrorKind> {
         if cfg!(feature = "auto-poll") {
-            self.poll()?;
-            // is initialized when auto-poll is enabled.
-            self.endpoint_poll_notifier.as_ref().unwrap().send(0)?;
-        } else {
+            // is only `None` when the endpoint is polled manually.
+            if let Some(notifier) = self.endpoint_poll_notifier.clone() {
+                if !notifier.notify_connection(self.connection_handle)? {
+                    self.poll()?;
+                    notifier.notify()?;
+                }
+            }
+        } else if !self.pollable_signaled {
+            self.pollable_signaled = self.coalesce_pollable;
             callbacks::on_connection_pollable(self.connection_id())
         }
 
         Ok(())
     }
 
+    /// Returns whether the endpoint removed the connection, see `on_connection_freed`.
+    pub fn is_freed(&self) -> bool {
+        self.freed.load(Ordering::SeqCst)
+    }
+
+    /// Returns an `UnknownConnection` error once the endpoint removed the connection.
+    pub(crate) fn ensure_not_freed(&self) -> Result<(), FFIErrorKind> {
+        if self.is_freed() {
+            return Err(FFIErrorKind::FFIResultKind(
+                FFIResultKind::UnknownConnection,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the signal the endpoint raises once it removed the connection.
+    pub(crate) fn freed_signal(&self) -> FreedSignal {
+        FreedSignal {
+            freed: self.freed.clone(),
+            state_watch: self.state_watch.clone(),
+        }
+    }
+
+    /// Returns whether a `ConnectionSender` or a file transfer worker requested a poll of the connection.
+    pub fn poll_requested(&self) -> bool {
+        self.poll_requested.load(Ordering::SeqCst)
+    }
+
+    /// Returns a producer for the send queue of this connection, see `connection_sender_handle`.
+    pub fn sender(&self) -> ConnectionSender {
+        ConnectionSender::new(self.queued_sends_tx.clone(), self.poll_request())
+    }
+
+    /// Lets another thread request a poll of this connection.
+    fn poll_request(&self) -> PollRequest {
+        PollRequest::new(
+            self.connection_handle,
+            self.endpoint_poll_notifier.clone(),
+            self.poll_requested.clone(),
+        )
+    }
+
+    /// Returns the id and state of this connection.
+    pub fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            connection_id: self.connection_id(),
+            state: self.state(),
+        }
+    }
+
+    /// Sets whether `on_connection_pollable` is invoked once until the connection is polled, rather than on every change.
+    pub fn set_pollable_coalescing(&mut self, enabled: bool) {
+        self.coalesce_pollable = enabled;
+        self.pollable_signaled = false;
+    }
+
+    /// Sets whether the events of the new connection are queued rather than invoking callbacks.
+    pub(crate) fn set_event_polling_enabled(&mut self, enabled: bool) {
+        self.app_events = enabled.then(VecDeque::new);
+    }
+
+    /// Starts writing qlog events of this connection to the file at the given path.
+    pub fn enable_qlog(&mut self, path: &Path) -> io::Result<()> {
+        self.qlog = Some(QlogWriter::create(path, self.inner.side())?);
+        Ok(())
+    }
+
+    /// Opens a stream in the given direction, `None` if the streams in that direction are exhausted.
+    ///
+    /// After a failed attempt `on_stream_available` is invoked once streams can be opened again.
+    pub fn open_stream(&mut self, dir: Dir) -> Option<StreamId> {
+        let stream_id = self.inner.streams().open(dir);
+
+        match stream_id {
+            Some(_) => self.opened_streams[dir as usize] += 1,
+            None => self.exhausted_streams[dir as usize] = true,
+        }
+
+        stream_id
+    }
+
+    /// Returns the number of streams this side opened in the given direction over the lifetime of the connection.
+    ///
+    /// Stream limits count the streams ever opened, so this can be compared against the limit granted by the peer.
+    pub fn opened_streams(&self, dir: Dir) -> u64 {
+        self.opened_streams[dir as usize]
+    }
+
+    /// Starts a file transfer over the stream, which then moves data whenever the stream is writable or readable.
+    ///
+    /// The file is sent if `send` is set, otherwise the stream is received into it.
+    pub(crate) fn start_file_transfer(
+        &mut self,
+        id: StreamId,
+        file: File,
+        send: bool,
+    ) -> Result<(), FFIErrorKind> {
+        self.ensure_alive()?;
+
+        let local = id.initiator() == self.inner.side();
+        if id.dir() == Dir::Uni && local != send {
+            return Err(FFIErrorKind::io_error(
+                "The unidirectional stream can't be used in this direction",
+            ));
+        }
+        if self.file_transfers.contains_key(&id) {
+            return Err(FFIErrorKind::io_error(
+                "A file transfer is already running on the stream",
+            ));
+        }
+
+        let transfer = match send {
+            true => FileTransfer::send(file, self.poll_request())?,
+            false => FileTransfer::receive(file, self.poll_request())?,
+        };
+
+        // The library reads the stream, it is never orphaned.
+        self.unread_streams.remove(&id);
+        self.file_transfers.insert(id, transfer);
+        self.advance_file_transfer(id);
+        self.mark_pollable()
+    }
+
+    /// Enables or disables framing mode of the stream, disabling fails while messages are buffered.
+    pub(crate) fn set_stream_framed(
+        &mut self,
+        id: StreamId,
+        framed: bool,
+    ) -> Result<(), FFIErrorKind> {
+        self.ensure_alive()?;
+
+        if framed {
+            if self.framed_streams.contains_key(&id) {
+                return Ok(());
+            }
+
+            self.unread_streams.remove(&id);
+            self.framed_streams.insert(id, FramedStream::default());
+            // Data received before framing mode was enabled is read as messages too.
+            if id.dir() == Dir::Bi || id.initiator() != self.inner.side() {
+                self.receive_messages(id);
+            }
+        } else if let Some(stream) = self.framed_streams.get(&id) {
+            if !stream.is_idle() {
+                return Err(FFIErrorKind::io_error("The stream has buffered messages"));
+            }
+
+            self.framed_streams.remove(&id);
+        }
+
+        Ok(())
+    }
+
+    /// Writes a message to the stream in framing mode.
+    pub(crate) fn write_message(
+        &mut self,
+        id: StreamId,
+        message: &[u8],
+    ) -> Result<(), FFIErrorKind> {
+        self.ensure_alive()?;
+
+        if id.dir() == Dir::Uni && id.initiator() != self.inner.side() {
+            return Err(FFIErrorKind::io_error(
+                "The unidirectional stream can't be written to",
+            ));
+        }
+
+        let budget = self.send_budget();
+        let stream = self
+            .framed_streams
+            .get_mut(&id)
+            .ok_or_else(|| FFIErrorKind::io_error("The stream is not in framing mode"))?;
+        let before = stream.sent();
+        let result = stream.write(&mut self.inner, id, message, budget);
+        let sent = stream.sent() - before;
+        let flushed = stream.is_flushed();
+
+        self.count_stream_data(id, StreamDataDirection::Sent, sent);
+        self.spend_stream_budget(id, budget, sent as usize, flushed);
+        result?;
+        self.mark_pollable()
+    }
+
+    /// Writes a buffer of the client application without copying it, the rest is written once flow control allows.
+    ///
+    /// The buffer is dropped once it is acknowledged, or once the stream or connection fails.
+    pub(crate) fn write_owned(&mut self, id: StreamId, buffer: Bytes) -> Result<(), FFIErrorKind> {
+        self.queue_owned_write(id, buffer)?;
+        self.mark_pollable()
+    }
+
+    /// Queues a buffer of the client application and writes as much as flow control allows, without requesting a poll.
+    fn queue_owned_write(&mut self, id: StreamId, buffer: Bytes) -> Result<(), FFIErrorKind> {
+        self.ensure_alive()?;
+
+        if id.dir() == Dir::Uni && id.initiator() != self.inner.side() {
+            return Err(FFIErrorKind::io_error(
+                "The unidirectional stream can't be written to",
+            ));
+        }
+
+        self.owned_writes.entry(id).or_default().push_back(buffer);
+        self.flush_owned_writes(id).map(|_| ())
+    }
+
+    /// Queues a datagram that is dropped if it can't be sent before the deadline.
+    ///
+    /// Quinn can't drop a datagram it queued, so datagrams are handed to it one at a time while it can send them.
+    pub(crate) fn send_expiring_datagram(
+        &mut self,
+        data: Bytes,
+        deadline: Instant,
+    ) -> Result<(), FFIErrorKind> {
+        self.ensure_alive()?;
+
+        // Quinn panics when asked for the size before it has the keys to send datagrams.
+        if self.inner.is_handshaking() {
+            return Err(FFIErrorKind::io_error(
+                "Datagrams can be sent once the handshake completed",
+            ));
+        }
+
+        match self.inner.datagrams().max_size() {
+            None => return Err(SendDatagramError::UnsupportedByPeer.into()),
+            Some(max_size) if data.len() > max_size => {
+                return Err(SendDatagramError::TooLarge.into())
+            }
+            Some(_) => {}
+        }
+
+        self.expiring_datagrams.push_back((data, deadline));
+        self.mark_pollable()
+    }
+
+    /// Hands the next datagram that did not expire to quinn, unless quinn still queues one.
+    ///
+    /// A handed datagram counts as sent once as many DATAGRAM frames were sent as datagrams were queued up to it.
+    /// Quinn drops the oldest datagrams once its send buffer is full, which the count does not see, so a handed datagram
+    /// also stops blocking the next one once its deadline passed.
+    fn queue_expiring_datagram(&mut self, now: Instant) {
+        let frames = self.inner.stats().frame_tx.datagram;
+        let sent = frames - self.seen_datagram_frames;
+        self.seen_datagram_frames = frames;
+        self.unsent_datagrams = self.unsent_datagrams.saturating_sub(sent);
+
+        if let Some(handed) = self.handed_expiring {
+            if frames < handed.sent_frames && now <= handed.deadline {
+                return;
+            }
+            self.handed_expiring = None;
+        }
+
+        while let Some((data, deadline)) = self.expiring_datagrams.pop_front() {
+            if deadline < now {
+                continue;
+            }
+            // Waits for the send rate cap, `timeout` includes when it admits the datagram.
+            if self.send_budget() < data.len() {
+                self.expiring_datagrams.push_front((data, deadline));
+                return;
+            }
+
+            let len = data.len();
+            match self.inner.datagrams().send(data) {
+                Ok(()) => {
+                    self.spend_send_budget(len);
+                    self.handed_expiring = Some(HandedDatagram {
+                        deadline,
+                        sent_frames: frames + self.unsent_datagrams + 1,
+                    });
+                    return;
+                }
+                Err(e) => tracing::warn!("Dropping an expiring datagram: {}", e),
+            }
+        }
+    }
+
+    /// Returns whether buffers written with `write_owned` are not yet written completely to the stream.
+    pub(crate) fn has_owned_writes(&self, id: StreamId) -> bool {
+        self.owned_writes.contains_key(&id)
+    }
+
+    /// Tags the stream with a value of the client application, 0 removes the tag.
+    ///
+    /// The tag is removed once the halves of the stream this side uses are closed.
+    pub(crate) fn set_stream_user_data(&mut self, id: StreamId, user_data: u64) {
+        if user_data == 0 {
+            self.stream_user_data.remove(&id);
+            return;
+        }
+
+        let local = id.initiator() == self.inner.side();
+        self.stream_user_data
+            .entry(id)
+            .or_insert(StreamTag {
+                user_data,
+                send_open: id.dir() == Dir::Bi || local,
+                recv_open: id.dir() == Dir::Bi || !local,
+            })
+            .user_data = user_data;
+    }
+
+    /// Marks the receiving half of the stream closed, as its end or reset was read or it was stopped.
+    pub(crate) fn stream_recv_closed(&mut self, id: StreamId) {
+        if let Some(tag) = self.stream_user_data.get_mut(&id) {
+            tag.recv_open = false;
+            if !tag.send_open {
+                self.stream_user_data.remove(&id);
+            }
+        }
+    }
+
+    /// Marks the sending half of the stream closed, as the peer received all data or stopped it.
+    fn stream_send_closed(&mut self, id: StreamId) {
+        if let Some(tag) = self.stream_user_data.get_mut(&id) {
+            tag.send_open = false;
+            if !tag.recv_open {
+                self.stream_user_data.remove(&id);
+            }
+        }
+    }
+
+    /// Sets the send priority of the stream, applied by quinn unless the scheduling is round-robin.
+    pub(crate) fn set_stream_priority(
+        &mut self,
+        id: StreamId,
+        priority: i32,
+    ) -> Result<(), FFIErrorKind> {
+        let applied = match self.stream_scheduling {
+            StreamScheduling::StrictPriority => priority,
+            StreamScheduling::RoundRobin => 0,
+        };
+        self.inner.send_stream(id).set_priority(applied)?;
+
+        if priority == 0 {
+            self.stream_priorities.remove(&id);
+        } else {
+            self.stream_priorities.insert(id, priority);
+        }
+        Ok(())
+    }
+
+    /// Changes how send capacity is shared between streams, also for streams that already have data queued.
+    pub(crate) fn set_stream_scheduling(&mut self, scheduling: StreamScheduling) {
+        self.stream_scheduling = scheduling;
+
+        let inner = &mut self.inner;
+        // Streams that were finished or reset since their priority was set are forgotten.
+        self.stream_priorities.retain(|id, priority| {
+            let applied = match scheduling {
+                StreamScheduling::StrictPriority => *priority,
+                StreamScheduling::RoundRobin => 0,
+            };
+            inner.send_stream(*id).set_priority(applied).is_ok()
+        });
+    }
+
+    /// Returns the tag of the stream, 0 if it is not tagged.
+    pub(crate) fn stream_user_data(&self, id: StreamId) -> u64 {
+        self.stream_user_data
+            .get(&id)
+            .map_or(0, |tag| tag.user_data)
+    }
+
+    /// Returns the stream in framing mode, to read its messages.
+    pub(crate) fn framed_stream(
+        &mut self,
+        id: StreamId,
+    ) -> Result<&mut FramedStream, FFIErrorKind> {
+        self.framed_streams
+            .get_mut(&id)
+            .ok_or_else(|| FFIErrorKind::io_error("The stream is not in framing mode"))
+    }
+
+    /// Moves queued events into `events`, returns the number of events moved.
+    ///
+    /// Returns 0 if event polling is not enabled for this connection.
+    pub fn drain_app_events(&mut self, events: &mut [MaybeUninit<AppEvent>]) -> usize {
+        let app_events = match self.app_events.as_mut() {
+            Some(app_events) => app_events,
+            None => return 0,
+        };
+
+        let count = events.len().min(app_events.len());
+        for (slot, event) in events.iter_mut().zip(app_events.drain(..count)) {
+            slot.write(event);
+        }
+
+        count
+    }
+
+    /// Returns the QUIC version used by this connection.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Returns the current estimate of the round trip time in microseconds.
+    pub fn rtt_micros(&self) -> u64 {
+        self.inner.rtt().as_micros() as u64
+    }
+
+    /// Returns the rate in bytes per second at which packets are paced.
+    ///
+    /// Derived like the quinn-proto pacer, which spreads 5/4 of the congestion window over one round trip.
+    pub fn pacing_rate(&self) -> u64 {
+        let window = self.inner.congestion_state().window() as u128;
+        let rtt_micros = self.inner.rtt().as_micros().max(1);
+
+        (window * 5 / 4 * 1_000_000 / rtt_micros).min(u64::MAX as u128) as u64
+    }
+
+    /// Returns the microseconds since a packet was last received or sent, or since the connection was created.
+    ///
+    /// Packets are counted when the connection is polled, the result lags behind by up to one poll.
+    pub fn last_activity_micros_ago(&self) -> u64 {
+        clock::now()
+            .saturating_duration_since(self.last_activity)
+            .as_micros() as u64
+    }
+
+    /// Returns when the connection timer, the orphan timeout of a stream, the wait for send rate, the deadline of an
+    /// expiring datagram quinn may still queue or the handshake timeout expires, `None` if no timer is armed.
+    pub fn timeout(&mut self) -> Option<Instant> {
+        let send_rate = self.send_rate_deadline();
+
+        // The next expiring datagram is handed to quinn at the latest once the one quinn may still queue expired.
+        let expiring = self
+            .handed_expiring
+            .filter(|_| !self.expiring_datagrams.is_empty())
+            .map(|handed| handed.deadline);
+
+        [
+            self.inner.poll_timeout(),
+            self.orphan_deadline(),
+            send_rate,
+            self.handshake_deadline,
+            expiring,
+        ]
+        .iter()
+        .flatten()
+        .min()
+        .copied()
+    }
+
+    /// Caps the payload bytes per second written to the streams and datagrams of the connection, zero removes the cap.
+    pub(crate) fn set_max_send_rate(&mut self, bytes_per_second: u64) {
+        self.send_rate =
+            (bytes_per_second > 0).then(|| SendRateLimiter::new(bytes_per_second, clock::now()));
+    }
+
+    /// Returns how many payload bytes the send rate cap admits now, `usize::MAX` without a cap.
+    fn send_budget(&mut self) -> usize {
+        match self.send_rate.as_mut() {
+            Some(send_rate) => send_rate.available(clock::now()),
+            None => usize::MAX,
+        }
+    }
+
+    /// Takes payload written to quinn from the send rate cap.
+    fn spend_send_budget(&mut self, len: usize) {
+        if let Some(send_rate) = self.send_rate.as_mut() {
+            send_rate.consume(len);
+        }
+    }
+
+    /// Returns when the send rate cap admits payload that is waiting for it, `None` if nothing waits.
+    fn send_rate_deadline(&self) -> Option<Instant> {
+        let send_rate = self.send_rate.as_ref()?;
+
+        let streams = (!self.rate_blocked_streams.is_empty()).then(|| send_rate.deadline(1));
+        let datagram = self
+            .expiring_datagrams
+            .front()
+            .map(|(data, _)| send_rate.deadline(data.len()));
+
+        streams.into_iter().chain(datagram).flatten().min()
+    }
+
+    /// Writes to a send stream as much as flow control and the send rate cap allow.
+    ///
+    /// A stream the cap refused is reported writable once the cap admits payload again.
+    pub(crate) fn write_stream_data(
+        &mut self,
+        id: StreamId,
+        data: &[u8],
+    ) -> Result<usize, WriteError> {
+        let budget = self.send_budget();
+        if budget == 0 && !data.is_empty() {
+            self.rate_blocked_streams.insert(id);
+            return Err(WriteError::Blocked);
+        }
+
+        let written = self
+            .inner
+            .send_stream(id)
+            .write(&data[..data.len().min(budget)])?;
+        self.spend_send_budget(written);
+        Ok(written)
+    }
+
+    /// Takes payload the library wrote to a stream on its own from the send rate cap.
+    ///
+    /// Remembers the stream if the cap stopped the write before it completed.
+    fn spend_stream_budget(&mut self, id: StreamId, budget: usize, sent: usize, completed: bool) {
+        self.spend_send_budget(sent);
+        if !completed && sent == budget {
+            self.rate_blocked_streams.insert(id);
+        }
+    }
+
+    /// Reports the streams the send rate cap refused writable, once it admits payload again.
+    fn unblock_rate_limited_streams(&mut self) {
+        if self.rate_blocked_streams.is_empty() || self.send_budget() == 0 {
+            return;
+        }
+
+        for id in std::mem::take(&mut self.rate_blocked_streams) {
+            self.handle_stream_writable(id);
+        }
+    }
+
+    /// Sets the token a client connection sends to the server once its handshake completes.
+    pub(crate) fn set_auth_token(&mut self, token: Bytes) -> Result<(), FFIErrorKind> {
+        if !self.inner.side().is_client() || !self.inner.is_handshaking() {
+            return Err(FFIErrorKind::io_error(
+                "The authentication token must be set on a client connection before its handshake completes",
+            ));
+        }
+
+        if token.len() > auth_token::MAX_AUTH_TOKEN_LEN {
+            return Err(FFIErrorKind::FFIResultKind(FFIResultKind::InvalidArgument));
+        }
+
+        self.auth_token = Some(token);
+        Ok(())
+    }
+
+    /// Closes the connection if its handshake did not complete within the timeout, from now on.
+    pub(crate) fn set_handshake_timeout(&mut self, timeout: Duration) {
+        self.handshake_deadline = Some(clock::now() + timeout);
+    }
+
+    /// Withholds the server connection and the streams of the peer until `on_auth_token` accepts the token of the client.
+    pub(crate) fn require_auth_token(&mut self, reject_code: VarInt) {
+        self.auth_gate = Some(AuthGate::new(reject_code));
+    }
+
+    /// Returns whether the connection timer expired at the given time, such that the connection should be polled.
+    pub fn timer_expired(&mut self, now: Instant) -> bool {
+        matches!(self.timeout(), Some(deadline) if deadline < now)
+    }
+
+    /// Sets after which time streams that stay readable without being read are stopped, zero never stops them.
+    pub(crate) fn set_stream_orphan_policy(&mut self, timeout: Duration, error_code: VarInt) {
+        self.orphan_policy = (timeout > Duration::ZERO).then_some(OrphanPolicy {
+            timeout,
+            error_code,
+        });
+        self.unread_streams.clear();
+    }
+
+    /// Sets whether the events of the connection are recorded, disabling discards the recorded events.
+    pub(crate) fn set_diagnostics_enabled(&mut self, enabled: bool) {
+        match (enabled, self.diagnostics.is_some()) {
+            (true, false) => self.diagnostics = Some(Box::new(DiagnosticsRing::new())),
+            (false, true) => self.diagnostics = None,
+            _ => {}
+        }
+    }
+
+    /// Returns the recorded events, oldest first, as two slices in order, `None` if diagnostics are disabled.
+    pub(crate) fn diagnostics(&self) -> Option<(&[DiagnosticsEvent], &[DiagnosticsEvent])> {
+        self.diagnostics.as_ref().map(|ring| ring.events())
+    }
+
+    /// Records an event in the diagnostics ring if diagnostics are enabled.
+    fn record_diagnostics(&mut self, kind: DiagnosticsEventKind, value: u64) {
+        if let Some(ring) = self.diagnostics.as_mut() {
+            ring.record(kind, value);
+        }
+    }
+
+    /// Sets whether stream data read and written by the client application is passed to the stream data tap.
+    pub(crate) fn set_stream_data_tap(&mut self, enabled: bool) {
+        self.tap_stream_data = enabled;
+    }
+
+    /// Counts stream data read or written by the client application, and passes it to the stream data tap if it is enabled.
+    pub(crate) fn record_stream_data(
+        &mut self,
+        id: StreamId,
+        direction: StreamDataDirection,
+        data: &[u8],
+    ) {
+        self.count_stream_data(id, direction, data.len() as u64);
+        self.tap_stream_data(id, direction, data);
+    }
+
+    /// Passes decrypted stream data to the stream data tap if it is enabled for this connection.
+    fn tap_stream_data(&self, id: StreamId, direction: StreamDataDirection, data: &[u8]) {
+        if self.tap_stream_data && !data.is_empty() {
+            callbacks::on_stream_data(
+                self.connection_id(),
+                VarInt::from(id).into_inner(),
+                direction,
+                data,
+            );
+        }
+    }
+
+    /// Returns the bytes written to and read from the stream, zero for streams that were not used.
+    pub(crate) fn stream_stats(&self, id: StreamId) -> StreamStats {
+        self.stream_stats.get(&id).copied().unwrap_or_default()
+    }
+
+    /// Adds bytes written to or read from the stream to its statistics.
+    fn count_stream_data(&mut self, id: StreamId, direction: StreamDataDirection, len: u64) {
+        if len == 0 {
+            return;
+        }
+
+        let stats = self.stream_stats.entry(id).or_default();
+        match direction {
+            StreamDataDirection::Received => stats.bytes_received += len,
+            StreamDataDirection::Sent => stats.bytes_sent += len,
+        }
+    }
+
+    /// Restarts the orphan timeout of the stream after a read that left data, a read of all available data stops
+    /// tracking the stream until it becomes readable again.
+    pub(crate) fn stream_read(&mut self, id: StreamId, drained: bool) {
+        if drained {
+            self.unread_streams.remove(&id);
+        } else if let Some(readable) = self.unread_streams.get_mut(&id) {
+            *readable = clock::now();
+        }
+        self.unclaimed_streams.remove(&id);
+    }
+
+    /// Keeps the end of the stream for the next read, as the read that reached it returned data.
+    pub(crate) fn defer_stream_end(&mut self, id: StreamId, end: RecvStreamEnd) {
+        self.pending_stream_ends.insert(id, end);
+    }
+
+    /// Takes the end of the stream a previous read reached.
+    pub(crate) fn take_stream_end(&mut self, id: StreamId) -> Option<RecvStreamEnd> {
+        self.pending_stream_ends.remove(&id)
+    }
+
+    /// Keeps a chunk read by a length query for the next read of the stream.
+    pub(crate) fn retain_chunk(&mut self, id: StreamId, chunk: Bytes) {
+        self.retained_chunks.insert(id, chunk);
+    }
+
+    /// Returns the length of the chunk kept by a length query of the stream.
+    pub(crate) fn retained_chunk_len(&self, id: StreamId) -> Option<usize> {
+        self.retained_chunks.get(&id).map(Bytes::len)
+    }
+
+    /// Takes the chunk kept by a length query of the stream.
+    pub(crate) fn take_retained_chunk(&mut self, id: StreamId) -> Option<Bytes> {
+        self.retained_chunks.remove(&id)
+    }
+
+    /// Returns the size of the largest datagram that can be sent, zero if datagrams can't be sent.
+    ///
+    /// The size depends on the path MTU, the connection id length and the limit of the peer, which are known after the handshake.
+    /// A connection that closed during the handshake never has them, quinn panics when asked for the size without 1-RTT keys.
+    pub(crate) fn max_datagram_size(&mut self) -> u64 {
+        if self.inner.is_handshaking() || self.inner.is_closed() {
+            return 0;
+        }
+
+        self.inner.datagrams().max_size().unwrap_or(0) as u64
+    }
+
+    /// Returns the state as of the last poll, which threads can wait on without locking the connection.
+    pub(crate) fn state_watch(&self) -> StateWatch {
+        self.state_watch.clone()
+    }
+
+    /// Claims a stream announced by `on_stream_opened`, fails if it is unknown or was already claimed.
+    pub(crate) fn claim_stream(&mut self, id: StreamId) -> Result<(), FFIErrorKind> {
+        if self.unclaimed_streams.remove(&id) {
+            Ok(())
+        } else {
+            Err(FFIErrorKind::FFIResultKind(FFIResultKind::UnknownStream))
+        }
+    }
+
+    /// Accepts the announced stream with the lowest id in the direction, or a stream quinn did not announce yet.
+    pub(crate) fn accept_stream(&mut self, dir: Dir) -> Option<StreamId> {
+        let announced = self
+            .unclaimed_streams
+            .iter()
+            .find(|id| id.dir() == dir)
+            .copied();
+
+        match announced {
+            Some(id) => {
+                self.unclaimed_streams.remove(&id);
+                Some(id)
+            }
+            None => self.inner.streams().accept(dir),
+        }
+    }
+
+    /// Returns how long until the closed connection is drained, zero once drained and `None` if it is not closed.
+    ///
+    /// A closed connection only runs its close timer, which drains it after three probe timeouts.
+    pub fn drain_remaining(&mut self, now: Instant) -> Option<Duration> {
+        if !self.inner.is_closed() {
+            return None;
+        }
+
+        match self.inner.poll_timeout() {
+            Some(deadline) if !self.inner.is_drained() => {
+                Some(deadline.saturating_duration_since(now))
+            }
+            _ => Some(Duration::ZERO),
+        }
+    }
+
+    /// Returns the server name and application protocol of the handshake, `None` before `HandshakeDataReady`.
+    pub fn handshake_data(&self) -> Option<HandshakeData> {
+        self.inner
+            .crypto_session()
+            .handshake_data()?
+            .downcast::<HandshakeData>()
+            .ok()
+            .map(|handshake_data| *handshake_data)
+    }
+
+    /// Returns the reason this connection was lost, `None` if the connection is not lost.
+    pub fn close_reason(&self) -> Option<&CloseReason> {
+        self.close_reason.as_ref()
+    }
+
+    /// Returns the lifecycle state of the connection.
+    pub fn state(&self) -> ConnectionState {
+        if self.inner.is_handshaking() {
+            return ConnectionState::Handshaking;
+        }
+
+        if !self.inner.is_closed() {
+            return ConnectionState::Established;
+        }
+
+        if self.inner.is_drained() {
+            return ConnectionState::Closed;
+        }
+
+        // quinn only drains without closing when the peer closed the connection.
+        match self.close_reason.as_ref().map(|reason| reason.kind) {
+            Some(ConnectionErrorKind::ConnectionClosed)
+            | Some(ConnectionErrorKind::ApplicationClosed)
+            | Some(ConnectionErrorKind::TlsAlertReceived)
+            | Some(ConnectionErrorKind::Reset) => ConnectionState::Draining,
+            _ => ConnectionState::Closing,
+        }
+    }
+
+    /// Returns a `ConnectionLost` error if the connection is lost.
+    pub fn ensure_alive(&self) -> Result<(), FFIErrorKind> {
+        match self.close_reason {
+            Some(_) => Err(FFIErrorKind::FFIResultKind(FFIResultKind::ConnectionLost)),
+            None => Ok(()),
+        }
+    }
+
+    /// Closes the connection with an APPLICATION_CLOSE frame, such that the peer receives the code and reason intact.
+    ///
+    /// Fails instead of closing while the handshake did not complete, as quinn then sends a transport error without them,
+    /// and if the reason could be truncated to fit a packet.
+    pub fn close_with_app_error(
+        &mut self,
+        error_code: VarInt,
+        reason: &[u8],
+    ) -> Result<(), FFIErrorKind> {
+        self.ensure_alive()?;
+
+        if self.inner.is_handshaking() {
+            return Err(FFIErrorKind::io_error(
+                "The handshake did not complete, the peer would receive a transport error without the code",
+            ));
+        }
+        if reason.len() > MAX_APPLICATION_CLOSE_REASON {
+            return Err(FFIErrorKind::io_error(&format!(
+                "The close reason exceeds {} bytes and could be truncated",
+                MAX_APPLICATION_CLOSE_REASON
+            )));
+        }
+
+        self.close(error_code, reason);
+        self.mark_pollable()
+    }
+
     pub fn close(&mut self, error_code: VarInt, reason: &[u8]) {
         self.inner
-            .close(Instant::now(), error_code, Bytes::copy_from_slice(reason));
+            .close(clock::now(), error_code, Bytes::copy_from_slice(reason));
     }
 
-    fn handle_timer(&mut self) -> bool {
+    fn handle_timer(&mut self, now: Instant) -> bool {
         match self.inner.poll_timeout() {
             Some(deadline) => {
                 self.timer_deadline = Some(deadline);
@@ -112,10 +1230,8 @@ impl ConnectionImpl {
             }
         }
 
-        let now = Instant::now();
-
         if now > self.timer_deadline.expect("timer deadline is initialized") {
-            self.inner.handle_timeout(Instant::now());
+            self.inner.handle_timeout(now);
             self.timer_deadline = None;
             return true;
         }
@@ -123,12 +1239,43 @@ impl ConnectionImpl {
         return false;
     }
 
-    fn handle_transmits(&mut self) -> Result<bool, FFIErrorKind> {
+    /// Closes the connection and invokes `on_handshake_timeout` if its handshake did not complete in time.
+    fn handle_handshake_timeout(&mut self, now: Instant) {
+        let deadline = match self.handshake_deadline {
+            Some(deadline) => deadline,
+            None => return,
+        };
+
+        if !self.inner.is_handshaking() || self.inner.is_closed() {
+            self.handshake_deadline = None;
+            return;
+        }
+
+        if now >= deadline {
+            self.handshake_deadline = None;
+            self.record_diagnostics(DiagnosticsEventKind::HandshakeTimedOut, 0);
+            self.inner.close(
+                now,
+                VarInt::from_u32(0),
+                Bytes::from_static(b"handshake timeout"),
+            );
+            callbacks::on_handshake_timeout(self.connection_id());
+        }
+    }
+
+    fn handle_transmits(&mut self, now: Instant) -> Result<bool, FFIErrorKind> {
         let mut should_notify = false;
-        while let Some(t) = self.inner.poll_transmit(Instant::now(), 1) {
+        self.unblock_rate_limited_streams();
+        self.queue_expiring_datagram(now);
+        while let Some(t) = self.inner.poll_transmit(now, 1) {
+            if let Some(qlog) = self.qlog.as_mut() {
+                qlog.datagram_sent(t.contents.len());
+            }
+
             self.endpoint_events
                 .send((self.connection_handle, EndpointEvent::Transmit(t)))?;
             should_notify = true;
+            self.queue_expiring_datagram(now);
             // TODO: when max transmits return true.
         }
 
@@ -140,80 +1287,595 @@ impl ConnectionImpl {
             self.endpoint_events
                 .send((self.connection_handle, EndpointEvent::Proto(event)))?;
 
-            if cfg!(feature = "auto-poll") {
-                self.endpoint_poll_notifier.as_ref().unwrap().send(0)?;
+            if let Some(notifier) = self.endpoint_poll_notifier.as_ref() {
+                notifier.notify()?;
             }
         }
         Ok(())
     }
-    fn handle_connection_events(&mut self) -> Result<(), FFIErrorKind> {
-        let event = self.connection_events.try_recv()?;
-
-        match event {
-            ConnectionEvent::Close { error_code, reason } => {}
-            ConnectionEvent::Proto(proto) => {
-                self.inner.handle_event(proto);
-            }
-            ConnectionEvent::Ping => {
-                self.inner.ping();
+    /// Handles all queued events, a batch of datagrams polls each of its connections once.
+    fn handle_connection_events(&mut self) {
+        while let Ok(event) = self.connection_events.try_recv() {
+            match event {
+                ConnectionEvent::Close { error_code, reason } => {
+                    self.close(error_code, &reason);
+                }
+                ConnectionEvent::Proto(proto) => {
+                    self.inner.handle_event(proto);
+                }
+                ConnectionEvent::Ping => {
+                    self.inner.ping();
+                }
             }
         }
+    }
 
-        Ok(())
+    /// Applies the stream writes and datagrams queued by `ConnectionSender`s.
+    ///
+    /// The sending threads already returned, failed sends are reported to the sender that queued them.
+    fn handle_queued_sends(&mut self) {
+        while let Ok((send, failures)) = self.queued_sends.try_recv() {
+            match send {
+                QueuedSend::Write { id, data } => {
+                    let len = data.len();
+                    if let Err(e) = self.queue_owned_write(id, data) {
+                        tracing::debug!(
+                            "Writing {} queued bytes on stream {} failed: {}",
+                            len,
+                            id,
+                            e
+                        );
+                        failures.report(Some(id), e);
+                    }
+                }
+                QueuedSend::Datagram(data) => {
+                    let len = data.len();
+                    if self.send_budget() < len {
+                        tracing::debug!("Dropping a queued datagram beyond the send rate cap");
+                        failures.report(
+                            None,
+                            FFIErrorKind::FFIResultKind(FFIResultKind::BufferBlocked),
+                        );
+                        continue;
+                    }
+
+                    match self.inner.datagrams().send(data) {
+                        Ok(()) => {
+                            self.spend_send_budget(len);
+                            self.unsent_datagrams += 1;
+                        }
+                        Err(e) => {
+                            tracing::debug!("Dropping a queued datagram: {}", e);
+                            failures.report(None, e.into());
+                        }
+                    }
+                }
+            }
+        }
     }
 
     fn handle_app_events(&mut self) {
         while let Some(event) = self.inner.poll() {
             use quinn_proto::Event::*;
+            if let Some(ring) = self.diagnostics.as_mut() {
+                ring.record_proto(&event);
+            }
+
             match event {
                 HandshakeDataReady => {
-                    // ignore for now
+                    if let Some(qlog) = self.qlog.as_mut() {
+                        qlog.connection_state_updated("handshake_started");
+                    }
+
+                    if !self.queue_event(AppEvent::new(AppEventKind::HandshakeDataReady)) {
+                        callbacks::on_handshake_data_ready(self.connection_id());
+                    }
+                }
+                Connected => {
+                    if let Some(qlog) = self.qlog.as_mut() {
+                        qlog.connection_state_updated("handshake_complete");
+                    }
+
+                    let _ = self
+                        .endpoint_events
+                        .send((self.connection_handle, EndpointEvent::Connected));
+
+                    if let Some(token) = self.auth_token.take() {
+                        if let Err(e) = auth_token::send_token(&mut self.inner, &token) {
+                            tracing::error!("Sending the authentication token failed: {}", e);
+                            self.close(VarInt::from_u32(0), b"authentication token not sent");
+                        }
+                    }
+
+                    match self.auth_gate.as_mut() {
+                        Some(gate) => gate.set_connected(),
+                        None => self.notify_connected(),
+                    }
+
+                    // The initial stream limits of the peer don't raise `StreamEvent::Available`.
+                    for dir in [Dir::Bi, Dir::Uni] {
+                        self.notify_stream_available(dir);
+                    }
                 }
-                Connected => callbacks::on_connected(self.connection_id()),
                 ConnectionLost { reason } => {
                     // TODO: self.terminate(reason);
 
-                    let reason = format!("{:?}", reason);
-                    let bytes = reason.as_bytes();
+                    let message = format!("{:?}", reason);
+                    let close_reason = CloseReason::from(&reason);
 
-                    callbacks::on_connection_lost(
-                        self.connection_id(),
-                        bytes.as_ptr(),
-                        bytes.len() as u32,
-                    );
-                }
-                Stream(StreamEvent::Writable { id }) => {
-                    callbacks::on_stream_writable(self.connection_id(), id)
-                }
-                Stream(StreamEvent::Opened { dir }) => {
-                    if let Some(stream_id) = self.inner.streams().accept(dir) {
-                        callbacks::on_stream_opened(
+                    if let Some(qlog) = self.qlog.as_mut() {
+                        qlog.connection_state_updated("closed");
+                    }
+
+                    let _ = self.endpoint_events.send((
+                        self.connection_handle,
+                        EndpointEvent::Lost {
+                            remote: self.remote_address,
+                            kind: close_reason.kind,
+                        },
+                    ));
+
+                    if !self.queue_event(AppEvent::closed(&close_reason)) {
+                        if close_reason.kind == ConnectionErrorKind::ApplicationClosed {
+                            callbacks::on_application_close(self.connection_id(), &close_reason);
+                        }
+
+                        callbacks::on_connection_lost(
                             self.connection_id(),
-                            VarInt::from(stream_id).into_inner(),
-                            dir as u8,
+                            &message,
+                            &close_reason,
                         );
                     }
+                    self.close_reason = Some(close_reason);
+
+                    for id in self.file_transfers.keys().copied().collect::<Vec<_>>() {
+                        self.finish_file_transfer(id, false);
+                    }
+                    self.owned_writes.clear();
+                    self.expiring_datagrams.clear();
+                }
+                Stream(StreamEvent::Writable { id }) => self.handle_stream_writable(id),
+                Stream(StreamEvent::Opened { dir }) => {
+                    // One event is raised for all streams the peer opened since the last event.
+                    while let Some(stream_id) = self.inner.streams().accept(dir) {
+                        match self.auth_gate.as_mut() {
+                            Some(_) if stream_id == auth_token::auth_stream() => {
+                                self.receive_auth_token()
+                            }
+                            Some(gate) => gate.hold_stream(stream_id, dir),
+                            None => self.notify_stream_opened(stream_id, dir),
+                        }
+                    }
                 }
                 DatagramReceived => {
-                    callbacks::on_datagram_received(self.connection_id());
+                    if !self.queue_event(AppEvent::new(AppEventKind::DatagramReceived)) {
+                        callbacks::on_datagram_received(self.connection_id());
+                    }
                 }
-                Stream(StreamEvent::Readable { id }) => {
-                    callbacks::on_stream_readable(self.connection_id(), id);
+                Stream(StreamEvent::Readable { id }) if self.file_transfers.contains_key(&id) => {
+                    self.advance_file_transfer(id);
                 }
-                Stream(StreamEvent::Available { dir }) => {
-                    callbacks::on_stream_available(self.connection_id(), dir as u8);
+                Stream(StreamEvent::Readable { id }) if self.framed_streams.contains_key(&id) => {
+                    self.receive_messages(id);
+                }
+                Stream(StreamEvent::Readable { id }) if self.auth_gate.is_some() => {
+                    // Streams held back by the gate are reported readable once they are announced.
+                    if id == auth_token::auth_stream() {
+                        self.receive_auth_token();
+                    }
+                }
+                Stream(StreamEvent::Readable { id }) => self.notify_stream_readable(id),
+                Stream(StreamEvent::Available { dir }) => self.notify_stream_available(dir),
+                Stream(StreamEvent::Finished { id }) if self.file_transfers.contains_key(&id) => {
+                    self.finish_file_transfer(id, true);
                 }
                 Stream(StreamEvent::Finished { id }) => {
-                    callbacks::on_stream_finished(self.connection_id(), id);
+                    if !self.queue_event(AppEvent::stream(AppEventKind::StreamFinished, id)) {
+                        let user_data = self.stream_user_data(id);
+                        callbacks::on_stream_finished(self.connection_id(), id, user_data);
+                    }
+                    self.stream_send_closed(id);
+                }
+                Stream(StreamEvent::Stopped { id, error_code: _ })
+                    if self.file_transfers.contains_key(&id) =>
+                {
+                    self.finish_file_transfer(id, false);
                 }
                 Stream(StreamEvent::Stopped { id, error_code: _ }) => {
-                    callbacks::on_stream_stopped(self.connection_id(), id);
+                    self.owned_writes.remove(&id);
+                    if !self.queue_event(AppEvent::stream(AppEventKind::StreamStopped, id)) {
+                        let user_data = self.stream_user_data(id);
+                        callbacks::on_stream_stopped(self.connection_id(), id, user_data);
+                    }
+                    self.stream_send_closed(id);
+                }
+            }
+        }
+    }
+
+    fn notify_connected(&mut self) {
+        if !self.queue_event(AppEvent::new(AppEventKind::Connected)) {
+            callbacks::on_connected(self.connection_id());
+        }
+    }
+
+    fn notify_stream_opened(&mut self, id: StreamId, dir: Dir) {
+        // The data that opens a stream raises no readable event, so the stream is unread from when it is opened.
+        if self.orphan_policy.is_some() {
+            self.unread_streams.insert(id, clock::now());
+        }
+        self.unclaimed_streams.insert(id);
+        if !self.queue_event(AppEvent::stream(AppEventKind::StreamOpened, id)) {
+            callbacks::on_stream_opened(
+                self.connection_id(),
+                VarInt::from(id).into_inner(),
+                dir.into(),
+            );
+        }
+    }
+
+    fn notify_stream_readable(&mut self, id: StreamId) {
+        if self.orphan_policy.is_some() {
+            self.unread_streams.entry(id).or_insert_with(clock::now);
+        }
+
+        if !self.queue_event(AppEvent::stream(AppEventKind::StreamReadable, id)) {
+            let user_data = self.stream_user_data(id);
+            callbacks::on_stream_readable(self.connection_id(), id, user_data);
+        }
+    }
+
+    /// Reads the token of the client, once it is complete either releases the connection to the client application
+    /// or closes it.
+    fn receive_auth_token(&mut self) {
+        let connection_id = self.connection_id();
+        let gate = match self.auth_gate.as_mut() {
+            Some(gate) => gate,
+            None => return,
+        };
+
+        let accepted = match gate.receive(&mut self.inner) {
+            Ok(false) => return,
+            Ok(true) => callbacks::on_auth_token(connection_id, gate.token()),
+            Err(e) => {
+                tracing::warn!("Receiving the authentication token failed: {}", e);
+                false
+            }
+        };
+
+        let gate = self.auth_gate.take().expect("The gate was checked above");
+        if !accepted {
+            self.close(gate.reject_code(), b"authentication rejected");
+            return;
+        }
+
+        let (connected, held_streams) = gate.into_released();
+        if connected {
+            self.notify_connected();
+        }
+
+        // The peer may have sent data before the token was accepted, those readable events were held back as well.
+        for (id, dir) in held_streams {
+            self.notify_stream_opened(id, dir);
+            self.notify_stream_readable(id);
+        }
+    }
+
+    /// Continues the writes of the library on a stream that can be written again, or reports it writable to the client application.
+    fn handle_stream_writable(&mut self, id: StreamId) {
+        if self.file_transfers.contains_key(&id) {
+            self.advance_file_transfer(id);
+        } else if self.framed_streams.contains_key(&id) {
+            self.flush_message(id);
+        } else if self.owned_writes.contains_key(&id) {
+            match self.flush_owned_writes(id) {
+                Ok(true) => self.notify_stream_writable(id),
+                Ok(false) => {}
+                Err(e) => tracing::error!("Writing a buffer on stream {} failed: {}", id, e),
+            }
+        } else {
+            self.notify_stream_writable(id);
+        }
+    }
+
+    /// Moves the data the file transfer workers read or wrote since the last poll.
+    fn advance_file_transfers(&mut self) {
+        for id in self.file_transfers.keys().copied().collect::<Vec<_>>() {
+            self.advance_file_transfer(id);
+        }
+    }
+
+    /// Moves data of the file transfer on the stream, reports progress and finishes it once it is complete or failed.
+    fn advance_file_transfer(&mut self, id: StreamId) {
+        let budget = self.send_budget();
+        let transfer = match self.file_transfers.get_mut(&id) {
+            Some(transfer) => transfer,
+            None => return,
+        };
+
+        let before = transfer.transferred();
+        let result = transfer.advance(&mut self.inner, id, budget);
+        let transferred = transfer.transferred();
+        let direction = if transfer.is_send() {
+            StreamDataDirection::Sent
+        } else {
+            StreamDataDirection::Received
+        };
+
+        self.count_stream_data(id, direction, transferred - before);
+        if direction == StreamDataDirection::Sent {
+            let pending = matches!(result, Ok(TransferState::Pending));
+            self.spend_stream_budget(id, budget, (transferred - before) as usize, !pending);
+        }
+        if transferred != before {
+            callbacks::on_file_transfer_progress(self.connection_id(), id, transferred);
+        }
+
+        match result {
+            Ok(TransferState::Pending) | Ok(TransferState::Finishing) => {}
+            Ok(TransferState::Complete) => self.finish_file_transfer(id, true),
+            Err(e) => {
+                tracing::error!("File transfer on stream {} failed: {}", id, e);
+                self.finish_file_transfer(id, false);
+            }
+        }
+    }
+
+    /// Removes the file transfer on the stream and invokes `on_file_transfer_finished`, aborts the stream if it failed.
+    fn finish_file_transfer(&mut self, id: StreamId, succeeded: bool) {
+        if let Some(transfer) = self.file_transfers.remove(&id) {
+            if !succeeded {
+                transfer.abort(&mut self.inner, id);
+            }
+
+            callbacks::on_file_transfer_finished(self.connection_id(), id, succeeded);
+        }
+    }
+
+    /// Writes the rest of the last message of the stream in framing mode, the stream is reported writable once it is written.
+    fn flush_message(&mut self, id: StreamId) {
+        let budget = self.send_budget();
+        let stream = match self.framed_streams.get_mut(&id) {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        let before = stream.sent();
+        let result = stream.flush(&mut self.inner, id, budget);
+        let sent = stream.sent() - before;
+
+        self.count_stream_data(id, StreamDataDirection::Sent, sent);
+        self.spend_stream_budget(id, budget, sent as usize, matches!(result, Ok(true)));
+        match result {
+            Ok(true) => self.notify_stream_writable(id),
+            Ok(false) => {}
+            Err(e) => tracing::error!("Writing a message on stream {} failed: {}", id, e),
+        }
+    }
+
+    /// Writes the buffers of the client application queued for the stream, returns whether all of them are written.
+    ///
+    /// The buffers are dropped if the stream fails.
+    fn flush_owned_writes(&mut self, id: StreamId) -> Result<bool, FFIErrorKind> {
+        let budget = self.send_budget();
+        let buffers = match self.owned_writes.get_mut(&id) {
+            Some(buffers) => buffers,
+            None => return Ok(true),
+        };
+        // quinn advances the written buffers, the tap needs their data from before the write.
+        let tapped = self.tap_stream_data.then(|| buffers.clone());
+
+        // Zero-copy slices of the buffers up to the send rate cap.
+        let mut remaining = budget;
+        let mut admitted = Vec::new();
+        for buffer in buffers.iter() {
+            if remaining == 0 {
+                break;
+            }
+            let len = remaining.min(buffer.len());
+            admitted.push(buffer.slice(..len));
+            remaining -= len;
+        }
+
+        let written = match self.inner.send_stream(id).write_chunks(&mut admitted) {
+            Ok(written) => written.bytes,
+            Err(WriteError::Blocked) => 0,
+            Err(e) => {
+                self.owned_writes.remove(&id);
+                return Err(e.into());
+            }
+        };
+
+        // Written buffers are dropped, a partially written buffer keeps its rest.
+        let mut advanced = written;
+        while let Some(buffer) = buffers.front_mut() {
+            if advanced < buffer.len() {
+                buffer.advance(advanced);
+                break;
+            }
+            advanced -= buffer.len();
+            buffers.pop_front();
+        }
+        let all_written = buffers.is_empty();
+
+        self.count_stream_data(id, StreamDataDirection::Sent, written as u64);
+        self.spend_stream_budget(id, budget, written, all_written);
+        let mut remaining = written;
+        for buffer in tapped.iter().flatten() {
+            let len = remaining.min(buffer.len());
+            self.tap_stream_data(id, StreamDataDirection::Sent, &buffer[..len]);
+            remaining -= len;
+        }
+
+        if all_written {
+            self.owned_writes.remove(&id);
+        }
+
+        Ok(all_written)
+    }
+
+    /// Reads the data of the stream in framing mode, invokes `on_message_received` for every completed message.
+    fn receive_messages(&mut self, id: StreamId) {
+        let stream = match self.framed_streams.get_mut(&id) {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        let before = stream.received();
+        let result = stream.receive(&mut self.inner, id);
+        let received = stream.received() - before;
+
+        self.count_stream_data(id, StreamDataDirection::Received, received);
+        match result {
+            Ok(completed) => {
+                for _ in 0..completed {
+                    if !self.queue_event(AppEvent::stream(AppEventKind::MessageReceived, id)) {
+                        callbacks::on_message_received(self.connection_id(), id);
+                    }
                 }
             }
+            Err(e) => {
+                tracing::error!("Receiving messages on stream {} failed: {}", id, e);
+                self.unread_streams.remove(&id);
+                self.stream_recv_closed(id);
+                let _ = self.inner.recv_stream(id).stop(VarInt::from_u32(0));
+            }
+        }
+    }
+
+    /// Returns when the stream that is unread the longest is stopped by the orphan policy.
+    fn orphan_deadline(&self) -> Option<Instant> {
+        let policy = self.orphan_policy?;
+        let readable = self.unread_streams.values().min()?;
+        Some(*readable + policy.timeout)
+    }
+
+    /// Stops the streams that were readable for longer than the orphan timeout without being read.
+    fn stop_orphaned_streams(&mut self, now: Instant) {
+        let policy = match self.orphan_policy {
+            Some(policy) => policy,
+            None => return,
+        };
+
+        let orphaned = self
+            .unread_streams
+            .iter()
+            .filter(|(_, readable)| now.saturating_duration_since(**readable) >= policy.timeout)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        for id in orphaned {
+            self.unread_streams.remove(&id);
+            self.record_diagnostics(DiagnosticsEventKind::OrphanStopped, id.0);
+            self.stream_recv_closed(id);
+            let _ = self.inner.recv_stream(id).stop(policy.error_code);
+        }
+    }
+
+    /// Invokes `on_path_migrated` if the peer migrated to a new address.
+    fn handle_migration(&mut self) {
+        let remote_address = self.inner.remote_address();
+
+        if remote_address != self.remote_address {
+            self.record_diagnostics(
+                DiagnosticsEventKind::PathMigrated,
+                remote_address.port() as u64,
+            );
+            self.remote_address = remote_address;
+            callbacks::on_path_migrated(self.connection_id(), remote_address.into());
+        }
+    }
+
+    /// Records the poll as the last activity if datagrams were received or sent since the last poll.
+    fn record_activity(&mut self, stats: &proto::ConnectionStats, now: Instant) {
+        let datagrams = stats.udp_rx.datagrams + stats.udp_tx.datagrams;
+
+        if datagrams != self.activity_datagrams {
+            self.activity_datagrams = datagrams;
+            self.last_activity = now;
+        }
+    }
+
+    /// Invokes `on_congestion_event` if a congestion event reduced the congestion window since the last poll.
+    fn handle_congestion(&mut self, stats: &proto::ConnectionStats) {
+        if let Some(kind) = self.congestion.update(stats) {
+            self.record_diagnostics(DiagnosticsEventKind::Congestion, stats.path.cwnd);
+            callbacks::on_congestion_event(
+                self.connection_id(),
+                kind,
+                stats.path.cwnd,
+                stats.path.rtt.as_micros() as u64,
+            );
+        }
+    }
+
+    /// Invokes `on_datagram_size_changed` if the largest datagram that can be sent changed since the last poll.
+    fn handle_datagram_size(&mut self) {
+        let max_size = self.max_datagram_size();
+
+        if max_size != self.max_datagram_size {
+            self.record_diagnostics(DiagnosticsEventKind::DatagramSizeChanged, max_size);
+            self.max_datagram_size = max_size;
+            callbacks::on_datagram_size_changed(self.connection_id(), max_size);
+        }
+    }
+
+    /// Records a change of the lifecycle state since the last poll.
+    fn record_state_transition(&mut self) {
+        let state = self.state();
+
+        if state != self.state {
+            self.record_diagnostics(DiagnosticsEventKind::StateChanged, state as u64);
+            self.state = state;
+            self.state_watch.set(state);
+        }
+    }
+
+    /// Invokes `on_stream_writable`, or queues the event.
+    fn notify_stream_writable(&mut self, id: StreamId) {
+        if !self.queue_event(AppEvent::stream(AppEventKind::StreamWritable, id)) {
+            let user_data = self.stream_user_data(id);
+            callbacks::on_stream_writable(self.connection_id(), id, user_data)
+        }
+    }
+
+    /// Invokes `on_stream_available` if opening a stream in the given direction failed before.
+    fn notify_stream_available(&mut self, dir: Dir) {
+        if std::mem::take(&mut self.exhausted_streams[dir as usize])
+            && !self.queue_event(AppEvent::stream_available(dir.into()))
+        {
+            callbacks::on_stream_available(self.connection_id(), dir.into());
+        }
+    }
+
+    /// Queues the event if event polling is enabled for this connection, returns whether it was queued.
+    ///
+    /// Coalesced events are not queued again while an equal event is queued. Once `MAX_QUEUED_EVENTS` are queued
+    /// further events except `Closed` are dropped and replaced by one `EventsDropped` event, they count as queued.
+    fn queue_event(&mut self, event: AppEvent) -> bool {
+        let app_events = match self.app_events.as_mut() {
+            Some(app_events) => app_events,
+            None => return false,
+        };
+
+        if event.is_coalesced() && app_events.iter().any(|queued| queued.same_as(&event)) {
+            return true;
+        }
+
+        if app_events.len() + 1 >= app_events::MAX_QUEUED_EVENTS
+            && event.kind != AppEventKind::Closed
+        {
+            if app_events.back().map(|queued| queued.kind) != Some(AppEventKind::EventsDropped) {
+                app_events.push_back(AppEvent::events_dropped());
+            }
+            return true;
         }
+
+        app_events.push_back(event);
+        true
     }
 
-    fn connection_id(&self) -> u32 {
+    pub(crate) fn connection_id(&self) -> u32 {
         return self.connection_handle.0 as u32;
     }
 }
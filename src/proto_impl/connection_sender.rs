@@ -0,0 +1,177 @@
+//! A queue that sending threads submit stream writes and datagrams to without locking the connection.
+//!
+//! Submitting only pushes to the queue, the sends are applied when the connection is polled next.
+//! The queue is bounded, and sends that fail when they are applied are reported back to the sender that submitted them.
+
+use crate::{
+    ffi::{
+        callbacks,
+        poison::lock_recovering,
+        FFIResultKind,
+    },
+    proto,
+    proto::StreamId,
+    proto_impl::{
+        FFIErrorKind,
+        PollNotifier,
+    },
+};
+use bytes::Bytes;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        mpsc,
+        Arc,
+        Mutex,
+    },
+};
+
+/// The number of sends a connection queues until it is polled, submitting more returns `BufferBlocked`.
+pub(crate) const SEND_QUEUE_CAPACITY: usize = 256;
+
+/// The number of failed sends kept per sender until they are taken, older failures are dropped.
+const RETAINED_FAILURES: usize = 64;
+
+/// A send submitted to the queue of a connection.
+pub(crate) enum QueuedSend {
+    /// Data written to a stream, the part flow control does not allow yet is written later.
+    Write { id: StreamId, data: Bytes },
+    /// An unreliable datagram.
+    Datagram(Bytes),
+}
+
+/// A send that failed when the connection applied it, taken with `sender_take_failure`.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct SendFailure {
+    /// The stream written to, 0 for a datagram.
+    pub stream_id: u64,
+    /// Whether the send was a datagram rather than a stream write.
+    pub datagram: bool,
+    /// Why the send failed, e.g. `UnknownStream`, `ConnectionLost` or `DatagramTooLarge`.
+    pub kind: FFIResultKind,
+}
+
+/// The failed sends of a sender, the connection reports failures to the sender that submitted the send.
+#[derive(Clone, Default)]
+pub(crate) struct SendFailures(Arc<Mutex<VecDeque<SendFailure>>>);
+
+impl SendFailures {
+    /// Reports a failed send to the stream, or of a datagram if `stream` is `None`.
+    ///
+    /// The oldest failure is dropped if `RETAINED_FAILURES` were not taken yet.
+    pub(crate) fn report(&self, stream: Option<StreamId>, error: FFIErrorKind) {
+        // The kind is taken from the error directly, an `FFIResult` would set the last error of the polling thread.
+        let kind = match error.root() {
+            FFIErrorKind::FFIResultKind(kind) => kind.clone(),
+            FFIErrorKind::Unsupported { .. } => FFIResultKind::Unsupported,
+            _ => FFIResultKind::Error,
+        };
+
+        let mut failures = lock_recovering(&self.0);
+        if failures.len() == RETAINED_FAILURES {
+            failures.pop_front();
+        }
+        failures.push_back(SendFailure {
+            stream_id: stream.map_or(0, |id| id.0),
+            datagram: stream.is_none(),
+            kind,
+        });
+    }
+
+    fn take(&self) -> Option<SendFailure> {
+        lock_recovering(&self.0).pop_front()
+    }
+}
+
+/// Requests a poll of a connection from another thread without locking it, e.g. of a sender or a file transfer worker.
+#[derive(Clone)]
+pub(crate) struct PollRequest {
+    connection_handle: proto::ConnectionHandle,
+    notifier: Option<PollNotifier>,
+    // whether a poll of the connection was requested that did not happen yet.
+    pending: Arc<AtomicBool>,
+}
+
+impl PollRequest {
+    pub(crate) fn new(
+        connection_handle: proto::ConnectionHandle,
+        notifier: Option<PollNotifier>,
+        pending: Arc<AtomicBool>,
+    ) -> Self {
+        PollRequest {
+            connection_handle,
+            notifier,
+            pending,
+        }
+    }
+
+    /// Requests a poll of the connection, unless one was requested since it was last polled.
+    pub(crate) fn request(&self) -> Result<(), FFIErrorKind> {
+        if self.pending.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        match self.notifier.as_ref() {
+            // The endpoint poller polls connections that requested a poll, the pool polls the connection itself.
+            Some(notifier) => {
+                if !notifier.notify_connection(self.connection_handle)? {
+                    notifier.notify()?;
+                }
+                Ok(())
+            }
+            // Manually polled endpoints poll all connections in `poll_endpoint`.
+            None if cfg!(feature = "auto-poll") => Ok(()),
+            None => {
+                callbacks::on_connection_pollable(self.connection_handle.0 as u32);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The producer side of the send queue of a connection, shared by the sending threads.
+pub struct ConnectionSender {
+    sender: mpsc::SyncSender<(QueuedSend, SendFailures)>,
+    failures: SendFailures,
+    poll: PollRequest,
+}
+
+impl ConnectionSender {
+    pub(crate) fn new(
+        sender: mpsc::SyncSender<(QueuedSend, SendFailures)>,
+        poll: PollRequest,
+    ) -> Self {
+        ConnectionSender {
+            sender,
+            failures: SendFailures::default(),
+            poll,
+        }
+    }
+
+    /// Queues the send, the first send after the queue was applied requests a poll of the connection.
+    ///
+    /// Fails with `BufferBlocked` if `SEND_QUEUE_CAPACITY` sends wait for the connection to be polled.
+    pub(crate) fn submit(&self, send: QueuedSend) -> Result<(), FFIErrorKind> {
+        match self.sender.try_send((send, self.failures.clone())) {
+            Ok(()) => {}
+            Err(mpsc::TrySendError::Full(_)) => {
+                return Err(FFIErrorKind::FFIResultKind(FFIResultKind::BufferBlocked))
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                return Err(FFIErrorKind::io_error("The connection was freed"))
+            }
+        }
+
+        self.poll.request()
+    }
+
+    /// Takes the oldest send of this sender that failed when the connection applied it.
+    pub(crate) fn take_failure(&self) -> Option<SendFailure> {
+        self.failures.take()
+    }
+}
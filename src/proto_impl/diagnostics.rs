@@ -0,0 +1,136 @@
+//! A per-connection ring of recent state transitions and protocol events.
+//!
+//! Recording is opt-in per connection, see `connection_set_diagnostics_enabled`, such that a host that hits a bug in
+//! the field can enable it for the affected connections and retrieve the last events with `connection_diagnostics`.
+//! Events are recorded as codes with a value into a fixed ring, recording does not allocate.
+
+use crate::{
+    proto::StreamEvent,
+    proto_impl::StreamDirection,
+};
+use std::time::Instant;
+
+/// The number of events kept per connection, older events are overwritten.
+pub(crate) const CAPACITY: usize = 64;
+
+/// The kind of a diagnostics event, the meaning of its value depends on the kind.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiagnosticsEventKind {
+    /// The lifecycle state changed, the value is the new `ConnectionState`.
+    StateChanged,
+    /// The handshake did not complete before the handshake timeout, the value is 0.
+    HandshakeTimedOut,
+    /// The handshake data is ready, the value is 0.
+    HandshakeDataReady,
+    /// The handshake completed, the value is 0.
+    Connected,
+    /// The connection was lost, the value is 0.
+    ConnectionLost,
+    /// The peer opened streams, the value is the `StreamDirection`.
+    StreamOpened,
+    /// A stream became readable, the value is the stream id.
+    StreamReadable,
+    /// A stream became writable, the value is the stream id.
+    StreamWritable,
+    /// The peer received all data of a stream, the value is the stream id.
+    StreamFinished,
+    /// The peer stopped a stream, the value is the stream id.
+    StreamStopped,
+    /// The peer allows opening more streams, the value is the `StreamDirection`.
+    StreamAvailable,
+    /// Datagrams were received, the value is 0.
+    DatagramReceived,
+    /// An unread stream was stopped by the orphan policy, the value is the stream id.
+    OrphanStopped,
+    /// The peer migrated to a new address, the value is the new port.
+    PathMigrated,
+    /// A congestion event reduced the congestion window, the value is the new window.
+    Congestion,
+    /// The largest datagram that can be sent changed, the value is the new size.
+    DatagramSizeChanged,
+}
+
+/// A recorded diagnostics event, returned by `connection_diagnostics`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct DiagnosticsEvent {
+    /// Microseconds since recording was enabled for the connection.
+    pub micros: u64,
+    /// The meaning depends on the kind.
+    pub value: u64,
+    pub kind: DiagnosticsEventKind,
+}
+
+/// The ring of the last `CAPACITY` events of a connection.
+#[derive(Debug)]
+pub(crate) struct DiagnosticsRing {
+    started: Instant,
+    events: [DiagnosticsEvent; CAPACITY],
+    /// The slot the next event is written to.
+    next: usize,
+    /// The number of events recorded, at most `CAPACITY`.
+    len: usize,
+}
+
+impl DiagnosticsRing {
+    pub(crate) fn new() -> Self {
+        DiagnosticsRing {
+            started: Instant::now(),
+            events: [DiagnosticsEvent {
+                micros: 0,
+                value: 0,
+                kind: DiagnosticsEventKind::StateChanged,
+            }; CAPACITY],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    /// Records an event, overwriting the oldest event if the ring is full.
+    pub(crate) fn record(&mut self, kind: DiagnosticsEventKind, value: u64) {
+        self.events[self.next] = DiagnosticsEvent {
+            micros: self.started.elapsed().as_micros() as u64,
+            value,
+            kind,
+        };
+        self.next = (self.next + 1) % CAPACITY;
+        self.len = (self.len + 1).min(CAPACITY);
+    }
+
+    /// Records an event of quinn-proto.
+    pub(crate) fn record_proto(&mut self, event: &quinn_proto::Event) {
+        use quinn_proto::Event::*;
+
+        let (kind, value) = match event {
+            HandshakeDataReady => (DiagnosticsEventKind::HandshakeDataReady, 0),
+            Connected => (DiagnosticsEventKind::Connected, 0),
+            ConnectionLost { .. } => (DiagnosticsEventKind::ConnectionLost, 0),
+            Stream(StreamEvent::Opened { dir }) => (
+                DiagnosticsEventKind::StreamOpened,
+                StreamDirection::from(*dir) as u64,
+            ),
+            Stream(StreamEvent::Readable { id }) => (DiagnosticsEventKind::StreamReadable, id.0),
+            Stream(StreamEvent::Writable { id }) => (DiagnosticsEventKind::StreamWritable, id.0),
+            Stream(StreamEvent::Finished { id }) => (DiagnosticsEventKind::StreamFinished, id.0),
+            Stream(StreamEvent::Stopped { id, .. }) => (DiagnosticsEventKind::StreamStopped, id.0),
+            Stream(StreamEvent::Available { dir }) => (
+                DiagnosticsEventKind::StreamAvailable,
+                StreamDirection::from(*dir) as u64,
+            ),
+            DatagramReceived => (DiagnosticsEventKind::DatagramReceived, 0),
+        };
+
+        self.record(kind, value);
+    }
+
+    /// Returns the recorded events, oldest first, as two slices in order.
+    pub(crate) fn events(&self) -> (&[DiagnosticsEvent], &[DiagnosticsEvent]) {
+        if self.len < CAPACITY {
+            (&self.events[..self.len], &[])
+        } else {
+            let (newer, older) = self.events.split_at(self.next);
+            (older, newer)
+        }
+    }
+}
@@ -1,9 +1,35 @@
 use crate::{
-    ffi::callbacks,
+    ffi::{
+        callbacks,
+        drain_datagram_ingress,
+        poison::lock_recovering,
+        FFIResult,
+        LockScope,
+    },
     proto,
-    proto_impl::connection::{
-        ConnectionEvent,
-        ConnectionImpl,
+    proto_impl::{
+        clock,
+        connection::{
+            ConnectionEvent,
+            ConnectionImpl,
+            FreedSignal,
+        },
+        ingress::{
+            DatagramIngress,
+            IngressDatagram,
+        },
+        poller_pool,
+        recv_buffers::RecvBufferPool,
+        registry,
+        state_watch::StateWatch,
+        ConnectionErrorKind,
+        ConnectionState,
+        EndpointConfigImpl,
+        EndpointPollerStats,
+        EndpointStats,
+        EndpointTransmitKind,
+        IpAddr,
+        TransmitBurstStats,
     },
 };
 
@@ -13,35 +39,68 @@ use crate::{
     proto::{
         ClientConfig,
         ConnectError,
+        ServerConfig,
+        Side,
+        VarInt,
     },
     proto_impl::FFIErrorKind,
 };
 use std::{
-    collections::HashMap,
+    collections::{
+        HashMap,
+        VecDeque,
+    },
     net::SocketAddr,
     sync::{
         atomic::{
-            AtomicU8,
+            AtomicBool,
             Ordering,
         },
-        mpsc,
+        mpsc::{
+            self,
+            RecvTimeoutError,
+            TryRecvError,
+        },
         Arc,
         Mutex,
+        Weak,
     },
     thread,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
 use crate::proto::ConnectionHandle;
-use std::sync::TryLockError;
 
 /// Maximum number of datagrams processed in send/recv calls to make before moving on to other processing
 ///
 /// This helps ensure we don't starve anything when the CPU is slower than the link.
 /// Value is selected by picking a low number which didn't degrade throughput in benchmarks.
-const IO_LOOP_BOUND: usize = 160;
+pub(crate) const IO_LOOP_BOUND: usize = 160;
+
+/// How long a poller waits before it polls an endpoint again whose poll failed, such that a persisting error does
+/// not spin the poller while timers of the endpoint keep running.
+const POLL_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// The number of transmits an endpoint in pull mode queues for `poll_transmit` before it stops polling transmits.
+///
+/// Connections stop sending once their congestion window is used up, the queue bounds what the endpoint holds.
+const PULLED_TRANSMITS: usize = IO_LOOP_BOUND;
 
-/// An endpoint id that is increased for each created endpoint.
-static ENDPOINT_ID: AtomicU8 = AtomicU8::new(0);
+/// Whether endpoints created from now on are polled by the client application rather than an `EndpointPoller`.
+static MANUAL_POLLING: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether endpoints created from now on are polled by the client application.
+pub(crate) fn set_manual_polling(enabled: bool) {
+    MANUAL_POLLING.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether new endpoints are polled by the client application.
+pub(crate) fn manual_polling() -> bool {
+    MANUAL_POLLING.load(Ordering::Relaxed)
+}
 
 /// Events for the endpoint.
 #[derive(Debug)]
@@ -50,13 +109,70 @@ pub enum EndpointEvent {
     Proto(proto::EndpointEvent),
     /// Transmit ready.
     Transmit(proto::Transmit),
+    /// Connection completed its handshake.
+    Connected,
+    /// Connection was lost, possibly before it completed its handshake.
+    Lost {
+        remote: SocketAddr,
+        kind: ConnectionErrorKind,
+    },
+}
+
+/// Requests polls of an endpoint from the thread or pool that polls it.
+#[derive(Clone)]
+pub enum PollNotifier {
+    /// The `EndpointPoller` thread of the endpoint.
+    Thread(mpsc::Sender<i8>),
+    /// The shared poller pool, see `init_runtime`.
+    Pool(Weak<Mutex<EndpointImpl>>),
 }
 
-/// Polls the endpoint when notified to do so.
-/// This polling happens on its own thread.
+impl PollNotifier {
+    /// Starts polling the endpoint on the poller pool if it is running, otherwise on a new `EndpointPoller` thread.
+    pub fn start(endpoint: Arc<Mutex<EndpointImpl>>) -> Self {
+        if poller_pool::is_running() {
+            return PollNotifier::Pool(Arc::downgrade(&endpoint));
+        }
+
+        let (poller, notifier) = EndpointPoller::new(endpoint);
+        poller.start_polling();
+        PollNotifier::Thread(notifier)
+    }
+
+    /// Requests a poll of the endpoint.
+    pub fn notify(&self) -> Result<(), FFIErrorKind> {
+        match self {
+            PollNotifier::Thread(notifier) => Ok(notifier.send(0)?),
+            PollNotifier::Pool(endpoint) => poller_pool::schedule_endpoint(endpoint.clone()),
+        }
+    }
+
+    /// Requests a poll of the connection, returns `false` if the caller should poll the connection itself.
+    pub fn notify_connection(
+        &self,
+        connection: proto::ConnectionHandle,
+    ) -> Result<bool, FFIErrorKind> {
+        match self {
+            PollNotifier::Thread(_) => Ok(false),
+            PollNotifier::Pool(endpoint) => {
+                poller_pool::schedule_connection(endpoint.clone(), connection)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Stops the `EndpointPoller` thread, the pool drops the jobs of endpoints that are gone.
+    pub fn stop(&self) {
+        if let PollNotifier::Thread(notifier) = self {
+            let _ = notifier.send(-1);
+        }
+    }
+}
+
+/// Polls the endpoint when notified to do so, or when the earliest timer of its connections expires.
+/// This polling happens on its own thread, which sleeps while there is nothing to do.
 pub struct EndpointPoller {
     receiver: mpsc::Receiver<i8>,
-    loop_again: bool,
     endpoint_ref: Arc<Mutex<EndpointImpl>>,
 }
 
@@ -68,7 +184,6 @@ impl EndpointPoller {
             EndpointPoller {
                 endpoint_ref,
                 receiver,
-                loop_again: false,
             },
             sender,
         )
@@ -76,65 +191,197 @@ impl EndpointPoller {
 
     /// Starts polling the endpoint.
     /// This will start a new thread.
-    pub fn start_polling(mut self) {
+    pub fn start_polling(self) {
         thread::spawn(move || {
-            let mut spin_counter = 0;
+            let mut deadline = None;
             loop {
-                if !self.loop_again {
-                    let code = self.receiver.recv();
+                let timer = match self.wait(deadline) {
+                    Some(timer) => timer,
+                    // exit this poll operation, endpoint sent exit code or is gone.
+                    None => return,
+                };
 
-                    if Ok(-1) == code {
-                        // exit this poll operation, endpoint sent exit code.
-                        return;
-                    }
-                }
+                deadline = self.poll(timer);
+            }
+        });
+    }
 
-                if self.loop_again {
-                    spin_counter += 1;
-                }
+    /// Sleeps until notified or until the deadline, returns whether the deadline expired or `None` to exit.
+    ///
+    /// Notifications that queued up while polling are coalesced into a single wakeup.
+    fn wait(&self, deadline: Option<Instant>) -> Option<bool> {
+        let received = match deadline {
+            Some(deadline) => self
+                .receiver
+                .recv_timeout(deadline.saturating_duration_since(clock::now())),
+            None => self.receiver.recv().map_err(RecvTimeoutError::from),
+        };
 
-                if spin_counter == 0 || (self.loop_again && spin_counter % 1000 == 0) {
-                    let lock = self.endpoint_ref.try_lock();
-                    match lock {
-                        Err(TryLockError::WouldBlock) => {
-                            // if blocking, spin thread a bit till lock is released.
-                            self.loop_again = true;
-                            spin_counter += 1;
-                        }
-                        Ok(mut e) => {
-                            spin_counter = 0;
-                            e.poll().expect("Endpoint polling thread panicked!");
-                            self.loop_again = false;
-                        }
-                        _ => {}
-                    }
-                }
+        let timer = match received {
+            Ok(-1) | Err(RecvTimeoutError::Disconnected) => return None,
+            Ok(_) => false,
+            Err(RecvTimeoutError::Timeout) => true,
+        };
+
+        loop {
+            match self.receiver.try_recv() {
+                Ok(-1) | Err(TryRecvError::Disconnected) => return None,
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => return Some(timer),
             }
-        });
+        }
+    }
+
+    /// Polls the endpoint and its connections, returns the earliest timer of the connections.
+    ///
+    /// A failed poll is reported and retried, the poller keeps running.
+    fn poll(&self, timer: bool) -> Option<Instant> {
+        let _scope = LockScope::enter();
+        match poll_with_timers(&self.endpoint_ref, timer) {
+            Ok(deadline) => deadline,
+            Err(e) => Some(report_poll_error(&self.endpoint_ref, e)),
+        }
+    }
+}
+
+/// Handles the submitted datagrams, polls the endpoint and the connections whose timer expired or that have queued sends,
+/// returns the earliest timer of the connections.
+///
+/// Connection timers only run when the connection is polled, without this a connection that neither sends nor
+/// receives would never time out. Used by the `EndpointPoller` thread and the poller pool.
+pub(crate) fn poll_with_timers(
+    endpoint_ref: &Arc<Mutex<EndpointImpl>>,
+    timer: bool,
+) -> Result<Option<Instant>, FFIErrorKind> {
+    drain_datagram_ingress(endpoint_ref)?;
+
+    let connections = {
+        let mut endpoint = lock_recovering(endpoint_ref);
+        endpoint.poller_stats.record_wakeup(timer);
+        endpoint.poll()?;
+        endpoint.connections()
+    };
+
+    // The endpoint lock is released before the connections are locked.
+    let now = clock::now();
+    let mut polled = false;
+    let mut deadline: Option<Instant> = None;
+    for connection in &connections {
+        let mut connection = lock_recovering(connection);
+        if connection.timer_expired(now) || connection.poll_requested() {
+            connection.poll_at(now)?;
+            polled = true;
+        }
+
+        if let Some(timeout) = connection.timeout() {
+            deadline = Some(deadline.map_or(timeout, |deadline| deadline.min(timeout)));
+        }
+    }
+
+    if polled {
+        // Sends the transmits and events of the timers.
+        lock_recovering(endpoint_ref).poll()?;
     }
+
+    Ok(deadline)
+}
+
+/// Logs a failed poll of the endpoint and invokes `on_poll_failed`, returns when the endpoint should be polled again.
+pub(crate) fn report_poll_error(
+    endpoint_ref: &Arc<Mutex<EndpointImpl>>,
+    e: FFIErrorKind,
+) -> Instant {
+    let endpoint_id = lock_recovering(endpoint_ref).id;
+    tracing::error!("Polling endpoint {} failed: {}", endpoint_id, e);
+
+    let result: FFIResult = Err::<(), _>(e).into();
+    callbacks::on_poll_failed(endpoint_id, result.kind);
+
+    clock::now() + POLL_RETRY_DELAY
+}
+
+/// Limits the number of idle connections of an endpoint.
+#[derive(Debug, Copy, Clone)]
+struct IdlePolicy {
+    /// The number of idle connections that are kept.
+    max_idle_connections: usize,
+    /// How long a connection has not received datagrams before it is idle, and how often idle connections are culled.
+    cull_interval: Duration,
 }
 
 /// A QUIC endpoint using quinn-proto.
 pub struct EndpointImpl {
     /// The endpoint id.
-    pub id: u8,
+    pub id: u64,
     pub(crate) inner: proto::Endpoint,
     endpoint_events_rx: mpsc::Receiver<(proto::ConnectionHandle, EndpointEvent)>,
     endpoint_events_tx: mpsc::Sender<(proto::ConnectionHandle, EndpointEvent)>,
-    endpoint_poll_notifier: Option<mpsc::Sender<i8>>,
+    endpoint_poll_notifier: Option<PollNotifier>,
     default_client_config: Option<ClientConfig>,
     connections: HashMap<proto::ConnectionHandle, mpsc::Sender<ConnectionEvent>>,
     // use the refs strictly for polling operations only.
     // Locking a connection could result in deadlocks if the application is already using the lock.
     // TODO: remove this, currently required in handle_datagram
     connection_refs: HashMap<proto::ConnectionHandle, Arc<Mutex<ConnectionImpl>>>,
+    // connections that did not yet complete their handshake, by the side of the endpoint.
+    handshaking: HashMap<proto::ConnectionHandle, Side>,
+    // the supported QUIC versions in order of preference, client connections use the first.
+    supported_versions: Vec<u32>,
+    // the largest datagram accepted from peers, larger datagrams are rejected by `handle_datagram`.
+    max_udp_payload_size: u64,
+    pub(crate) stats: EndpointStats,
+    pub(crate) poller_stats: EndpointPollerStats,
+    pub(crate) recv_buffers: RecvBufferPool,
+    idle_policy: Option<IdlePolicy>,
+    // when the connections last received a datagram, culled connections are removed.
+    last_activity: HashMap<proto::ConnectionHandle, Instant>,
+    next_cull: Option<Instant>,
+    ingress_tx: mpsc::Sender<IngressDatagram>,
+    ingress_rx: mpsc::Receiver<IngressDatagram>,
+    ingress_pending: Arc<AtomicBool>,
+    // whether connections created from now on coalesce `on_connection_pollable`.
+    coalesce_pollable: bool,
+    // whether connections created from now on queue their events, `None` for the setting of `set_event_polling_enabled`.
+    event_polling: Option<bool>,
+    // the local addresses of a multi-homed endpoint, see `endpoint_add_local_address`.
+    local_addresses: Vec<SocketAddr>,
+    // connections made by `endpoint_get_or_connect` by their remote address and server name, with their state.
+    pooled_connections: HashMap<(SocketAddr, String), (proto::ConnectionHandle, StateWatch)>,
+    // the token passed to the transmit callback with the next transmit.
+    next_transmit_token: u64,
+    // the packets quinn answered handled datagrams with, taken right after each datagram and sent on the next poll.
+    answers: VecDeque<Transmit>,
+    // transmits the client application failed to send, passed to the callback again once the socket is writable.
+    failed_transmits: VecDeque<Transmit>,
+    // whether transmits wait until the client application reports the socket writable, see `report_transmit_writable`.
+    transmits_paused: bool,
+    // the transmits waiting for `poll_transmit` in pull mode, `None` when transmits are passed to the callback.
+    pulled_transmits: Option<VecDeque<Transmit>>,
+    // the error code server connections are closed with if their token is rejected, `None` if no token is required.
+    auth_reject_code: Option<VarInt>,
+    // the server configuration for new incoming connections, kept to restore it once the backlog has room.
+    server_config: Option<ServerConfig>,
+    // the incoming connections that may be handshaking at once, `None` if unlimited.
+    max_pending_handshakes: Option<usize>,
+    // whether new incoming connections are refused because the backlog is full.
+    backlog_full: bool,
+    // the flags of the connections set once they are removed, such that their handles fail with `UnknownConnection`.
+    freed_flags: HashMap<proto::ConnectionHandle, FreedSignal>,
+    // the transmits emitted per poll before the endpoint yields, see `endpoint_set_max_transmit_burst`.
+    max_transmit_burst: usize,
+    // the transmits emitted by the current poll.
+    burst: usize,
+    burst_stats: TransmitBurstStats,
+    // how long new connections may take to complete their handshake, `None` for no limit.
+    handshake_timeout: Option<Duration>,
 }
 
 impl EndpointImpl {
-    pub fn new(endpoint: proto::Endpoint) -> Self {
+    pub fn new(endpoint: proto::Endpoint, config: &EndpointConfigImpl) -> Self {
         let (tx, rx) = mpsc::channel();
+        let (ingress_tx, ingress_rx) = mpsc::channel();
 
-        let id = ENDPOINT_ID.load(Ordering::Relaxed).wrapping_add(1);
+        let id = registry::next_endpoint_id();
 
         return EndpointImpl {
             inner: endpoint,
@@ -145,52 +392,303 @@ impl EndpointImpl {
             id,
             default_client_config: None,
             connection_refs: HashMap::new(),
+            handshaking: HashMap::new(),
+            supported_versions: config.supported_versions().to_vec(),
+            max_udp_payload_size: config.max_udp_payload_size(),
+            stats: EndpointStats::default(),
+            poller_stats: EndpointPollerStats::default(),
+            recv_buffers: RecvBufferPool::for_payload_size(config.max_udp_payload_size() as usize),
+            idle_policy: None,
+            last_activity: HashMap::new(),
+            next_cull: None,
+            ingress_tx,
+            ingress_rx,
+            ingress_pending: Arc::new(AtomicBool::new(false)),
+            coalesce_pollable: false,
+            event_polling: None,
+            local_addresses: Vec::new(),
+            pooled_connections: HashMap::new(),
+            next_transmit_token: 0,
+            answers: VecDeque::new(),
+            failed_transmits: VecDeque::new(),
+            transmits_paused: false,
+            pulled_transmits: None,
+            auth_reject_code: None,
+            server_config: None,
+            max_pending_handshakes: None,
+            backlog_full: false,
+            freed_flags: HashMap::new(),
+            max_transmit_burst: IO_LOOP_BOUND,
+            burst: 0,
+            burst_stats: TransmitBurstStats::default(),
+            handshake_timeout: config.handshake_timeout(),
         };
     }
 
     /// Sets the endpoint poll notifier.
     /// This sender can be used to trigger a endpoint poll operation.
-    pub fn set_poll_notifier(&mut self, notifer: mpsc::Sender<i8>) {
+    pub fn set_poll_notifier(&mut self, notifer: PollNotifier) {
         self.endpoint_poll_notifier = Some(notifer);
     }
 
     /// Polls the endpoint.
     ///
-    /// - Triggers a callback for all outgoing transmits.
-    /// - Handles all connection sent endpoint events.
+    /// - Triggers a callback for at most `max_transmit_burst` outgoing transmits, of the endpoint and its connections.
+    /// - Handles at most `IO_LOOP_BOUND` connection sent endpoint events.
+    ///
+    /// Returns whether work remains, in which case the endpoint poller is notified to poll again
+    /// or, without a poller, the `on_endpoint_pollable` callback is invoked.
     pub fn poll(&mut self) -> Result<bool, FFIErrorKind> {
-        while let Some(transmit) = self.inner.poll_transmit() {
-            // TODO: batch transmits
-            self.notify_transmit(transmit);
+        self.cull_idle_connections(clock::now())?;
+        self.burst = 0;
+
+        while !self.transmits_paused {
+            match self.failed_transmits.pop_front() {
+                Some(transmit) => {
+                    self.burst += 1;
+                    self.push_transmit(transmit);
+                }
+                None => break,
+            }
+        }
+
+        let mut more_transmits = true;
+
+        while self.burst < self.max_transmit_burst {
+            if self.transmits_blocked() {
+                break;
+            }
+
+            match self
+                .answers
+                .pop_front()
+                .or_else(|| self.inner.poll_transmit())
+            {
+                Some(transmit) => {
+                    // TODO: batch transmits
+                    self.poller_stats.work_items += 1;
+                    let kind = self.stats.record_endpoint_transmit(&transmit);
+                    self.notify_endpoint_transmit(kind, transmit);
+                }
+                None => {
+                    more_transmits = false;
+                    break;
+                }
+            }
+        }
+
+        // The events carry the transmits of the connections, they wait until the client application pulled transmits.
+        let more_events = self.transmits_blocked() || self.handle_connection_events()?;
+
+        let capped = self.burst >= self.max_transmit_burst && (more_transmits || more_events);
+        self.burst_stats.record_burst(self.burst, capped);
+
+        // `take_transmit` requests the next poll once the queue has room.
+        if (more_transmits || more_events) && !self.transmits_blocked() {
+            self.request_poll()?;
+        }
+
+        Ok(more_transmits || more_events)
+    }
+
+    /// Takes the packet quinn answered the datagram it just handled with, returns its kind or `None` if there is none.
+    ///
+    /// Quinn queues at most one answer per datagram, which is taken right after the datagram was handled,
+    /// so a queued packet answers the last datagram. The answer is sent on the next poll, which is requested.
+    pub(crate) fn take_answer(&mut self) -> Result<Option<EndpointTransmitKind>, FFIErrorKind> {
+        let transmit = match self.inner.poll_transmit() {
+            Some(transmit) => transmit,
+            None => return Ok(None),
+        };
+
+        let kind = EndpointTransmitKind::classify(&transmit);
+        self.answers.push_back(transmit);
+        self.request_poll()?;
+        Ok(kind)
+    }
+
+    /// Sets whether transmits are queued for `poll_transmit` instead of passed to the transmit callback.
+    ///
+    /// The transmits queued when pull mode is disabled are passed to the callback.
+    pub fn set_transmit_pull_mode(&mut self, enabled: bool) {
+        match (enabled, self.pulled_transmits.take()) {
+            (true, queued) => self.pulled_transmits = Some(queued.unwrap_or_default()),
+            (false, Some(queued)) => queued
+                .into_iter()
+                .for_each(|transmit| self.push_transmit(transmit)),
+            (false, None) => {}
+        }
+    }
+
+    /// Returns whether the queue of transmits for `poll_transmit` is full, or transmits wait for the socket.
+    fn transmits_blocked(&self) -> bool {
+        self.transmits_paused
+            || self
+                .pulled_transmits
+                .as_ref()
+                .is_some_and(|queued| queued.len() >= PULLED_TRANSMITS)
+    }
+
+    /// Returns the length of the next transmit for `poll_transmit`, `None` if no transmit is queued.
+    ///
+    /// Polls the endpoint when the queue is empty, such that the transmits the connections produced since are queued.
+    pub(crate) fn next_transmit_len(&mut self) -> Result<Option<usize>, FFIErrorKind> {
+        match self.pulled_transmits.as_ref() {
+            Some(queued) if queued.is_empty() => {
+                self.poll()?;
+            }
+            Some(_) => {}
+            None => {
+                return Err(FFIErrorKind::io_error(
+                    "The endpoint is not in transmit pull mode",
+                ))
+            }
+        }
+
+        Ok(self
+            .pulled_transmits
+            .as_ref()
+            .and_then(VecDeque::front)
+            .map(|transmit| transmit.contents.len()))
+    }
+
+    /// Takes the next transmit for `poll_transmit`, polling resumes once the queue has room again.
+    pub(crate) fn take_transmit(&mut self) -> Result<Option<Transmit>, FFIErrorKind> {
+        let was_blocked = self.transmits_blocked();
+        let transmit = self.pulled_transmits.as_mut().and_then(VecDeque::pop_front);
+
+        if was_blocked {
+            self.request_poll()?;
+        }
+
+        Ok(transmit)
+    }
+
+    /// Requests another poll from the endpoint poller or, without a poller, the client application.
+    fn request_poll(&self) -> Result<(), FFIErrorKind> {
+        match self.endpoint_poll_notifier.as_ref() {
+            Some(notifier) => notifier.notify(),
+            None => {
+                callbacks::on_endpoint_pollable(self.id);
+                Ok(())
+            }
         }
+    }
+
+    /// Returns a producer for the ingress queue of this endpoint, see `submit_datagram`.
+    pub fn datagram_ingress(&self) -> DatagramIngress {
+        DatagramIngress::new(
+            self.ingress_tx.clone(),
+            self.id,
+            self.endpoint_poll_notifier.clone(),
+            self.max_udp_payload_size,
+            self.ingress_pending.clone(),
+        )
+    }
+
+    /// Takes at most `IO_LOOP_BOUND` datagrams from the ingress queue, another poll is requested if more may be queued.
+    pub(crate) fn take_ingress_datagrams(&mut self) -> Result<Vec<IngressDatagram>, FFIErrorKind> {
+        // Cleared before draining, such that datagrams submitted during the drain request another poll.
+        self.ingress_pending.store(false, Ordering::SeqCst);
 
-        // TODO limit max outgoing, invoke callback to poll again.
+        let datagrams: Vec<_> = self.ingress_rx.try_iter().take(IO_LOOP_BOUND).collect();
+        self.poller_stats.work_items += datagrams.len() as u64;
+
+        if datagrams.len() == IO_LOOP_BOUND && !self.ingress_pending.swap(true, Ordering::SeqCst) {
+            self.request_poll()?;
+        }
 
-        self.handle_connection_events()
+        Ok(datagrams)
     }
 
-    /// Creates and adds a connection for this endpoint.
+    /// Creates and adds a connection, using the given QUIC version, for this endpoint.
     pub fn add_connection(
         &mut self,
         handle: proto::ConnectionHandle,
         connection: proto::Connection,
+        version: u32,
     ) -> ConnectionImpl {
         let (send, recv) = mpsc::channel();
         let _ = self.connections.insert(handle, send);
+        self.handshaking.insert(handle, connection.side());
+        self.last_activity.insert(handle, clock::now());
+        self.update_backlog();
 
-        ConnectionImpl::new(
+        let mut connection = ConnectionImpl::new(
             connection,
             handle,
+            version,
             recv,
             self.endpoint_events_tx.clone(),
             self.endpoint_poll_notifier.clone(),
-        )
+        );
+        connection.set_pollable_coalescing(self.coalesce_pollable);
+        if let Some(enabled) = self.event_polling {
+            connection.set_event_polling_enabled(enabled);
+        }
+        if let Some(timeout) = self.handshake_timeout {
+            connection.set_handshake_timeout(timeout);
+        }
+        self.freed_flags.insert(handle, connection.freed_signal());
+        if let Some(reject_code) = self.auth_reject_code {
+            if connection.inner.side().is_server() {
+                connection.require_auth_token(reject_code);
+            }
+        }
+        connection
     }
 
     /// Removes, not closing, the connection from the endpoint.
+    ///
+    /// `on_connection_freed` is invoked once, after which calls with handles of the connection fail.
     pub fn remove_connection(&mut self, handle: proto::ConnectionHandle) {
+        if let Some(freed) = self.freed_flags.remove(&handle) {
+            freed.signal();
+            callbacks::on_connection_freed(handle.0 as u32);
+        }
+
         self.connection_refs.remove(&handle);
         self.connections.remove(&handle);
+        self.handshaking.remove(&handle);
+        self.last_activity.remove(&handle);
+        self.pooled_connections
+            .retain(|_, (pooled, _)| *pooled != handle);
+        self.update_backlog();
+    }
+
+    /// Adds a connection to the pool, such that `pooled_connection` returns it for the same address and server name.
+    ///
+    /// A closing connection to the same address and server name is replaced.
+    pub(crate) fn pool_connection(
+        &mut self,
+        addr: SocketAddr,
+        server_name: String,
+        connection: &ConnectionImpl,
+    ) {
+        self.pooled_connections.insert(
+            (addr, server_name),
+            (connection.connection_handle, connection.state_watch()),
+        );
+    }
+
+    /// Returns the pooled connection to the address and server name if it is handshaking or established.
+    ///
+    /// The state is read from the watch of the connection, the connection is not locked under the endpoint lock.
+    pub fn pooled_connection(
+        &self,
+        addr: SocketAddr,
+        server_name: &str,
+    ) -> Option<(proto::ConnectionHandle, Arc<Mutex<ConnectionImpl>>)> {
+        let (handle, state) = self
+            .pooled_connections
+            .get(&(addr, server_name.to_owned()))?;
+
+        match state.state() {
+            ConnectionState::Handshaking | ConnectionState::Established => {
+                Some((*handle, self.connection(*handle)?))
+            }
+            _ => None,
+        }
     }
 
     /// Registers a connection for polling.
@@ -203,18 +701,16 @@ impl EndpointImpl {
         self.connection_refs.insert(handle, connection);
     }
 
-    /// Polls a connection by the given connection handle.
-    pub fn poll_connection(&self, handle: ConnectionHandle) -> Result<(), FFIErrorKind> {
-        // if lock is blocked its oke to skip one poll since this function is triggered in various cases.
-        if let Some(connection) = self.connection_refs.get(&handle) {
-            if let Ok(mut conn) = connection.try_lock() {
-                conn.mark_pollable()?;
-            } else {
-                return Err(FFIErrorKind::io_error("Locked endpoint connection lock"));
-            }
-        }
+    /// Returns the connection registered with the given connection handle.
+    pub fn connection(&self, handle: ConnectionHandle) -> Option<Arc<Mutex<ConnectionImpl>>> {
+        self.connection_refs.get(&handle).cloned()
+    }
 
-        Ok(())
+    /// Returns all connections registered for polling.
+    ///
+    /// Lock the connections only after releasing the endpoint lock.
+    pub fn connections(&self) -> Vec<Arc<Mutex<ConnectionImpl>>> {
+        self.connection_refs.values().cloned().collect()
     }
 
     /// Sends a `ConnectionEvent` to a particular connection.
@@ -223,6 +719,10 @@ impl EndpointImpl {
         handle: proto::ConnectionHandle,
         event: proto::ConnectionEvent,
     ) -> Result<(), FFIErrorKind> {
+        if let Some(last_activity) = self.last_activity.get_mut(&handle) {
+            *last_activity = clock::now();
+        }
+
         self.connections
             .get_mut(&handle)
             .unwrap()
@@ -231,11 +731,103 @@ impl EndpointImpl {
         Ok(())
     }
 
+    /// Returns the statistics of this endpoint.
+    pub fn stats(&self) -> EndpointStats {
+        let mut stats = self.stats;
+        stats.handshaking_connections = self.handshaking.len() as u64;
+        stats
+    }
+
+    /// Returns the statistics of the transmits emitted per poll.
+    pub fn transmit_burst_stats(&self) -> TransmitBurstStats {
+        self.burst_stats.with_average()
+    }
+
+    /// Sets the transmits emitted per poll before the endpoint yields to other work, at least 1.
+    ///
+    /// Larger bursts let the client application batch more datagrams per socket call, smaller ones lower the latency
+    /// of other endpoints and connections sharing the poller.
+    pub fn set_max_transmit_burst(&mut self, packets: usize) {
+        self.max_transmit_burst = packets.max(1);
+    }
+
+    /// Returns the statistics of the poller of this endpoint.
+    pub fn poller_stats(&self) -> EndpointPollerStats {
+        self.poller_stats.with_average()
+    }
+
+    /// Returns the largest datagram accepted from peers.
+    pub fn max_udp_payload_size(&self) -> u64 {
+        self.max_udp_payload_size
+    }
+
+    /// Returns the supported QUIC versions in order of preference.
+    pub fn supported_versions(&self) -> &[u32] {
+        &self.supported_versions
+    }
+
     /// Set the client configuration used by `connect`.
     pub fn set_default_client_config(&mut self, config: ClientConfig) {
         self.default_client_config = Some(config);
     }
 
+    /// Replaces the server configuration used for new incoming connections.
+    ///
+    /// Established connections keep the configuration they were accepted with.
+    pub fn set_server_config(&mut self, config: ServerConfig) {
+        self.server_config = Some(config);
+        self.apply_server_config();
+    }
+
+    /// Returns the incoming connections that did not complete their handshake yet.
+    pub fn pending_handshakes(&self) -> usize {
+        self.handshaking
+            .values()
+            .filter(|side| side.is_server())
+            .count()
+    }
+
+    /// Returns whether new incoming connections are refused because `max_pending_handshakes` are handshaking.
+    pub fn backlog_full(&self) -> bool {
+        self.backlog_full
+    }
+
+    /// Sets the incoming connections that may be handshaking at once, `None` for no limit.
+    pub fn set_max_pending_handshakes(&mut self, max_pending_handshakes: Option<usize>) {
+        self.max_pending_handshakes = max_pending_handshakes;
+        self.update_backlog();
+    }
+
+    /// Refuses or again accepts new incoming connections when the backlog filled up or got room.
+    fn update_backlog(&mut self) {
+        let pending = self.pending_handshakes();
+        let full = self
+            .max_pending_handshakes
+            .is_some_and(|max| pending >= max);
+
+        if full != self.backlog_full {
+            self.backlog_full = full;
+            self.apply_server_config();
+        }
+    }
+
+    /// Passes the server configuration to quinn, with a connection limit of zero while the backlog is full.
+    ///
+    /// quinn answers connections above its limit with a `CONNECTION_REFUSED` close, without keeping any state.
+    fn apply_server_config(&mut self) {
+        let config = self.server_config.as_ref().map(|config| {
+            let mut config = config.clone();
+            if self.backlog_full {
+                config.concurrent_connections(0);
+            }
+            Arc::new(config)
+        });
+
+        if config.is_some() {
+            self.inner.set_server_config(config);
+        }
+    }
+
     /// Connects to a remote endpoint
     ///
     /// `server_name` must be covered by the certificate presented by the server. This prevents a
@@ -264,37 +856,164 @@ impl EndpointImpl {
     /// [`connect()`]: EndpointImpl::connect
     pub fn connect_with(
         &mut self,
-        config: ClientConfig,
+        mut config: ClientConfig,
         addr: SocketAddr,
         server_name: &str,
     ) -> Result<ConnectionImpl, ConnectError> {
+        let version = self.supported_versions[0];
+        config.version(version);
+
         let (ch, conn) = self.inner.connect(config, addr, server_name)?;
 
-        Ok(self.add_connection(ch, conn))
+        Ok(self.add_connection(ch, conn, version))
     }
 
     /// Closes the endpoint.
+    ///
+    /// The connections are freed like drained ones, `on_connection_freed` is invoked for each of them.
     pub fn close(&mut self) {
-        self.endpoint_poll_notifier.as_ref().map(|val| {
-            val.send(-1);
-        });
+        registry::unregister_endpoint(self.id);
+
+        if let Some(notifier) = self.endpoint_poll_notifier.as_ref() {
+            notifier.stop();
+        }
 
         self.connections.clear();
         self.connection_refs.clear();
+        self.handshaking.clear();
+        self.last_activity.clear();
+        self.pooled_connections.clear();
+
+        for (handle, freed) in self.freed_flags.drain() {
+            freed.signal();
+            callbacks::on_connection_freed(handle.0 as u32);
+        }
+    }
+
+    /// Registers a local address the endpoint receives datagrams on.
+    pub fn add_local_address(&mut self, addr: SocketAddr) {
+        if !self.local_addresses.contains(&addr) {
+            self.local_addresses.push(addr);
+        }
+    }
+
+    /// Returns the IP of a registered local address, an error if the address is not registered.
+    pub fn local_ip(&self, addr: SocketAddr) -> Result<std::net::IpAddr, FFIErrorKind> {
+        if self.local_addresses.contains(&addr) {
+            Ok(addr.ip())
+        } else {
+            Err(FFIErrorKind::io_error(
+                "The local address is not registered with `endpoint_add_local_address`",
+            ))
+        }
+    }
+
+    /// Sets whether connections created from now on invoke `on_connection_pollable` once until they are polled.
+    pub fn set_pollable_coalescing(&mut self, enabled: bool) {
+        self.coalesce_pollable = enabled;
+    }
+
+    /// Sets whether connections created from now on queue their events rather than invoking callbacks.
+    pub fn set_event_polling_enabled(&mut self, enabled: bool) {
+        self.event_polling = Some(enabled);
+    }
+
+    /// Sets whether server connections created from now on require a token, closing them with the code if it is rejected.
+    pub fn set_auth_reject_code(&mut self, reject_code: Option<VarInt>) {
+        self.auth_reject_code = reject_code;
+    }
+
+    /// Sets the idle policy, a `cull_interval` of zero disables culling.
+    ///
+    /// Every `cull_interval` the connections that did not receive datagrams for `cull_interval` are counted as idle,
+    /// and the longest idle connections above `max_idle_connections` are closed.
+    pub fn set_idle_policy(&mut self, max_idle_connections: usize, cull_interval: Duration) {
+        self.idle_policy = (cull_interval > Duration::ZERO).then(|| IdlePolicy {
+            max_idle_connections,
+            cull_interval,
+        });
+        self.next_cull = None;
+    }
+
+    /// Closes the idle connections above the limit of the idle policy, invokes `on_connection_culled` for each.
+    ///
+    /// The connections are closed through their event channel, as the endpoint must not lock them.
+    fn cull_idle_connections(&mut self, now: Instant) -> Result<(), FFIErrorKind> {
+        let policy = match self.idle_policy {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+
+        if self.next_cull.is_some_and(|next_cull| now < next_cull) {
+            return Ok(());
+        }
+        self.next_cull = Some(now + policy.cull_interval);
+
+        let mut idle: Vec<(proto::ConnectionHandle, Instant)> = self
+            .last_activity
+            .iter()
+            .filter(|(_, last_activity)| {
+                now.saturating_duration_since(**last_activity) >= policy.cull_interval
+            })
+            .map(|(handle, last_activity)| (*handle, *last_activity))
+            .collect();
+
+        if idle.len() <= policy.max_idle_connections {
+            return Ok(());
+        }
+
+        idle.sort_by_key(|(_, last_activity)| *last_activity);
+        let cull_count = idle.len() - policy.max_idle_connections;
+
+        for (handle, _) in idle.into_iter().take(cull_count) {
+            self.last_activity.remove(&handle);
+
+            if let Some(connection) = self.connections.get(&handle) {
+                connection.send(ConnectionEvent::Close {
+                    error_code: VarInt::from_u32(0),
+                    reason: b"idle".to_vec(),
+                })?;
+            }
+
+            tracing::debug!("Culling idle connection {}", handle.0);
+            callbacks::on_connection_culled(handle.0 as u32);
+            self.request_connection_poll(handle)?;
+        }
+
+        Ok(())
+    }
+
+    /// Requests a poll of a connection, which can't be polled while the endpoint is locked.
+    fn request_connection_poll(&self, handle: proto::ConnectionHandle) -> Result<(), FFIErrorKind> {
+        match self.endpoint_poll_notifier.as_ref() {
+            Some(notifier) if cfg!(feature = "auto-poll") => {
+                if !notifier.notify_connection(handle)? {
+                    callbacks::on_connection_pollable(handle.0 as u32);
+                }
+            }
+            // Manually polled endpoints poll all connections in `poll_endpoint`.
+            None if cfg!(feature = "auto-poll") => {}
+            _ => callbacks::on_connection_pollable(handle.0 as u32),
+        }
+
+        Ok(())
     }
 
     /// Handles events sent by connections which in turn might trigger new events for connections.
     fn handle_connection_events(&mut self) -> Result<bool, FFIErrorKind> {
         for _ in 0..IO_LOOP_BOUND {
+            // The remaining events, which may carry transmits, wait for the next poll.
+            if self.burst >= self.max_transmit_burst {
+                return Ok(true);
+            }
+
             match self.endpoint_events_rx.try_recv() {
                 Ok((handle, event)) => {
+                    self.poller_stats.work_items += 1;
                     match event {
                         EndpointEvent::Proto(proto) => {
                             if proto.is_drained() {
-                                self.connections.remove(&handle);
-                                if self.connections.is_empty() {
-                                    //self.idle.notify_waiters();
-                                }
+                                self.remove_connection(handle);
                             }
 
                             if let Some(event) = self.inner.handle_event(handle, proto) {
@@ -308,6 +1027,18 @@ impl EndpointImpl {
                         EndpointEvent::Transmit(transmit) => {
                             self.notify_transmit(transmit);
                         }
+                        EndpointEvent::Connected => {
+                            self.handshaking.remove(&handle);
+                            self.update_backlog();
+                        }
+                        EndpointEvent::Lost { remote, kind } => {
+                            // The host closed its own attempt, there is no failure to present.
+                            if self.handshaking.remove(&handle).is_some()
+                                && kind != ConnectionErrorKind::LocallyClosed
+                            {
+                                callbacks::on_connect_failed(self.id, IpAddr::from(remote), kind);
+                            }
+                        }
                     }
                 }
                 Err(_) => {
@@ -322,6 +1053,67 @@ impl EndpointImpl {
 
     /// Invokes a initialized callback by the client application.
     fn notify_transmit(&mut self, transmit: Transmit) {
-        callbacks::on_transmit(self.id, transmit);
+        self.stats.emitted_transmits += 1;
+        self.burst += 1;
+
+        if let Some(queued) = self.pulled_transmits.as_mut() {
+            queued.push_back(transmit);
+            if queued.len() == 1 {
+                callbacks::on_transmit_ready(self.id);
+            }
+            return;
+        }
+
+        self.push_transmit(transmit);
+    }
+
+    /// Passes a packet generated by the endpoint to the endpoint transmit callback if set, else like any other transmit.
+    fn notify_endpoint_transmit(&mut self, kind: Option<EndpointTransmitKind>, transmit: Transmit) {
+        match kind {
+            Some(kind) if callbacks::has_endpoint_transmit() => {
+                self.stats.emitted_transmits += 1;
+                self.burst += 1;
+                let source = self.transmit_source(&transmit);
+                callbacks::on_endpoint_transmit(self.id, kind, transmit, source);
+            }
+            _ => self.notify_transmit(transmit),
+        }
+    }
+
+    /// Passes a transmit to the transmit callback.
+    fn push_transmit(&mut self, transmit: Transmit) {
+        let source = self.transmit_source(&transmit);
+
+        let token = self.next_transmit_token;
+        self.next_transmit_token += 1;
+
+        callbacks::on_transmit(self.id, token, transmit, source);
+    }
+
+    /// Keeps a transmit the client application failed to send, transmits pause until the socket is reported writable.
+    pub(crate) fn hold_failed_transmit(&mut self, transmit: Transmit) {
+        self.failed_transmits.push_back(transmit);
+        self.transmits_paused = true;
+    }
+
+    /// Resumes transmits once the socket is writable, the failed transmits are passed to the callback first.
+    pub(crate) fn resume_transmits(&mut self) -> Result<(), FFIErrorKind> {
+        if !std::mem::take(&mut self.transmits_paused) {
+            return Ok(());
+        }
+
+        self.request_poll()
+    }
+
+    /// Returns the registered local address a transmit is sent from, the unspecified address if it has no source.
+    fn transmit_source(&self, transmit: &Transmit) -> IpAddr {
+        let source = transmit.src_ip.and_then(|ip| {
+            self.local_addresses
+                .iter()
+                .find(|addr| addr.ip() == ip)
+                .copied()
+        });
+
+        IpAddr::from(source.unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0))))
     }
 }
@@ -0,0 +1,196 @@
+//! Endpoint settings of an `EndpointConfigHandle`, used by `create_server_endpoint_ex` and `create_client_endpoint_ex`.
+
+use crate::{
+    proto::EndpointConfig,
+    proto_impl::{
+        cid_generator,
+        endpoint,
+        reset_key,
+        versions,
+        FFIErrorKind,
+    },
+};
+use std::{
+    ops::RangeInclusive,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+    },
+    time::Duration,
+};
+
+/// The UDP payload sizes allowed by QUIC version 1, from the minimum datagram size to the maximum of the transport parameter.
+const UDP_PAYLOAD_SIZES: RangeInclusive<u64> = 1200..=65527;
+
+/// The maximum UDP payload size of endpoints created from now on, zero for the default of quinn.
+static MAX_UDP_PAYLOAD_SIZE: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the maximum UDP payload size accepted from peers by endpoints created from now on.
+pub(crate) fn set_max_udp_payload_size(size: u64) -> Result<(), FFIErrorKind> {
+    check_max_udp_payload_size(size)?;
+    MAX_UDP_PAYLOAD_SIZE.store(size, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns the maximum UDP payload size of endpoints created from now on.
+pub(crate) fn max_udp_payload_size() -> u64 {
+    match MAX_UDP_PAYLOAD_SIZE.load(Ordering::Relaxed) {
+        0 => EndpointConfig::default().get_max_udp_payload_size(),
+        size => size,
+    }
+}
+
+/// The handshake timeout of endpoints created from now on in milliseconds, zero for none.
+static HANDSHAKE_TIMEOUT_MILLIS: AtomicU64 = AtomicU64::new(0);
+
+/// Sets how long connections of endpoints created from now on may take to complete their handshake, `None` for no limit.
+pub(crate) fn set_handshake_timeout(timeout: Option<Duration>) {
+    let millis = timeout.map_or(0, |timeout| timeout.as_millis() as u64);
+    HANDSHAKE_TIMEOUT_MILLIS.store(millis, Ordering::Relaxed);
+}
+
+/// Returns the handshake timeout of endpoints created from now on.
+pub(crate) fn handshake_timeout() -> Option<Duration> {
+    match HANDSHAKE_TIMEOUT_MILLIS.load(Ordering::Relaxed) {
+        0 => None,
+        millis => Some(Duration::from_millis(millis)),
+    }
+}
+
+/// Fails if the payload size is not allowed by QUIC version 1.
+fn check_max_udp_payload_size(size: u64) -> Result<(), FFIErrorKind> {
+    if !UDP_PAYLOAD_SIZES.contains(&size) {
+        return Err(FFIErrorKind::io_error(&format!(
+            "The maximum UDP payload size must be between {} and {} bytes",
+            UDP_PAYLOAD_SIZES.start(),
+            UDP_PAYLOAD_SIZES.end()
+        )));
+    }
+
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct EndpointConfigImpl {
+    cid_length: usize,
+    max_udp_payload_size: u64,
+    supported_versions: Vec<u32>,
+    handshake_timeout: Option<Duration>,
+    cid_generator_enabled: Option<bool>,
+    manual_polling: bool,
+    reset_key: Option<Vec<u8>>,
+}
+
+impl EndpointConfigImpl {
+    /// Uses the endpoint settings set at the time of creation.
+    pub fn new() -> Self {
+        EndpointConfigImpl {
+            cid_length: cid_generator::cid_length(),
+            max_udp_payload_size: max_udp_payload_size(),
+            supported_versions: versions::supported_versions(),
+            handshake_timeout: handshake_timeout(),
+            cid_generator_enabled: None,
+            manual_polling: endpoint::manual_polling(),
+            reset_key: reset_key::configured_key(),
+        }
+    }
+
+    /// Sets the length of the connection ids generated by the endpoint.
+    pub fn set_cid_length(&mut self, length: usize) -> Result<(), FFIErrorKind> {
+        cid_generator::check_cid_length(length)?;
+        self.cid_length = length;
+        Ok(())
+    }
+
+    /// Sets the maximum UDP payload size accepted from peers.
+    pub fn set_max_udp_payload_size(&mut self, size: u64) -> Result<(), FFIErrorKind> {
+        check_max_udp_payload_size(size)?;
+        self.max_udp_payload_size = size;
+        Ok(())
+    }
+
+    /// Sets the QUIC versions supported by the endpoint, in order of preference.
+    pub fn set_supported_versions(&mut self, versions: Vec<u32>) -> Result<(), FFIErrorKind> {
+        if versions.is_empty() {
+            return Err(FFIErrorKind::io_error(
+                "At least one QUIC version must be supported",
+            ));
+        }
+
+        self.supported_versions = versions;
+        Ok(())
+    }
+
+    /// Sets how long connections may take to complete their handshake, `None` for no limit.
+    pub fn set_handshake_timeout(&mut self, timeout: Option<Duration>) {
+        self.handshake_timeout = timeout;
+    }
+
+    /// Sets whether the generator callback fills in the connection ids, by default it does if it is set.
+    pub fn set_cid_generator_enabled(&mut self, enabled: bool) {
+        self.cid_generator_enabled = Some(enabled);
+    }
+
+    /// Sets whether the endpoint is polled by the client application rather than a polling thread.
+    pub fn set_manual_polling(&mut self, enabled: bool) {
+        self.manual_polling = enabled;
+    }
+
+    /// Sets the stateless reset key of the endpoint, at least `reset_key::MIN_KEY_LENGTH` bytes.
+    pub fn set_reset_key(&mut self, key: Vec<u8>) -> Result<(), FFIErrorKind> {
+        if key.len() < reset_key::MIN_KEY_LENGTH {
+            return Err(FFIErrorKind::io_error(&format!(
+                "The stateless reset key must be at least {} bytes",
+                reset_key::MIN_KEY_LENGTH
+            )));
+        }
+
+        self.reset_key = Some(key);
+        Ok(())
+    }
+
+    pub fn cid_length(&self) -> usize {
+        self.cid_length
+    }
+
+    pub fn max_udp_payload_size(&self) -> u64 {
+        self.max_udp_payload_size
+    }
+
+    pub fn supported_versions(&self) -> &[u32] {
+        &self.supported_versions
+    }
+
+    pub fn handshake_timeout(&self) -> Option<Duration> {
+        self.handshake_timeout
+    }
+
+    pub fn manual_polling(&self) -> bool {
+        self.manual_polling
+    }
+
+    /// Returns the quinn configuration, with a stateless reset key drawn at the time of the call if none is configured.
+    pub fn config(&self) -> Arc<EndpointConfig> {
+        let (cid_length, cid_generator_enabled) = (self.cid_length, self.cid_generator_enabled);
+        let mut config = EndpointConfig::default();
+        config.supported_versions(self.supported_versions.clone());
+        config.cid_generator(move || cid_generator::generator(cid_length, cid_generator_enabled));
+        config
+            .max_udp_payload_size(self.max_udp_payload_size)
+            .expect("the payload size is checked when set");
+        if let Some(key) = reset_key::hmac_key(self.reset_key.as_deref()) {
+            config.reset_key(key);
+        }
+
+        Arc::new(config)
+    }
+}
+
+impl Default for EndpointConfigImpl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,24 @@
+//! Random bytes for connection ids and stateless reset keys, optionally provided by the client application.
+//!
+//! quinn-proto draws its other randomness, e.g. packet number skips and the TLS secrets, from its own generators.
+//! Those can't be replaced, such that an entropy callback does not make a connection fully deterministic.
+
+use crate::ffi::callbacks;
+use ring::rand::{
+    SecureRandom,
+    SystemRandom,
+};
+
+/// Returns whether the entropy callback is set.
+pub(crate) fn is_host_provided() -> bool {
+    callbacks::has_entropy_source()
+}
+
+/// Fills `bytes` with the entropy callback if it is set, and with the system random number generator otherwise.
+pub(crate) fn fill(bytes: &mut [u8]) {
+    if !callbacks::on_entropy(bytes) {
+        SystemRandom::new()
+            .fill(bytes)
+            .expect("the system random number generator failed");
+    }
+}
@@ -0,0 +1,324 @@
+//! Transfers of files over streams, driven by the stream events of the connection instead of the client application.
+//!
+//! Data is moved between the file and the stream whenever the stream becomes writable or readable,
+//! which saves the client application a round-trip over the FFI for every chunk.
+//! The file is read and written by a worker thread of the transfer, such that file I/O does not hold the connection
+//! lock. The worker hands chunks to the connection over a bounded channel and requests a poll of the connection.
+
+use crate::{
+    proto,
+    proto::{
+        ReadError,
+        StreamId,
+        VarInt,
+        WriteError,
+    },
+    proto_impl::{
+        connection_sender::PollRequest,
+        FFIErrorKind,
+    },
+};
+use bytes::{
+    Buf,
+    Bytes,
+};
+use std::{
+    fs::File,
+    io,
+    io::{
+        Read,
+        Write,
+    },
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        mpsc,
+        mpsc::{
+            TryRecvError,
+            TrySendError,
+        },
+        Arc,
+    },
+    thread,
+};
+
+/// The number of bytes read from the file, or from the stream, at once.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The number of chunks buffered between the worker and the connection.
+const BUFFERED_CHUNKS: usize = 4;
+
+/// The state of a transfer after it advanced.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum TransferState {
+    /// Waiting for the stream to become writable or readable, or for the worker.
+    Pending,
+    /// The whole file is written and the stream is finished, waiting for the peer to acknowledge all data.
+    Finishing,
+    /// The transfer is complete.
+    Complete,
+}
+
+/// A file that is sent over, or received from, a stream.
+pub(crate) struct FileTransfer {
+    direction: Direction,
+}
+
+enum Direction {
+    Send {
+        /// Chunks read by the worker, an empty chunk ends the file.
+        chunks: mpsc::Receiver<io::Result<Bytes>>,
+        /// The part of the last chunk not yet written to the stream.
+        chunk: Bytes,
+        finishing: bool,
+        written: u64,
+    },
+    Receive {
+        chunks: mpsc::SyncSender<Received>,
+        /// Read from the stream, but the worker had no room for it yet.
+        unsent: Option<Received>,
+        /// Whether the end of the stream was read.
+        ended: bool,
+        /// The number of bytes the worker wrote to the file.
+        written: Arc<AtomicU64>,
+        /// The result of the worker once it flushed the file after the end of the stream.
+        done: mpsc::Receiver<io::Result<()>>,
+    },
+}
+
+enum Received {
+    Data(Bytes),
+    End,
+}
+
+impl FileTransfer {
+    /// Starts a worker that reads the file to send it.
+    pub(crate) fn send(mut file: File, poll: PollRequest) -> io::Result<Self> {
+        let (tx, chunks) = mpsc::sync_channel(BUFFERED_CHUNKS);
+
+        spawn_worker(move || loop {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let read = match file.read(&mut chunk) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    let _ = poll.request();
+                    return;
+                }
+            };
+
+            chunk.truncate(read);
+            // The transfer was aborted if the connection dropped the receiver.
+            if tx.send(Ok(Bytes::from(chunk))).is_err() {
+                return;
+            }
+            let _ = poll.request();
+
+            if read == 0 {
+                return;
+            }
+        })?;
+
+        Ok(FileTransfer {
+            direction: Direction::Send {
+                chunks,
+                chunk: Bytes::new(),
+                finishing: false,
+                written: 0,
+            },
+        })
+    }
+
+    /// Starts a worker that writes the data received from the stream to the file.
+    pub(crate) fn receive(mut file: File, poll: PollRequest) -> io::Result<Self> {
+        let (chunks, rx) = mpsc::sync_channel(BUFFERED_CHUNKS);
+        let (done_tx, done) = mpsc::channel();
+        let written = Arc::new(AtomicU64::new(0));
+
+        let worker_written = written.clone();
+        spawn_worker(move || {
+            // The transfer was aborted if the connection dropped the sender.
+            while let Ok(received) = rx.recv() {
+                let (result, end) = match received {
+                    Received::Data(data) => (
+                        file.write_all(&data).map(|_| {
+                            worker_written.fetch_add(data.len() as u64, Ordering::SeqCst);
+                        }),
+                        false,
+                    ),
+                    Received::End => (file.flush(), true),
+                };
+
+                let stop = result.is_err() || end;
+                if stop {
+                    let _ = done_tx.send(result);
+                }
+                let _ = poll.request();
+
+                if stop {
+                    return;
+                }
+            }
+        })?;
+
+        Ok(FileTransfer {
+            direction: Direction::Receive {
+                chunks,
+                unsent: None,
+                ended: false,
+                written,
+                done,
+            },
+        })
+    }
+
+    /// Whether the file is sent, rather than received.
+    pub(crate) fn is_send(&self) -> bool {
+        matches!(self.direction, Direction::Send { .. })
+    }
+
+    /// The number of bytes written to the stream, or to the file.
+    pub(crate) fn transferred(&self) -> u64 {
+        match &self.direction {
+            Direction::Send { written, .. } => *written,
+            Direction::Receive { written, .. } => written.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Moves data between the worker and the stream until either blocks, sends at most `budget` bytes.
+    pub(crate) fn advance(
+        &mut self,
+        connection: &mut proto::Connection,
+        id: StreamId,
+        mut budget: usize,
+    ) -> Result<TransferState, FFIErrorKind> {
+        match &mut self.direction {
+            Direction::Send {
+                chunks,
+                chunk,
+                finishing,
+                written,
+            } => loop {
+                if *finishing {
+                    return Ok(TransferState::Finishing);
+                }
+
+                if chunk.is_empty() {
+                    match chunks.try_recv() {
+                        Ok(Ok(next)) if next.is_empty() => {
+                            connection.send_stream(id).finish()?;
+                            *finishing = true;
+                            continue;
+                        }
+                        Ok(Ok(next)) => *chunk = next,
+                        Ok(Err(e)) => return Err(e.into()),
+                        Err(TryRecvError::Empty) => return Ok(TransferState::Pending),
+                        Err(TryRecvError::Disconnected) => {
+                            return Err(FFIErrorKind::io_error("The file transfer worker stopped"))
+                        }
+                    }
+                }
+
+                if budget == 0 {
+                    return Ok(TransferState::Pending);
+                }
+
+                let len = chunk.len().min(budget);
+                match connection.send_stream(id).write(&chunk[..len]) {
+                    Ok(sent) => {
+                        chunk.advance(sent);
+                        *written += sent as u64;
+                        budget -= sent;
+                    }
+                    Err(WriteError::Blocked) => return Ok(TransferState::Pending),
+                    Err(e) => return Err(e.into()),
+                }
+            },
+            Direction::Receive {
+                chunks,
+                unsent,
+                ended,
+                done,
+                ..
+            } => {
+                let mut result: Result<(), FFIErrorKind> = Ok(());
+
+                // A worker that failed dropped its receiver, its error is taken below.
+                if let Some(received) = unsent.take() {
+                    if let Err(TrySendError::Full(received)) = chunks.try_send(received) {
+                        *unsent = Some(received);
+                    }
+                }
+
+                if unsent.is_none() && !*ended {
+                    let mut stream = connection.recv_stream(id);
+                    let mut read = stream.read(true)?;
+
+                    // Data is only consumed while the worker has room, the rest holds flow control credit.
+                    loop {
+                        let received = match read.next(CHUNK_SIZE) {
+                            Ok(Some(chunk)) => Received::Data(chunk.bytes),
+                            Ok(None) => {
+                                *ended = true;
+                                Received::End
+                            }
+                            Err(ReadError::Blocked) => break,
+                            Err(e) => {
+                                result = Err(e.into());
+                                break;
+                            }
+                        };
+
+                        match chunks.try_send(received) {
+                            Ok(()) if !*ended => {}
+                            Ok(()) => break,
+                            Err(TrySendError::Full(received)) => {
+                                *unsent = Some(received);
+                                break;
+                            }
+                            Err(TrySendError::Disconnected(_)) => break,
+                        }
+                    }
+
+                    // The connection transmits flow control updates when it is polled after the stream event.
+                    let _ = read.finalize();
+                }
+
+                result?;
+                match done.try_recv() {
+                    Ok(result) => result
+                        .map(|_| TransferState::Complete)
+                        .map_err(FFIErrorKind::from),
+                    Err(TryRecvError::Empty) => Ok(TransferState::Pending),
+                    Err(TryRecvError::Disconnected) => {
+                        Err(FFIErrorKind::io_error("The file transfer worker stopped"))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Tells the peer that the transfer was aborted, by resetting or stopping the stream.
+    ///
+    /// The worker stops once the transfer is dropped.
+    pub(crate) fn abort(&self, connection: &mut proto::Connection, id: StreamId) {
+        match self.direction {
+            Direction::Send { .. } => {
+                let _ = connection.send_stream(id).reset(VarInt::from_u32(0));
+            }
+            Direction::Receive { .. } => {
+                let _ = connection.recv_stream(id).stop(VarInt::from_u32(0));
+            }
+        }
+    }
+}
+
+fn spawn_worker(work: impl FnOnce() + Send + 'static) -> io::Result<()> {
+    thread::Builder::new()
+        .name("quinn-ffi-file-transfer".to_owned())
+        .spawn(work)
+        .map(|_| ())
+}
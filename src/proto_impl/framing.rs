@@ -0,0 +1,184 @@
+//! Messages on streams, such that client applications exchange whole messages instead of reassembling bytes.
+//!
+//! Each message is prefixed with its length as a 4-byte big-endian integer.
+
+use crate::{
+    ffi::FFIResultKind,
+    proto,
+    proto::{
+        ReadError,
+        StreamId,
+        WriteError,
+    },
+    proto_impl::FFIErrorKind,
+};
+use bytes::{
+    Buf,
+    BufMut,
+    Bytes,
+    BytesMut,
+};
+use std::{
+    collections::VecDeque,
+    convert::TryInto,
+};
+
+/// The largest message that is sent or accepted, larger messages from the peer fail the stream.
+pub(crate) const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+const PREFIX_SIZE: usize = 4;
+
+/// The messages of a stream in framing mode.
+#[derive(Default)]
+pub(crate) struct FramedStream {
+    // received bytes of the message that is not complete yet, including its prefix.
+    partial: BytesMut,
+    // complete messages that are not read yet.
+    messages: VecDeque<Bytes>,
+    // bytes of the last written message that flow control did not allow yet.
+    unwritten: Bytes,
+    // bytes written to and read from the stream, including the length prefixes.
+    sent: u64,
+    received: u64,
+}
+
+impl FramedStream {
+    /// Writes the message, at most `budget` bytes of it now, the rest is written by `flush` once flow control allows.
+    ///
+    /// Fails with `BufferBlocked` while the previous message is not written completely.
+    pub(crate) fn write(
+        &mut self,
+        connection: &mut proto::Connection,
+        id: StreamId,
+        message: &[u8],
+        budget: usize,
+    ) -> Result<(), FFIErrorKind> {
+        if !self.unwritten.is_empty() {
+            return Err(FFIErrorKind::FFIResultKind(FFIResultKind::BufferBlocked));
+        }
+        if message.len() > MAX_MESSAGE_SIZE {
+            return Err(FFIErrorKind::io_error(
+                "The message exceeds the maximum size",
+            ));
+        }
+
+        let mut frame = BytesMut::with_capacity(PREFIX_SIZE + message.len());
+        frame.put_u32(message.len() as u32);
+        frame.put_slice(message);
+        self.unwritten = frame.freeze();
+
+        self.flush(connection, id, budget).map(|_| ())
+    }
+
+    /// Writes at most `budget` bytes of the rest of the last message, returns whether it is written completely.
+    pub(crate) fn flush(
+        &mut self,
+        connection: &mut proto::Connection,
+        id: StreamId,
+        mut budget: usize,
+    ) -> Result<bool, FFIErrorKind> {
+        while !self.unwritten.is_empty() {
+            if budget == 0 {
+                return Ok(false);
+            }
+
+            let len = self.unwritten.len().min(budget);
+            match connection.send_stream(id).write(&self.unwritten[..len]) {
+                Ok(written) => {
+                    self.unwritten.advance(written);
+                    self.sent += written as u64;
+                    budget -= written;
+                }
+                Err(WriteError::Blocked) => return Ok(false),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Reads the received data of the stream, returns the number of messages that were completed.
+    pub(crate) fn receive(
+        &mut self,
+        connection: &mut proto::Connection,
+        id: StreamId,
+    ) -> Result<usize, FFIErrorKind> {
+        let mut stream = connection.recv_stream(id);
+        let mut chunks = stream.read(true)?;
+        let mut error: Option<FFIErrorKind> = None;
+
+        loop {
+            match chunks.next(usize::MAX) {
+                Ok(Some(chunk)) => {
+                    self.partial.extend_from_slice(&chunk.bytes);
+                    self.received += chunk.bytes.len() as u64;
+                }
+                Ok(None) | Err(ReadError::Blocked) => break,
+                Err(e) => {
+                    error = Some(e.into());
+                    break;
+                }
+            }
+        }
+
+        // The connection transmits flow control updates when it is polled after the stream event.
+        let _ = chunks.finalize();
+
+        let mut completed = 0;
+        while self.partial.len() >= PREFIX_SIZE {
+            let length =
+                u32::from_be_bytes(self.partial[..PREFIX_SIZE].try_into().unwrap()) as usize;
+            if length > MAX_MESSAGE_SIZE {
+                error = Some(FFIErrorKind::io_error(
+                    "The peer sent a message exceeding the maximum size",
+                ));
+                break;
+            }
+            if self.partial.len() < PREFIX_SIZE + length {
+                break;
+            }
+
+            self.partial.advance(PREFIX_SIZE);
+            self.messages
+                .push_back(self.partial.split_to(length).freeze());
+            completed += 1;
+        }
+
+        match error {
+            // Messages completed before the error are delivered first, the next receive fails.
+            Some(_) if completed > 0 => Ok(completed),
+            Some(e) => Err(e),
+            None => Ok(completed),
+        }
+    }
+
+    /// Returns the next complete message without removing it.
+    pub(crate) fn peek_message(&self) -> Option<&Bytes> {
+        self.messages.front()
+    }
+
+    /// Removes the next complete message.
+    pub(crate) fn pop_message(&mut self) -> Option<Bytes> {
+        self.messages.pop_front()
+    }
+
+    /// The number of bytes written to the stream, including the length prefixes.
+    pub(crate) fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Returns whether the last written message is written completely.
+    pub(crate) fn is_flushed(&self) -> bool {
+        self.unwritten.is_empty()
+    }
+
+    /// The number of bytes read from the stream, including the length prefixes.
+    pub(crate) fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Returns whether no data is buffered in either direction.
+    pub(crate) fn is_idle(&self) -> bool {
+        self.partial.is_empty() && self.messages.is_empty() && self.unwritten.is_empty()
+    }
+}
@@ -0,0 +1,191 @@
+//! HTTP/3 control-plane scaffolding with the `h3` feature: the `h3` ALPN and the SETTINGS frame of the control stream.
+//!
+//! Bindings exchange requests on raw streams themselves, this module only opens the control stream and parses the one
+//! of the peer, see RFC 9114 section 6.2.1. Field sections are expected to be encoded without the QPACK dynamic table,
+//! such that no encoder or decoder streams are needed, so QPACK settings other than zero are rejected.
+
+use crate::proto_impl::FFIErrorKind;
+
+/// The ALPN protocol of HTTP/3.
+pub(crate) const ALPN: &[u8] = b"h3";
+
+/// A setting of the SETTINGS frame, an identifier and its value.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct H3Setting {
+    pub id: u64,
+    pub value: u64,
+}
+
+#[cfg(feature = "h3")]
+mod codec {
+    use super::H3Setting;
+    use crate::{
+        ffi::FFIResultKind,
+        proto_impl::FFIErrorKind,
+    };
+
+    /// The stream type of the control stream.
+    const CONTROL_STREAM_TYPE: u64 = 0x00;
+    /// The frame type of a SETTINGS frame.
+    const SETTINGS_FRAME_TYPE: u64 = 0x04;
+    /// The setting identifiers of the QPACK dynamic table, which must stay zero.
+    const SETTINGS_QPACK_MAX_TABLE_CAPACITY: u64 = 0x01;
+    const SETTINGS_QPACK_BLOCKED_STREAMS: u64 = 0x07;
+    /// Setting identifiers of HTTP/2 that must not be sent in HTTP/3.
+    const RESERVED_HTTP2_SETTINGS: [u64; 4] = [0x02, 0x03, 0x04, 0x05];
+    /// The largest value of a QUIC variable-length integer.
+    const MAX_VARINT: u64 = (1 << 62) - 1;
+
+    /// Returns the bytes that start the control stream: its stream type and the SETTINGS frame.
+    pub(crate) fn encode_control_stream(settings: &[H3Setting]) -> Result<Vec<u8>, FFIErrorKind> {
+        validate(settings)?;
+
+        let mut payload = Vec::new();
+        for setting in settings {
+            if setting.id > MAX_VARINT || setting.value > MAX_VARINT {
+                return Err(FFIErrorKind::FFIResultKind(FFIResultKind::InvalidArgument));
+            }
+            put_varint(&mut payload, setting.id);
+            put_varint(&mut payload, setting.value);
+        }
+
+        let mut stream = Vec::with_capacity(payload.len() + 10);
+        put_varint(&mut stream, CONTROL_STREAM_TYPE);
+        put_varint(&mut stream, SETTINGS_FRAME_TYPE);
+        put_varint(&mut stream, payload.len() as u64);
+        stream.extend_from_slice(&payload);
+        Ok(stream)
+    }
+
+    /// Parses the start of the control stream of the peer, `None` if the SETTINGS frame is not complete yet.
+    ///
+    /// Returns the settings and the bytes they took, the frames that follow are left to the client application.
+    pub(crate) fn parse_control_stream(
+        data: &[u8],
+    ) -> Result<Option<(Vec<H3Setting>, usize)>, FFIErrorKind> {
+        let mut position = 0;
+        let header = (|| {
+            let stream_type = get_varint(data, &mut position)?;
+            let frame_type = get_varint(data, &mut position)?;
+            let length = get_varint(data, &mut position)?;
+            Some((stream_type, frame_type, length))
+        })();
+
+        let (stream_type, frame_type, length) = match header {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+
+        if stream_type != CONTROL_STREAM_TYPE {
+            return Err(FFIErrorKind::io_error(
+                "The stream is not an HTTP/3 control stream",
+            ));
+        }
+        if frame_type != SETTINGS_FRAME_TYPE {
+            return Err(FFIErrorKind::io_error(
+                "The first frame of the HTTP/3 control stream is not a SETTINGS frame",
+            ));
+        }
+
+        let end = match position.checked_add(length as usize) {
+            Some(end) if end <= data.len() => end,
+            _ => return Ok(None),
+        };
+
+        let payload = &data[..end];
+        let mut settings = Vec::new();
+        while position < end {
+            let id = get_varint(payload, &mut position);
+            let value = get_varint(payload, &mut position);
+            match (id, value) {
+                (Some(id), Some(value)) => settings.push(H3Setting { id, value }),
+                _ => {
+                    return Err(FFIErrorKind::io_error(
+                        "The HTTP/3 SETTINGS frame is malformed",
+                    ))
+                }
+            }
+        }
+
+        validate(&settings)?;
+        Ok(Some((settings, end)))
+    }
+
+    /// Rejects duplicate settings, HTTP/2 settings and a QPACK dynamic table.
+    fn validate(settings: &[H3Setting]) -> Result<(), FFIErrorKind> {
+        for (index, setting) in settings.iter().enumerate() {
+            if settings[..index].iter().any(|other| other.id == setting.id) {
+                return Err(FFIErrorKind::io_error("An HTTP/3 setting is repeated"));
+            }
+            if RESERVED_HTTP2_SETTINGS.contains(&setting.id) {
+                return Err(FFIErrorKind::io_error(
+                    "HTTP/2 settings are not allowed in HTTP/3",
+                ));
+            }
+            if matches!(
+                setting.id,
+                SETTINGS_QPACK_MAX_TABLE_CAPACITY | SETTINGS_QPACK_BLOCKED_STREAMS
+            ) && setting.value != 0
+            {
+                return Err(FFIErrorKind::io_error(
+                    "The QPACK dynamic table is not supported, its settings must be zero",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn put_varint(buf: &mut Vec<u8>, value: u64) {
+        match value {
+            0..=0x3f => buf.push(value as u8),
+            0x40..=0x3fff => buf.extend_from_slice(&(value as u16 | 0x4000).to_be_bytes()),
+            0x4000..=0x3fff_ffff => {
+                buf.extend_from_slice(&(value as u32 | 0x8000_0000).to_be_bytes())
+            }
+            _ => buf.extend_from_slice(&(value | 0xc000_0000_0000_0000).to_be_bytes()),
+        }
+    }
+
+    fn get_varint(data: &[u8], position: &mut usize) -> Option<u64> {
+        let first = *data.get(*position)?;
+        let length = 1 << (first >> 6);
+        let bytes = data.get(*position..*position + length)?;
+
+        let value = bytes[1..]
+            .iter()
+            .fold(u64::from(first & 0x3f), |value, byte| {
+                value << 8 | u64::from(*byte)
+            });
+        *position += length;
+        Some(value)
+    }
+}
+
+#[cfg(feature = "h3")]
+pub(crate) use codec::{
+    encode_control_stream,
+    parse_control_stream,
+};
+
+#[cfg(not(feature = "h3"))]
+pub(crate) fn encode_control_stream(_: &[H3Setting]) -> Result<Vec<u8>, FFIErrorKind> {
+    Err(FFIErrorKind::unsupported("h3"))
+}
+
+#[cfg(not(feature = "h3"))]
+pub(crate) fn parse_control_stream(
+    _: &[u8],
+) -> Result<Option<(Vec<H3Setting>, usize)>, FFIErrorKind> {
+    Err(FFIErrorKind::unsupported("h3"))
+}
+
+/// Returns the ALPN protocols of HTTP/3.
+pub(crate) fn alpn_protocols() -> Result<Vec<Vec<u8>>, FFIErrorKind> {
+    if cfg!(feature = "h3") {
+        Ok(vec![ALPN.to_vec()])
+    } else {
+        Err(FFIErrorKind::unsupported("h3"))
+    }
+}
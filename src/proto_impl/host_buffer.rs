@@ -0,0 +1,56 @@
+//! Buffers of the client application that are written to streams without copying them.
+
+use crate::ffi::defer;
+use bytes::Bytes;
+use std::slice;
+
+/// Frees a buffer of the client application, with the buffer and the context passed to `write_stream_owned`.
+pub type FreeFn = extern "C" fn(*const u8, u64);
+
+/// A buffer owned by the library until `free_fn` is invoked.
+pub(crate) struct HostBuffer {
+    data: *const u8,
+    len: usize,
+    free_fn: FreeFn,
+    free_ctx: u64,
+}
+
+// The client application passes ownership of the buffer, which is only read until `free_fn` is invoked.
+unsafe impl Send for HostBuffer {}
+
+impl HostBuffer {
+    /// Wraps the buffer such that `free_fn` is invoked once the last reference to its `Bytes` is dropped.
+    ///
+    /// # Safety
+    ///
+    /// `data` must be valid for reads of `len` bytes until `free_fn` is invoked.
+    pub(crate) unsafe fn into_bytes(
+        data: *const u8,
+        len: usize,
+        free_fn: FreeFn,
+        free_ctx: u64,
+    ) -> Bytes {
+        Bytes::from_owner(HostBuffer {
+            data,
+            len,
+            free_fn,
+            free_ctx,
+        })
+    }
+}
+
+impl AsRef<[u8]> for HostBuffer {
+    fn as_ref(&self) -> &[u8] {
+        // Valid until `free_fn` is invoked, as required by `into_bytes`.
+        unsafe { slice::from_raw_parts(self.data, self.len) }
+    }
+}
+
+impl Drop for HostBuffer {
+    fn drop(&mut self) {
+        // Quinn drops the buffer while the connection is locked, e.g. when the data is acknowledged.
+        // The address is moved as an integer, since the callback may be queued for the dispatching thread.
+        let (data, free_fn, free_ctx) = (self.data as usize, self.free_fn, self.free_ctx);
+        defer(move || free_fn(data as *const u8, free_ctx));
+    }
+}
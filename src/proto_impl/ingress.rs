@@ -0,0 +1,86 @@
+//! A queue that socket reader threads submit datagrams to without locking the endpoint.
+//!
+//! Submitting only pushes to the queue, the thread or pool that polls the endpoint handles the queued datagrams.
+
+use crate::{
+    ffi::{
+        callbacks,
+        FFIResultKind,
+    },
+    proto_impl::{
+        FFIErrorKind,
+        PollNotifier,
+    },
+};
+use bytes::BytesMut;
+use quinn_proto::EcnCodepoint;
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        mpsc,
+        Arc,
+    },
+};
+
+/// A datagram submitted to the ingress queue of an endpoint.
+pub(crate) struct IngressDatagram {
+    pub(crate) data: BytesMut,
+    pub(crate) addr: SocketAddr,
+    pub(crate) ecn: Option<EcnCodepoint>,
+}
+
+/// The producer side of the ingress queue of an endpoint, shared by the reader threads.
+pub struct DatagramIngress {
+    sender: mpsc::Sender<IngressDatagram>,
+    endpoint_id: u64,
+    notifier: Option<PollNotifier>,
+    // the maximum UDP payload size of the endpoint, larger datagrams are rejected before they are queued.
+    max_udp_payload_size: u64,
+    // whether a poll of the endpoint was requested that did not yet drain the queue.
+    pending: Arc<AtomicBool>,
+}
+
+impl DatagramIngress {
+    pub(crate) fn new(
+        sender: mpsc::Sender<IngressDatagram>,
+        endpoint_id: u64,
+        notifier: Option<PollNotifier>,
+        max_udp_payload_size: u64,
+        pending: Arc<AtomicBool>,
+    ) -> Self {
+        DatagramIngress {
+            sender,
+            endpoint_id,
+            notifier,
+            max_udp_payload_size,
+            pending,
+        }
+    }
+
+    /// Queues the datagram, the first datagram after a drain requests a poll of the endpoint.
+    ///
+    /// Fails with `DatagramTooLarge` if the datagram exceeds the maximum UDP payload size, like `handle_datagram`.
+    pub(crate) fn submit(&self, datagram: IngressDatagram) -> Result<(), FFIErrorKind> {
+        if datagram.data.len() as u64 > self.max_udp_payload_size {
+            return Err(FFIErrorKind::FFIResultKind(FFIResultKind::DatagramTooLarge));
+        }
+
+        self.sender.send(datagram)?;
+
+        if self.pending.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        match self.notifier.as_ref() {
+            Some(notifier) => notifier.notify(),
+            None => {
+                callbacks::on_endpoint_pollable(self.endpoint_id);
+                Ok(())
+            }
+        }
+    }
+}
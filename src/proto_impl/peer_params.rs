@@ -0,0 +1,27 @@
+//! The transport parameters negotiated with the peer of a connection.
+
+use quinn_proto::Connection;
+
+/// The transport parameters of the peer that are FFI safe.
+///
+/// quinn-proto keeps the other parameters of the peer private, e.g. its idle timeout and initial stream limits.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PeerParams {
+    /// Whether unreliable datagrams can be sent, the peer negotiated them and they are enabled locally.
+    pub datagrams_supported: bool,
+    /// The largest datagram that can be sent, zero if datagrams are not supported.
+    pub max_datagram_size: u64,
+}
+
+impl PeerParams {
+    /// Reads the parameters of the peer, these are only known once the handshake completed.
+    pub(crate) fn of(connection: &mut Connection) -> Self {
+        let max_datagram_size = connection.datagrams().max_size();
+
+        PeerParams {
+            datagrams_supported: max_datagram_size.is_some(),
+            max_datagram_size: max_datagram_size.unwrap_or(0) as u64,
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! A shared pool of threads that polls endpoints and their connections, instead of an `EndpointPoller` thread per endpoint.
+//!
+//! Poll requests are queued in a single queue that all workers take from, such that an idle worker picks up the next pollable
+//! endpoint or connection while the others are busy. Requests for endpoints with pending work, i.e. received datagrams,
+//! transmits or connections marked pollable, are taken before endpoints whose connection timers expired.
+//! A request for an endpoint or connection that is already queued is not queued again.
+
+use crate::{
+    ffi::{
+        poison::lock_recovering,
+        LockScope,
+    },
+    proto,
+    proto_impl::{
+        clock,
+        endpoint,
+        EndpointImpl,
+        FFIErrorKind,
+    },
+};
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+        VecDeque,
+    },
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+        PoisonError,
+        Weak,
+    },
+    thread,
+    time::Instant,
+};
+
+/// The scheduler of the pool, `None` if the pool is not running.
+static SCHEDULER: Mutex<Option<Arc<Scheduler>>> = Mutex::new(None);
+
+/// A poll request, holds the endpoint weakly such that queued jobs don't keep a disposed endpoint alive.
+enum Job {
+    Endpoint(Weak<Mutex<EndpointImpl>>),
+    Connection(Weak<Mutex<EndpointImpl>>, proto::ConnectionHandle),
+    /// The earliest connection timer of the endpoint expired.
+    Timer(Weak<Mutex<EndpointImpl>>),
+}
+
+impl Job {
+    /// Identifies the job, such that a job that is already queued is not queued again.
+    fn key(&self) -> (usize, Option<proto::ConnectionHandle>) {
+        match self {
+            Job::Endpoint(endpoint) | Job::Timer(endpoint) => (endpoint.as_ptr() as usize, None),
+            Job::Connection(endpoint, connection) => {
+                (endpoint.as_ptr() as usize, Some(*connection))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    /// Polls requested because of pending work, in order of the requests.
+    ready: VecDeque<Job>,
+    /// The keys of the jobs in `ready`.
+    queued: HashSet<(usize, Option<proto::ConnectionHandle>)>,
+    /// The earliest connection timer of each endpoint polled by the pool.
+    timers: HashMap<usize, (Instant, Weak<Mutex<EndpointImpl>>)>,
+    /// Cleared when the pool is stopped, the workers exit once `ready` is empty.
+    running: bool,
+}
+
+struct Scheduler {
+    queue: Mutex<Queue>,
+    wakeup: Condvar,
+}
+
+impl Scheduler {
+    fn schedule(&self, job: Job) {
+        let mut queue = lock_recovering(&self.queue);
+        if queue.queued.insert(job.key()) {
+            queue.ready.push_back(job);
+            self.wakeup.notify_one();
+        }
+    }
+
+    /// Sets the earliest connection timer of the endpoint, `None` if its connections have no timer.
+    fn set_timer(&self, endpoint: Weak<Mutex<EndpointImpl>>, deadline: Option<Instant>) {
+        let mut queue = lock_recovering(&self.queue);
+        let key = endpoint.as_ptr() as usize;
+        match deadline {
+            Some(deadline) => {
+                queue.timers.insert(key, (deadline, endpoint));
+            }
+            None => {
+                queue.timers.remove(&key);
+            }
+        }
+
+        // A sleeping worker may wait for a later timer.
+        self.wakeup.notify_one();
+    }
+
+    /// Waits for the next job, `None` once the pool is stopped.
+    ///
+    /// Jobs of pending work are taken first, timers only once no such job is queued.
+    fn next(&self) -> Option<Job> {
+        let mut queue = lock_recovering(&self.queue);
+        loop {
+            if let Some(job) = queue.ready.pop_front() {
+                queue.queued.remove(&job.key());
+                return Some(job);
+            }
+            if !queue.running {
+                return None;
+            }
+
+            let now = clock::now();
+            let earliest = queue
+                .timers
+                .iter()
+                .min_by_key(|(_, (deadline, _))| *deadline)
+                .map(|(key, (deadline, _))| (*key, *deadline));
+
+            queue = match earliest {
+                Some((key, deadline)) if deadline <= now => {
+                    let (_, endpoint) = queue.timers.remove(&key).unwrap();
+                    return Some(Job::Timer(endpoint));
+                }
+                Some((_, deadline)) => {
+                    self.wakeup
+                        .wait_timeout(queue, deadline.saturating_duration_since(now))
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .wakeup
+                    .wait(queue)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+}
+
+/// Starts the pool with the given number of worker threads, endpoints created from now on are polled by the pool.
+///
+/// The workers run until the pool is stopped, the pool can only be started while it is not running.
+pub(crate) fn start(threads: usize) -> Result<(), FFIErrorKind> {
+    if threads == 0 {
+        return Err(FFIErrorKind::io_error(
+            "The poller pool needs at least one thread",
+        ));
+    }
+
+    let mut current = lock_recovering(&SCHEDULER);
+    if current.is_some() {
+        return Err(FFIErrorKind::io_error("The poller pool is already running"));
+    }
+
+    let scheduler = Arc::new(Scheduler {
+        queue: Mutex::new(Queue {
+            running: true,
+            ..Queue::default()
+        }),
+        wakeup: Condvar::new(),
+    });
+
+    for i in 0..threads {
+        let scheduler = scheduler.clone();
+        thread::Builder::new()
+            .name(format!("quinn-ffi-poller-{}", i))
+            .spawn(move || work(&scheduler))?;
+    }
+
+    *current = Some(scheduler);
+    Ok(())
+}
+
+/// Stops the pool, the workers exit once they finished the queued jobs.
+///
+/// Endpoints polled by the pool are no longer polled, scheduling a poll fails.
+pub(crate) fn stop() {
+    if let Some(scheduler) = lock_recovering(&SCHEDULER).take() {
+        let mut queue = lock_recovering(&scheduler.queue);
+        queue.running = false;
+        queue.timers.clear();
+        scheduler.wakeup.notify_all();
+    }
+}
+
+/// Returns whether the pool is running.
+pub(crate) fn is_running() -> bool {
+    lock_recovering(&SCHEDULER).is_some()
+}
+
+/// Queues a poll of the endpoint.
+pub(crate) fn schedule_endpoint(endpoint: Weak<Mutex<EndpointImpl>>) -> Result<(), FFIErrorKind> {
+    schedule(Job::Endpoint(endpoint))
+}
+
+/// Queues a poll of the connection of the endpoint, followed by a poll of the endpoint.
+pub(crate) fn schedule_connection(
+    endpoint: Weak<Mutex<EndpointImpl>>,
+    connection: proto::ConnectionHandle,
+) -> Result<(), FFIErrorKind> {
+    schedule(Job::Connection(endpoint, connection))
+}
+
+fn schedule(job: Job) -> Result<(), FFIErrorKind> {
+    match lock_recovering(&SCHEDULER).as_ref() {
+        Some(scheduler) => {
+            scheduler.schedule(job);
+            Ok(())
+        }
+        None => Err(FFIErrorKind::io_error("The poller pool is not running")),
+    }
+}
+
+fn work(scheduler: &Scheduler) {
+    while let Some(job) = scheduler.next() {
+        let _scope = LockScope::enter();
+        let result = match job {
+            Job::Endpoint(endpoint) => poll_endpoint(scheduler, endpoint, false),
+            Job::Timer(endpoint) => poll_endpoint(scheduler, endpoint, true),
+            Job::Connection(endpoint, connection) => {
+                poll_connection(scheduler, endpoint, connection)
+            }
+        };
+
+        if let Err(e) = result {
+            tracing::error!("Polling on the poller pool failed: {}", e);
+        }
+    }
+}
+
+/// Polls the endpoint and the connections whose timer expired, then waits for the earliest timer of its connections.
+fn poll_endpoint(
+    scheduler: &Scheduler,
+    endpoint: Weak<Mutex<EndpointImpl>>,
+    timer: bool,
+) -> Result<(), FFIErrorKind> {
+    if let Some(endpoint_ref) = endpoint.upgrade() {
+        // A failed poll is retried, such that the timers of the endpoint keep running.
+        let deadline = match endpoint::poll_with_timers(&endpoint_ref, timer) {
+            Ok(deadline) => deadline,
+            Err(e) => Some(endpoint::report_poll_error(&endpoint_ref, e)),
+        };
+        scheduler.set_timer(endpoint, deadline);
+    }
+
+    Ok(())
+}
+
+fn poll_connection(
+    scheduler: &Scheduler,
+    endpoint: Weak<Mutex<EndpointImpl>>,
+    connection: proto::ConnectionHandle,
+) -> Result<(), FFIErrorKind> {
+    // The endpoint lock is released before the connection is locked.
+    let connection = match endpoint.upgrade() {
+        Some(endpoint_ref) => lock_recovering(&endpoint_ref).connection(connection),
+        None => None,
+    };
+
+    if let Some(connection) = connection {
+        lock_recovering(&connection).poll()?;
+        poll_endpoint(scheduler, endpoint, false)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,133 @@
+//! Writes connection events in the qlog JSON-SEQ format which can be loaded by qvis.
+//!
+//! Quinn does not expose packet level events, so only the events observable by this crate are logged:
+//! connection state changes, sent and received datagrams and recovery metrics.
+
+use crate::proto::{
+    ConnectionStats,
+    Side,
+};
+use std::{
+    fs::File,
+    io,
+    io::{
+        BufWriter,
+        Write,
+    },
+    path::Path,
+    time::{
+        Instant,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+/// The record separator that precedes every JSON-SEQ record.
+const RECORD_SEPARATOR: u8 = 0x1e;
+
+/// Writes qlog events of a single connection to a file.
+pub struct QlogWriter {
+    writer: BufWriter<File>,
+    start: Instant,
+    received_datagrams: u64,
+    smoothed_rtt_micros: u128,
+    congestion_window: u64,
+}
+
+impl QlogWriter {
+    /// Creates the qlog file at the given path and writes the qlog header.
+    pub fn create(path: &Path, side: Side) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let reference_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis())
+            .unwrap_or_default();
+        let vantage_point = if side.is_client() { "client" } else { "server" };
+
+        writer.write_all(&[RECORD_SEPARATOR])?;
+        writeln!(
+            writer,
+            r#"{{"qlog_version":"0.3","qlog_format":"JSON-SEQ","title":"quinn-ffi","trace":{{"vantage_point":{{"type":"{}"}},"common_fields":{{"time_format":"relative","reference_time":{}}}}}}}"#,
+            vantage_point, reference_time
+        )?;
+
+        Ok(QlogWriter {
+            writer,
+            start: Instant::now(),
+            received_datagrams: 0,
+            smoothed_rtt_micros: 0,
+            congestion_window: 0,
+        })
+    }
+
+    /// Logs a change of the connection state.
+    pub fn connection_state_updated(&mut self, new_state: &str) {
+        self.event(
+            "connectivity:connection_state_updated",
+            &format!(r#"{{"new":"{}"}}"#, new_state),
+        );
+    }
+
+    /// Logs a datagram sent by the connection.
+    pub fn datagram_sent(&mut self, length: usize) {
+        self.event(
+            "transport:datagrams_sent",
+            &format!(r#"{{"count":1,"raw":[{{"length":{}}}]}}"#, length),
+        );
+    }
+
+    /// Logs the received datagrams and recovery metrics that changed since the last update.
+    pub fn update_metrics(&mut self, stats: &ConnectionStats) {
+        if stats.udp_rx.datagrams > self.received_datagrams {
+            self.event(
+                "transport:datagrams_received",
+                &format!(
+                    r#"{{"count":{}}}"#,
+                    stats.udp_rx.datagrams - self.received_datagrams
+                ),
+            );
+            self.received_datagrams = stats.udp_rx.datagrams;
+        }
+
+        let smoothed_rtt_micros = stats.path.rtt.as_micros();
+
+        if smoothed_rtt_micros != self.smoothed_rtt_micros
+            || stats.path.cwnd != self.congestion_window
+        {
+            self.event(
+                "recovery:metrics_updated",
+                &format!(
+                    r#"{{"smoothed_rtt":{:.3},"congestion_window":{}}}"#,
+                    smoothed_rtt_micros as f64 / 1000.0,
+                    stats.path.cwnd
+                ),
+            );
+            self.smoothed_rtt_micros = smoothed_rtt_micros;
+            self.congestion_window = stats.path.cwnd;
+        }
+    }
+
+    /// Flushes the buffered events to the file.
+    pub fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            tracing::warn!("Failed to flush qlog: {}", e);
+        }
+    }
+
+    fn event(&mut self, name: &str, data: &str) {
+        let time = self.start.elapsed().as_micros() as f64 / 1000.0;
+
+        let result = self.writer.write_all(&[RECORD_SEPARATOR]).and_then(|_| {
+            writeln!(
+                self.writer,
+                r#"{{"time":{:.3},"name":"{}","data":{}}}"#,
+                time, name, data
+            )
+        });
+
+        if let Err(e) = result {
+            tracing::warn!("Failed to write qlog event: {}", e);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::proto_impl::{
+    FFIErrorKind,
+    IpAddr,
+};
+use bytes::BytesMut;
+use std::collections::HashMap;
+
+/// The default size of a receive buffer, enough for any datagram on a typical path MTU.
+const DEFAULT_BUFFER_SIZE: usize = 2048;
+/// The default number of receive buffers that share one allocation.
+const DEFAULT_BUFFER_COUNT: usize = 64;
+
+/// Receive buffers the client application reads datagrams into, such that they can be handed to quinn without copying.
+///
+/// Buffers are split off a shared allocation, which is reclaimed once quinn dropped all buffers split off it.
+pub struct RecvBufferPool {
+    arena: BytesMut,
+    buffer_size: usize,
+    buffer_count: usize,
+    acquired: HashMap<u32, BytesMut>,
+    next_id: u32,
+}
+
+impl Default for RecvBufferPool {
+    fn default() -> Self {
+        RecvBufferPool {
+            arena: BytesMut::new(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            buffer_count: DEFAULT_BUFFER_COUNT,
+            acquired: HashMap::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl RecvBufferPool {
+    /// Creates a pool whose buffers fit datagrams of the maximum UDP payload size of the endpoint.
+    pub fn for_payload_size(max_udp_payload_size: usize) -> Self {
+        RecvBufferPool {
+            buffer_size: DEFAULT_BUFFER_SIZE.max(max_udp_payload_size),
+            ..Default::default()
+        }
+    }
+
+    /// Changes the size of the buffers and the number of buffers that share one allocation.
+    ///
+    /// Buffers that are already acquired keep their size.
+    pub fn configure(
+        &mut self,
+        buffer_size: usize,
+        buffer_count: usize,
+    ) -> Result<(), FFIErrorKind> {
+        if buffer_size == 0 || buffer_count == 0 {
+            return Err(FFIErrorKind::io_error(
+                "Receive buffer size and count must be greater than zero",
+            ));
+        }
+
+        self.buffer_size = buffer_size;
+        self.buffer_count = buffer_count;
+        self.arena = BytesMut::new();
+
+        Ok(())
+    }
+
+    /// Acquires a zeroed buffer, returns its id and the buffer.
+    ///
+    /// The buffer stays at the same memory location until it is committed.
+    pub fn acquire(&mut self) -> (u32, &mut [u8]) {
+        if self.arena.capacity() < self.buffer_size {
+            // Reclaims the previous allocation if quinn dropped all buffers split off it.
+            self.arena.reserve(self.buffer_size * self.buffer_count);
+        }
+
+        self.arena.resize(self.buffer_size, 0);
+        let buffer = self.arena.split_to(self.buffer_size);
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        self.acquired.insert(id, buffer);
+        (id, &mut self.acquired.get_mut(&id).unwrap()[..])
+    }
+
+    /// Copies a datagram the client application keeps ownership of into the shared allocation.
+    ///
+    /// quinn decrypts in place and keeps the buffer of packets for a connection, such that the datagram must be copied,
+    /// but the copies share an allocation instead of allocating one buffer per datagram.
+    pub fn copy_in(&mut self, data: &[u8]) -> BytesMut {
+        if self.arena.capacity() < data.len() {
+            // Reclaims the previous allocation if quinn dropped all buffers split off it.
+            self.arena
+                .reserve((self.buffer_size * self.buffer_count).max(data.len()));
+        }
+
+        self.arena.extend_from_slice(data);
+        self.arena.split_to(data.len())
+    }
+
+    /// Returns the buffer with the given id truncated to the given length.
+    pub fn commit(&mut self, id: u32, length: usize) -> Result<BytesMut, FFIErrorKind> {
+        let mut buffer = self
+            .acquired
+            .remove(&id)
+            .ok_or_else(|| FFIErrorKind::io_error("Unknown receive buffer"))?;
+
+        if length > buffer.len() {
+            return Err(FFIErrorKind::io_error(
+                "Length exceeds the size of the receive buffer",
+            ));
+        }
+
+        buffer.truncate(length);
+        Ok(buffer)
+    }
+}
+
+/// A received datagram, an entry of the batch passed to `handle_datagrams`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct RecvDatagram {
+    /// Pointer to the datagram contents.
+    pub data: *const u8,
+    /// The length of the datagram contents.
+    pub length: usize,
+    /// The address the datagram was received from.
+    pub address: IpAddr,
+    /// The ECN bits of the IP header, 0 if not available.
+    pub ecn: u8,
+}
+
+// The datagram is only read for the duration of the FFI call it is passed to.
+unsafe impl Send for RecvDatagram {}
+unsafe impl Sync for RecvDatagram {}
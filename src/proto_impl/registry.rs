@@ -0,0 +1,53 @@
+//! Process-wide registry of the endpoints created over FFI.
+
+use crate::proto_impl::EndpointImpl;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+        Weak,
+    },
+};
+
+/// The id handed out to the next created endpoint.
+static NEXT_ENDPOINT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Endpoints by their id.
+///
+/// Weak references are stored such that the registry does not keep disposed endpoints alive.
+static ENDPOINTS: Mutex<BTreeMap<u64, Weak<Mutex<EndpointImpl>>>> = Mutex::new(BTreeMap::new());
+
+/// Returns a new, monotonically increasing, endpoint id.
+pub(crate) fn next_endpoint_id() -> u64 {
+    NEXT_ENDPOINT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Registers an endpoint such that it can be looked up by its id.
+pub(crate) fn register_endpoint(id: u64, endpoint: &Arc<Mutex<EndpointImpl>>) {
+    registered().insert(id, Arc::downgrade(endpoint));
+}
+
+/// Removes an endpoint from the registry.
+pub(crate) fn unregister_endpoint(id: u64) {
+    registered().remove(&id);
+}
+
+/// Looks up an endpoint by its id.
+pub(crate) fn endpoint(id: u64) -> Option<Arc<Mutex<EndpointImpl>>> {
+    registered().get(&id).and_then(Weak::upgrade)
+}
+
+/// Returns the registered endpoints that are alive.
+pub(crate) fn endpoints() -> Vec<Arc<Mutex<EndpointImpl>>> {
+    registered().values().filter_map(Weak::upgrade).collect()
+}
+
+fn registered() -> std::sync::MutexGuard<'static, BTreeMap<u64, Weak<Mutex<EndpointImpl>>>> {
+    // The registry holds no invariants a panic could break.
+    ENDPOINTS.lock().unwrap_or_else(|e| e.into_inner())
+}
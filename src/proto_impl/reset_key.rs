@@ -0,0 +1,55 @@
+//! The stateless reset key of endpoints created from now on.
+//!
+//! Stateless resets are only accepted by a peer if they are derived from the key its connection was created with, a server
+//! that restarts with the same key can thus reset the connections of its previous incarnation.
+
+use crate::{
+    proto::crypto::HmacKey,
+    proto_impl::entropy,
+};
+use ring::hmac;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+/// The minimum key length in bytes, the output length of the HMAC-SHA256 used to derive reset tokens.
+pub(crate) const MIN_KEY_LENGTH: usize = 32;
+
+/// The key of endpoints created from now on, `None` for a random key per endpoint.
+static RESET_KEY: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+/// Sets the key of endpoints created from now on.
+pub(crate) fn set_reset_key(key: Vec<u8>) {
+    *RESET_KEY.lock().unwrap() = Some(key);
+}
+
+/// Returns the key of endpoints created from now on, `None` for a random key per endpoint.
+pub(crate) fn configured_key() -> Option<Vec<u8>> {
+    RESET_KEY.lock().unwrap().clone()
+}
+
+/// The length of the keys drawn from the entropy callback, like the random keys of quinn.
+const ENTROPY_KEY_LENGTH: usize = 64;
+
+/// Returns the HMAC key of a new endpoint with the key set by `set_reset_key`, see `hmac_key`.
+pub(crate) fn reset_key() -> Option<Arc<dyn HmacKey>> {
+    hmac_key(configured_key().as_deref())
+}
+
+/// Returns the HMAC key of a new endpoint with a configured key, `None` if it should keep its random key.
+///
+/// Without a configured key, a key is drawn from the entropy callback if it is set.
+pub(crate) fn hmac_key(configured: Option<&[u8]>) -> Option<Arc<dyn HmacKey>> {
+    let key = match configured {
+        Some(key) => key.to_vec(),
+        None if entropy::is_host_provided() => {
+            let mut key = vec![0; ENTROPY_KEY_LENGTH];
+            entropy::fill(&mut key);
+            key
+        }
+        None => return None,
+    };
+
+    Some(Arc::new(hmac::Key::new(hmac::HMAC_SHA256, &key)))
+}
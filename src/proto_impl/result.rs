@@ -33,6 +33,48 @@ macro_rules! impl_io_error {
     };
 }
 
+/// Where an error occurred, attached with `FFIErrorKind::context` as the error is passed up.
+#[derive(Debug, Copy, Clone)]
+pub struct ErrorContext {
+    /// The FFI function or the internal step that failed, e.g. `poll_connection`.
+    pub operation: &'static str,
+    pub connection_id: Option<u32>,
+    pub stream_id: Option<u64>,
+}
+
+impl ErrorContext {
+    pub fn new(operation: &'static str) -> Self {
+        ErrorContext {
+            operation,
+            connection_id: None,
+            stream_id: None,
+        }
+    }
+
+    pub fn connection(mut self, connection_id: u32) -> Self {
+        self.connection_id = Some(connection_id);
+        self
+    }
+
+    pub fn stream(mut self, stream_id: u64) -> Self {
+        self.stream_id = Some(stream_id);
+        self
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.operation)?;
+        if let Some(connection_id) = self.connection_id {
+            write!(f, " on connection {}", connection_id)?;
+        }
+        if let Some(stream_id) = self.stream_id {
+            write!(f, " stream {}", stream_id)?;
+        }
+        Ok(())
+    }
+}
+
 /// An `Error` implementing type that can be returned in a `Result`.
 #[derive(Debug)]
 pub enum FFIErrorKind {
@@ -44,12 +86,69 @@ pub enum FFIErrorKind {
     FFIError,
     /// IO Error.
     IoError(io::Error),
+    /// The function needs a cargo feature the library was built without.
+    Unsupported { feature: &'static str },
+    /// An error with the context it occurred in, contexts chain from the outermost to the original error.
+    Context {
+        context: ErrorContext,
+        source: Box<FFIErrorKind>,
+    },
 }
 
 impl FFIErrorKind {
     pub fn io_error(str: &str) -> FFIErrorKind {
         FFIErrorKind::IoError(io::Error::new(io::ErrorKind::Other, str))
     }
+
+    pub fn unsupported(feature: &'static str) -> FFIErrorKind {
+        FFIErrorKind::Unsupported { feature }
+    }
+
+    /// Wraps the error with the context it occurred in, e.g. the connection being polled.
+    ///
+    /// Plain result kinds, like `BufferBlocked`, carry no message and are returned unchanged,
+    /// such that they don't replace the last error.
+    pub fn context(self, context: ErrorContext) -> FFIErrorKind {
+        match self {
+            e @ FFIErrorKind::FFIResultKind(_) => e,
+            e => FFIErrorKind::Context {
+                context,
+                source: Box::new(e),
+            },
+        }
+    }
+
+    /// Returns the original error without its contexts.
+    pub fn root(&self) -> &FFIErrorKind {
+        match self {
+            FFIErrorKind::Context { source, .. } => source.root(),
+            e => e,
+        }
+    }
+
+    /// Returns the contexts from the outermost to the innermost.
+    pub fn contexts(&self) -> impl Iterator<Item = &ErrorContext> {
+        let mut error = self;
+        std::iter::from_fn(move || match error {
+            FFIErrorKind::Context { context, source } => {
+                error = source;
+                Some(context)
+            }
+            _ => None,
+        })
+    }
+
+    /// Returns the QUIC or OS error code, 0 if the error has no code.
+    pub fn code(&self) -> u64 {
+        match self {
+            FFIErrorKind::QuinnError { code, .. } => *code as u64,
+            FFIErrorKind::IoError(err) => err.raw_os_error().unwrap_or_default() as u64,
+            FFIErrorKind::Context { source, .. } => source.code(),
+            FFIErrorKind::FFIResultKind(_)
+            | FFIErrorKind::FFIError
+            | FFIErrorKind::Unsupported { .. } => 0,
+        }
+    }
 }
 
 impl Error for FFIErrorKind {}
@@ -65,20 +164,62 @@ impl fmt::Display for FFIErrorKind {
             FFIErrorKind::FFIResultKind(kind) => {
                 write!(f, "Quinn error kind Occurred: {:?}", kind)
             }
+            FFIErrorKind::Unsupported { feature } => {
+                write!(f, "The library was built without the `{}` feature", feature)
+            }
+            FFIErrorKind::Context { context, source } => write!(f, "{}: {}", context, source),
         }
     }
 }
 
 // For now most protocol errors are treated as IO errors
-impl_io_error!(quinn_proto::ConnectionError);
 impl_io_error!(quinn_proto::TransportError);
+impl_io_error!(quinn_proto::ConnectError);
+impl_io_error!(quinn_proto::SendDatagramError);
 impl_io_error!(io::Error);
 impl_io_error!(TryRecvError);
 impl_io_error!(RecvError);
 impl_io_error!(ReadError);
-impl_io_error!(WriteError);
-impl_io_error!(ReadableError);
 impl_io_error!(VarIntBoundsExceeded);
+impl_io_error!(rustls::Error);
+
+/// Turns the unknown stream variant of a quinn stream error into `UnknownStream`, and other variants into IO errors.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! impl_stream_error {
+    ($from:path, $unknown:pat) => {
+        impl From<$from> for FFIErrorKind {
+            fn from(error: $from) -> Self {
+                match error {
+                    $unknown => FFIErrorKind::FFIResultKind(FFIResultKind::UnknownStream),
+                    error => FFIErrorKind::IoError(io::Error::new(
+                        io::ErrorKind::Other,
+                        error.to_string(),
+                    )),
+                }
+            }
+        }
+    };
+}
+
+impl_stream_error!(WriteError, WriteError::UnknownStream);
+impl_stream_error!(
+    quinn_proto::FinishError,
+    quinn_proto::FinishError::UnknownStream
+);
+impl_stream_error!(ReadableError, ReadableError::UnknownStream);
+
+impl From<quinn_proto::UnknownStream> for FFIErrorKind {
+    fn from(_: quinn_proto::UnknownStream) -> Self {
+        FFIErrorKind::FFIResultKind(FFIResultKind::UnknownStream)
+    }
+}
+
+impl From<quinn_proto::ConnectionError> for FFIErrorKind {
+    fn from(_: quinn_proto::ConnectionError) -> Self {
+        FFIErrorKind::FFIResultKind(FFIResultKind::ConnectionLost)
+    }
+}
 
 impl<T> From<SendError<T>> for FFIErrorKind {
     fn from(error: SendError<T>) -> Self {
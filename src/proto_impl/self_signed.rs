@@ -0,0 +1,191 @@
+//! Generates self-signed certificates in memory, e.g. for tests and local development.
+//!
+//! Certificates are X.509 v3 with an ECDSA P-256 key, signed with SHA-256, and carry the given subject alternative names.
+
+use crate::proto_impl::FFIErrorKind;
+use ring::{
+    rand::{
+        SecureRandom,
+        SystemRandom,
+    },
+    signature::{
+        EcdsaKeyPair,
+        KeyPair,
+        ECDSA_P256_SHA256_ASN1_SIGNING,
+    },
+};
+use std::{
+    net::IpAddr,
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+/// DER encoded object identifiers, including tag and length.
+const OID_ECDSA_WITH_SHA256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_PRIME256V1: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_COMMON_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x1d, 0x11];
+
+const COMMON_NAME: &str = "quinn-ffi self signed";
+
+/// A generated certificate and its private key.
+pub struct SelfSignedCert {
+    /// The DER encoded X.509 certificate.
+    pub cert: Vec<u8>,
+    /// The DER encoded PKCS#8 private key.
+    pub key: Vec<u8>,
+}
+
+/// Generates a certificate for the given names, valid from now for `validity_days` days.
+///
+/// Names that parse as IP addresses become IP address entries, all others DNS name entries.
+pub fn generate(
+    subject_alt_names: &[&str],
+    validity_days: u32,
+) -> Result<SelfSignedCert, FFIErrorKind> {
+    if subject_alt_names.is_empty() {
+        return Err(FFIErrorKind::io_error(
+            "At least one subject alternative name is required",
+        ));
+    }
+
+    let rng = SystemRandom::new();
+    let key = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+        .map_err(|_| FFIErrorKind::io_error("Failed to generate the key pair"))?;
+    let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, key.as_ref())
+        .map_err(|_| FFIErrorKind::io_error("Failed to load the generated key pair"))?;
+
+    let mut serial = [0u8; 16];
+    rng.fill(&mut serial)
+        .map_err(|_| FFIErrorKind::io_error("Failed to generate the serial number"))?;
+    // Positive and without leading zero byte, as required for DER integers.
+    serial[0] = (serial[0] & 0x7f) | 0x01;
+
+    let not_before = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let not_after = not_before + Duration::from_secs(u64::from(validity_days) * 24 * 60 * 60);
+
+    let name = sequence(&[&set(&[&sequence(&[
+        OID_COMMON_NAME,
+        &tlv(0x0c, COMMON_NAME.as_bytes()),
+    ])])]);
+    let algorithm = sequence(&[OID_ECDSA_WITH_SHA256]);
+
+    let tbs_cert = sequence(&[
+        // Version 3.
+        &tlv(0xa0, &tlv(0x02, &[0x02])),
+        &tlv(0x02, &serial),
+        &algorithm,
+        &name,
+        &sequence(&[&time(not_before.as_secs()), &time(not_after.as_secs())]),
+        &name,
+        &sequence(&[
+            &sequence(&[OID_EC_PUBLIC_KEY, OID_PRIME256V1]),
+            &bit_string(key_pair.public_key().as_ref()),
+        ]),
+        &tlv(
+            0xa3,
+            &sequence(&[&sequence(&[
+                OID_SUBJECT_ALT_NAME,
+                &tlv(0x04, &general_names(subject_alt_names)),
+            ])]),
+        ),
+    ]);
+
+    let signature = key_pair
+        .sign(&rng, &tbs_cert)
+        .map_err(|_| FFIErrorKind::io_error("Failed to sign the certificate"))?;
+
+    Ok(SelfSignedCert {
+        cert: sequence(&[&tbs_cert, &algorithm, &bit_string(signature.as_ref())]),
+        key: key.as_ref().to_vec(),
+    })
+}
+
+fn general_names(names: &[&str]) -> Vec<u8> {
+    let names: Vec<Vec<u8>> = names
+        .iter()
+        .map(|name| match name.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => tlv(0x87, &ip.octets()),
+            Ok(IpAddr::V6(ip)) => tlv(0x87, &ip.octets()),
+            Err(_) => tlv(0x82, name.as_bytes()),
+        })
+        .collect();
+
+    sequence(&names.iter().map(Vec::as_slice).collect::<Vec<_>>())
+}
+
+/// Encodes seconds since the unix epoch as `UTCTime` before 2050 and as `GeneralizedTime` after, as required by RFC 5280.
+fn time(unix_seconds: u64) -> Vec<u8> {
+    let (year, month, day) = civil_from_days((unix_seconds / 86400) as i64);
+    let seconds_of_day = unix_seconds % 86400;
+    let clock = format!(
+        "{:02}{:02}{:02}{:02}{:02}Z",
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    );
+
+    if year < 2050 {
+        tlv(0x17, format!("{:02}{}", year % 100, clock).as_bytes())
+    } else {
+        tlv(0x18, format!("{:04}{}", year, clock).as_bytes())
+    }
+}
+
+/// Converts days since the unix epoch to a (year, month, day) date in the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+fn sequence(items: &[&[u8]]) -> Vec<u8> {
+    tlv(0x30, &items.concat())
+}
+
+fn set(items: &[&[u8]]) -> Vec<u8> {
+    tlv(0x31, &items.concat())
+}
+
+fn bit_string(bytes: &[u8]) -> Vec<u8> {
+    // No unused bits.
+    tlv(0x03, &[&[0x00], bytes].concat())
+}
+
+/// Encodes a tag, the DER length and the value.
+fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+
+    if value.len() < 0x80 {
+        out.push(value.len() as u8);
+    } else {
+        let length = (value.len() as u64).to_be_bytes();
+        let skip = length.iter().take_while(|byte| **byte == 0).count();
+        out.push(0x80 | (length.len() - skip) as u8);
+        out.extend_from_slice(&length[skip..]);
+    }
+
+    out.extend_from_slice(value);
+    out
+}
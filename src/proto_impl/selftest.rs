@@ -0,0 +1,682 @@
+//! A client and server endpoint exchanging packets in memory, see `ffi_selftest`.
+//!
+//! The test is driven through the exported functions, declared like a client application declares them, such that it
+//! covers the same code as the bindings do: the endpoints are polled by their own threads, their transmits are pulled
+//! with `poll_transmit` and passed to the other endpoint with `handle_datagram_v2`. The callbacks of the client
+//! application are invoked for the connections of the test.
+
+use crate::{
+    ffi::{
+        dispatch_mode,
+        EndpointOptions,
+        EndpointRole,
+        FFIResult,
+        FFIResultKind,
+        PollerMode,
+    },
+    proto_impl::{
+        ConnectionInfo,
+        ConnectionState,
+        DatagramOutcome,
+        DiagnosticsEvent,
+        DiagnosticsEventKind,
+        FFIErrorKind,
+        IpAddr,
+        RecvDatagram,
+        StreamDirection,
+    },
+};
+use std::{
+    convert::TryFrom,
+    ffi::c_void,
+    mem,
+    net::SocketAddr,
+    ptr,
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+type Handle = *mut c_void;
+
+const SERVER_NAME: &[u8] = b"localhost";
+const ECHO_MESSAGE: &[u8] = b"quinn-ffi selftest echo";
+const DATAGRAM_MESSAGE: &[u8] = b"quinn-ffi selftest datagram";
+
+/// How long a step of the test may take before it fails.
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the poller threads when no packets were exchanged.
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+/// The size of the buffers transmits are pulled into, larger than any UDP payload.
+const TRANSMIT_BUFFER_SIZE: usize = 65536;
+
+// The exported functions, the symbols are those the bindings link against.
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "generate_self_signed_cert")]
+    fn generate_self_signed_cert(
+        subject_alt_names: *const u8,
+        subject_alt_names_len: u32,
+        validity_days: u32,
+        cert_buf: *mut u8,
+        cert_buf_len: usize,
+        actual_cert_len: *mut usize,
+        key_buf: *mut u8,
+        key_buf_len: usize,
+        actual_key_len: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_server_config")]
+    fn create_server_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_config")]
+    fn create_client_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_server_config")]
+    fn free_server_config(handle: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_client_config")]
+    fn free_client_config(handle: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_endpoint_ex")]
+    fn create_endpoint_ex(
+        options: *const c_void,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_set_transmit_pull_mode")]
+    fn endpoint_set_transmit_pull_mode(endpoint: Handle, enabled: bool) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "dispose_endpoint")]
+    fn dispose_endpoint(endpoint: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connect_client")]
+    fn connect_client(
+        endpoint: Handle,
+        host: *const u8,
+        host_len: u32,
+        address: IpAddr,
+        out_connection: *mut Handle,
+        out_connection_id: *mut u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "poll_transmit")]
+    fn poll_transmit(
+        endpoint: Handle,
+        out_buf: *mut u8,
+        buf_len: usize,
+        out_len: *mut usize,
+        out_addr: *mut IpAddr,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "handle_datagram_v2")]
+    fn handle_datagram_v2(
+        endpoint: Handle,
+        data: *const u8,
+        length: usize,
+        address: IpAddr,
+        out_outcome: *mut DatagramOutcome,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_list_connections")]
+    fn endpoint_list_connections(
+        endpoint: Handle,
+        out_connections: *mut ConnectionInfo,
+        capacity: usize,
+        out_count: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "get_connection_handle")]
+    fn get_connection_handle(
+        endpoint: Handle,
+        connection_id: u32,
+        out_handle: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_handle_release")]
+    fn connection_handle_release(connection: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "poll_connection")]
+    fn poll_connection(connection: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_state")]
+    fn connection_state(connection: Handle, out_state: *mut ConnectionState) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_set_diagnostics_enabled")]
+    fn connection_set_diagnostics_enabled(connection: Handle, enabled: bool) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_diagnostics")]
+    fn connection_diagnostics(
+        connection: Handle,
+        out_events: *mut DiagnosticsEvent,
+        capacity: usize,
+        out_count: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "open_stream")]
+    fn open_stream(
+        connection: Handle,
+        stream_direction: u8,
+        opened_stream_id: *mut u64,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "write_stream")]
+    fn write_stream(
+        connection: Handle,
+        stream_id: u64,
+        buffer: *const u8,
+        buf_len: usize,
+        written_bytes: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "read_stream")]
+    fn read_stream(
+        connection: Handle,
+        stream_id: u64,
+        message_buf: *mut u8,
+        message_buf_len: usize,
+        actual_message_len: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "send_datagram_expiring")]
+    fn send_datagram_expiring(
+        connection: Handle,
+        buffer: *const u8,
+        buf_len: usize,
+        expiry_micros: u64,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "close_connection")]
+    fn close_connection(
+        connection: Handle,
+        reason: *const u8,
+        reason_len: u32,
+        error_code: u64,
+    ) -> FFIResult;
+}
+
+/// The result of `ffi_selftest`.
+///
+/// Compare the sizes with those of the declarations in the bindings, and `server_address` with the address passed in.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SelfTestReport {
+    /// The size of `SelfTestReport`.
+    pub report_size: u32,
+    /// The size of `FFIResult`.
+    pub ffi_result_size: u32,
+    /// The size of `IpAddr`.
+    pub ip_addr_size: u32,
+    /// The size of `RecvDatagram`.
+    pub recv_datagram_size: u32,
+    /// The address of the server, as read from the argument.
+    pub server_address: IpAddr,
+    /// The number of bytes the server echoed on a stream.
+    pub echoed_bytes: u64,
+    /// The number of datagrams exchanged, one in each direction.
+    pub exchanged_datagrams: u32,
+}
+
+impl SelfTestReport {
+    fn new(server_address: IpAddr) -> Self {
+        SelfTestReport {
+            report_size: mem::size_of::<SelfTestReport>() as u32,
+            ffi_result_size: mem::size_of::<FFIResult>() as u32,
+            ip_addr_size: mem::size_of::<IpAddr>() as u32,
+            recv_datagram_size: mem::size_of::<RecvDatagram>() as u32,
+            server_address,
+            echoed_bytes: 0,
+            exchanged_datagrams: 0,
+        }
+    }
+}
+
+/// Connects a client to a server at the given address, echoes a message on a stream and exchanges datagrams.
+///
+/// The client uses the same address with the next port.
+pub(crate) fn run(server_address: IpAddr) -> Result<SelfTestReport, FFIErrorKind> {
+    let mut report = SelfTestReport::new(server_address);
+    let server_address = SocketAddr::try_from(server_address)?;
+    let mut client_address = server_address;
+    client_address.set_port(server_address.port().wrapping_add(1).max(1));
+
+    let mut test = SelfTest::new(server_address, client_address)?;
+
+    test.run_until("handshake", |test| {
+        Ok(test.server_connection()?.is_some()
+            && state(test.client.connection)? == ConnectionState::Established
+            && state(test.server.connection)? == ConnectionState::Established)
+    })?;
+
+    // The client writes a message on a new stream and the server echoes it.
+    let mut stream = 0;
+    check("open_stream", unsafe {
+        open_stream(
+            test.client.connection,
+            StreamDirection::Bi as u8,
+            &mut stream,
+        )
+    })?;
+    write_all(test.client.connection, stream, ECHO_MESSAGE)?;
+
+    let mut received = Vec::new();
+    let mut echoed = Vec::new();
+    test.run_until("stream echo", |test| {
+        // Reading the stream claims it, it need not be accepted.
+        if received.len() < ECHO_MESSAGE.len() {
+            read_available(test.server.connection, stream, &mut received)?;
+            if received.len() >= ECHO_MESSAGE.len() {
+                write_all(test.server.connection, stream, &received)?;
+            }
+        }
+
+        read_available(test.client.connection, stream, &mut echoed)?;
+        Ok(echoed.len() >= ECHO_MESSAGE.len())
+    })?;
+
+    if echoed != ECHO_MESSAGE {
+        return Err(failure("the echoed message differs"));
+    }
+    report.echoed_bytes = echoed.len() as u64;
+
+    // Datagrams are passed to `on_datagram_received`, their reception is read from the diagnostics of the connections.
+    for connection in [test.client.connection, test.server.connection] {
+        check("connection_set_diagnostics_enabled", unsafe {
+            connection_set_diagnostics_enabled(connection, true)
+        })?;
+    }
+
+    // The client sends a datagram and the server sends one back.
+    send_datagram(test.client.connection)?;
+    let mut returned = false;
+    test.run_until("datagram exchange", |test| {
+        if !returned && received_datagram(test.server.connection)? {
+            send_datagram(test.server.connection)?;
+            returned = true;
+        }
+
+        Ok(returned && received_datagram(test.client.connection)?)
+    })?;
+    report.exchanged_datagrams = 2;
+
+    let reason = b"selftest done";
+    check("close_connection", unsafe {
+        close_connection(
+            test.client.connection,
+            reason.as_ptr(),
+            reason.len() as u32,
+            0,
+        )
+    })?;
+    test.run_until("close", |test| {
+        Ok(matches!(
+            state(test.server.connection)?,
+            ConnectionState::Draining | ConnectionState::Closed
+        ))
+    })?;
+
+    Ok(report)
+}
+
+/// An endpoint with at most one connection.
+struct Peer {
+    endpoint: Handle,
+    connection: Handle,
+    address: SocketAddr,
+}
+
+impl Peer {
+    /// Creates an endpoint polled by its own thread, whose transmits are pulled.
+    fn new(role: EndpointRole, crypto: Handle, address: SocketAddr) -> Result<Self, FFIErrorKind> {
+        // Zero selects the defaults, the dispatch mode of the client application is kept.
+        let mut options: EndpointOptions = unsafe { mem::zeroed() };
+        options.struct_size = mem::size_of::<EndpointOptions>() as u32;
+        options.role = role as u8;
+        options.poller_mode = PollerMode::Automatic as u8;
+        options.dispatch_mode = dispatch_mode() as u8;
+        let crypto_field = match role {
+            EndpointRole::Server => ptr::addr_of_mut!(options.server_crypto).cast::<Handle>(),
+            EndpointRole::Client => ptr::addr_of_mut!(options.client_crypto).cast::<Handle>(),
+        };
+        unsafe { crypto_field.write(crypto) };
+
+        let mut endpoint_id = 0;
+        let mut peer = Peer {
+            endpoint: ptr::null_mut(),
+            connection: ptr::null_mut(),
+            address,
+        };
+        check("create_endpoint_ex", unsafe {
+            create_endpoint_ex(
+                &options as *const EndpointOptions as *const c_void,
+                &mut endpoint_id,
+                &mut peer.endpoint,
+            )
+        })?;
+        check("endpoint_set_transmit_pull_mode", unsafe {
+            endpoint_set_transmit_pull_mode(peer.endpoint, true)
+        })?;
+
+        Ok(peer)
+    }
+
+    /// Passes the transmits of this endpoint to the other, returns whether any were passed.
+    fn transmit_to(&self, other: &Peer, buffer: &mut [u8]) -> Result<bool, FFIErrorKind> {
+        let mut transmitted = false;
+
+        loop {
+            let (mut length, mut destination) = (0, IpAddr::from(self.address));
+            let result = unsafe {
+                poll_transmit(
+                    self.endpoint,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut length,
+                    &mut destination,
+                )
+            };
+            if result.kind == FFIResultKind::BufferBlocked {
+                return Ok(transmitted);
+            }
+            check("poll_transmit", result)?;
+
+            if SocketAddr::try_from(destination)? != other.address {
+                return Err(failure(
+                    "a transmit is addressed to another address than the peer",
+                ));
+            }
+
+            let mut outcome = DatagramOutcome::Dropped;
+            check("handle_datagram_v2", unsafe {
+                handle_datagram_v2(
+                    other.endpoint,
+                    buffer.as_ptr(),
+                    length,
+                    IpAddr::from(self.address),
+                    &mut outcome,
+                )
+            })?;
+            transmitted = true;
+        }
+    }
+
+    /// Polls the connection, the client application may not poll it in its `on_pollable_connection` callback.
+    fn poll(&self) -> Result<(), FFIErrorKind> {
+        if self.connection.is_null() {
+            return Ok(());
+        }
+
+        check("poll_connection", unsafe {
+            poll_connection(self.connection)
+        })
+    }
+
+    /// Releases the connection handle and disposes the endpoint, which frees its connections.
+    fn close(&mut self) {
+        if !self.connection.is_null() {
+            let _ = unsafe { connection_handle_release(self.connection) };
+        }
+        if !self.endpoint.is_null() {
+            let _ = unsafe { dispose_endpoint(self.endpoint) };
+        }
+    }
+}
+
+/// A client connecting to a server, packets are exchanged in memory as they are pulled.
+struct SelfTest {
+    server_config: Handle,
+    client_config: Handle,
+    client: Peer,
+    server: Peer,
+    buffer: Vec<u8>,
+}
+
+impl SelfTest {
+    fn new(server_address: SocketAddr, client_address: SocketAddr) -> Result<Self, FFIErrorKind> {
+        let (cert, key) = self_signed_cert()?;
+
+        // Fields are only set once their handles are valid, such that `drop` frees what was created.
+        let mut test = SelfTest {
+            server_config: ptr::null_mut(),
+            client_config: ptr::null_mut(),
+            client: Peer {
+                endpoint: ptr::null_mut(),
+                connection: ptr::null_mut(),
+                address: client_address,
+            },
+            server: Peer {
+                endpoint: ptr::null_mut(),
+                connection: ptr::null_mut(),
+                address: server_address,
+            },
+            buffer: vec![0; TRANSMIT_BUFFER_SIZE],
+        };
+
+        check("create_server_config", unsafe {
+            create_server_config(
+                &mut test.server_config,
+                cert.as_ptr(),
+                cert.len() as u32,
+                key.as_ptr(),
+                key.len() as u32,
+            )
+        })?;
+        check("create_client_config", unsafe {
+            create_client_config(
+                &mut test.client_config,
+                cert.as_ptr(),
+                cert.len() as u32,
+                key.as_ptr(),
+                key.len() as u32,
+            )
+        })?;
+
+        test.server = Peer::new(EndpointRole::Server, test.server_config, server_address)?;
+        test.client = Peer::new(EndpointRole::Client, test.client_config, client_address)?;
+
+        let mut connection_id = 0;
+        check("connect_client", unsafe {
+            connect_client(
+                test.client.endpoint,
+                SERVER_NAME.as_ptr(),
+                SERVER_NAME.len() as u32,
+                IpAddr::from(server_address),
+                &mut test.client.connection,
+                &mut connection_id,
+            )
+        })?;
+
+        Ok(test)
+    }
+
+    /// Retrieves the connection the server accepted, returns whether it did.
+    fn server_connection(&mut self) -> Result<Option<Handle>, FFIErrorKind> {
+        if self.server.connection.is_null() {
+            let mut info = mem::MaybeUninit::<ConnectionInfo>::uninit();
+            let mut count = 0;
+            let result = unsafe {
+                endpoint_list_connections(self.server.endpoint, info.as_mut_ptr(), 1, &mut count)
+            };
+            if result.kind != FFIResultKind::BufferToSmall {
+                check("endpoint_list_connections", result)?;
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+
+            let info = unsafe { info.assume_init() };
+            check("get_connection_handle", unsafe {
+                get_connection_handle(
+                    self.server.endpoint,
+                    info.connection_id,
+                    &mut self.server.connection,
+                )
+            })?;
+        }
+
+        Ok(Some(self.server.connection))
+    }
+
+    /// Exchanges packets, and waits for the poller threads when none are sent, until `done` returns `true`.
+    fn run_until(
+        &mut self,
+        step: &str,
+        mut done: impl FnMut(&mut SelfTest) -> Result<bool, FFIErrorKind>,
+    ) -> Result<(), FFIErrorKind> {
+        let deadline = Instant::now() + STEP_TIMEOUT;
+
+        while Instant::now() < deadline {
+            if done(self)? {
+                return Ok(());
+            }
+
+            self.client.poll()?;
+            self.server.poll()?;
+            let to_server = self.client.transmit_to(&self.server, &mut self.buffer)?;
+            let to_client = self.server.transmit_to(&self.client, &mut self.buffer)?;
+
+            if !to_server && !to_client {
+                thread::sleep(IDLE_WAIT);
+            }
+        }
+
+        Err(failure(&format!("the {} did not complete", step)))
+    }
+}
+
+impl Drop for SelfTest {
+    fn drop(&mut self) {
+        self.client.close();
+        self.server.close();
+        if !self.client_config.is_null() {
+            let _ = unsafe { free_client_config(self.client_config) };
+        }
+        if !self.server_config.is_null() {
+            let _ = unsafe { free_server_config(self.server_config) };
+        }
+    }
+}
+
+/// Generates a certificate for `SERVER_NAME`, returns the DER-encoded certificate and key.
+fn self_signed_cert() -> Result<(Vec<u8>, Vec<u8>), FFIErrorKind> {
+    let (mut cert, mut key) = (vec![0; 4096], vec![0; 4096]);
+    let (mut cert_len, mut key_len) = (0, 0);
+
+    check("generate_self_signed_cert", unsafe {
+        generate_self_signed_cert(
+            SERVER_NAME.as_ptr(),
+            SERVER_NAME.len() as u32,
+            1,
+            cert.as_mut_ptr(),
+            cert.len(),
+            &mut cert_len,
+            key.as_mut_ptr(),
+            key.len(),
+            &mut key_len,
+        )
+    })?;
+
+    cert.truncate(cert_len);
+    key.truncate(key_len);
+    Ok((cert, key))
+}
+
+fn state(connection: Handle) -> Result<ConnectionState, FFIErrorKind> {
+    let mut state = ConnectionState::Handshaking;
+    check("connection_state", unsafe {
+        connection_state(connection, &mut state)
+    })?;
+    Ok(state)
+}
+
+/// Writes the whole message, fails if flow control does not allow it.
+fn write_all(connection: Handle, stream: u64, message: &[u8]) -> Result<(), FFIErrorKind> {
+    let mut written = 0;
+    check("write_stream", unsafe {
+        write_stream(
+            connection,
+            stream,
+            message.as_ptr(),
+            message.len(),
+            &mut written,
+        )
+    })?;
+
+    if written != message.len() {
+        return Err(failure("flow control blocked the echo message"));
+    }
+    Ok(())
+}
+
+/// Appends the available data of the stream to `buffer`.
+fn read_available(
+    connection: Handle,
+    stream: u64,
+    buffer: &mut Vec<u8>,
+) -> Result<(), FFIErrorKind> {
+    let mut chunk = [0; 1024];
+
+    loop {
+        let mut read = 0;
+        let result = unsafe {
+            read_stream(
+                connection,
+                stream,
+                chunk.as_mut_ptr(),
+                chunk.len(),
+                &mut read,
+            )
+        };
+        match result.kind {
+            FFIResultKind::Ok => buffer.extend_from_slice(&chunk[..read]),
+            // The stream is not opened on the server yet, or all data was read.
+            FFIResultKind::BufferBlocked | FFIResultKind::UnknownStream => return Ok(()),
+            _ => return check("read_stream", result),
+        }
+    }
+}
+
+fn send_datagram(connection: Handle) -> Result<(), FFIErrorKind> {
+    check("send_datagram_expiring", unsafe {
+        send_datagram_expiring(
+            connection,
+            DATAGRAM_MESSAGE.as_ptr(),
+            DATAGRAM_MESSAGE.len(),
+            STEP_TIMEOUT.as_micros() as u64,
+        )
+    })
+}
+
+/// Returns whether the connection received a datagram since diagnostics were enabled.
+fn received_datagram(connection: Handle) -> Result<bool, FFIErrorKind> {
+    let empty = DiagnosticsEvent {
+        micros: 0,
+        value: 0,
+        kind: DiagnosticsEventKind::StateChanged,
+    };
+    let mut events = [empty; 64];
+    let mut count = 0;
+    let result = unsafe {
+        connection_diagnostics(connection, events.as_mut_ptr(), events.len(), &mut count)
+    };
+    if result.kind != FFIResultKind::BufferToSmall {
+        check("connection_diagnostics", result)?;
+    }
+
+    Ok(events[..count.min(events.len())]
+        .iter()
+        .any(|event| event.kind == DiagnosticsEventKind::DatagramReceived))
+}
+
+/// Turns the result of an exported function into an error naming the function.
+fn check(function: &str, result: FFIResult) -> Result<(), FFIErrorKind> {
+    if result.kind == FFIResultKind::Ok {
+        return Ok(());
+    }
+
+    let message = FFIResult::from_last_result(|error| error.map(|error| error.to_string()));
+    Err(failure(&format!(
+        "`{}` returned {:?}: {}",
+        function,
+        result.kind,
+        message.unwrap_or_else(|| result.to_string())
+    )))
+}
+
+fn failure(reason: &str) -> FFIErrorKind {
+    FFIErrorKind::io_error(&format!("Self test failed: {}", reason))
+}
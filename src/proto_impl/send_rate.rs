@@ -0,0 +1,61 @@
+use std::time::{
+    Duration,
+    Instant,
+};
+
+/// The time the bucket of a rate limiter fills up in, the burst written after the connection was idle.
+const BURST_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The least a bucket holds, one Ethernet MTU, such that a low rate still admits a whole datagram.
+const MIN_CAPACITY: f64 = 1500.0;
+
+/// A token bucket that caps the stream and datagram payload per second a connection hands to quinn.
+///
+/// Only payload is counted, such that acknowledgements, probes and the close of the connection are never held back.
+#[derive(Debug)]
+pub(crate) struct SendRateLimiter {
+    bytes_per_second: u64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl SendRateLimiter {
+    pub(crate) fn new(bytes_per_second: u64, now: Instant) -> Self {
+        let capacity = (bytes_per_second as f64 * BURST_INTERVAL.as_secs_f64()).max(MIN_CAPACITY);
+
+        SendRateLimiter {
+            bytes_per_second,
+            capacity,
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    /// Adds the tokens accumulated since the last refill, returns the payload bytes that may be written now.
+    pub(crate) fn available(&mut self, now: Instant) -> usize {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.last_refill = now;
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.bytes_per_second as f64).min(self.capacity);
+
+        self.tokens as usize
+    }
+
+    /// Takes the tokens of written payload.
+    pub(crate) fn consume(&mut self, len: usize) {
+        self.tokens -= len as f64;
+    }
+
+    /// Returns when the bucket holds `len` bytes again, `None` if it holds them now.
+    pub(crate) fn deadline(&self, len: usize) -> Option<Instant> {
+        let missing = len as f64 - self.tokens;
+        if missing <= 0.0 {
+            return None;
+        }
+
+        let wait = missing / self.bytes_per_second as f64;
+        // Rounded up, such that the bucket holds the bytes when the connection is polled at the deadline.
+        Some(self.last_refill + Duration::from_secs_f64(wait) + Duration::from_micros(1))
+    }
+}
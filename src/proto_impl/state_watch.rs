@@ -0,0 +1,79 @@
+//! Lets threads block until a connection reaches a lifecycle state, see `wait_connected` and `wait_closed`.
+//!
+//! Waits with a `CancellationToken` also return once it is cancelled, the token wakes up the watch.
+
+use crate::proto_impl::{
+    CancellationToken,
+    ConnectionState,
+};
+use std::{
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// The lifecycle state of a connection as of its last poll, shared with the threads waiting for it to change.
+///
+/// Waiting does not lock the connection, such that the poller can make progress on the connection in the meantime.
+#[derive(Clone)]
+pub(crate) struct StateWatch(Arc<(Mutex<ConnectionState>, Condvar)>);
+
+impl StateWatch {
+    pub(crate) fn new(state: ConnectionState) -> Self {
+        StateWatch(Arc::new((Mutex::new(state), Condvar::new())))
+    }
+
+    /// Publishes a new state and wakes up the waiting threads.
+    pub(crate) fn set(&self, state: ConnectionState) {
+        let (lock, condvar) = &*self.0;
+        *lock.lock().unwrap() = state;
+        condvar.notify_all();
+    }
+
+    /// Returns the state as of the last poll of the connection.
+    pub(crate) fn state(&self) -> ConnectionState {
+        *self.0 .0.lock().unwrap()
+    }
+
+    /// Wakes up the waiting threads without changing the state, such that they check for cancellation.
+    pub(crate) fn wake(&self) {
+        let (lock, condvar) = &*self.0;
+        let _state = lock.lock().unwrap();
+        condvar.notify_all();
+    }
+
+    /// Returns whether both watch the same connection.
+    pub(crate) fn same(&self, other: &StateWatch) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Blocks until `reached` returns true for the state, returns that state, or `None` if the timeout elapsed first.
+    ///
+    /// With a cancellation token the wait also returns once the token is cancelled, check the token for that.
+    pub(crate) fn wait(
+        &self,
+        timeout: Duration,
+        reached: impl Fn(ConnectionState) -> bool,
+        cancellation: Option<&CancellationToken>,
+    ) -> Option<ConnectionState> {
+        let cancelled = || cancellation.is_some_and(CancellationToken::is_cancelled);
+        if let Some(cancellation) = cancellation {
+            cancellation.register(self);
+        }
+
+        let (lock, condvar) = &*self.0;
+        let state = lock.lock().unwrap();
+        let (state, _) = condvar
+            .wait_timeout_while(state, timeout, |state| !reached(*state) && !cancelled())
+            .unwrap();
+        let state = *state;
+
+        if let Some(cancellation) = cancellation {
+            cancellation.unregister(self);
+        }
+        reached(state).then_some(state)
+    }
+}
@@ -0,0 +1,212 @@
+use quinn_proto::Transmit;
+
+/// Long header form bit of the first byte of a QUIC packet.
+const LONG_HEADER_FORM: u8 = 0x80;
+/// Long header packet type of an `Initial` packet.
+const LONG_TYPE_INITIAL: u8 = 0x0;
+/// Long header packet type of a `Retry` packet.
+const LONG_TYPE_RETRY: u8 = 0x3;
+
+/// Endpoint statistics that are FFI safe.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct EndpointStats {
+    /// Incoming connections accepted by this endpoint.
+    pub accepted_connections: u64,
+    /// Incoming connections refused by this endpoint.
+    pub refused_connections: u64,
+    /// Connections that did not complete their handshake yet.
+    pub handshaking_connections: u64,
+    /// Datagrams handed to this endpoint by `handle_datagram`.
+    pub handled_datagrams: u64,
+    /// Transmits emitted through the `on_transmit` or `on_endpoint_transmit` callback.
+    pub emitted_transmits: u64,
+    /// Version negotiation packets sent by this endpoint.
+    pub version_negotiation_packets: u64,
+    /// Retry packets sent by this endpoint.
+    pub retry_packets: u64,
+    /// Stateless resets sent by this endpoint, in response to packets of unknown connections.
+    pub stateless_resets: u64,
+    /// Datagrams that were neither passed to a connection nor answered, e.g. because they could not be parsed.
+    pub dropped_datagrams: u64,
+}
+
+/// Statistics of the thread or pool that polls an endpoint, to tune how often the endpoint is woken.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct EndpointPollerStats {
+    /// Times the poller woke to poll the endpoint, because it was notified or a connection timer expired.
+    pub wakeups: u64,
+    /// Wakeups because a connection timer expired.
+    pub timer_wakeups: u64,
+    /// Transmits and connection events handled by the polls of the endpoint.
+    pub work_items: u64,
+    /// The average number of work items per wakeup, zero before the first wakeup.
+    pub average_work_per_wakeup: f64,
+}
+
+impl EndpointPollerStats {
+    /// Records a wakeup of the poller.
+    pub(crate) fn record_wakeup(&mut self, timer: bool) {
+        self.wakeups += 1;
+        if timer {
+            self.timer_wakeups += 1;
+        }
+    }
+
+    /// Returns the statistics including the average work per wakeup.
+    pub(crate) fn with_average(mut self) -> Self {
+        if self.wakeups > 0 {
+            self.average_work_per_wakeup = self.work_items as f64 / self.wakeups as f64;
+        }
+        self
+    }
+}
+
+/// Statistics of the transmits an endpoint emits per poll, to tune `endpoint_set_max_transmit_burst`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TransmitBurstStats {
+    /// Polls that emitted at least one transmit.
+    pub bursts: u64,
+    /// Transmits emitted by those polls.
+    pub transmits: u64,
+    /// The most transmits emitted by a single poll.
+    pub largest_burst: u64,
+    /// Polls that reached the maximum burst and yielded with transmits left for the next poll.
+    pub capped_bursts: u64,
+    /// The average number of transmits per burst, zero before the first burst.
+    pub average_burst: f64,
+}
+
+impl TransmitBurstStats {
+    /// Records the transmits emitted by a poll, `capped` if it stopped at the maximum burst.
+    pub(crate) fn record_burst(&mut self, transmits: usize, capped: bool) {
+        if transmits == 0 {
+            return;
+        }
+
+        self.bursts += 1;
+        self.transmits += transmits as u64;
+        self.largest_burst = self.largest_burst.max(transmits as u64);
+        if capped {
+            self.capped_bursts += 1;
+        }
+    }
+
+    /// Returns the statistics including the average burst.
+    pub(crate) fn with_average(mut self) -> Self {
+        if self.bursts > 0 {
+            self.average_burst = self.transmits as f64 / self.bursts as f64;
+        }
+        self
+    }
+}
+
+/// Statistics of a stream that are FFI safe.
+///
+/// quinn-proto tracks acknowledged and retransmitted data per connection only, so those are not available per stream.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct StreamStats {
+    /// Bytes written to the stream, including the length prefixes of messages and the data of file transfers.
+    pub bytes_sent: u64,
+    /// Bytes read from the stream, including the length prefixes of messages and the data of file transfers.
+    pub bytes_received: u64,
+}
+
+/// What handling a datagram resulted in.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DatagramOutcome {
+    /// The datagram was passed to an existing connection.
+    ConnectionEvent,
+    /// The datagram started a new connection.
+    NewConnection,
+    /// The datagram used an unsupported version and was answered with a version negotiation packet.
+    VersionNegotiationSent,
+    /// The datagram belonged to an unknown connection and was answered with a stateless reset.
+    StatelessResetSent,
+    /// The datagram started a connection and was answered with a retry packet.
+    RetrySent,
+    /// The datagram started a connection that was refused.
+    ConnectionRefused,
+    /// The datagram was dropped without an answer, e.g. because it could not be parsed.
+    Dropped,
+}
+
+impl DatagramOutcome {
+    /// Classifies a datagram that quinn did not pass to a connection by the kind of packet the endpoint answered with.
+    pub(crate) fn from_answer(answer: Option<EndpointTransmitKind>) -> Self {
+        match answer {
+            Some(EndpointTransmitKind::VersionNegotiation) => {
+                DatagramOutcome::VersionNegotiationSent
+            }
+            Some(EndpointTransmitKind::StatelessReset) => DatagramOutcome::StatelessResetSent,
+            Some(EndpointTransmitKind::Retry) => DatagramOutcome::RetrySent,
+            Some(EndpointTransmitKind::ConnectionRefused) => DatagramOutcome::ConnectionRefused,
+            None => DatagramOutcome::Dropped,
+        }
+    }
+}
+
+/// The class of a packet generated by the endpoint itself rather than by one of its connections.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EndpointTransmitKind {
+    /// A version negotiation packet, answering a datagram with an unsupported version.
+    VersionNegotiation,
+    /// A retry packet, answering a new connection to validate the address of the client.
+    Retry,
+    /// A stateless reset, answering a packet of an unknown connection.
+    StatelessReset,
+    /// An initial packet closing a new connection the endpoint refused.
+    ConnectionRefused,
+}
+
+impl EndpointTransmitKind {
+    /// Classifies a transmit generated by the endpoint.
+    ///
+    /// Quinn only generates endpoint level packets for version negotiation, retries, stateless resets and refused connections.
+    /// Those can be told apart by the unprotected header bits of the first byte and the version field.
+    pub(crate) fn classify(transmit: &Transmit) -> Option<Self> {
+        let contents = &transmit.contents;
+
+        match contents.first() {
+            Some(first) if first & LONG_HEADER_FORM != 0 => {
+                if contents.len() >= 5 && contents[1..5] == [0, 0, 0, 0] {
+                    Some(EndpointTransmitKind::VersionNegotiation)
+                } else {
+                    match (first & 0x30) >> 4 {
+                        LONG_TYPE_INITIAL => Some(EndpointTransmitKind::ConnectionRefused),
+                        LONG_TYPE_RETRY => Some(EndpointTransmitKind::Retry),
+                        _ => None,
+                    }
+                }
+            }
+            // Quinn only sends short header packets from the endpoint as stateless resets.
+            Some(_) => Some(EndpointTransmitKind::StatelessReset),
+            None => None,
+        }
+    }
+}
+
+impl EndpointStats {
+    /// Records a transmit generated by the endpoint itself rather than by one of its connections, returns its class.
+    pub(crate) fn record_endpoint_transmit(
+        &mut self,
+        transmit: &Transmit,
+    ) -> Option<EndpointTransmitKind> {
+        let kind = EndpointTransmitKind::classify(transmit);
+
+        match kind {
+            Some(EndpointTransmitKind::VersionNegotiation) => self.version_negotiation_packets += 1,
+            Some(EndpointTransmitKind::Retry) => self.retry_packets += 1,
+            Some(EndpointTransmitKind::StatelessReset) => self.stateless_resets += 1,
+            Some(EndpointTransmitKind::ConnectionRefused) => self.refused_connections += 1,
+            None => {}
+        }
+
+        kind
+    }
+}
@@ -0,0 +1,47 @@
+use crate::{
+    ffi::FFIResultKind,
+    proto::Dir,
+    proto_impl::FFIErrorKind,
+};
+use std::convert::TryFrom;
+
+/// The direction of a stream, passed to and from the client application as a `u8`.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StreamDirection {
+    /// Both sides can send data.
+    Bi = 0,
+    /// Only the side that opened the stream can send data.
+    Uni = 1,
+}
+
+impl TryFrom<u8> for StreamDirection {
+    type Error = FFIErrorKind;
+
+    /// Fails with `InvalidArgument` for values other than those of the variants.
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(StreamDirection::Bi),
+            1 => Ok(StreamDirection::Uni),
+            _ => Err(FFIErrorKind::FFIResultKind(FFIResultKind::InvalidArgument)),
+        }
+    }
+}
+
+impl From<StreamDirection> for Dir {
+    fn from(dir: StreamDirection) -> Self {
+        match dir {
+            StreamDirection::Bi => Dir::Bi,
+            StreamDirection::Uni => Dir::Uni,
+        }
+    }
+}
+
+impl From<Dir> for StreamDirection {
+    fn from(dir: Dir) -> Self {
+        match dir {
+            Dir::Bi => StreamDirection::Bi,
+            Dir::Uni => StreamDirection::Uni,
+        }
+    }
+}
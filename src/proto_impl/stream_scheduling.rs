@@ -0,0 +1,28 @@
+use crate::{
+    ffi::FFIResultKind,
+    proto_impl::FFIErrorKind,
+};
+use std::convert::TryFrom;
+
+/// How a connection shares its send capacity between streams with data to send, passed as a `u8`.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StreamScheduling {
+    /// Streams with a higher priority, see `stream_set_priority`, are sent first, streams of equal priority take turns.
+    StrictPriority = 0,
+    /// All streams take turns one frame at a time regardless of their priority, such that no stream starves.
+    RoundRobin = 1,
+}
+
+impl TryFrom<u8> for StreamScheduling {
+    type Error = FFIErrorKind;
+
+    /// Fails with `InvalidArgument` for values other than those of the variants.
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(StreamScheduling::StrictPriority),
+            1 => Ok(StreamScheduling::RoundRobin),
+            _ => Err(FFIErrorKind::FFIResultKind(FFIResultKind::InvalidArgument)),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Transport settings of the client and server configurations created from now on, and of `TransportConfigHandle`s.
+
+use crate::{
+    proto::{
+        congestion::CubicConfig,
+        TransportConfig,
+    },
+    proto_impl::validation::{
+        ConfigError,
+        ConfigIssue,
+    },
+};
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+/// Settings that are changed from the quinn defaults, `None` keeps the default.
+///
+/// The global settings apply to configurations created from now on, a `TransportConfigHandle` holds its own copy.
+#[derive(Clone)]
+pub struct TransportSettings {
+    datagram_receive_buffer_size: Option<Option<usize>>,
+    datagram_send_buffer_size: Option<usize>,
+    initial_window: Option<u64>,
+    crypto_buffer_size: Option<usize>,
+}
+
+static SETTINGS: Mutex<TransportSettings> = Mutex::new(TransportSettings {
+    datagram_receive_buffer_size: None,
+    datagram_send_buffer_size: None,
+    initial_window: None,
+    crypto_buffer_size: None,
+});
+
+/// Sets the number of bytes of incoming datagrams to buffer, `None` disables sending and receiving datagrams.
+pub(crate) fn set_datagram_receive_buffer_size(size: Option<usize>) {
+    SETTINGS
+        .lock()
+        .unwrap()
+        .set_datagram_receive_buffer_size(size);
+}
+
+/// Sets the number of bytes of outgoing datagrams to buffer.
+pub(crate) fn set_datagram_send_buffer_size(size: usize) {
+    SETTINGS.lock().unwrap().set_datagram_send_buffer_size(size);
+}
+
+/// Sets the initial congestion window in bytes, `None` keeps the default.
+pub(crate) fn set_initial_window(window: Option<u64>) {
+    SETTINGS.lock().unwrap().set_initial_window(window);
+}
+
+/// Sets the number of bytes of out-of-order crypto data to buffer, `None` keeps the default.
+pub(crate) fn set_crypto_buffer_size(size: Option<usize>) {
+    SETTINGS.lock().unwrap().set_crypto_buffer_size(size);
+}
+
+/// The minimum congestion window of quinn, two datagrams of the minimum QUIC datagram size.
+const MIN_WINDOW: u64 = 2 * 1200;
+
+/// The smallest crypto buffer that holds a CRYPTO frame of a datagram of the minimum QUIC datagram size.
+const MIN_CRYPTO_BUFFER_SIZE: usize = 1200;
+
+/// Checks the current settings for values that can't work, returns a message telling which setting to change.
+pub(crate) fn validate() -> Result<(), ConfigError> {
+    SETTINGS.lock().unwrap().validate()
+}
+
+/// Returns a transport configuration with the current settings.
+pub(crate) fn transport_config() -> Arc<TransportConfig> {
+    SETTINGS.lock().unwrap().config()
+}
+
+impl TransportSettings {
+    /// Copies the current global settings.
+    pub fn current() -> Self {
+        SETTINGS.lock().unwrap().clone()
+    }
+
+    pub fn set_datagram_receive_buffer_size(&mut self, size: Option<usize>) {
+        self.datagram_receive_buffer_size = Some(size);
+    }
+
+    pub fn set_datagram_send_buffer_size(&mut self, size: usize) {
+        self.datagram_send_buffer_size = Some(size);
+    }
+
+    pub fn set_initial_window(&mut self, window: Option<u64>) {
+        self.initial_window = window;
+    }
+
+    pub fn set_crypto_buffer_size(&mut self, size: Option<usize>) {
+        self.crypto_buffer_size = size;
+    }
+
+    /// Checks the settings for values that can't work, returns a message telling which setting to change.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: &str| Err(ConfigError::new(ConfigIssue::InvalidTransport, message));
+
+        if let Some(Some(0)) = self.datagram_receive_buffer_size {
+            return invalid(
+                "The datagram receive buffer size is zero, pass a larger size or disable datagrams",
+            );
+        }
+        if let Some(0) = self.datagram_send_buffer_size {
+            return invalid("The datagram send buffer size is zero, no datagram could be sent");
+        }
+        if matches!(self.initial_window, Some(window) if window < MIN_WINDOW) {
+            return invalid(
+                "The initial window is below the minimum congestion window of 2400 bytes",
+            );
+        }
+        if matches!(self.crypto_buffer_size, Some(size) if size < MIN_CRYPTO_BUFFER_SIZE) {
+            return invalid("The crypto buffer size is below 1200 bytes, handshakes with reordered packets would fail");
+        }
+
+        Ok(())
+    }
+
+    /// Returns a transport configuration with the settings.
+    pub fn config(&self) -> Arc<TransportConfig> {
+        let mut config = TransportConfig::default();
+
+        if let Some(size) = self.datagram_receive_buffer_size {
+            config.datagram_receive_buffer_size(size);
+        }
+        if let Some(size) = self.datagram_send_buffer_size {
+            config.datagram_send_buffer_size(size);
+        }
+        if let Some(window) = self.initial_window {
+            // Cubic is the default congestion controller of quinn.
+            let mut cubic = CubicConfig::default();
+            cubic.initial_window(window);
+            config.congestion_controller_factory(Arc::new(cubic));
+        }
+        if let Some(size) = self.crypto_buffer_size {
+            config.crypto_buffer_size(size);
+        }
+
+        Arc::new(config)
+    }
+}
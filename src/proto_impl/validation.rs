@@ -0,0 +1,139 @@
+//! Checks of client and server configurations, such that a bad certificate or key fails before the handshake.
+
+use crate::proto_impl::FFIErrorKind;
+use rustls::{
+    sign,
+    Certificate,
+    PrivateKey,
+    SignatureScheme,
+};
+use std::convert::TryFrom;
+
+/// The first problem found by `validate_server_config` or `validate_client_config`.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConfigIssue {
+    /// The configuration is valid.
+    None,
+    /// The certificate is not a DER-encoded X.509 certificate.
+    InvalidCertificate,
+    /// The private key is malformed or of an unsupported type.
+    UnsupportedKey,
+    /// The private key does not belong to the certificate.
+    KeyMismatch,
+    /// No ALPN protocol is configured, QUIC requires one to interoperate with other implementations.
+    MissingAlpn,
+    /// The transport settings at the time the configuration was created are inconsistent.
+    InvalidTransport,
+}
+
+/// A problem of a configuration with a message that tells how to fix it.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub issue: ConfigIssue,
+    pub message: String,
+}
+
+impl ConfigError {
+    pub(crate) fn new(issue: ConfigIssue, message: impl Into<String>) -> Self {
+        ConfigError {
+            issue,
+            message: message.into(),
+        }
+    }
+}
+
+/// The signature schemes offered to the key, one per key type supported by rustls.
+const SCHEMES: [SignatureScheme; 4] = [
+    SignatureScheme::ED25519,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+];
+
+/// Checks that the key signs messages that verify with the public key of the end-entity certificate.
+pub(crate) fn check_certified_key(
+    chain: &[Certificate],
+    key: &PrivateKey,
+) -> Result<(), ConfigError> {
+    let end_entity = chain.first().ok_or_else(|| {
+        ConfigError::new(
+            ConfigIssue::InvalidCertificate,
+            "The certificate chain is empty",
+        )
+    })?;
+    let certificate = webpki::EndEntityCert::try_from(end_entity.0.as_slice()).map_err(|e| {
+        ConfigError::new(
+            ConfigIssue::InvalidCertificate,
+            format!(
+                "The certificate is not a DER-encoded X.509 certificate: {:?}",
+                e
+            ),
+        )
+    })?;
+
+    let signer = sign::any_supported_type(key)
+        .ok()
+        .and_then(|key| key.choose_scheme(&SCHEMES))
+        .ok_or_else(|| {
+            ConfigError::new(
+                ConfigIssue::UnsupportedKey,
+                "The private key must be a DER-encoded PKCS#8 or PKCS#1 ECDSA, Ed25519 or RSA key",
+            )
+        })?;
+
+    let message = b"quinn-ffi certificate and key check";
+    let signature = signer.sign(message).map_err(|e| {
+        ConfigError::new(
+            ConfigIssue::UnsupportedKey,
+            format!("Signing with the private key failed: {}", e),
+        )
+    })?;
+    let algorithm = match signer.scheme() {
+        SignatureScheme::ED25519 => &webpki::ED25519,
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &webpki::ECDSA_P256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &webpki::ECDSA_P384_SHA384,
+        _ => &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    };
+
+    certificate
+        .verify_signature(algorithm, message, &signature)
+        .map_err(|_| {
+            ConfigError::new(
+                ConfigIssue::KeyMismatch,
+                "The private key does not belong to the certificate, check that both come from the same pair",
+            )
+        })
+}
+
+/// Checks that at least one ALPN protocol is configured.
+pub(crate) fn check_alpn(protocols: &[Vec<u8>]) -> Result<(), ConfigError> {
+    if protocols.is_empty() {
+        return Err(ConfigError::new(
+            ConfigIssue::MissingAlpn,
+            "No ALPN protocol is configured, set one with `server_config_set_alpn_protocols` or `client_config_set_alpn_protocols`",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parses protocols in the wire format of the ALPN extension, each protocol preceded by its length in one byte.
+pub(crate) fn parse_alpn(encoded: &[u8]) -> Result<Vec<Vec<u8>>, FFIErrorKind> {
+    let mut protocols = Vec::new();
+    let mut rest = encoded;
+
+    while let Some((&len, tail)) = rest.split_first() {
+        if len == 0 || tail.len() < len as usize {
+            return Err(FFIErrorKind::io_error(
+                "ALPN protocols must be non-empty and each preceded by its length in one byte",
+            ));
+        }
+
+        let (protocol, tail) = tail.split_at(len as usize);
+        protocols.push(protocol.to_vec());
+        rest = tail;
+    }
+
+    Ok(protocols)
+}
@@ -0,0 +1,60 @@
+//! QUIC versions supported by new endpoints, and parsing of the version fields in long header packets.
+
+use crate::proto::DEFAULT_SUPPORTED_VERSIONS;
+use std::{
+    convert::TryInto,
+    sync::Mutex,
+};
+
+/// Long header form bit of the first byte of a QUIC packet.
+const LONG_HEADER_FORM: u8 = 0x80;
+
+/// The versions supported by endpoints created from now on, empty for the quinn defaults.
+static SUPPORTED_VERSIONS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Sets the versions supported by endpoints created from now on, in order of preference.
+///
+/// Client connections use the first version.
+pub(crate) fn set_supported_versions(versions: Vec<u32>) {
+    *SUPPORTED_VERSIONS.lock().unwrap() = versions;
+}
+
+/// Returns the versions supported by new endpoints, in order of preference.
+pub(crate) fn supported_versions() -> Vec<u32> {
+    let versions = SUPPORTED_VERSIONS.lock().unwrap();
+
+    if versions.is_empty() {
+        DEFAULT_SUPPORTED_VERSIONS.to_vec()
+    } else {
+        versions.clone()
+    }
+}
+
+/// Returns the version of a long header packet, `None` for short header packets.
+pub(crate) fn long_header_version(packet: &[u8]) -> Option<u32> {
+    match packet.first() {
+        Some(first) if first & LONG_HEADER_FORM != 0 => {
+            Some(u32::from_be_bytes(packet.get(1..5)?.try_into().ok()?))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the versions offered by a version negotiation packet, `None` for other packets.
+pub(crate) fn offered_versions(packet: &[u8]) -> Option<Vec<u32>> {
+    if long_header_version(packet)? != 0 {
+        return None;
+    }
+
+    // Skip the destination and source connection ids which are prefixed by their length.
+    let dst_cid_len = *packet.get(5)? as usize;
+    let src_cid_len = *packet.get(6 + dst_cid_len)? as usize;
+    let versions = packet.get(7 + dst_cid_len + src_cid_len..)?;
+
+    Some(
+        versions
+            .chunks_exact(4)
+            .map(|version| u32::from_be_bytes(version.try_into().unwrap()))
+            .collect(),
+    )
+}
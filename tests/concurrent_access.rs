@@ -0,0 +1,326 @@
+//! Callbacks are registered and handles are accessed from several threads at once.
+//!
+//! Registering callbacks races with their invocation, and a connection is read, cloned and released from several
+//! threads while its endpoint is polled. To check the access paths for data races, run the test with the thread
+//! sanitizer:
+//!
+//! ```sh
+//! RUSTFLAGS=-Zsanitizer=thread cargo +nightly test -Zbuild-std --target x86_64-unknown-linux-gnu --test concurrent_access
+//! ```
+
+use quinn_ffi::{
+    ffi::{
+        FFIResult,
+        FFIResultKind,
+    },
+    proto_impl::{
+        ConnectionState,
+        IpAddr,
+    },
+};
+use std::{
+    ffi::c_void,
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+        Barrier,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+type Handle = *mut c_void;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const THREADS: usize = 8;
+const ITERATIONS: usize = 1000;
+const BI: u8 = 0;
+/// Nothing answers on the discard port, the client keeps retransmitting its handshake.
+const UNANSWERED_ADDRESS: &str = "127.0.0.1:9";
+
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "generate_self_signed_cert")]
+    fn generate_self_signed_cert(
+        subject_alt_names: *const u8,
+        subject_alt_names_len: u32,
+        validity_days: u32,
+        cert_buf: *mut u8,
+        cert_buf_len: usize,
+        actual_cert_len: *mut usize,
+        key_buf: *mut u8,
+        key_buf_len: usize,
+        actual_key_len: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_config")]
+    fn create_client_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_endpoint")]
+    fn create_client_endpoint(
+        config: Handle,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connect_client")]
+    fn connect_client(
+        endpoint: Handle,
+        host: *const u8,
+        host_len: u32,
+        address: IpAddr,
+        out_connection: *mut Handle,
+        out_connection_id: *mut u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "poll_endpoint")]
+    fn poll_endpoint(endpoint: Handle, now_micros: u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_handle_clone")]
+    fn connection_handle_clone(connection: Handle, out_handle: *mut Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_handle_release")]
+    fn connection_handle_release(connection: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_state")]
+    fn connection_state(connection: Handle, out_state: *mut ConnectionState) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_rtt_micros")]
+    fn connection_rtt_micros(connection: Handle, out_rtt_micros: *mut u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_open_streams")]
+    fn connection_open_streams(connection: Handle, direction: u8, out_count: *mut u64)
+        -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "dispose_endpoint")]
+    fn dispose_endpoint(endpoint: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_client_config")]
+    fn free_client_config(config: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_transmit_v2")]
+    fn set_on_transmit_v2(callback: extern "C" fn(u64, *const u8, usize, IpAddr)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_connected")]
+    fn set_on_connected(callback: extern "C" fn(u32)) -> FFIResult;
+}
+
+static FIRST_TRANSMITS: AtomicUsize = AtomicUsize::new(0);
+static SECOND_TRANSMITS: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn on_transmit_first(
+    _endpoint_id: u64,
+    _data: *const u8,
+    _length: usize,
+    _address: IpAddr,
+) {
+    FIRST_TRANSMITS.fetch_add(1, Ordering::SeqCst);
+}
+
+extern "C" fn on_transmit_second(
+    _endpoint_id: u64,
+    _data: *const u8,
+    _length: usize,
+    _address: IpAddr,
+) {
+    SECOND_TRANSMITS.fetch_add(1, Ordering::SeqCst);
+}
+
+extern "C" fn on_connected(_connection_id: u32) {}
+
+fn check(result: FFIResult) {
+    assert_eq!(result.kind, FFIResultKind::Ok, "{:?}", result);
+}
+
+fn transmits() -> usize {
+    FIRST_TRANSMITS.load(Ordering::SeqCst) + SECOND_TRANSMITS.load(Ordering::SeqCst)
+}
+
+fn self_signed_cert() -> (Vec<u8>, Vec<u8>) {
+    let names = b"localhost";
+    let (mut cert, mut key) = (vec![0; 1024], vec![0; 1024]);
+    let (mut cert_len, mut key_len) = (0, 0);
+
+    check(unsafe {
+        generate_self_signed_cert(
+            names.as_ptr(),
+            names.len() as u32,
+            1,
+            cert.as_mut_ptr(),
+            cert.len(),
+            &mut cert_len,
+            key.as_mut_ptr(),
+            key.len(),
+            &mut key_len,
+        )
+    });
+
+    cert.truncate(cert_len);
+    key.truncate(key_len);
+    (cert, key)
+}
+
+/// A client endpoint with a connection to an address that does not answer.
+struct Client {
+    started: Instant,
+    config: Handle,
+    endpoint: Handle,
+    connection: Handle,
+}
+
+impl Client {
+    fn connect() -> Self {
+        let (cert, key) = self_signed_cert();
+        let (mut config, mut endpoint_id, mut endpoint) =
+            (std::ptr::null_mut(), 0, std::ptr::null_mut());
+        unsafe {
+            check(create_client_config(
+                &mut config,
+                cert.as_ptr(),
+                cert.len() as u32,
+                key.as_ptr(),
+                key.len() as u32,
+            ));
+            check(create_client_endpoint(
+                config,
+                &mut endpoint_id,
+                &mut endpoint,
+            ));
+        }
+
+        let host = b"localhost";
+        let (mut connection, mut connection_id) = (std::ptr::null_mut(), 0);
+        check(unsafe {
+            connect_client(
+                endpoint,
+                host.as_ptr(),
+                host.len() as u32,
+                IpAddr::from(UNANSWERED_ADDRESS.parse::<SocketAddr>().unwrap()),
+                &mut connection,
+                &mut connection_id,
+            )
+        });
+
+        Client {
+            started: Instant::now(),
+            config,
+            endpoint,
+            connection,
+        }
+    }
+
+    fn poll(&self) {
+        check(unsafe { poll_endpoint(self.endpoint, self.started.elapsed().as_micros() as u64) });
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        unsafe {
+            connection_handle_release(self.connection);
+            dispose_endpoint(self.endpoint);
+            free_client_config(self.config);
+        }
+    }
+}
+
+// The callbacks are global, so all cases run in one test.
+#[test]
+fn concurrent_access() {
+    registering_callbacks_races_invocation();
+    connection_is_shared_between_threads();
+}
+
+/// Threads replace the transmit callback while the endpoint transmits, every transmit reaches one of the callbacks.
+fn registering_callbacks_races_invocation() {
+    check(unsafe { set_on_transmit_v2(on_transmit_first) });
+    let client = Client::connect();
+    let barrier = Arc::new(Barrier::new(THREADS + 1));
+
+    let registering = (0..THREADS)
+        .map(|thread| {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                for iteration in 0..ITERATIONS {
+                    let callback = if (thread + iteration) % 2 == 0 {
+                        on_transmit_first
+                    } else {
+                        on_transmit_second
+                    };
+                    check(unsafe { set_on_transmit_v2(callback) });
+                    check(unsafe { set_on_connected(on_connected) });
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    barrier.wait();
+    let before = transmits();
+    while registering.iter().any(|thread| !thread.is_finished()) || transmits() == before {
+        assert!(
+            client.started.elapsed() < TIMEOUT,
+            "the endpoint did not transmit"
+        );
+        client.poll();
+        thread::sleep(Duration::from_millis(1));
+    }
+    for thread in registering {
+        thread.join().unwrap();
+    }
+    assert!(transmits() > before);
+}
+
+/// Threads clone, read and release a connection while the endpoint is polled, then the original handle still works.
+fn connection_is_shared_between_threads() {
+    check(unsafe { set_on_transmit_v2(on_transmit_first) });
+    let client = Client::connect();
+    let stop = Arc::new(AtomicBool::new(false));
+    let barrier = Arc::new(Barrier::new(THREADS + 1));
+
+    let connection = client.connection as usize;
+    let accessing = (0..THREADS)
+        .map(|_| {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                for _ in 0..ITERATIONS {
+                    let mut clone = std::ptr::null_mut();
+                    let (mut state, mut rtt, mut streams) = (ConnectionState::Closed, 0, 0);
+                    unsafe {
+                        check(connection_handle_clone(connection as Handle, &mut clone));
+                        check(connection_state(clone, &mut state));
+                        check(connection_rtt_micros(clone, &mut rtt));
+                        check(connection_open_streams(clone, BI, &mut streams));
+                        check(connection_handle_release(clone));
+                    }
+                    assert_eq!(state, ConnectionState::Handshaking);
+                    assert_eq!(streams, 0);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let polling = {
+        let stop = stop.clone();
+        let (started, endpoint) = (client.started, client.endpoint as usize);
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                check(unsafe {
+                    poll_endpoint(endpoint as Handle, started.elapsed().as_micros() as u64)
+                });
+            }
+        })
+    };
+
+    barrier.wait();
+    for thread in accessing {
+        thread.join().unwrap();
+    }
+    stop.store(true, Ordering::SeqCst);
+    polling.join().unwrap();
+
+    let mut state = ConnectionState::Closed;
+    check(unsafe { connection_state(client.connection, &mut state) });
+    assert_eq!(state, ConnectionState::Handshaking);
+}
@@ -0,0 +1,58 @@
+//! `create_server_config` and `create_client_config` return `InvalidArgument` for a certificate or key that can't be
+//! used, rather than panicking across the FFI boundary.
+
+use quinn_ffi::ffi::{
+    FFIResult,
+    FFIResultKind,
+};
+use std::ffi::c_void;
+
+type Handle = *mut c_void;
+
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_server_config")]
+    fn create_server_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_config")]
+    fn create_client_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+}
+
+#[test]
+fn invalid_cert_and_key_are_rejected() {
+    let (cert, key) = (b"not a certificate", b"not a key");
+    let mut handle = std::ptr::null_mut();
+
+    let result = unsafe {
+        create_server_config(
+            &mut handle,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        )
+    };
+    assert_eq!(result.kind, FFIResultKind::InvalidArgument);
+
+    let result = unsafe {
+        create_client_config(
+            &mut handle,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        )
+    };
+    assert_eq!(result.kind, FFIResultKind::InvalidArgument);
+    assert!(handle.is_null());
+}
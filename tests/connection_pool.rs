@@ -0,0 +1,199 @@
+//! `endpoint_get_or_connect` returns the connection made by an earlier call to the same address and server name, also
+//! while it is handshaking, and concurrent callers share one connection.
+//!
+//! The client endpoint is polled with `poll_endpoint`, the connections don't need the handshake to complete.
+
+use quinn_ffi::{
+    ffi::{
+        FFIResult,
+        FFIResultKind,
+    },
+    proto_impl::IpAddr,
+};
+use std::{
+    ffi::c_void,
+    net::SocketAddr,
+    sync::Barrier,
+    thread,
+};
+
+type Handle = *mut c_void;
+
+const SERVER_ADDRESS: &str = "127.0.0.1:4433";
+const OTHER_SERVER_ADDRESS: &str = "127.0.0.1:4435";
+const THREADS: usize = 8;
+
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "generate_self_signed_cert")]
+    fn generate_self_signed_cert(
+        subject_alt_names: *const u8,
+        subject_alt_names_len: u32,
+        validity_days: u32,
+        cert_buf: *mut u8,
+        cert_buf_len: usize,
+        actual_cert_len: *mut usize,
+        key_buf: *mut u8,
+        key_buf_len: usize,
+        actual_key_len: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_config")]
+    fn create_client_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_endpoint")]
+    fn create_client_endpoint(
+        config: Handle,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_get_or_connect")]
+    fn endpoint_get_or_connect(
+        endpoint: Handle,
+        host: *const u8,
+        host_len: u32,
+        address: IpAddr,
+        out_connection: *mut Handle,
+        out_connection_id: *mut u32,
+        out_reused: *mut bool,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "close_connection")]
+    fn close_connection(
+        connection: Handle,
+        reason: *const u8,
+        reason_len: u32,
+        error_code: u64,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "poll_endpoint")]
+    fn poll_endpoint(endpoint: Handle, now_micros: u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_handle_release")]
+    fn connection_handle_release(connection: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "dispose_endpoint")]
+    fn dispose_endpoint(endpoint: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_client_config")]
+    fn free_client_config(config: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_config_set_manual_polling")]
+    fn endpoint_config_set_manual_polling(enabled: bool) -> FFIResult;
+}
+
+fn check(result: FFIResult) {
+    assert_eq!(result.kind, FFIResultKind::Ok, "{:?}", result);
+}
+
+fn self_signed_cert() -> (Vec<u8>, Vec<u8>) {
+    let names = b"localhost";
+    let (mut cert, mut key) = (vec![0; 1024], vec![0; 1024]);
+    let (mut cert_len, mut key_len) = (0, 0);
+
+    check(unsafe {
+        generate_self_signed_cert(
+            names.as_ptr(),
+            names.len() as u32,
+            1,
+            cert.as_mut_ptr(),
+            cert.len(),
+            &mut cert_len,
+            key.as_mut_ptr(),
+            key.len(),
+            &mut key_len,
+        )
+    });
+
+    cert.truncate(cert_len);
+    key.truncate(key_len);
+    (cert, key)
+}
+
+/// Returns the connection handle, as address, its id and whether it was reused.
+fn get_or_connect(endpoint: Handle, address: &str) -> (usize, u32, bool) {
+    let host = b"localhost";
+    let (mut connection, mut connection_id, mut reused) = (std::ptr::null_mut(), 0, false);
+    check(unsafe {
+        endpoint_get_or_connect(
+            endpoint,
+            host.as_ptr(),
+            host.len() as u32,
+            IpAddr::from(address.parse::<SocketAddr>().unwrap()),
+            &mut connection,
+            &mut connection_id,
+            &mut reused,
+        )
+    });
+    (connection as usize, connection_id, reused)
+}
+
+#[test]
+fn handshaking_connections_are_shared() {
+    check(unsafe { endpoint_config_set_manual_polling(true) });
+    let (cert, key) = self_signed_cert();
+
+    let (mut config, mut endpoint_id, mut endpoint) =
+        (std::ptr::null_mut(), 0, std::ptr::null_mut());
+    unsafe {
+        check(create_client_config(
+            &mut config,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        ));
+        check(create_client_endpoint(
+            config,
+            &mut endpoint_id,
+            &mut endpoint,
+        ));
+    }
+    let mut connections = Vec::new();
+
+    // The second call returns the connection of the first, although its handshake did not complete.
+    let first = get_or_connect(endpoint, SERVER_ADDRESS);
+    let second = get_or_connect(endpoint, SERVER_ADDRESS);
+    assert!(!first.2);
+    assert!(second.2);
+    assert_eq!(first.1, second.1);
+    connections.extend([first.0, second.0]);
+
+    // Concurrent callers make a single connection.
+    let barrier = Barrier::new(THREADS);
+    let endpoint_address = endpoint as usize;
+    let concurrent: Vec<_> = thread::scope(|scope| {
+        let threads: Vec<_> = (0..THREADS)
+            .map(|_| {
+                scope.spawn(|| {
+                    barrier.wait();
+                    get_or_connect(endpoint_address as Handle, OTHER_SERVER_ADDRESS)
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect()
+    });
+    assert_eq!(
+        concurrent.iter().filter(|(_, _, reused)| !reused).count(),
+        1
+    );
+    assert!(concurrent.iter().all(|(_, id, _)| *id == concurrent[0].1));
+    connections.extend(concurrent.iter().map(|(connection, _, _)| *connection));
+
+    // A closed connection is replaced.
+    let reason = b"done";
+    check(unsafe { close_connection(first.0 as Handle, reason.as_ptr(), reason.len() as u32, 0) });
+    check(unsafe { poll_endpoint(endpoint, 0) });
+    let replaced = get_or_connect(endpoint, SERVER_ADDRESS);
+    assert!(!replaced.2);
+    assert_ne!(replaced.1, first.1);
+    connections.push(replaced.0);
+
+    unsafe {
+        for connection in connections {
+            check(connection_handle_release(connection as Handle));
+        }
+        check(dispose_endpoint(endpoint));
+        check(free_client_config(config));
+    }
+}
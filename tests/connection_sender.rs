@@ -0,0 +1,194 @@
+//! Sends queued with a connection sender are bounded until the connection is polled, and sends that fail when the
+//! connection applies them are taken with `sender_take_failure`.
+//!
+//! The client endpoint is polled with `poll_endpoint`, the sends don't need the handshake to complete.
+
+use quinn_ffi::{
+    ffi::{
+        FFIResult,
+        FFIResultKind,
+    },
+    proto_impl::{
+        IpAddr,
+        SendFailure,
+    },
+};
+use std::{
+    ffi::c_void,
+    mem::MaybeUninit,
+    net::SocketAddr,
+};
+
+type Handle = *mut c_void;
+
+const SERVER_ADDRESS: &str = "127.0.0.1:4433";
+/// A unidirectional stream the server would open, the client can't write to it.
+const SERVER_UNI_STREAM: u64 = 3;
+
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "generate_self_signed_cert")]
+    fn generate_self_signed_cert(
+        subject_alt_names: *const u8,
+        subject_alt_names_len: u32,
+        validity_days: u32,
+        cert_buf: *mut u8,
+        cert_buf_len: usize,
+        actual_cert_len: *mut usize,
+        key_buf: *mut u8,
+        key_buf_len: usize,
+        actual_key_len: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_config")]
+    fn create_client_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_endpoint")]
+    fn create_client_endpoint(
+        config: Handle,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connect_client")]
+    fn connect_client(
+        endpoint: Handle,
+        host: *const u8,
+        host_len: u32,
+        address: IpAddr,
+        out_connection: *mut Handle,
+        out_connection_id: *mut u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "poll_endpoint")]
+    fn poll_endpoint(endpoint: Handle, now_micros: u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_sender_handle")]
+    fn connection_sender_handle(connection: Handle, out_sender: *mut Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "sender_write_stream")]
+    fn sender_write_stream(
+        sender: Handle,
+        stream_id: u64,
+        buffer: *const u8,
+        buf_len: usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "sender_take_failure")]
+    fn sender_take_failure(sender: Handle, out_failure: *mut SendFailure) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_connection_sender")]
+    fn free_connection_sender(sender: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_handle_release")]
+    fn connection_handle_release(connection: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "dispose_endpoint")]
+    fn dispose_endpoint(endpoint: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_client_config")]
+    fn free_client_config(config: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_config_set_manual_polling")]
+    fn endpoint_config_set_manual_polling(enabled: bool) -> FFIResult;
+}
+
+fn check(result: FFIResult) {
+    assert_eq!(result.kind, FFIResultKind::Ok, "{:?}", result);
+}
+
+fn self_signed_cert() -> (Vec<u8>, Vec<u8>) {
+    let names = b"localhost";
+    let (mut cert, mut key) = (vec![0; 1024], vec![0; 1024]);
+    let (mut cert_len, mut key_len) = (0, 0);
+
+    check(unsafe {
+        generate_self_signed_cert(
+            names.as_ptr(),
+            names.len() as u32,
+            1,
+            cert.as_mut_ptr(),
+            cert.len(),
+            &mut cert_len,
+            key.as_mut_ptr(),
+            key.len(),
+            &mut key_len,
+        )
+    });
+
+    cert.truncate(cert_len);
+    key.truncate(key_len);
+    (cert, key)
+}
+
+fn take_failure(sender: Handle) -> Option<SendFailure> {
+    let mut failure = MaybeUninit::uninit();
+    let result = unsafe { sender_take_failure(sender, failure.as_mut_ptr()) };
+    match result.kind {
+        FFIResultKind::Ok => Some(unsafe { failure.assume_init() }),
+        FFIResultKind::BufferBlocked => None,
+        _ => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn sender_queue_is_bounded_and_reports_failures() {
+    check(unsafe { endpoint_config_set_manual_polling(true) });
+    let (cert, key) = self_signed_cert();
+
+    let (mut config, mut endpoint_id, mut endpoint) =
+        (std::ptr::null_mut(), 0, std::ptr::null_mut());
+    let (mut connection, mut connection_id) = (std::ptr::null_mut(), 0);
+    let mut sender = std::ptr::null_mut();
+    let host = b"localhost";
+    unsafe {
+        check(create_client_config(
+            &mut config,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        ));
+        check(create_client_endpoint(
+            config,
+            &mut endpoint_id,
+            &mut endpoint,
+        ));
+        check(connect_client(
+            endpoint,
+            host.as_ptr(),
+            host.len() as u32,
+            IpAddr::from(SERVER_ADDRESS.parse::<SocketAddr>().unwrap()),
+            &mut connection,
+            &mut connection_id,
+        ));
+        check(connection_sender_handle(connection, &mut sender));
+    }
+    assert!(take_failure(sender).is_none());
+
+    // The write is queued, it fails once the connection applies it.
+    let data = b"data";
+    check(unsafe { sender_write_stream(sender, SERVER_UNI_STREAM, data.as_ptr(), data.len()) });
+    assert!(take_failure(sender).is_none());
+    check(unsafe { poll_endpoint(endpoint, 0) });
+
+    let failure = take_failure(sender).expect("the failed write is reported to the sender");
+    assert_eq!(failure.stream_id, SERVER_UNI_STREAM);
+    assert!(!failure.datagram);
+    assert_eq!(failure.kind, FFIResultKind::Error);
+    assert!(take_failure(sender).is_none());
+
+    // Sends beyond the capacity of the queue are refused until the connection is polled.
+    for _ in 0..256 {
+        check(unsafe { sender_write_stream(sender, SERVER_UNI_STREAM, data.as_ptr(), data.len()) });
+    }
+    assert_eq!(
+        unsafe { sender_write_stream(sender, SERVER_UNI_STREAM, data.as_ptr(), data.len()) }.kind,
+        FFIResultKind::BufferBlocked
+    );
+    check(unsafe { poll_endpoint(endpoint, 0) });
+    check(unsafe { sender_write_stream(sender, SERVER_UNI_STREAM, data.as_ptr(), data.len()) });
+
+    // Only the last failures are kept.
+    assert_eq!(std::iter::from_fn(|| take_failure(sender)).count(), 64);
+
+    unsafe {
+        check(free_connection_sender(sender));
+        check(connection_handle_release(connection));
+        check(dispose_endpoint(endpoint));
+        check(free_client_config(config));
+    }
+}
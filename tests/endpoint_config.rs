@@ -0,0 +1,75 @@
+//! The `endpoint_config_handle_set_*` functions validate their settings and only change the configuration they are
+//! given, unlike the `endpoint_config_set_*` functions which apply to every endpoint created afterwards.
+
+use quinn_ffi::ffi::{
+    FFIResult,
+    FFIResultKind,
+};
+use std::{
+    ffi::c_void,
+    ptr,
+};
+
+type Handle = *mut c_void;
+
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_endpoint_config")]
+    fn create_endpoint_config(out_handle: *mut Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_endpoint_config")]
+    fn free_endpoint_config(handle: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_config_handle_set_supported_versions")]
+    fn endpoint_config_handle_set_supported_versions(
+        handle: Handle,
+        versions: *const u32,
+        count: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_config_handle_set_reset_key")]
+    fn endpoint_config_handle_set_reset_key(
+        handle: Handle,
+        key: *const u8,
+        key_len: usize,
+    ) -> FFIResult;
+}
+
+fn check(result: FFIResult) {
+    assert_eq!(result.kind, FFIResultKind::Ok, "{:?}", result);
+}
+
+fn endpoint_config() -> Handle {
+    let mut handle = ptr::null_mut();
+    check(unsafe { create_endpoint_config(&mut handle) });
+    handle
+}
+
+#[test]
+fn supported_versions_must_not_be_empty() {
+    let handle = endpoint_config();
+    let versions = [1u32, 0xff00_001d];
+
+    let result =
+        unsafe { endpoint_config_handle_set_supported_versions(handle, versions.as_ptr(), 0) };
+    assert_ne!(result.kind, FFIResultKind::Ok);
+
+    check(unsafe {
+        endpoint_config_handle_set_supported_versions(
+            handle,
+            versions.as_ptr(),
+            versions.len() as u32,
+        )
+    });
+
+    check(unsafe { free_endpoint_config(handle) });
+}
+
+#[test]
+fn reset_key_must_be_long_enough() {
+    let handle = endpoint_config();
+    let key = [7u8; 32];
+
+    let result = unsafe { endpoint_config_handle_set_reset_key(handle, key.as_ptr(), 31) };
+    assert_ne!(result.kind, FFIResultKind::Ok);
+
+    check(unsafe { endpoint_config_handle_set_reset_key(handle, key.as_ptr(), key.len()) });
+
+    check(unsafe { free_endpoint_config(handle) });
+}
@@ -0,0 +1,229 @@
+//! Builds and runs the example hosts in `examples/`, which echo streams of a native quinn client.
+//!
+//! The hosts load the `cdylib` like bindings do and drive it over real UDP sockets, so a breaking change of the ABI,
+//! e.g. of a signature, a struct layout or a result kind, fails these tests instead of downstream binding repos.
+//! Requires the `example-hosts` feature and a C compiler, the .NET host is skipped if `dotnet` can't be run:
+//!
+//! ```sh
+//! cargo test --features example-hosts --test example_hosts
+//! ```
+#![cfg(feature = "example-hosts")]
+
+use quinn_ffi::ffi::{
+    FFIResult,
+    FFIResultKind,
+};
+use std::{
+    env,
+    io::{
+        BufRead,
+        BufReader,
+    },
+    net::SocketAddr,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::{
+        Child,
+        Command,
+        Stdio,
+    },
+    sync::OnceLock,
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use tempdir::TempDir;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "generate_self_signed_cert")]
+    fn generate_self_signed_cert(
+        subject_alt_names: *const u8,
+        subject_alt_names_len: u32,
+        validity_days: u32,
+        cert_buf: *mut u8,
+        cert_buf_len: usize,
+        actual_cert_len: *mut usize,
+        key_buf: *mut u8,
+        key_buf_len: usize,
+        actual_key_len: *mut usize,
+    ) -> FFIResult;
+}
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Builds the `cdylib`, which `cargo test` does not build, and returns the directory it is in.
+///
+/// It is built into a target directory of its own, such that the build does not wait for the lock of the running tests.
+fn library_dir() -> &'static Path {
+    static LIBRARY_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+    LIBRARY_DIR.get_or_init(|| {
+        // The test runs from `target/<profile>/deps`.
+        let exe = env::current_exe().unwrap();
+        let target_dir = exe.ancestors().nth(3).unwrap().join("example-hosts");
+
+        let status = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".into()))
+            .args(["build", "--lib", "--manifest-path"])
+            .arg(manifest_dir().join("Cargo.toml"))
+            .arg("--target-dir")
+            .arg(&target_dir)
+            // The example hosts import the functions with the default prefix.
+            .env_remove("QUINN_FFI_SYMBOL_PREFIX")
+            .status()
+            .unwrap();
+        assert!(status.success(), "building the cdylib failed");
+
+        target_dir.join("debug")
+    })
+}
+
+/// Writes a self-signed certificate for `localhost` and its key, returns their paths and the certificate.
+fn write_cert(dir: &Path) -> (PathBuf, PathBuf, Vec<u8>) {
+    let names = b"localhost";
+    let (mut cert, mut key) = (vec![0; 1024], vec![0; 1024]);
+    let (mut cert_len, mut key_len) = (0, 0);
+
+    let result = unsafe {
+        generate_self_signed_cert(
+            names.as_ptr(),
+            names.len() as u32,
+            1,
+            cert.as_mut_ptr(),
+            cert.len(),
+            &mut cert_len,
+            key.as_mut_ptr(),
+            key.len(),
+            &mut key_len,
+        )
+    };
+    assert_eq!(result.kind, FFIResultKind::Ok);
+    cert.truncate(cert_len);
+    key.truncate(key_len);
+
+    let (cert_path, key_path) = (dir.join("cert.der"), dir.join("key.der"));
+    std::fs::write(&cert_path, &cert).unwrap();
+    std::fs::write(&key_path, &key).unwrap();
+    (cert_path, key_path, cert)
+}
+
+/// Runs an example host: a native client echoes a stream through it and closes, after which the host must exit with 0.
+async fn echo_through(mut host: Command, cert: &[u8]) {
+    let mut child = host.stdout(Stdio::piped()).spawn().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+
+    // Build tools, e.g. `dotnet run`, may print before the host does.
+    let port = loop {
+        let mut line = String::new();
+        if stdout.read_line(&mut line).unwrap() == 0 {
+            panic!("the host exited before listening: {:?}", child.wait());
+        }
+        if let Some(port) = line.trim().strip_prefix("listening ") {
+            break port.parse::<u16>().unwrap();
+        }
+    };
+    let server_address = SocketAddr::from(([127, 0, 0, 1], port));
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(&rustls::Certificate(cert.to_vec())).unwrap();
+    let mut client = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    client.set_default_client_config(quinn::ClientConfig::with_root_certificates(roots));
+
+    let echo = tokio::time::timeout(TIMEOUT, async {
+        let connection = client
+            .connect(server_address, "localhost")
+            .unwrap()
+            .await
+            .unwrap()
+            .connection;
+
+        let (mut send, recv) = connection.open_bi().await.unwrap();
+        send.write_all(b"hello from quinn").await.unwrap();
+        send.finish().await.unwrap();
+        let echo = recv.read_to_end(1024).await.unwrap();
+
+        connection.close(quinn::VarInt::from_u32(0), b"done");
+        echo
+    })
+    .await
+    .expect("the host did not echo the stream");
+    assert_eq!(echo, b"hello from quinn");
+    client.wait_idle().await;
+
+    let status = wait_with_timeout(&mut child);
+    assert!(status.success(), "the host failed: {}", status);
+
+    let mut rest = String::new();
+    std::io::Read::read_to_string(&mut stdout, &mut rest).unwrap();
+    assert!(rest.contains("closed"), "unexpected output: {}", rest);
+}
+
+fn wait_with_timeout(child: &mut Child) -> std::process::ExitStatus {
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status;
+        }
+        if started.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            panic!("the host did not exit after the connection was closed");
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn c_echo_server() {
+    let dir = TempDir::new("c_echo_server").unwrap();
+    let (cert_path, key_path, cert) = write_cert(dir.path());
+    let library_dir = library_dir();
+
+    let executable = dir.path().join("c_echo_server");
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".into()))
+        .arg(manifest_dir().join("examples/c_echo_server.c"))
+        .arg("-o")
+        .arg(&executable)
+        .arg("-L")
+        .arg(library_dir)
+        .arg("-lquinn_ffi")
+        .arg(format!("-Wl,-rpath,{}", library_dir.display()))
+        .status()
+        .unwrap();
+    assert!(status.success(), "compiling the C example failed");
+
+    let mut host = Command::new(executable);
+    host.arg(cert_path).arg(key_path);
+    echo_through(host, &cert).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn dotnet_echo_server() {
+    let sdk = Command::new("dotnet")
+        .arg("--list-sdks")
+        .output()
+        .is_ok_and(|output| output.status.success() && !output.stdout.is_empty());
+    if !sdk {
+        eprintln!("skipping the .NET example host, `dotnet` or its SDK is not installed");
+        return;
+    }
+
+    let dir = TempDir::new("dotnet_echo_server").unwrap();
+    let (cert_path, key_path, cert) = write_cert(dir.path());
+
+    let mut host = Command::new("dotnet");
+    host.args(["run", "--project"])
+        .arg(manifest_dir().join("examples/dotnet_echo_server"))
+        .arg("--")
+        .arg(cert_path)
+        .arg(key_path)
+        .env("QUINN_FFI_LIBRARY_DIR", library_dir());
+    echo_through(host, &cert).await;
+}
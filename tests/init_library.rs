@@ -0,0 +1,34 @@
+//! `init_library` accepts repeated calls with the same options and rejects conflicting ones.
+
+use quinn_ffi::ffi::{
+    CallbackDispatchMode,
+    FFIResult,
+    FFIResultKind,
+    LibraryOptions,
+};
+
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "init_library")]
+    fn init_library(options: LibraryOptions) -> FFIResult;
+}
+
+#[test]
+fn rejects_conflicting_options() {
+    let options = LibraryOptions {
+        dispatch_mode: CallbackDispatchMode::Inline,
+        suppress_output: true,
+        worker_threads: 0,
+    };
+
+    assert_eq!(unsafe { init_library(options) }.kind, FFIResultKind::Ok);
+    assert_eq!(unsafe { init_library(options) }.kind, FFIResultKind::Ok);
+
+    let conflicting = LibraryOptions {
+        dispatch_mode: CallbackDispatchMode::Queued,
+        ..options
+    };
+    assert_eq!(
+        unsafe { init_library(conflicting) }.kind,
+        FFIResultKind::Error
+    );
+}
@@ -0,0 +1,2106 @@
+//! Runs the FFI surface against a native quinn endpoint over UDP sockets on the loopback interface.
+//!
+//! The FFI endpoints are driven like a client application drives them: datagrams received on a socket are passed to
+//! `handle_datagram`, transmits are sent from the `set_on_transmit_v2` callback and events arrive through callbacks.
+//! Covers the handshake, an echoed bidirectional stream, datagrams and application closes in both roles,
+//! the diagnostics a connection records once enabled,
+//! that a connection keeps working after a call panicked while it held the connection lock,
+//! that a server requiring an authentication token withholds connections until their token is accepted,
+//! and that calls into the connection from `on_auth_token` fail instead of deadlocking,
+//! that a closed connection is reported lost and then freed, after which its handle is rejected,
+//! that reads tell a blocked stream from a finished and a reset one and keep what does not fit the buffer,
+//! that a handshake with an unresponsive peer is closed once the handshake timeout expires,
+//! that a blocking wait returns once its cancellation is cancelled from another thread,
+//! that a manually polled endpoint serves a connection from `poll_endpoint` calls,
+//! that an endpoint serves a connection whose datagrams several reader threads pass to `submit_datagram`,
+//! that opening a stream beyond the peer's limit returns `StreamsExhausted` until `on_stream_available` is invoked,
+//! that streams can be accepted, read and written from within the callbacks,
+//! that a connection polling its events queues one datagram event for several datagrams received meanwhile,
+//! that files passed as descriptors are received from and sent over streams,
+//! and that IPv6 scope ids, flow info and IPv4-mapped addresses round-trip from `handle_datagram` to the transmit callback.
+
+use bytes::Bytes;
+use futures_util::StreamExt;
+use quinn_ffi::{
+    ffi::{
+        FFIResult,
+        FFIResultKind,
+    },
+    proto_impl::{
+        AppEvent,
+        AppEventKind,
+        ConnectionState,
+        DiagnosticsEvent,
+        DiagnosticsEventKind,
+        IpAddr,
+    },
+};
+use std::{
+    convert::TryFrom,
+    ffi::c_void,
+    fs::File,
+    mem::MaybeUninit,
+    net::{
+        Ipv4Addr,
+        Ipv6Addr,
+        SocketAddr,
+        SocketAddrV6,
+        UdpSocket,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        mpsc,
+        Arc,
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+type Handle = *mut c_void;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const BI: u8 = 0;
+const UNI: u8 = 1;
+const AUTH_TOKEN: &[u8] = b"ticket";
+const AUTH_REJECTED: u64 = 42;
+const STREAM_RESET: u64 = 11;
+
+// The calling convention of the exported functions is `cdecl`, which is `C` on the tested targets.
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "generate_self_signed_cert")]
+    fn generate_self_signed_cert(
+        subject_alt_names: *const u8,
+        subject_alt_names_len: u32,
+        validity_days: u32,
+        cert_buf: *mut u8,
+        cert_buf_len: usize,
+        actual_cert_len: *mut usize,
+        key_buf: *mut u8,
+        key_buf_len: usize,
+        actual_key_len: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_server_config")]
+    fn create_server_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_config")]
+    fn create_client_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_server_endpoint")]
+    fn create_server_endpoint(
+        config: Handle,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_endpoint")]
+    fn create_client_endpoint(
+        config: Handle,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connect_client")]
+    fn connect_client(
+        endpoint: Handle,
+        host: *const u8,
+        host_len: u32,
+        address: IpAddr,
+        out_connection: *mut Handle,
+        out_connection_id: *mut u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "handle_datagram")]
+    fn handle_datagram(
+        endpoint: Handle,
+        data: *const u8,
+        length: usize,
+        address: IpAddr,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "open_stream")]
+    fn open_stream(connection: Handle, direction: u8, out_stream_id: *mut u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "accept_stream")]
+    fn accept_stream(connection: Handle, direction: u8, out_stream_id: *mut u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "read_stream_v2")]
+    fn read_stream_v2(
+        connection: Handle,
+        stream_id: u64,
+        buf: *mut u8,
+        buf_len: usize,
+        out_len: *mut usize,
+        out_finished: *mut bool,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "read_stream_v3")]
+    fn read_stream_v3(
+        connection: Handle,
+        stream_id: u64,
+        buf: *mut u8,
+        buf_len: usize,
+        out_len: *mut usize,
+        out_reset_code: *mut u64,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "write_stream")]
+    fn write_stream(
+        connection: Handle,
+        stream_id: u64,
+        buf: *const u8,
+        buf_len: usize,
+        out_written: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "finish_stream")]
+    fn finish_stream(connection: Handle, stream_id: u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "send_datagram_expiring")]
+    fn send_datagram_expiring(
+        connection: Handle,
+        buf: *const u8,
+        buf_len: usize,
+        expiry_micros: u64,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "test_hook_panic_in_connection")]
+    fn test_hook_panic_in_connection(connection: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "close_connection")]
+    fn close_connection(
+        connection: Handle,
+        reason: *const u8,
+        reason_len: u32,
+        error_code: u64,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_set_event_polling_enabled")]
+    fn endpoint_set_event_polling_enabled(endpoint: Handle, enabled: bool) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "poll_connection_events")]
+    fn poll_connection_events(
+        connection: Handle,
+        out_events: *mut AppEvent,
+        capacity: usize,
+        out_count: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "close_connection_with_app_error")]
+    fn close_connection_with_app_error(
+        connection: Handle,
+        error_code: u64,
+        reason: *const u8,
+        reason_len: usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_handle_release")]
+    fn connection_handle_release(connection: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "send_file_fd")]
+    fn send_file_fd(connection: Handle, stream_id: u64, file: i64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "receive_to_file_fd")]
+    fn receive_to_file_fd(connection: Handle, stream_id: u64, file: i64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "stream_set_user_data")]
+    fn stream_set_user_data(connection: Handle, stream_id: u64, user_data: u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "stream_user_data")]
+    fn stream_user_data(connection: Handle, stream_id: u64, out_user_data: *mut u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_set_diagnostics_enabled")]
+    fn connection_set_diagnostics_enabled(connection: Handle, enabled: bool) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_diagnostics")]
+    fn connection_diagnostics(
+        connection: Handle,
+        out_events: *mut DiagnosticsEvent,
+        capacity: usize,
+        out_count: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_cancellation")]
+    fn create_cancellation(out_handle: *mut Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "cancellation_cancel")]
+    fn cancellation_cancel(handle: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_cancellation")]
+    fn free_cancellation(handle: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "wait_connected_v2")]
+    fn wait_connected_v2(connection: Handle, timeout_ms: u64, cancellation: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "poll_connection")]
+    fn poll_connection(connection: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "poll_endpoint")]
+    fn poll_endpoint(endpoint: Handle, now_micros: u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_datagram_ingress")]
+    fn endpoint_datagram_ingress(endpoint: Handle, out_ingress: *mut Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "submit_datagram")]
+    fn submit_datagram(
+        ingress: Handle,
+        data: *const u8,
+        length: usize,
+        address: IpAddr,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_datagram_ingress")]
+    fn free_datagram_ingress(ingress: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "dispose_endpoint")]
+    fn dispose_endpoint(endpoint: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_server_config")]
+    fn free_server_config(config: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_client_config")]
+    fn free_client_config(config: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_require_auth_token")]
+    fn endpoint_require_auth_token(
+        endpoint: Handle,
+        enabled: bool,
+        reject_error_code: u64,
+    ) -> FFIResult;
+
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_config_set_handshake_timeout")]
+    fn endpoint_config_set_handshake_timeout(timeout_millis: u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_config_set_manual_polling")]
+    fn endpoint_config_set_manual_polling(enabled: bool) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_transmit_v2")]
+    fn set_on_transmit_v2(callback: extern "C" fn(u64, *const u8, usize, IpAddr)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_new_connection")]
+    fn set_on_new_connection(callback: extern "C" fn(Handle, u32, u64)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_connected")]
+    fn set_on_connected(callback: extern "C" fn(u32)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_stream_opened")]
+    fn set_on_stream_opened(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_stream_readable")]
+    fn set_on_stream_readable(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_datagram_received")]
+    fn set_on_datagram_received(callback: extern "C" fn(u32)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_application_close")]
+    fn set_on_application_close(callback: extern "C" fn(u32, u64, *const u8, u32)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_auth_token")]
+    fn set_on_auth_token(callback: extern "C" fn(u32, *const u8, u32, *mut bool)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_connection_lost")]
+    fn set_on_connection_lost(callback: extern "C" fn(u32, *const u8, u32)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_connection_freed")]
+    fn set_on_connection_freed(callback: extern "C" fn(u32)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_handshake_timeout")]
+    fn set_on_handshake_timeout(callback: extern "C" fn(u32)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_stream_available")]
+    fn set_on_stream_available(callback: extern "C" fn(u32, u8)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_file_transfer_finished")]
+    fn set_on_file_transfer_finished(callback: extern "C" fn(u32, u64, bool)) -> FFIResult;
+}
+
+/// An event reported by a callback, forwarded to the test thread.
+#[derive(Debug)]
+enum Event {
+    /// The connection handle, as an address such that the event can be sent across threads.
+    NewConnection(usize, u32),
+    Connected(u32),
+    StreamOpened(u32, u64),
+    StreamReadable(u32, u64),
+    DatagramReceived(u32),
+    ApplicationClose(u32, u64, Vec<u8>),
+    ConnectionLost(u32),
+    ConnectionFreed(u32),
+    HandshakeTimeout(u32),
+    StreamAvailable(u32, u8),
+    /// A stream accepted, read and echoed from within the callbacks, with the result of the accept and the data.
+    EchoedInCallback(u32, FFIResultKind, Vec<u8>),
+    FileTransferFinished(u32, u64, bool),
+    /// The result of a call into the connection from within `on_auth_token`.
+    AuthTokenCall(u32, FFIResultKind),
+    /// A transmit of an endpoint without a socket, e.g. to an address that is not routable.
+    UnroutedTransmit(u64, SocketAddr),
+}
+
+static EVENTS: Mutex<Option<mpsc::Sender<Event>>> = Mutex::new(None);
+static SOCKETS: Mutex<Vec<(u64, UdpSocket)>> = Mutex::new(Vec::new());
+/// Whether new connections echo their streams from within the callbacks.
+static ECHO_IN_CALLBACKS: AtomicBool = AtomicBool::new(false);
+/// The connections that echo their streams from within the callbacks, with their handle as an address.
+static ECHOING: Mutex<Vec<(u32, usize)>> = Mutex::new(Vec::new());
+/// The data read so far from the streams echoed from within the callbacks.
+static ECHO_READS: Mutex<Vec<(u32, u64, Vec<u8>)>> = Mutex::new(Vec::new());
+/// The handles of all new connections as addresses, for the callbacks that are only passed the connection id.
+static CONNECTIONS: Mutex<Vec<(u32, usize)>> = Mutex::new(Vec::new());
+
+fn report(event: Event) {
+    if let Some(events) = EVENTS.lock().unwrap().as_ref() {
+        let _ = events.send(event);
+    }
+}
+
+extern "C" fn on_transmit(endpoint_id: u64, data: *const u8, length: usize, address: IpAddr) {
+    let data = unsafe { std::slice::from_raw_parts(data, length) };
+    let address = SocketAddr::try_from(address).unwrap();
+
+    let sockets = SOCKETS.lock().unwrap();
+    match sockets.iter().find(|(id, _)| *id == endpoint_id) {
+        Some((_, socket)) => {
+            let _ = socket.send_to(data, address);
+        }
+        None => report(Event::UnroutedTransmit(endpoint_id, address)),
+    }
+}
+
+extern "C" fn on_new_connection(connection: Handle, connection_id: u32, _endpoint_id: u64) {
+    if ECHO_IN_CALLBACKS.load(Ordering::SeqCst) {
+        ECHOING
+            .lock()
+            .unwrap()
+            .push((connection_id, connection as usize));
+    }
+    CONNECTIONS
+        .lock()
+        .unwrap()
+        .push((connection_id, connection as usize));
+    report(Event::NewConnection(connection as usize, connection_id));
+}
+
+extern "C" fn on_connected(connection_id: u32) {
+    report(Event::Connected(connection_id));
+}
+
+extern "C" fn on_stream_opened(connection_id: u32, stream_id: u64, _direction: u8) {
+    if let Some(connection) = echoing(connection_id) {
+        let mut accepted = 0;
+        let result = unsafe { accept_stream(connection, BI, &mut accepted) };
+        if result.kind != FFIResultKind::Ok {
+            report(Event::EchoedInCallback(
+                connection_id,
+                result.kind,
+                Vec::new(),
+            ));
+            return;
+        }
+        assert_eq!(accepted, stream_id);
+        echo_in_callback(connection, connection_id, stream_id);
+    }
+    report(Event::StreamOpened(connection_id, stream_id));
+}
+
+extern "C" fn on_stream_readable(connection_id: u32, stream_id: u64, _direction: u8) {
+    if let Some(connection) = echoing(connection_id) {
+        echo_in_callback(connection, connection_id, stream_id);
+    }
+    report(Event::StreamReadable(connection_id, stream_id));
+}
+
+extern "C" fn on_stream_available(connection_id: u32, direction: u8) {
+    report(Event::StreamAvailable(connection_id, direction));
+}
+
+extern "C" fn on_datagram_received(connection_id: u32) {
+    report(Event::DatagramReceived(connection_id));
+}
+
+extern "C" fn on_application_close(
+    connection_id: u32,
+    code: u64,
+    reason: *const u8,
+    reason_len: u32,
+) {
+    let reason = unsafe { std::slice::from_raw_parts(reason, reason_len as usize) };
+    report(Event::ApplicationClose(
+        connection_id,
+        code,
+        reason.to_vec(),
+    ));
+}
+
+extern "C" fn on_connection_lost(connection_id: u32, _message: *const u8, _message_len: u32) {
+    report(Event::ConnectionLost(connection_id));
+}
+
+extern "C" fn on_connection_freed(connection_id: u32) {
+    report(Event::ConnectionFreed(connection_id));
+}
+
+extern "C" fn on_handshake_timeout(connection_id: u32) {
+    report(Event::HandshakeTimeout(connection_id));
+}
+
+extern "C" fn on_file_transfer_finished(connection_id: u32, stream_id: u64, succeeded: bool) {
+    report(Event::FileTransferFinished(
+        connection_id,
+        stream_id,
+        succeeded,
+    ));
+}
+
+extern "C" fn on_auth_token(connection_id: u32, token: *const u8, len: u32, accept: *mut bool) {
+    let token = unsafe { std::slice::from_raw_parts(token, len as usize) };
+    unsafe { *accept = token == AUTH_TOKEN };
+
+    // The connection is locked while the token is checked, the call has to fail rather than deadlock.
+    let connection = CONNECTIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(id, _)| *id == connection_id)
+        .map(|(_, connection)| *connection as Handle);
+    if let Some(connection) = connection {
+        let datagram = b"from on_auth_token";
+        let result = unsafe {
+            send_datagram_expiring(connection, datagram.as_ptr(), datagram.len(), 1_000_000)
+        };
+        report(Event::AuthTokenCall(connection_id, result.kind));
+    }
+}
+
+/// Returns the handle of the connection if it echoes its streams from within the callbacks.
+fn echoing(connection_id: u32) -> Option<Handle> {
+    ECHOING
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(id, _)| *id == connection_id)
+        .map(|(_, connection)| *connection as Handle)
+}
+
+/// Reads the stream from within a callback, once it is finished writes the data back and reports it.
+fn echo_in_callback(connection: Handle, connection_id: u32, stream_id: u64) {
+    let (read, kind, _) = read_until_empty(connection, stream_id, 1024);
+
+    let mut reads = ECHO_READS.lock().unwrap();
+    let index = match reads
+        .iter()
+        .position(|(id, stream, _)| (*id, *stream) == (connection_id, stream_id))
+    {
+        Some(index) => index,
+        None => {
+            reads.push((connection_id, stream_id, Vec::new()));
+            reads.len() - 1
+        }
+    };
+    reads[index].2.extend_from_slice(&read);
+    if kind != FFIResultKind::StreamFinished {
+        return;
+    }
+    let (_, _, data) = reads.remove(index);
+    drop(reads);
+
+    write_and_finish(connection, stream_id, &data);
+    report(Event::EchoedInCallback(
+        connection_id,
+        FFIResultKind::Ok,
+        data,
+    ));
+}
+
+fn check(result: FFIResult) {
+    assert_eq!(result.kind, FFIResultKind::Ok);
+}
+
+/// An FFI endpoint with a socket whose datagrams are passed to the endpoint by receiving threads.
+struct FfiEndpoint {
+    handle: Handle,
+    address: SocketAddr,
+    stop: Arc<AtomicBool>,
+    receivers: Vec<thread::JoinHandle<()>>,
+    ingress: Option<Handle>,
+}
+
+impl FfiEndpoint {
+    /// Binds a socket whose datagrams one thread passes to `handle_datagram`.
+    fn bind(endpoint_id: u64, handle: Handle) -> Self {
+        Self::spawn_receivers(endpoint_id, handle, 1, None)
+    }
+
+    /// Binds a socket that `readers` threads read, they pass its datagrams to `submit_datagram`.
+    fn bind_ingress(endpoint_id: u64, handle: Handle, readers: usize) -> Self {
+        let mut ingress = std::ptr::null_mut();
+        check(unsafe { endpoint_datagram_ingress(handle, &mut ingress) });
+        Self::spawn_receivers(endpoint_id, handle, readers, Some(ingress))
+    }
+
+    fn spawn_receivers(
+        endpoint_id: u64,
+        handle: Handle,
+        readers: usize,
+        ingress: Option<Handle>,
+    ) -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        let address = socket.local_addr().unwrap();
+
+        SOCKETS
+            .lock()
+            .unwrap()
+            .push((endpoint_id, socket.try_clone().unwrap()));
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let receivers = (0..readers)
+            .map(|_| {
+                let socket = socket.try_clone().unwrap();
+                let stop = stop.clone();
+                let (endpoint, ingress) =
+                    (handle as usize, ingress.map(|ingress| ingress as usize));
+                thread::spawn(move || {
+                    let mut buf = [0; 65536];
+                    while !stop.load(Ordering::SeqCst) {
+                        if let Ok((length, peer)) = socket.recv_from(&mut buf) {
+                            let (data, address) = (buf.as_ptr(), IpAddr::from(peer));
+                            unsafe {
+                                match ingress {
+                                    Some(ingress) => {
+                                        submit_datagram(ingress as Handle, data, length, address)
+                                    }
+                                    None => {
+                                        handle_datagram(endpoint as Handle, data, length, address)
+                                    }
+                                }
+                            };
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        FfiEndpoint {
+            handle,
+            address,
+            stop,
+            receivers,
+            ingress,
+        }
+    }
+}
+
+impl Drop for FfiEndpoint {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for receiver in self.receivers.drain(..) {
+            receiver.join().unwrap();
+        }
+        unsafe {
+            if let Some(ingress) = self.ingress {
+                free_datagram_ingress(ingress);
+            }
+            dispose_endpoint(self.handle);
+        }
+    }
+}
+
+/// Replaces the event channel, such that events of a previous scenario are not received.
+fn events() -> mpsc::Receiver<Event> {
+    let (tx, rx) = mpsc::channel();
+    *EVENTS.lock().unwrap() = Some(tx);
+    rx
+}
+
+/// Waits for the first event `select` returns a value for, other events are skipped.
+fn wait_for<T>(events: &mpsc::Receiver<Event>, mut select: impl FnMut(Event) -> Option<T>) -> T {
+    loop {
+        let event = events
+            .recv_timeout(TIMEOUT)
+            .expect("timed out waiting for an event");
+        if let Some(value) = select(event) {
+            return value;
+        }
+    }
+}
+
+/// Reads a stream up to its end, waiting for it to become readable in between.
+fn read_to_end(
+    events: &mpsc::Receiver<Event>,
+    connection: Handle,
+    connection_id: u32,
+    stream_id: u64,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut buf = [0; 1024];
+
+    loop {
+        let (mut length, mut finished) = (0, false);
+        let result = unsafe {
+            read_stream_v2(
+                connection,
+                stream_id,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut length,
+                &mut finished,
+            )
+        };
+
+        // `BufferBlocked` tells that no data is available until the next readable event.
+        if result.kind != FFIResultKind::BufferBlocked {
+            check(result);
+            data.extend_from_slice(&buf[..length]);
+
+            if finished {
+                return data;
+            }
+        }
+
+        wait_for(events, |event| match event {
+            Event::StreamReadable(id, stream) if id == connection_id && stream == stream_id => {
+                Some(())
+            }
+            _ => None,
+        });
+    }
+}
+
+/// Reads a stream with `read_stream_v3` into a buffer of `buf_len` bytes until a read returns no data,
+/// returns the data, the result and the reset code.
+fn read_until_empty(
+    connection: Handle,
+    stream_id: u64,
+    buf_len: usize,
+) -> (Vec<u8>, FFIResultKind, u64) {
+    let mut data = Vec::new();
+    let mut buf = vec![0; buf_len];
+
+    loop {
+        let (mut length, mut code) = (0, 0);
+        let result = unsafe {
+            read_stream_v3(
+                connection,
+                stream_id,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut length,
+                &mut code,
+            )
+        };
+
+        if result.kind != FFIResultKind::Ok {
+            return (data, result.kind, code);
+        }
+        assert!(length > 0, "a successful read returned no data");
+        data.extend_from_slice(&buf[..length]);
+    }
+}
+
+/// Reads a stream until a read returns a result other than `BufferBlocked`, waiting for it to become readable.
+fn read_until_end(
+    events: &mpsc::Receiver<Event>,
+    connection: Handle,
+    connection_id: u32,
+    stream_id: u64,
+) -> (Vec<u8>, FFIResultKind, u64) {
+    let mut data = Vec::new();
+
+    loop {
+        let (read, kind, code) = read_until_empty(connection, stream_id, 1024);
+        data.extend_from_slice(&read);
+        if kind != FFIResultKind::BufferBlocked {
+            return (data, kind, code);
+        }
+
+        wait_for(events, |event| match event {
+            Event::StreamReadable(id, stream) if id == connection_id && stream == stream_id => {
+                Some(())
+            }
+            _ => None,
+        });
+    }
+}
+
+fn write_and_finish(connection: Handle, stream_id: u64, data: &[u8]) {
+    let mut written = 0;
+    check(unsafe {
+        write_stream(
+            connection,
+            stream_id,
+            data.as_ptr(),
+            data.len(),
+            &mut written,
+        )
+    });
+    assert_eq!(written, data.len());
+    check(unsafe { finish_stream(connection, stream_id) });
+}
+
+/// Creates an FFI server endpoint with the certificate, returning its server configuration, id and handle.
+fn ffi_server_endpoint(cert: &[u8], key: &[u8]) -> (Handle, u64, Handle) {
+    let mut config = std::ptr::null_mut();
+    let (mut endpoint_id, mut endpoint) = (0, std::ptr::null_mut());
+    unsafe {
+        check(create_server_config(
+            &mut config,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        ));
+        check(create_server_endpoint(
+            config,
+            &mut endpoint_id,
+            &mut endpoint,
+        ));
+    }
+    (config, endpoint_id, endpoint)
+}
+
+/// Creates a native client endpoint that trusts the certificate.
+fn native_client(cert: &[u8]) -> quinn::Endpoint {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(&rustls::Certificate(cert.to_vec())).unwrap();
+    let mut client = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    client.set_default_client_config(quinn::ClientConfig::with_root_certificates(roots));
+    client
+}
+
+fn self_signed_cert() -> (Vec<u8>, Vec<u8>) {
+    let names = b"localhost";
+    let (mut cert, mut key) = (vec![0; 1024], vec![0; 1024]);
+    let (mut cert_len, mut key_len) = (0, 0);
+
+    check(unsafe {
+        generate_self_signed_cert(
+            names.as_ptr(),
+            names.len() as u32,
+            1,
+            cert.as_mut_ptr(),
+            cert.len(),
+            &mut cert_len,
+            key.as_mut_ptr(),
+            key.len(),
+            &mut key_len,
+        )
+    });
+
+    cert.truncate(cert_len);
+    key.truncate(key_len);
+    (cert, key)
+}
+
+fn set_callbacks() {
+    unsafe {
+        check(set_on_transmit_v2(on_transmit));
+        check(set_on_new_connection(on_new_connection));
+        check(set_on_connected(on_connected));
+        check(set_on_stream_opened(on_stream_opened));
+        check(set_on_stream_readable(on_stream_readable));
+        check(set_on_datagram_received(on_datagram_received));
+        check(set_on_application_close(on_application_close));
+        check(set_on_auth_token(on_auth_token));
+        check(set_on_connection_lost(on_connection_lost));
+        check(set_on_connection_freed(on_connection_freed));
+        check(set_on_handshake_timeout(on_handshake_timeout));
+        check(set_on_stream_available(on_stream_available));
+        check(set_on_file_transfer_finished(on_file_transfer_finished));
+    }
+}
+
+/// A native client connects to an FFI server, which echoes a stream and answers a datagram, then closes.
+async fn native_client_ffi_server(cert: &[u8], key: &[u8]) {
+    let events = events();
+
+    let (config, endpoint_id, endpoint) = ffi_server_endpoint(cert, key);
+    let server = FfiEndpoint::bind(endpoint_id, endpoint);
+
+    let client = native_client(cert);
+
+    let server_address = server.address;
+    let native = tokio::spawn(async move {
+        let mut connection = client
+            .connect(server_address, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+
+        let (mut send, recv) = connection.connection.open_bi().await.unwrap();
+        send.write_all(b"ping").await.unwrap();
+        send.finish().await.unwrap();
+        let echo = recv.read_to_end(1024).await.unwrap();
+
+        connection
+            .connection
+            .send_datagram(Bytes::from_static(b"datagram"))
+            .unwrap();
+        let datagram = connection.datagrams.next().await.unwrap().unwrap();
+
+        let close = connection.datagrams.next().await.unwrap().unwrap_err();
+        (echo, datagram, close)
+    });
+
+    let (connection, connection_id) = wait_for(&events, |event| match event {
+        Event::NewConnection(connection, id) => Some((connection as Handle, id)),
+        _ => None,
+    });
+    wait_for(&events, |event| match event {
+        Event::Connected(id) if id == connection_id => Some(()),
+        _ => None,
+    });
+
+    let stream_id = wait_for(&events, |event| match event {
+        // The data that opens the stream is read on the open event, it raises no readable event.
+        Event::StreamOpened(id, stream_id) if id == connection_id => Some(stream_id),
+        _ => None,
+    });
+    let message = read_to_end(&events, connection, connection_id, stream_id);
+    assert_eq!(message, b"ping");
+    write_and_finish(connection, stream_id, &message);
+
+    wait_for(&events, |event| match event {
+        Event::DatagramReceived(id) if id == connection_id => Some(()),
+        _ => None,
+    });
+    let reply = b"reply";
+    check(unsafe { send_datagram_expiring(connection, reply.as_ptr(), reply.len(), 1_000_000) });
+
+    // The close must not overtake the datagram, the native client closes the connection on the close.
+    let (echo, datagram, close) = {
+        thread::sleep(Duration::from_millis(100));
+        let reason = b"bye";
+        check(unsafe {
+            close_connection_with_app_error(connection, 7, reason.as_ptr(), reason.len())
+        });
+        tokio::time::timeout(TIMEOUT, native)
+            .await
+            .unwrap()
+            .unwrap()
+    };
+
+    assert_eq!(echo, b"ping");
+    assert_eq!(&datagram[..], reply);
+    match close {
+        quinn::ConnectionError::ApplicationClosed(close) => {
+            assert_eq!(close.error_code.into_inner(), 7);
+            assert_eq!(&close.reason[..], b"bye");
+        }
+        e => panic!("unexpected close {:?}", e),
+    }
+
+    unsafe {
+        check(connection_handle_release(connection));
+        check(free_server_config(config));
+    }
+}
+
+/// An FFI client connects to a native server, which echoes a stream and answers a datagram, then closes.
+async fn ffi_client_native_server(cert: &[u8], key: &[u8]) {
+    let events = events();
+
+    let server_config = quinn::ServerConfig::with_single_cert(
+        vec![rustls::Certificate(cert.to_vec())],
+        rustls::PrivateKey(key.to_vec()),
+    )
+    .unwrap();
+    let (server, mut incoming) =
+        quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+    let server_address = server.local_addr().unwrap();
+
+    let native = tokio::spawn(async move {
+        let mut connection = incoming.next().await.unwrap().await.unwrap();
+
+        let (mut send, recv) = connection.bi_streams.next().await.unwrap().unwrap();
+        let message = recv.read_to_end(1024).await.unwrap();
+        send.write_all(&message).await.unwrap();
+        send.finish().await.unwrap();
+
+        let datagram = connection.datagrams.next().await.unwrap().unwrap();
+        connection
+            .connection
+            .send_datagram(datagram.clone())
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        connection
+            .connection
+            .close(quinn::VarInt::from_u32(9), b"done");
+        (message, datagram, server)
+    });
+
+    let mut config = std::ptr::null_mut();
+    let (mut endpoint_id, mut endpoint) = (0, std::ptr::null_mut());
+    unsafe {
+        check(create_client_config(
+            &mut config,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        ));
+        check(create_client_endpoint(
+            config,
+            &mut endpoint_id,
+            &mut endpoint,
+        ));
+    }
+    let client = FfiEndpoint::bind(endpoint_id, endpoint);
+
+    let host = b"localhost";
+    let (mut connection, mut connection_id) = (std::ptr::null_mut(), 0);
+    check(unsafe {
+        connect_client(
+            client.handle,
+            host.as_ptr(),
+            host.len() as u32,
+            IpAddr::from(server_address),
+            &mut connection,
+            &mut connection_id,
+        )
+    });
+    let (mut recorded, mut count) = (
+        vec![
+            DiagnosticsEvent {
+                micros: 0,
+                value: 0,
+                kind: DiagnosticsEventKind::StateChanged,
+            };
+            64
+        ],
+        0,
+    );
+    let disabled = unsafe {
+        connection_diagnostics(
+            connection,
+            recorded.as_mut_ptr(),
+            recorded.len(),
+            &mut count,
+        )
+    };
+    assert_eq!(disabled.kind, FFIResultKind::Error);
+    check(unsafe { connection_set_diagnostics_enabled(connection, true) });
+    wait_for(&events, |event| match event {
+        Event::Connected(id) if id == connection_id => Some(()),
+        _ => None,
+    });
+
+    let mut stream_id = 0;
+    check(unsafe { open_stream(connection, BI, &mut stream_id) });
+    write_and_finish(connection, stream_id, b"pong");
+    let echo = read_to_end(&events, connection, connection_id, stream_id);
+    assert_eq!(echo, b"pong");
+
+    let datagram = b"datagram";
+    check(unsafe {
+        send_datagram_expiring(connection, datagram.as_ptr(), datagram.len(), 1_000_000)
+    });
+    // The FFI reports received datagrams, the content is checked on the native side.
+    wait_for(&events, |event| match event {
+        Event::DatagramReceived(id) if id == connection_id => Some(()),
+        _ => None,
+    });
+
+    let (code, reason) = wait_for(&events, |event| match event {
+        Event::ApplicationClose(id, code, reason) if id == connection_id => Some((code, reason)),
+        _ => None,
+    });
+    assert_eq!(code, 9);
+    assert_eq!(reason, b"done");
+
+    check(unsafe {
+        connection_diagnostics(
+            connection,
+            recorded.as_mut_ptr(),
+            recorded.len(),
+            &mut count,
+        )
+    });
+    recorded.truncate(count);
+    let kinds = recorded
+        .iter()
+        .map(|event| (event.kind, event.value))
+        .collect::<Vec<_>>();
+    assert!(kinds.contains(&(DiagnosticsEventKind::Connected, 0)));
+    assert!(kinds.contains(&(
+        DiagnosticsEventKind::StateChanged,
+        ConnectionState::Established as u64
+    )));
+    assert!(kinds.contains(&(DiagnosticsEventKind::ConnectionLost, 0)));
+    assert!(recorded
+        .windows(2)
+        .all(|pair| pair[0].micros <= pair[1].micros));
+
+    let (message, received, _server) = tokio::time::timeout(TIMEOUT, native)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message, b"pong");
+    assert_eq!(&received[..], datagram);
+
+    unsafe {
+        check(connection_handle_release(connection));
+        check(free_client_config(config));
+    }
+    drop(client);
+}
+
+/// A call panics while it holds the lock of an FFI connection, which then still sends a datagram and closes.
+async fn panic_in_call_recovers(cert: &[u8], key: &[u8]) {
+    let events = events();
+
+    let (config, endpoint_id, endpoint) = ffi_server_endpoint(cert, key);
+    let server = FfiEndpoint::bind(endpoint_id, endpoint);
+
+    let client = native_client(cert);
+
+    let server_address = server.address;
+    let native = tokio::spawn(async move {
+        let mut connection = client
+            .connect(server_address, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+
+        let datagram = connection.datagrams.next().await.unwrap().unwrap();
+        let close = connection.datagrams.next().await.unwrap().unwrap_err();
+        (datagram, close)
+    });
+
+    let (connection, connection_id) = wait_for(&events, |event| match event {
+        Event::NewConnection(connection, id) => Some((connection as Handle, id)),
+        _ => None,
+    });
+    wait_for(&events, |event| match event {
+        Event::Connected(id) if id == connection_id => Some(()),
+        _ => None,
+    });
+
+    // Error codes are below 2^62, a larger code is rejected without closing the connection.
+    let result = unsafe { close_connection(connection, b"x".as_ptr(), 1, u64::MAX) };
+    assert_eq!(result.kind, FFIResultKind::InvalidArgument);
+
+    let result = unsafe { test_hook_panic_in_connection(connection) };
+    assert_eq!(result.kind, FFIResultKind::Error);
+
+    // The poller may recover the connection first, in which case no call reports the warning.
+    let datagram = b"recovered";
+    let result =
+        unsafe { send_datagram_expiring(connection, datagram.as_ptr(), datagram.len(), 1_000_000) };
+    assert!(matches!(
+        result.kind,
+        FFIResultKind::Ok | FFIResultKind::Poisoned
+    ));
+
+    thread::sleep(Duration::from_millis(100));
+    let reason = b"bye";
+    check(unsafe { close_connection_with_app_error(connection, 3, reason.as_ptr(), reason.len()) });
+
+    let (received, close) = tokio::time::timeout(TIMEOUT, native)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&received[..], datagram);
+    match close {
+        quinn::ConnectionError::ApplicationClosed(close) => {
+            assert_eq!(close.error_code.into_inner(), 3)
+        }
+        e => panic!("unexpected close {:?}", e),
+    }
+
+    unsafe {
+        check(connection_handle_release(connection));
+        check(free_server_config(config));
+    }
+}
+
+/// Native clients send a token on their first unidirectional stream to an FFI server that requires one,
+/// the server only sees the connection and its streams once the token is accepted.
+async fn auth_token_gates_connection(cert: &[u8], key: &[u8]) {
+    let events = events();
+
+    let (config, endpoint_id, endpoint) = ffi_server_endpoint(cert, key);
+    check(unsafe { endpoint_require_auth_token(endpoint, true, AUTH_REJECTED) });
+    let server = FfiEndpoint::bind(endpoint_id, endpoint);
+
+    let client = native_client(cert);
+
+    let server_address = server.address;
+    let native = tokio::spawn(async move {
+        let mut rejected = client
+            .connect(server_address, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+        let mut send = rejected.connection.open_uni().await.unwrap();
+        send.write_all(b"forged").await.unwrap();
+        send.finish().await.unwrap();
+        let rejected = rejected.datagrams.next().await.unwrap().unwrap_err();
+
+        let connection = client
+            .connect(server_address, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+        let mut send = connection.connection.open_uni().await.unwrap();
+        send.write_all(AUTH_TOKEN).await.unwrap();
+        send.finish().await.unwrap();
+
+        let (mut send, recv) = connection.connection.open_bi().await.unwrap();
+        send.write_all(b"ping").await.unwrap();
+        send.finish().await.unwrap();
+        let echo = recv.read_to_end(1024).await.unwrap();
+        (rejected, echo)
+    });
+
+    let connection = wait_for(&events, |event| match event {
+        Event::NewConnection(connection, _) => Some(connection as Handle),
+        _ => None,
+    });
+    // The rejected connection is never reported connected, the second one once its token is accepted.
+    let (accepted, accepted_id) = wait_for(&events, |event| match event {
+        Event::NewConnection(connection, id) => Some((connection as Handle, id)),
+        Event::Connected(id) => panic!("connection {} reported before its token", id),
+        _ => None,
+    });
+    let kind = wait_for(&events, |event| match event {
+        Event::AuthTokenCall(id, kind) if id == accepted_id => Some(kind),
+        Event::Connected(id) if id == accepted_id => {
+            panic!("connection {} reported before its token", id)
+        }
+        _ => None,
+    });
+    assert_eq!(kind, FFIResultKind::Error);
+    wait_for(&events, |event| match event {
+        Event::Connected(id) if id == accepted_id => Some(()),
+        _ => None,
+    });
+
+    // The stream carrying the token is consumed by the library, the bidirectional stream is the first one announced.
+    let stream_id = wait_for(&events, |event| match event {
+        Event::StreamOpened(id, stream_id) if id == accepted_id => Some(stream_id),
+        _ => None,
+    });
+    assert_eq!(stream_id, 0);
+    let message = read_to_end(&events, accepted, accepted_id, stream_id);
+    write_and_finish(accepted, stream_id, &message);
+
+    let (rejected, echo) = tokio::time::timeout(TIMEOUT, native)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(echo, b"ping");
+    match rejected {
+        quinn::ConnectionError::ApplicationClosed(close) => {
+            assert_eq!(close.error_code.into_inner(), AUTH_REJECTED)
+        }
+        e => panic!("unexpected close {:?}", e),
+    }
+
+    unsafe {
+        check(connection_handle_release(connection));
+        check(connection_handle_release(accepted));
+        check(free_server_config(config));
+    }
+}
+
+/// A native server closes its connection to an FFI client, which reports it lost, then freed once drained.
+async fn close_lost_freed_ordering(cert: &[u8], key: &[u8]) {
+    let events = events();
+
+    let server_config = quinn::ServerConfig::with_single_cert(
+        vec![rustls::Certificate(cert.to_vec())],
+        rustls::PrivateKey(key.to_vec()),
+    )
+    .unwrap();
+    let (server, mut incoming) =
+        quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+    let server_address = server.local_addr().unwrap();
+
+    let native = tokio::spawn(async move {
+        let connection = incoming.next().await.unwrap().await.unwrap();
+        connection
+            .connection
+            .close(quinn::VarInt::from_u32(5), b"closing");
+        server
+    });
+
+    let mut config = std::ptr::null_mut();
+    let (mut endpoint_id, mut endpoint) = (0, std::ptr::null_mut());
+    unsafe {
+        check(create_client_config(
+            &mut config,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        ));
+        check(create_client_endpoint(
+            config,
+            &mut endpoint_id,
+            &mut endpoint,
+        ));
+    }
+    let client = FfiEndpoint::bind(endpoint_id, endpoint);
+
+    let host = b"localhost";
+    let (mut connection, mut connection_id) = (std::ptr::null_mut(), 0);
+    check(unsafe {
+        connect_client(
+            client.handle,
+            host.as_ptr(),
+            host.len() as u32,
+            IpAddr::from(server_address),
+            &mut connection,
+            &mut connection_id,
+        )
+    });
+    wait_for(&events, |event| match event {
+        Event::Connected(id) if id == connection_id => Some(()),
+        _ => None,
+    });
+
+    let mut lost = false;
+    wait_for(&events, |event| match event {
+        Event::ConnectionLost(id) if id == connection_id => {
+            lost = true;
+            None
+        }
+        Event::ConnectionFreed(id) if id == connection_id => Some(()),
+        _ => None,
+    });
+    assert!(lost, "the connection was freed before it was reported lost");
+
+    let result = unsafe { poll_connection(connection) };
+    assert_eq!(result.kind, FFIResultKind::UnknownConnection);
+
+    let _server = tokio::time::timeout(TIMEOUT, native)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // The connection is freed exactly once.
+    thread::sleep(Duration::from_millis(100));
+    assert!(events
+        .try_iter()
+        .all(|event| !matches!(event, Event::ConnectionFreed(id) if id == connection_id)));
+
+    unsafe {
+        check(connection_handle_release(connection));
+        check(free_client_config(config));
+    }
+
+    // A freed handle is rejected before it is dereferenced, also once its slot holds a new handle.
+    let result = unsafe { free_client_config(config) };
+    assert_eq!(result.kind, FFIResultKind::InvalidHandle);
+    let mut reused = std::ptr::null_mut();
+    unsafe {
+        check(create_client_config(
+            &mut reused,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        ));
+        assert_eq!(
+            free_client_config(config).kind,
+            FFIResultKind::InvalidHandle
+        );
+        check(free_client_config(reused));
+    }
+    drop(client);
+}
+
+/// A native client sends on two streams to an FFI server, which reads one blocked and then finished,
+/// and the other reset with the error code of the native client.
+async fn read_blocked_finished_reset(cert: &[u8], key: &[u8]) {
+    let events = events();
+
+    let (config, endpoint_id, endpoint) = ffi_server_endpoint(cert, key);
+    let server = FfiEndpoint::bind(endpoint_id, endpoint);
+
+    let client = native_client(cert);
+
+    // The native client finishes the first stream once the server saw it blocked.
+    let (blocked_tx, blocked_rx) = mpsc::channel::<()>();
+    let server_address = server.address;
+    let native = tokio::spawn(async move {
+        let mut connection = client
+            .connect(server_address, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+
+        let mut finished = connection.connection.open_uni().await.unwrap();
+        finished.write_all(b"part").await.unwrap();
+        while blocked_rx.try_recv().is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        finished.finish().await.unwrap();
+
+        let mut reset = connection.connection.open_uni().await.unwrap();
+        reset.write_all(b"discarded").await.unwrap();
+        reset
+            .reset(quinn::VarInt::from_u64(STREAM_RESET).unwrap())
+            .unwrap();
+
+        connection.datagrams.next().await.unwrap().unwrap_err()
+    });
+
+    let (connection, connection_id) = wait_for(&events, |event| match event {
+        Event::NewConnection(connection, id) => Some((connection as Handle, id)),
+        _ => None,
+    });
+    let finished_id = wait_for(&events, |event| match event {
+        Event::StreamOpened(id, stream_id) if id == connection_id => Some(stream_id),
+        _ => None,
+    });
+    check(unsafe { stream_set_user_data(connection, finished_id, 7) });
+
+    // An empty buffer is rejected before the stream is read, the data stays for the next read.
+    let (mut length, mut code) = (0, 0);
+    let mut buf = [0; 1];
+    let result = unsafe {
+        read_stream_v3(
+            connection,
+            finished_id,
+            buf.as_mut_ptr(),
+            0,
+            &mut length,
+            &mut code,
+        )
+    };
+    assert_eq!(result.kind, FFIResultKind::BufferToSmall);
+
+    // A null buffer queries the length of the next chunk, which is kept for the next read.
+    let mut queried = Vec::new();
+    for _ in 0..2 {
+        check(unsafe {
+            read_stream_v3(
+                connection,
+                finished_id,
+                std::ptr::null_mut(),
+                0,
+                &mut length,
+                &mut code,
+            )
+        });
+        queried.push(length);
+    }
+    assert!(queried[0] > 0 && queried[0] <= 4);
+    assert_eq!(queried[0], queried[1]);
+
+    // A buffer smaller than the chunk consumes what fits, the remainder of the chunk is read next.
+    let mut data = Vec::new();
+    loop {
+        let (read, kind, _) = read_until_empty(connection, finished_id, 3);
+        assert_eq!(kind, FFIResultKind::BufferBlocked);
+        data.extend_from_slice(&read);
+        if data.len() == 4 {
+            break;
+        }
+
+        wait_for(&events, |event| match event {
+            Event::StreamReadable(id, stream) if id == connection_id && stream == finished_id => {
+                Some(())
+            }
+            _ => None,
+        });
+    }
+    assert_eq!(data, b"part");
+    assert_eq!(user_data(connection, finished_id), 7);
+    blocked_tx.send(()).unwrap();
+
+    // The tag of the stream is removed once its end was read.
+    let (read, kind, _) = read_until_end(&events, connection, connection_id, finished_id);
+    assert!(read.is_empty());
+    assert_eq!(kind, FFIResultKind::StreamFinished);
+    assert_eq!(user_data(connection, finished_id), 0);
+
+    let reset_id = wait_for(&events, |event| match event {
+        Event::StreamOpened(id, stream_id) if id == connection_id => Some(stream_id),
+        _ => None,
+    });
+    check(unsafe { stream_set_user_data(connection, reset_id, 8) });
+    // Data received before the reset may be read, the reset discards the rest.
+    let (_, kind, code) = read_until_end(&events, connection, connection_id, reset_id);
+    assert_eq!(kind, FFIResultKind::StreamReset);
+    assert_eq!(code, STREAM_RESET);
+    assert_eq!(user_data(connection, reset_id), 0);
+
+    let reason = b"bye";
+    check(unsafe { close_connection_with_app_error(connection, 1, reason.as_ptr(), reason.len()) });
+    let _close = tokio::time::timeout(TIMEOUT, native)
+        .await
+        .unwrap()
+        .unwrap();
+
+    unsafe {
+        check(connection_handle_release(connection));
+        check(free_server_config(config));
+    }
+}
+
+fn user_data(connection: Handle, stream_id: u64) -> u64 {
+    let mut user_data = 0;
+    check(unsafe { stream_user_data(connection, stream_id, &mut user_data) });
+    user_data
+}
+
+/// An FFI client connects to a socket that never answers, its handshake times out long before the idle timeout.
+async fn handshake_timeout_closes_connection(cert: &[u8], key: &[u8]) {
+    let events = events();
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let mut config = std::ptr::null_mut();
+    let (mut endpoint_id, mut endpoint) = (0, std::ptr::null_mut());
+    unsafe {
+        check(create_client_config(
+            &mut config,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        ));
+        check(endpoint_config_set_handshake_timeout(200));
+        check(create_client_endpoint(
+            config,
+            &mut endpoint_id,
+            &mut endpoint,
+        ));
+        check(endpoint_config_set_handshake_timeout(0));
+    }
+    let client = FfiEndpoint::bind(endpoint_id, endpoint);
+
+    let host = b"localhost";
+    let (mut connection, mut connection_id) = (std::ptr::null_mut(), 0);
+    let started = std::time::Instant::now();
+    check(unsafe {
+        connect_client(
+            client.handle,
+            host.as_ptr(),
+            host.len() as u32,
+            IpAddr::from(silent.local_addr().unwrap()),
+            &mut connection,
+            &mut connection_id,
+        )
+    });
+
+    wait_for(&events, |event| match event {
+        Event::HandshakeTimeout(id) if id == connection_id => Some(()),
+        Event::Connected(id) if id == connection_id => panic!("connected to a silent peer"),
+        _ => None,
+    });
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(200));
+    assert!(
+        elapsed < Duration::from_secs(5),
+        "timed out after {:?}",
+        elapsed
+    );
+
+    unsafe {
+        check(connection_handle_release(connection));
+        check(free_client_config(config));
+    }
+    drop(client);
+}
+
+/// A wait for the handshake with a socket that never answers is cancelled from another thread, long before its timeout.
+async fn cancelled_wait_returns(cert: &[u8], key: &[u8]) {
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let mut config = std::ptr::null_mut();
+    let (mut endpoint_id, mut endpoint) = (0, std::ptr::null_mut());
+    unsafe {
+        check(create_client_config(
+            &mut config,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        ));
+        check(create_client_endpoint(
+            config,
+            &mut endpoint_id,
+            &mut endpoint,
+        ));
+    }
+    let client = FfiEndpoint::bind(endpoint_id, endpoint);
+
+    let host = b"localhost";
+    let (mut connection, mut connection_id) = (std::ptr::null_mut(), 0);
+    let mut cancellation = std::ptr::null_mut();
+    unsafe {
+        check(connect_client(
+            client.handle,
+            host.as_ptr(),
+            host.len() as u32,
+            IpAddr::from(silent.local_addr().unwrap()),
+            &mut connection,
+            &mut connection_id,
+        ));
+        check(create_cancellation(&mut cancellation));
+    }
+
+    let canceller = {
+        let cancellation = cancellation as usize;
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            check(unsafe { cancellation_cancel(cancellation as Handle) });
+        })
+    };
+
+    let started = std::time::Instant::now();
+    let result = unsafe { wait_connected_v2(connection, 30_000, cancellation) };
+    assert_eq!(result.kind, FFIResultKind::Cancelled);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    canceller.join().unwrap();
+
+    // Cancelling is permanent, later waits return at once.
+    let result = unsafe { wait_connected_v2(connection, 30_000, cancellation) };
+    assert_eq!(result.kind, FFIResultKind::Cancelled);
+
+    unsafe {
+        check(free_cancellation(cancellation));
+        check(connection_handle_release(connection));
+        check(free_client_config(config));
+    }
+    drop(client);
+}
+
+/// A native client echoes a stream through a manually polled FFI server, which a thread polls with `poll_endpoint`.
+///
+/// The stream spans many datagrams, such that connections receive several datagrams per poll.
+async fn manually_polled_endpoint_serves(cert: &[u8], key: &[u8]) {
+    let events = events();
+
+    assert_eq!(
+        unsafe { poll_endpoint(std::ptr::null_mut(), 0) }.kind,
+        FFIResultKind::ArgumentNull
+    );
+
+    check(unsafe { endpoint_config_set_manual_polling(true) });
+    let (config, endpoint_id, endpoint) = ffi_server_endpoint(cert, key);
+    check(unsafe { endpoint_config_set_manual_polling(false) });
+    let server = FfiEndpoint::bind(endpoint_id, endpoint);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let poller = {
+        let stop = stop.clone();
+        let endpoint = endpoint as usize;
+        thread::spawn(move || {
+            let started = std::time::Instant::now();
+            while !stop.load(Ordering::SeqCst) {
+                let now_micros = started.elapsed().as_micros() as u64;
+                check(unsafe { poll_endpoint(endpoint as Handle, now_micros) });
+                thread::sleep(Duration::from_millis(2));
+            }
+        })
+    };
+
+    let client = native_client(cert);
+
+    let message = vec![7u8; 64 * 1024];
+    let server_address = server.address;
+    let native = {
+        let message = message.clone();
+        tokio::spawn(async move {
+            let connection = client
+                .connect(server_address, "localhost")
+                .unwrap()
+                .await
+                .unwrap()
+                .connection;
+
+            let (mut send, recv) = connection.open_bi().await.unwrap();
+            send.write_all(&message).await.unwrap();
+            send.finish().await.unwrap();
+            let echo = recv.read_to_end(message.len()).await.unwrap();
+
+            connection.close(quinn::VarInt::from_u32(0), b"done");
+            client.wait_idle().await;
+            echo
+        })
+    };
+
+    let (connection, connection_id) = wait_for(&events, |event| match event {
+        Event::NewConnection(connection, id) => Some((connection as Handle, id)),
+        _ => None,
+    });
+    let stream_id = wait_for(&events, |event| match event {
+        Event::StreamOpened(id, stream_id) if id == connection_id => Some(stream_id),
+        _ => None,
+    });
+    let (received, kind, _) = read_until_end(&events, connection, connection_id, stream_id);
+    assert_eq!(kind, FFIResultKind::StreamFinished);
+    assert_eq!(received, message);
+    write_and_finish(connection, stream_id, &received);
+
+    let echo = tokio::time::timeout(TIMEOUT, native)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(echo, message);
+
+    stop.store(true, Ordering::SeqCst);
+    poller.join().unwrap();
+    unsafe {
+        check(connection_handle_release(connection));
+        check(free_server_config(config));
+    }
+    drop(server);
+}
+
+/// A native client echoes a stream through an FFI server whose socket is read by four threads,
+/// which submit the datagrams to the ingress queue of the endpoint.
+async fn ingress_readers_serve(cert: &[u8], key: &[u8]) {
+    let events = events();
+
+    let (config, endpoint_id, endpoint) = ffi_server_endpoint(cert, key);
+    let server = FfiEndpoint::bind_ingress(endpoint_id, endpoint, 4);
+
+    let client = native_client(cert);
+
+    let message = vec![9u8; 64 * 1024];
+    let server_address = server.address;
+    let native = {
+        let message = message.clone();
+        tokio::spawn(async move {
+            let connection = client
+                .connect(server_address, "localhost")
+                .unwrap()
+                .await
+                .unwrap()
+                .connection;
+
+            let (mut send, recv) = connection.open_bi().await.unwrap();
+            send.write_all(&message).await.unwrap();
+            send.finish().await.unwrap();
+            let echo = recv.read_to_end(message.len()).await.unwrap();
+
+            connection.close(quinn::VarInt::from_u32(0), b"done");
+            client.wait_idle().await;
+            echo
+        })
+    };
+
+    let (connection, connection_id) = wait_for(&events, |event| match event {
+        Event::NewConnection(connection, id) => Some((connection as Handle, id)),
+        _ => None,
+    });
+    let stream_id = wait_for(&events, |event| match event {
+        Event::StreamOpened(id, stream_id) if id == connection_id => Some(stream_id),
+        _ => None,
+    });
+    let (received, kind, _) = read_until_end(&events, connection, connection_id, stream_id);
+    assert_eq!(kind, FFIResultKind::StreamFinished);
+    assert_eq!(received, message);
+    write_and_finish(connection, stream_id, &received);
+
+    let echo = tokio::time::timeout(TIMEOUT, native)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(echo, message);
+
+    unsafe {
+        check(connection_handle_release(connection));
+        check(free_server_config(config));
+    }
+    drop(server);
+}
+
+/// A native client sends a file to the FFI server, which sends it back, the files are passed as descriptors.
+async fn file_transfer_round_trip(cert: &[u8], key: &[u8]) {
+    let events = events();
+
+    let (config, endpoint_id, endpoint) = ffi_server_endpoint(cert, key);
+    let server = FfiEndpoint::bind(endpoint_id, endpoint);
+
+    let client = native_client(cert);
+
+    // Larger than the chunks the transfer workers hand over and the chunks they buffer.
+    let data: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let sent = data.clone();
+    let server_address = server.address;
+    let native = tokio::spawn(async move {
+        let mut connection = client
+            .connect(server_address, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+
+        let mut send = connection.connection.open_uni().await.unwrap();
+        send.write_all(&sent).await.unwrap();
+        send.finish().await.unwrap();
+
+        let recv = connection.uni_streams.next().await.unwrap().unwrap();
+        let echoed = recv.read_to_end(2 * sent.len()).await.unwrap();
+        (echoed, connection)
+    });
+
+    let (connection, connection_id) = wait_for(&events, |event| match event {
+        Event::NewConnection(connection, id) => Some((connection as Handle, id)),
+        _ => None,
+    });
+    let received_id = wait_for(&events, |event| match event {
+        Event::StreamOpened(id, stream_id) if id == connection_id => Some(stream_id),
+        _ => None,
+    });
+
+    let path = std::env::temp_dir().join(format!("quinn-ffi-transfer-{}", std::process::id()));
+    let file = File::create(&path).unwrap();
+    check(unsafe { receive_to_file_fd(connection, received_id, raw_file(file)) });
+    let succeeded = wait_for(&events, |event| match event {
+        Event::FileTransferFinished(id, stream_id, succeeded)
+            if id == connection_id && stream_id == received_id =>
+        {
+            Some(succeeded)
+        }
+        _ => None,
+    });
+    assert!(succeeded);
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+
+    let mut sent_id = 0;
+    check(unsafe { open_stream(connection, UNI, &mut sent_id) });
+    let file = File::open(&path).unwrap();
+    check(unsafe { send_file_fd(connection, sent_id, raw_file(file)) });
+    let succeeded = wait_for(&events, |event| match event {
+        Event::FileTransferFinished(id, stream_id, succeeded)
+            if id == connection_id && stream_id == sent_id =>
+        {
+            Some(succeeded)
+        }
+        _ => None,
+    });
+    assert!(succeeded);
+
+    let (echoed, _connection) = tokio::time::timeout(TIMEOUT, native)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(echoed, data);
+    std::fs::remove_file(&path).unwrap();
+
+    unsafe {
+        check(close_connection_with_app_error(
+            connection,
+            0,
+            b"".as_ptr(),
+            0,
+        ));
+        check(connection_handle_release(connection));
+        check(free_server_config(config));
+    }
+    drop(server);
+}
+
+/// Passes the ownership of the file to a transfer, as a descriptor or a handle on Windows.
+fn raw_file(file: File) -> i64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::IntoRawFd;
+        file.into_raw_fd() as i64
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::io::IntoRawHandle;
+        file.into_raw_handle() as i64
+    }
+}
+
+/// The initials of native clients are passed to an FFI server as if they came from IPv6 addresses,
+/// An FFI client opens a stream to a native server allowing one, the next open is exhausted until the first stream
+/// closed and the server granted another one.
+async fn streams_exhausted_then_available(cert: &[u8], key: &[u8]) {
+    let events = events();
+
+    let mut server_config = quinn::ServerConfig::with_single_cert(
+        vec![rustls::Certificate(cert.to_vec())],
+        rustls::PrivateKey(key.to_vec()),
+    )
+    .unwrap();
+    let mut transport = quinn::TransportConfig::default();
+    transport.max_concurrent_bidi_streams(1u32.into());
+    server_config.transport = Arc::new(transport);
+    let (server, mut incoming) =
+        quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+    let server_address = server.local_addr().unwrap();
+
+    let native = tokio::spawn(async move {
+        let mut connection = incoming.next().await.unwrap().await.unwrap();
+
+        let mut messages = Vec::new();
+        for _ in 0..2 {
+            let (mut send, recv) = connection.bi_streams.next().await.unwrap().unwrap();
+            let message = recv.read_to_end(1024).await.unwrap();
+            send.write_all(&message).await.unwrap();
+            send.finish().await.unwrap();
+            messages.push(message);
+        }
+        (messages, connection, server)
+    });
+
+    let mut config = std::ptr::null_mut();
+    let (mut endpoint_id, mut endpoint) = (0, std::ptr::null_mut());
+    unsafe {
+        check(create_client_config(
+            &mut config,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        ));
+        check(create_client_endpoint(
+            config,
+            &mut endpoint_id,
+            &mut endpoint,
+        ));
+    }
+    let client = FfiEndpoint::bind(endpoint_id, endpoint);
+
+    let host = b"localhost";
+    let (mut connection, mut connection_id) = (std::ptr::null_mut(), 0);
+    check(unsafe {
+        connect_client(
+            client.handle,
+            host.as_ptr(),
+            host.len() as u32,
+            IpAddr::from(server_address),
+            &mut connection,
+            &mut connection_id,
+        )
+    });
+    wait_for(&events, |event| match event {
+        Event::Connected(id) if id == connection_id => Some(()),
+        _ => None,
+    });
+
+    let (mut first, mut second) = (0, 0);
+    check(unsafe { open_stream(connection, BI, &mut first) });
+    let exhausted = unsafe { open_stream(connection, BI, &mut second) };
+    assert_eq!(exhausted.kind, FFIResultKind::StreamsExhausted);
+
+    write_and_finish(connection, first, b"one");
+    assert_eq!(
+        read_to_end(&events, connection, connection_id, first),
+        b"one"
+    );
+    wait_for(&events, |event| match event {
+        Event::StreamAvailable(id, BI) if id == connection_id => Some(()),
+        _ => None,
+    });
+
+    check(unsafe { open_stream(connection, BI, &mut second) });
+    write_and_finish(connection, second, b"two");
+    assert_eq!(
+        read_to_end(&events, connection, connection_id, second),
+        b"two"
+    );
+
+    let (messages, _connection, _server) = tokio::time::timeout(TIMEOUT, native)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(messages, vec![b"one".to_vec(), b"two".to_vec()]);
+
+    // The peer grants another stream once the second one is done, no open failed since so it is not announced.
+    thread::sleep(Duration::from_millis(100));
+    assert!(events
+        .try_iter()
+        .all(|event| !matches!(event, Event::StreamAvailable(id, _) if id == connection_id)));
+
+    unsafe {
+        check(close_connection_with_app_error(
+            connection,
+            0,
+            b"".as_ptr(),
+            0,
+        ));
+        check(connection_handle_release(connection));
+        check(free_client_config(config));
+    }
+    drop(client);
+}
+
+/// The FFI server accepts, reads and echoes a stream of a native client from within the callbacks, which are only
+/// invoked once the endpoint and connection locks are released.
+async fn callbacks_call_back_in(cert: &[u8], key: &[u8]) {
+    let events = events();
+    ECHO_IN_CALLBACKS.store(true, Ordering::SeqCst);
+
+    let (config, endpoint_id, endpoint) = ffi_server_endpoint(cert, key);
+    let server = FfiEndpoint::bind(endpoint_id, endpoint);
+
+    let client = native_client(cert);
+
+    let server_address = server.address;
+    let native = tokio::spawn(async move {
+        let connection = client
+            .connect(server_address, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+
+        let (mut send, recv) = connection.connection.open_bi().await.unwrap();
+        send.write_all(b"reentrant").await.unwrap();
+        send.finish().await.unwrap();
+        let echo = recv.read_to_end(1024).await.unwrap();
+        (echo, connection, client)
+    });
+
+    let (connection, connection_id) = wait_for(&events, |event| match event {
+        Event::NewConnection(connection, id) => Some((connection as Handle, id)),
+        _ => None,
+    });
+    let (kind, data) = wait_for(&events, |event| match event {
+        Event::EchoedInCallback(id, kind, data) if id == connection_id => Some((kind, data)),
+        _ => None,
+    });
+    assert_eq!(kind, FFIResultKind::Ok);
+    assert_eq!(data, b"reentrant");
+
+    let (echo, _connection, _client) = tokio::time::timeout(TIMEOUT, native)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(echo, b"reentrant");
+
+    ECHO_IN_CALLBACKS.store(false, Ordering::SeqCst);
+    ECHOING.lock().unwrap().clear();
+    unsafe {
+        check(close_connection_with_app_error(
+            connection,
+            0,
+            b"".as_ptr(),
+            0,
+        ));
+        check(connection_handle_release(connection));
+    }
+    drop(server);
+    check(unsafe { free_server_config(config) });
+}
+
+/// the server answers each to its address with the same flow info and scope id, and rejects an invalid family.
+async fn addresses_round_trip(cert: &[u8], key: &[u8]) {
+    let events = events();
+
+    let (config, endpoint_id, endpoint) = ffi_server_endpoint(cert, key);
+
+    let peers = [
+        SocketAddr::V6(SocketAddrV6::new(
+            Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+            4433,
+            0x12345,
+            2,
+        )),
+        SocketAddr::V6(SocketAddrV6::new(
+            Ipv4Addr::LOCALHOST.to_ipv6_mapped(),
+            4434,
+            0,
+            0,
+        )),
+    ];
+
+    let mut buf = [0; 65536];
+    for peer in peers {
+        // The server has no socket, the initial is received on a relay socket of its own for each client,
+        // such that retransmits of an earlier client are not mistaken for it.
+        let relay = UdpSocket::bind("127.0.0.1:0").unwrap();
+        relay.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let client = native_client(cert);
+
+        // The handshake never completes, the connect only makes the client send its initial.
+        let _connecting = client
+            .connect(relay.local_addr().unwrap(), "localhost")
+            .unwrap();
+        let (length, _) = relay.recv_from(&mut buf).unwrap();
+        check(unsafe { handle_datagram(endpoint, buf.as_ptr(), length, IpAddr::from(peer)) });
+
+        // Earlier connections retransmit to their peers, an address without its flow info or scope id never matches.
+        wait_for(&events, |event| match event {
+            Event::UnroutedTransmit(id, address) if id == endpoint_id && address == peer => {
+                Some(())
+            }
+            _ => None,
+        });
+    }
+
+    // `is_v6` follows the 2 byte port in the `repr(C)` layout.
+    let mut invalid = IpAddr::from(peers[0]);
+    unsafe { *(&mut invalid as *mut IpAddr as *mut u8).add(2) = 2 };
+    let result = unsafe { handle_datagram(endpoint, buf.as_ptr(), 1200, invalid) };
+    assert_eq!(result.kind, FFIResultKind::Error);
+
+    unsafe {
+        check(dispose_endpoint(endpoint));
+        check(free_server_config(config));
+    }
+}
+
+/// A native client sends several datagrams to an FFI server that queues the events of its connections, which are only
+/// polled once all datagrams arrived.
+async fn queued_events_coalesce(cert: &[u8], key: &[u8]) {
+    let events = events();
+
+    let (config, endpoint_id, endpoint) = ffi_server_endpoint(cert, key);
+    check(unsafe { endpoint_set_event_polling_enabled(endpoint, true) });
+    let server = FfiEndpoint::bind(endpoint_id, endpoint);
+
+    let client = native_client(cert);
+
+    let server_address = server.address;
+    let native = tokio::spawn(async move {
+        let connection = client
+            .connect(server_address, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+        for i in 0..10u8 {
+            connection
+                .connection
+                .send_datagram(Bytes::from(vec![i]))
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        (connection, client)
+    });
+
+    let connection = wait_for(&events, |event| match event {
+        Event::NewConnection(connection, _) => Some(connection as Handle),
+        _ => None,
+    });
+    let _native = tokio::time::timeout(TIMEOUT, native)
+        .await
+        .unwrap()
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let mut queued = [MaybeUninit::<AppEvent>::uninit(); 64];
+    let mut count = 0;
+    check(unsafe {
+        poll_connection_events(
+            connection,
+            queued.as_mut_ptr() as *mut AppEvent,
+            queued.len(),
+            &mut count,
+        )
+    });
+    let kinds: Vec<AppEventKind> = queued[..count]
+        .iter()
+        .map(|event| unsafe { event.assume_init() }.kind)
+        .collect();
+    assert_eq!(
+        kinds
+            .iter()
+            .filter(|kind| **kind == AppEventKind::DatagramReceived)
+            .count(),
+        1,
+        "{:?}",
+        kinds
+    );
+
+    unsafe {
+        check(close_connection_with_app_error(
+            connection,
+            0,
+            b"".as_ptr(),
+            0,
+        ));
+        check(connection_handle_release(connection));
+        check(free_server_config(config));
+    }
+    drop(server);
+}
+
+// The callbacks are global, so all scenarios run in one test.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn interop_with_native_quinn() {
+    set_callbacks();
+    let (cert, key) = self_signed_cert();
+
+    native_client_ffi_server(&cert, &key).await;
+    ffi_client_native_server(&cert, &key).await;
+    panic_in_call_recovers(&cert, &key).await;
+    auth_token_gates_connection(&cert, &key).await;
+    close_lost_freed_ordering(&cert, &key).await;
+    read_blocked_finished_reset(&cert, &key).await;
+    handshake_timeout_closes_connection(&cert, &key).await;
+    cancelled_wait_returns(&cert, &key).await;
+    manually_polled_endpoint_serves(&cert, &key).await;
+    ingress_readers_serve(&cert, &key).await;
+    streams_exhausted_then_available(&cert, &key).await;
+    callbacks_call_back_in(&cert, &key).await;
+    queued_events_coalesce(&cert, &key).await;
+    file_transfer_round_trip(&cert, &key).await;
+    addresses_round_trip(&cert, &key).await;
+}
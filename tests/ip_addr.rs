@@ -0,0 +1,115 @@
+//! Round-trips `IpAddr` through the FFI in host byte order, as parsed and formatted socket addresses,
+//! and in network byte order, as stored in `sockaddr_in` and `sockaddr_in6`.
+//!
+//! Network byte order is written as bytes in memory, such that the expectations hold on hosts of either endianness.
+
+use quinn_ffi::{
+    ffi::{
+        FFIResult,
+        FFIResultKind,
+    },
+    proto_impl::IpAddr,
+};
+use std::mem::MaybeUninit;
+
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "parse_socket_addr")]
+    fn parse_socket_addr(text: *const u8, len: usize, out_ipaddr: *mut IpAddr) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "format_socket_addr")]
+    fn format_socket_addr(
+        ipaddr: IpAddr,
+        out_buf: *mut u8,
+        buf_len: usize,
+        actual_len: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "ip_addr_from_network_order")]
+    fn ip_addr_from_network_order(
+        address: *const u8,
+        address_len: u32,
+        network_port: u16,
+        network_flow_info: u32,
+        scope_id: u32,
+        out_ipaddr: *mut IpAddr,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "ip_addr_network_order")]
+    fn ip_addr_network_order(
+        ipaddr: IpAddr,
+        out_network_port: *mut u16,
+        out_network_flow_info: *mut u32,
+    ) -> FFIResult;
+}
+
+/// Port 4444 as its bytes in network byte order.
+const PORT_BYTES: [u8; 2] = [0x11, 0x5c];
+/// Flow info 0x12345 as its bytes in network byte order.
+const FLOW_INFO_BYTES: [u8; 4] = [0x00, 0x01, 0x23, 0x45];
+
+fn check(result: FFIResult) {
+    assert_eq!(result.kind, FFIResultKind::Ok, "{:?}", result);
+}
+
+fn parse(text: &str) -> IpAddr {
+    let mut ipaddr = MaybeUninit::uninit();
+    check(unsafe { parse_socket_addr(text.as_ptr(), text.len(), ipaddr.as_mut_ptr()) });
+    unsafe { ipaddr.assume_init() }
+}
+
+fn format(ipaddr: IpAddr) -> String {
+    let mut buf = [0u8; 64];
+    let mut len = 0;
+    check(unsafe { format_socket_addr(ipaddr, buf.as_mut_ptr(), buf.len(), &mut len) });
+    String::from_utf8(buf[..len].to_vec()).unwrap()
+}
+
+fn from_network_order(address: &[u8], scope_id: u32) -> IpAddr {
+    let mut ipaddr = MaybeUninit::uninit();
+    check(unsafe {
+        ip_addr_from_network_order(
+            address.as_ptr(),
+            address.len() as u32,
+            u16::from_ne_bytes(PORT_BYTES),
+            u32::from_ne_bytes(FLOW_INFO_BYTES),
+            scope_id,
+            ipaddr.as_mut_ptr(),
+        )
+    });
+    unsafe { ipaddr.assume_init() }
+}
+
+fn network_order(ipaddr: IpAddr) -> ([u8; 2], [u8; 4]) {
+    let (mut port, mut flow_info) = (0, 0);
+    check(unsafe { ip_addr_network_order(ipaddr, &mut port, &mut flow_info) });
+    (port.to_ne_bytes(), flow_info.to_ne_bytes())
+}
+
+#[test]
+fn host_order_round_trip() {
+    for text in ["127.0.0.1:4444", "[::1]:4444", "[fe80::1%2]:4444"] {
+        assert_eq!(format(parse(text)), text);
+    }
+}
+
+#[test]
+fn network_order_round_trip() {
+    let v4 = from_network_order(&[127, 0, 0, 1], 0);
+    assert_eq!(format(v4), "127.0.0.1:4444");
+    assert_eq!(network_order(v4), (PORT_BYTES, [0; 4]));
+
+    let mut loopback = [0; 16];
+    loopback[15] = 1;
+    let v6 = from_network_order(&loopback, 2);
+    assert_eq!(format(v6), "[::1%2]:4444");
+    assert_eq!(network_order(v6), (PORT_BYTES, FLOW_INFO_BYTES));
+
+    // Host byte order in, network byte order out.
+    assert_eq!(network_order(parse("[::1]:4444")).0, PORT_BYTES);
+}
+
+#[test]
+fn rejects_invalid_address_length() {
+    let mut ipaddr = MaybeUninit::uninit();
+    let address = [127, 0, 0];
+    let result =
+        unsafe { ip_addr_from_network_order(address.as_ptr(), 3, 0, 0, 0, ipaddr.as_mut_ptr()) };
+    assert_eq!(result.kind, FFIResultKind::Error);
+}
@@ -0,0 +1,394 @@
+//! Connections of an endpoint with pollable coalescing invoke `on_connection_pollable` once for a burst of datagrams,
+//! and again once they were polled. Without coalescing every datagram invokes it.
+//!
+//! The callback is only invoked without the `auto-poll` feature, the endpoints are polled with `poll_endpoint`
+//! and exchange datagrams in memory:
+//!
+//! ```sh
+//! cargo test --no-default-features --features safe-api,handle-validation,self-signed --test pollable_coalescing
+//! ```
+#![cfg(not(feature = "auto-poll"))]
+
+use quinn_ffi::{
+    ffi::{
+        FFIResult,
+        FFIResultKind,
+    },
+    proto_impl::IpAddr,
+};
+use std::{
+    ffi::c_void,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+type Handle = *mut c_void;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const UNI: u8 = 1;
+const SERVER_ADDRESS: &str = "127.0.0.1:4433";
+const CLIENT_ADDRESS: &str = "127.0.0.1:4434";
+
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "generate_self_signed_cert")]
+    fn generate_self_signed_cert(
+        subject_alt_names: *const u8,
+        subject_alt_names_len: u32,
+        validity_days: u32,
+        cert_buf: *mut u8,
+        cert_buf_len: usize,
+        actual_cert_len: *mut usize,
+        key_buf: *mut u8,
+        key_buf_len: usize,
+        actual_key_len: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_server_config")]
+    fn create_server_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_config")]
+    fn create_client_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_server_endpoint")]
+    fn create_server_endpoint(
+        config: Handle,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_endpoint")]
+    fn create_client_endpoint(
+        config: Handle,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_set_pollable_coalescing")]
+    fn endpoint_set_pollable_coalescing(endpoint: Handle, enabled: bool) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connect_client")]
+    fn connect_client(
+        endpoint: Handle,
+        host: *const u8,
+        host_len: u32,
+        address: IpAddr,
+        out_connection: *mut Handle,
+        out_connection_id: *mut u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "handle_datagram")]
+    fn handle_datagram(
+        endpoint: Handle,
+        data: *const u8,
+        length: usize,
+        address: IpAddr,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "poll_endpoint")]
+    fn poll_endpoint(endpoint: Handle, now_micros: u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "poll_connection")]
+    fn poll_connection(connection: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "open_stream")]
+    fn open_stream(connection: Handle, direction: u8, out_stream_id: *mut u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "write_stream")]
+    fn write_stream(
+        connection: Handle,
+        stream_id: u64,
+        buf: *const u8,
+        buf_len: usize,
+        out_written: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_handle_release")]
+    fn connection_handle_release(connection: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "dispose_endpoint")]
+    fn dispose_endpoint(endpoint: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_server_config")]
+    fn free_server_config(config: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_client_config")]
+    fn free_client_config(config: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_transmit_v2")]
+    fn set_on_transmit_v2(callback: extern "C" fn(u64, *const u8, usize, IpAddr)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_new_connection")]
+    fn set_on_new_connection(callback: extern "C" fn(Handle, u32, u64)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_connected")]
+    fn set_on_connected(callback: extern "C" fn(u32)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_pollable_connection")]
+    fn set_on_pollable_connection(callback: extern "C" fn(u32)) -> FFIResult;
+}
+
+/// The datagrams sent by the endpoints, with the id of the sending endpoint.
+static WIRE: Mutex<Vec<(u64, Vec<u8>)>> = Mutex::new(Vec::new());
+/// The server connections, as addresses of their handles, with their id.
+static NEW_CONNECTIONS: Mutex<Vec<(usize, u32)>> = Mutex::new(Vec::new());
+/// The connections whose handshake completed.
+static CONNECTED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+/// The connections `on_connection_pollable` was invoked for.
+static POLLABLE: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+extern "C" fn on_transmit(endpoint_id: u64, data: *const u8, length: usize, _address: IpAddr) {
+    let data = unsafe { std::slice::from_raw_parts(data, length) };
+    WIRE.lock().unwrap().push((endpoint_id, data.to_vec()));
+}
+
+extern "C" fn on_new_connection(connection: Handle, connection_id: u32, _endpoint_id: u64) {
+    NEW_CONNECTIONS
+        .lock()
+        .unwrap()
+        .push((connection as usize, connection_id));
+}
+
+extern "C" fn on_connected(connection_id: u32) {
+    CONNECTED.lock().unwrap().push(connection_id);
+}
+
+extern "C" fn on_connection_pollable(connection_id: u32) {
+    POLLABLE.lock().unwrap().push(connection_id);
+}
+
+fn check(result: FFIResult) {
+    assert_eq!(result.kind, FFIResultKind::Ok, "{:?}", result);
+}
+
+fn self_signed_cert() -> (Vec<u8>, Vec<u8>) {
+    let names = b"localhost";
+    let (mut cert, mut key) = (vec![0; 1024], vec![0; 1024]);
+    let (mut cert_len, mut key_len) = (0, 0);
+
+    check(unsafe {
+        generate_self_signed_cert(
+            names.as_ptr(),
+            names.len() as u32,
+            1,
+            cert.as_mut_ptr(),
+            cert.len(),
+            &mut cert_len,
+            key.as_mut_ptr(),
+            key.len(),
+            &mut key_len,
+        )
+    });
+
+    cert.truncate(cert_len);
+    key.truncate(key_len);
+    (cert, key)
+}
+
+/// A connected client and server endpoint, polled with `poll_endpoint`.
+struct Pair {
+    started: Instant,
+    server_config: Handle,
+    server_id: u64,
+    server: Handle,
+    client_config: Handle,
+    client_id: u64,
+    client: Handle,
+    server_connection: Handle,
+    server_connection_id: u32,
+    client_connection: Handle,
+}
+
+impl Pair {
+    fn connect(cert: &[u8], key: &[u8], coalesce: bool) -> Self {
+        WIRE.lock().unwrap().clear();
+        NEW_CONNECTIONS.lock().unwrap().clear();
+        CONNECTED.lock().unwrap().clear();
+
+        let (mut server_config, mut server_id, mut server) =
+            (std::ptr::null_mut(), 0, std::ptr::null_mut());
+        let (mut client_config, mut client_id, mut client) =
+            (std::ptr::null_mut(), 0, std::ptr::null_mut());
+        unsafe {
+            check(create_server_config(
+                &mut server_config,
+                cert.as_ptr(),
+                cert.len() as u32,
+                key.as_ptr(),
+                key.len() as u32,
+            ));
+            check(create_server_endpoint(
+                server_config,
+                &mut server_id,
+                &mut server,
+            ));
+            // Only affects connections created afterwards.
+            check(endpoint_set_pollable_coalescing(server, coalesce));
+            check(create_client_config(
+                &mut client_config,
+                cert.as_ptr(),
+                cert.len() as u32,
+                key.as_ptr(),
+                key.len() as u32,
+            ));
+            check(create_client_endpoint(
+                client_config,
+                &mut client_id,
+                &mut client,
+            ));
+        }
+
+        let host = b"localhost";
+        let (mut client_connection, mut client_connection_id) = (std::ptr::null_mut(), 0);
+        check(unsafe {
+            connect_client(
+                client,
+                host.as_ptr(),
+                host.len() as u32,
+                IpAddr::from(SERVER_ADDRESS.parse::<SocketAddr>().unwrap()),
+                &mut client_connection,
+                &mut client_connection_id,
+            )
+        });
+
+        let mut pair = Pair {
+            started: Instant::now(),
+            server_config,
+            server_id,
+            server,
+            client_config,
+            client_id,
+            client,
+            server_connection: std::ptr::null_mut(),
+            server_connection_id: 0,
+            client_connection,
+        };
+
+        while !CONNECTED.lock().unwrap().contains(&client_connection_id) {
+            assert!(
+                pair.started.elapsed() < TIMEOUT,
+                "the handshake did not complete"
+            );
+            pair.poll_endpoints();
+            pair.deliver(pair.take_datagrams());
+        }
+        // Settles the handshake, such that only the datagrams of the test are exchanged afterwards.
+        for _ in 0..10 {
+            pair.poll_endpoints();
+            pair.deliver(pair.take_datagrams());
+        }
+
+        let (connection, connection_id) = NEW_CONNECTIONS.lock().unwrap()[0];
+        pair.server_connection = connection as Handle;
+        pair.server_connection_id = connection_id;
+        pair
+    }
+
+    fn poll_endpoints(&self) {
+        let now_micros = self.started.elapsed().as_micros() as u64;
+        unsafe {
+            check(poll_endpoint(self.client, now_micros));
+            check(poll_endpoint(self.server, now_micros));
+        }
+    }
+
+    fn take_datagrams(&self) -> Vec<(u64, Vec<u8>)> {
+        std::mem::take(&mut *WIRE.lock().unwrap())
+    }
+
+    /// Passes the datagrams to the endpoint they are sent to.
+    fn deliver(&self, datagrams: Vec<(u64, Vec<u8>)>) {
+        for (endpoint_id, datagram) in datagrams {
+            let (endpoint, from) = if endpoint_id == self.server_id {
+                (self.client, SERVER_ADDRESS)
+            } else {
+                (self.server, CLIENT_ADDRESS)
+            };
+            let from = IpAddr::from(from.parse::<SocketAddr>().unwrap());
+            check(unsafe { handle_datagram(endpoint, datagram.as_ptr(), datagram.len(), from) });
+        }
+    }
+
+    /// Writes on a new stream of the client, returns the datagrams the client sends for it.
+    fn client_burst(&self, length: usize) -> Vec<Vec<u8>> {
+        let (mut stream_id, mut written) = (0, 0);
+        let data = vec![1u8; length];
+        unsafe {
+            check(open_stream(self.client_connection, UNI, &mut stream_id));
+            check(write_stream(
+                self.client_connection,
+                stream_id,
+                data.as_ptr(),
+                data.len(),
+                &mut written,
+            ));
+            check(poll_endpoint(
+                self.client,
+                self.started.elapsed().as_micros() as u64,
+            ));
+        }
+
+        self.take_datagrams()
+            .into_iter()
+            .filter(|(endpoint_id, _)| *endpoint_id == self.client_id)
+            .map(|(_, datagram)| datagram)
+            .collect()
+    }
+
+    /// Passes datagrams of the client to the server without polling it, returns the callbacks of the server connection.
+    fn pollable_callbacks(&self, datagrams: Vec<Vec<u8>>) -> usize {
+        POLLABLE.lock().unwrap().clear();
+        self.deliver(
+            datagrams
+                .into_iter()
+                .map(|datagram| (self.client_id, datagram))
+                .collect(),
+        );
+
+        POLLABLE
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|id| **id == self.server_connection_id)
+            .count()
+    }
+}
+
+impl Drop for Pair {
+    fn drop(&mut self) {
+        unsafe {
+            connection_handle_release(self.server_connection);
+            connection_handle_release(self.client_connection);
+            dispose_endpoint(self.client);
+            dispose_endpoint(self.server);
+            free_client_config(self.client_config);
+            free_server_config(self.server_config);
+        }
+    }
+}
+
+// The callbacks are global, so both cases run in one test.
+#[test]
+fn pollable_coalescing() {
+    unsafe {
+        check(set_on_transmit_v2(on_transmit));
+        check(set_on_new_connection(on_new_connection));
+        check(set_on_connected(on_connected));
+        check(set_on_pollable_connection(on_connection_pollable));
+    }
+    let (cert, key) = self_signed_cert();
+
+    // Every datagram invokes the callback.
+    let pair = Pair::connect(&cert, &key, false);
+    let burst = pair.client_burst(8 * 1024);
+    assert!(burst.len() > 1, "the write fits a single datagram");
+    let datagrams = burst.len();
+    assert!(pair.pollable_callbacks(burst) >= datagrams);
+    drop(pair);
+
+    // The burst invokes the callback once, the next datagram after a poll invokes it again.
+    let pair = Pair::connect(&cert, &key, true);
+    let burst = pair.client_burst(8 * 1024);
+    assert!(burst.len() > 1, "the write fits a single datagram");
+    assert_eq!(pair.pollable_callbacks(burst), 1);
+
+    check(unsafe { poll_connection(pair.server_connection) });
+    let next = pair.client_burst(100);
+    assert_eq!(pair.pollable_callbacks(next), 1);
+}
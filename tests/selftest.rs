@@ -0,0 +1,137 @@
+//! `ffi_selftest` connects a client to a server through the exported functions and invokes the callbacks of the
+//! client application for them.
+
+use quinn_ffi::{
+    ffi::{
+        FFIResult,
+        FFIResultKind,
+    },
+    proto_impl::{
+        IpAddr,
+        SelfTestReport,
+    },
+};
+use std::{
+    convert::TryFrom,
+    ffi::c_void,
+    mem::{
+        self,
+        MaybeUninit,
+    },
+    net::SocketAddr,
+    sync::atomic::{
+        AtomicU32,
+        Ordering,
+    },
+};
+
+type Handle = *mut c_void;
+
+const SERVER_ADDRESS: &str = "127.0.0.1:4433";
+
+static CONNECTED: AtomicU32 = AtomicU32::new(0);
+static DATAGRAMS_RECEIVED: AtomicU32 = AtomicU32::new(0);
+
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "ffi_selftest")]
+    fn ffi_selftest(server_address: IpAddr, out_report: *mut SelfTestReport) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_handle_release")]
+    fn connection_handle_release(connection: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_new_connection")]
+    fn set_on_new_connection(callback: extern "C" fn(Handle, u32, u64)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_connected")]
+    fn set_on_connected(callback: extern "C" fn(u32)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_connection_lost")]
+    fn set_on_connection_lost(callback: extern "C" fn(u32, *const u8, u32)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_stream_available")]
+    fn set_on_stream_available(callback: extern "C" fn(u32, u8)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_stream_opened")]
+    fn set_on_stream_opened(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_stream_readable")]
+    fn set_on_stream_readable(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_stream_writable")]
+    fn set_on_stream_writable(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_stream_finished")]
+    fn set_on_stream_finished(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_stream_stopped")]
+    fn set_on_stream_stopped(callback: extern "C" fn(u32, u64, u8)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_datagram_received")]
+    fn set_on_datagram_received(callback: extern "C" fn(u32)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_pollable_connection")]
+    fn set_on_pollable_connection(callback: extern "C" fn(u32)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_transmit_v2")]
+    fn set_on_transmit_v2(callback: extern "C" fn(u64, *const u8, usize, IpAddr)) -> FFIResult;
+}
+
+fn check(result: FFIResult) {
+    assert_eq!(result.kind, FFIResultKind::Ok, "{:?}", result);
+}
+
+extern "C" fn on_new_connection(connection: Handle, _connection_id: u32, _endpoint_id: u64) {
+    check(unsafe { connection_handle_release(connection) });
+}
+
+extern "C" fn on_connected(_connection_id: u32) {
+    CONNECTED.fetch_add(1, Ordering::SeqCst);
+}
+
+extern "C" fn on_datagram_received(_connection_id: u32) {
+    DATAGRAMS_RECEIVED.fetch_add(1, Ordering::SeqCst);
+}
+
+extern "C" fn on_transmit(_endpoint_id: u64, _data: *const u8, _length: usize, _address: IpAddr) {
+    panic!("the transmits of the self test are pulled");
+}
+
+extern "C" fn ignore_connection(_connection_id: u32) {}
+
+extern "C" fn ignore_connection_lost(_connection_id: u32, _reason: *const u8, _reason_len: u32) {}
+
+extern "C" fn ignore_direction(_connection_id: u32, _direction: u8) {}
+
+extern "C" fn ignore_stream(_connection_id: u32, _stream_id: u64, _direction: u8) {}
+
+#[test]
+fn selftest_invokes_callbacks() {
+    let server_address = IpAddr::from(SERVER_ADDRESS.parse::<SocketAddr>().unwrap());
+    let mut report = MaybeUninit::uninit();
+
+    // Fails until the required callbacks are set.
+    let result = unsafe { ffi_selftest(server_address, report.as_mut_ptr()) };
+    assert_eq!(result.kind, FFIResultKind::Error);
+
+    unsafe {
+        check(set_on_new_connection(on_new_connection));
+        check(set_on_connected(on_connected));
+        check(set_on_connection_lost(ignore_connection_lost));
+        check(set_on_stream_available(ignore_direction));
+        check(set_on_stream_opened(ignore_stream));
+        check(set_on_stream_readable(ignore_stream));
+        check(set_on_stream_writable(ignore_stream));
+        check(set_on_stream_finished(ignore_stream));
+        check(set_on_stream_stopped(ignore_stream));
+        check(set_on_datagram_received(on_datagram_received));
+        check(set_on_pollable_connection(ignore_connection));
+        check(set_on_transmit_v2(on_transmit));
+    }
+
+    check(unsafe { ffi_selftest(server_address, report.as_mut_ptr()) });
+    let report = unsafe { report.assume_init() };
+
+    assert_eq!(
+        report.report_size as usize,
+        mem::size_of::<SelfTestReport>()
+    );
+    assert_eq!(report.ffi_result_size as usize, mem::size_of::<FFIResult>());
+    assert_eq!(report.ip_addr_size as usize, mem::size_of::<IpAddr>());
+    assert_eq!(
+        SocketAddr::try_from(report.server_address).unwrap(),
+        SERVER_ADDRESS.parse::<SocketAddr>().unwrap()
+    );
+    assert_eq!(report.echoed_bytes, b"quinn-ffi selftest echo".len() as u64);
+    assert_eq!(report.exchanged_datagrams, 2);
+
+    // Both connections are reported to the callbacks.
+    assert_eq!(CONNECTED.load(Ordering::SeqCst), 2);
+    assert_eq!(DATAGRAMS_RECEIVED.load(Ordering::SeqCst), 2);
+}
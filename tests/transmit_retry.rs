@@ -0,0 +1,295 @@
+//! A transmit the client application fails to send is handed back with `report_transmit_failed` from the transmit
+//! callback. The endpoint then holds its transmits until `report_transmit_writable`, and passes the failed transmit to
+//! the callback again first.
+//!
+//! The endpoints are polled with `poll_endpoint` and exchange datagrams in memory.
+
+use quinn_ffi::{
+    ffi::{
+        FFIResult,
+        FFIResultKind,
+    },
+    proto_impl::IpAddr,
+};
+use std::{
+    ffi::c_void,
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+type Handle = *mut c_void;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const SERVER_ADDRESS: &str = "127.0.0.1:4433";
+const CLIENT_ADDRESS: &str = "127.0.0.1:4434";
+
+extern "C" {
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "generate_self_signed_cert")]
+    fn generate_self_signed_cert(
+        subject_alt_names: *const u8,
+        subject_alt_names_len: u32,
+        validity_days: u32,
+        cert_buf: *mut u8,
+        cert_buf_len: usize,
+        actual_cert_len: *mut usize,
+        key_buf: *mut u8,
+        key_buf_len: usize,
+        actual_key_len: *mut usize,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_server_config")]
+    fn create_server_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_config")]
+    fn create_client_config(
+        out_handle: *mut Handle,
+        cert: *const u8,
+        cert_len: u32,
+        key: *const u8,
+        key_len: u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_server_endpoint")]
+    fn create_server_endpoint(
+        config: Handle,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "create_client_endpoint")]
+    fn create_client_endpoint(
+        config: Handle,
+        out_endpoint_id: *mut u64,
+        out_endpoint: *mut Handle,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connect_client")]
+    fn connect_client(
+        endpoint: Handle,
+        host: *const u8,
+        host_len: u32,
+        address: IpAddr,
+        out_connection: *mut Handle,
+        out_connection_id: *mut u32,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "handle_datagram")]
+    fn handle_datagram(
+        endpoint: Handle,
+        data: *const u8,
+        length: usize,
+        address: IpAddr,
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "poll_endpoint")]
+    fn poll_endpoint(endpoint: Handle, now_micros: u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "report_transmit_failed")]
+    fn report_transmit_failed(endpoint_id: u64, transmit_token: u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "report_transmit_writable")]
+    fn report_transmit_writable(endpoint_id: u64) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "connection_handle_release")]
+    fn connection_handle_release(connection: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "dispose_endpoint")]
+    fn dispose_endpoint(endpoint: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_server_config")]
+    fn free_server_config(config: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "free_client_config")]
+    fn free_client_config(config: Handle) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "endpoint_config_set_manual_polling")]
+    fn endpoint_config_set_manual_polling(enabled: bool) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_transmit_v4")]
+    fn set_on_transmit_v4(
+        callback: extern "C" fn(u64, u64, *const u8, usize, IpAddr, IpAddr),
+    ) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_new_connection")]
+    fn set_on_new_connection(callback: extern "C" fn(Handle, u32, u64)) -> FFIResult;
+    #[link_name = concat!(env!("QUINN_FFI_SYMBOL_PREFIX"), "set_on_connected")]
+    fn set_on_connected(callback: extern "C" fn(u32)) -> FFIResult;
+}
+
+/// The datagrams sent by the endpoints, with the id of the sending endpoint.
+static WIRE: Mutex<Vec<(u64, Vec<u8>)>> = Mutex::new(Vec::new());
+/// Whether the transmit callback fails to send.
+static SOCKET_BLOCKED: AtomicBool = AtomicBool::new(false);
+/// The transmits the callback failed to send, with the result of `report_transmit_failed`.
+static FAILED: Mutex<Vec<(Vec<u8>, FFIResultKind)>> = Mutex::new(Vec::new());
+/// The server connections, as addresses of their handles.
+static NEW_CONNECTIONS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+/// The connections whose handshake completed.
+static CONNECTED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+extern "C" fn on_transmit(
+    endpoint_id: u64,
+    token: u64,
+    data: *const u8,
+    length: usize,
+    _destination: IpAddr,
+    _source: IpAddr,
+) {
+    let data = unsafe { std::slice::from_raw_parts(data, length) }.to_vec();
+
+    if SOCKET_BLOCKED.load(Ordering::SeqCst) {
+        let result = unsafe { report_transmit_failed(endpoint_id, token) };
+        FAILED.lock().unwrap().push((data, result.kind));
+    } else {
+        WIRE.lock().unwrap().push((endpoint_id, data));
+    }
+}
+
+extern "C" fn on_new_connection(connection: Handle, _connection_id: u32, _endpoint_id: u64) {
+    NEW_CONNECTIONS.lock().unwrap().push(connection as usize);
+}
+
+extern "C" fn on_connected(connection_id: u32) {
+    CONNECTED.lock().unwrap().push(connection_id);
+}
+
+fn check(result: FFIResult) {
+    assert_eq!(result.kind, FFIResultKind::Ok, "{:?}", result);
+}
+
+fn self_signed_cert() -> (Vec<u8>, Vec<u8>) {
+    let names = b"localhost";
+    let (mut cert, mut key) = (vec![0; 1024], vec![0; 1024]);
+    let (mut cert_len, mut key_len) = (0, 0);
+
+    check(unsafe {
+        generate_self_signed_cert(
+            names.as_ptr(),
+            names.len() as u32,
+            1,
+            cert.as_mut_ptr(),
+            cert.len(),
+            &mut cert_len,
+            key.as_mut_ptr(),
+            key.len(),
+            &mut key_len,
+        )
+    });
+
+    cert.truncate(cert_len);
+    key.truncate(key_len);
+    (cert, key)
+}
+
+#[test]
+fn failed_transmit_is_resent_once_writable() {
+    unsafe {
+        check(set_on_transmit_v4(on_transmit));
+        check(set_on_new_connection(on_new_connection));
+        check(set_on_connected(on_connected));
+        check(endpoint_config_set_manual_polling(true));
+    }
+    let (cert, key) = self_signed_cert();
+    let started = Instant::now();
+    let now_micros = || started.elapsed().as_micros() as u64;
+
+    let (mut server_config, mut server_id, mut server) =
+        (std::ptr::null_mut(), 0, std::ptr::null_mut());
+    let (mut client_config, mut client_id, mut client) =
+        (std::ptr::null_mut(), 0, std::ptr::null_mut());
+    unsafe {
+        check(create_server_config(
+            &mut server_config,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        ));
+        check(create_server_endpoint(
+            server_config,
+            &mut server_id,
+            &mut server,
+        ));
+        check(create_client_config(
+            &mut client_config,
+            cert.as_ptr(),
+            cert.len() as u32,
+            key.as_ptr(),
+            key.len() as u32,
+        ));
+        check(create_client_endpoint(
+            client_config,
+            &mut client_id,
+            &mut client,
+        ));
+    }
+
+    // Outside the transmit callback there is no transmit to hand back.
+    assert_eq!(
+        unsafe { report_transmit_failed(client_id, 0) }.kind,
+        FFIResultKind::Error
+    );
+
+    SOCKET_BLOCKED.store(true, Ordering::SeqCst);
+    let host = b"localhost";
+    let (mut connection, mut connection_id) = (std::ptr::null_mut(), 0);
+    check(unsafe {
+        connect_client(
+            client,
+            host.as_ptr(),
+            host.len() as u32,
+            IpAddr::from(SERVER_ADDRESS.parse::<SocketAddr>().unwrap()),
+            &mut connection,
+            &mut connection_id,
+        )
+    });
+
+    // The first transmit fails, the endpoint transmits nothing else until the socket is writable.
+    for _ in 0..10 {
+        check(unsafe { poll_endpoint(client, now_micros()) });
+    }
+    let failed = std::mem::take(&mut *FAILED.lock().unwrap());
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].1, FFIResultKind::Ok);
+    assert!(WIRE.lock().unwrap().is_empty());
+
+    SOCKET_BLOCKED.store(false, Ordering::SeqCst);
+    check(unsafe { report_transmit_writable(client_id) });
+    check(unsafe { poll_endpoint(client, now_micros()) });
+    assert_eq!(WIRE.lock().unwrap().first().unwrap().1, failed[0].0);
+
+    // The handshake completes with the resent transmit.
+    while !CONNECTED.lock().unwrap().contains(&connection_id) {
+        assert!(
+            started.elapsed() < TIMEOUT,
+            "the handshake did not complete"
+        );
+        let now = now_micros();
+        unsafe {
+            check(poll_endpoint(client, now));
+            check(poll_endpoint(server, now));
+        }
+
+        let datagrams = std::mem::take(&mut *WIRE.lock().unwrap());
+        for (endpoint_id, datagram) in datagrams {
+            let (endpoint, from) = if endpoint_id == server_id {
+                (client, SERVER_ADDRESS)
+            } else {
+                (server, CLIENT_ADDRESS)
+            };
+            let from = IpAddr::from(from.parse::<SocketAddr>().unwrap());
+            check(unsafe { handle_datagram(endpoint, datagram.as_ptr(), datagram.len(), from) });
+        }
+    }
+
+    unsafe {
+        for server_connection in NEW_CONNECTIONS.lock().unwrap().drain(..) {
+            check(connection_handle_release(server_connection as Handle));
+        }
+        check(connection_handle_release(connection));
+        check(dispose_endpoint(client));
+        check(dispose_endpoint(server));
+        check(free_client_config(client_config));
+        check(free_server_config(server_config));
+    }
+}
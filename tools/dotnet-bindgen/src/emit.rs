@@ -0,0 +1,342 @@
+//! Emits the C# source of the bindings.
+
+use crate::parse::{
+    split_top_level,
+    Arg,
+    Function,
+};
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+};
+
+const HEADER: &str = "// <auto-generated>
+// Generated by tools/dotnet-bindgen from the quinn-ffi sources, do not edit.
+// </auto-generated>
+
+using System;
+using System.Runtime.InteropServices;
+
+namespace QuinnFfi
+{
+";
+
+/// Returns the C# source with the P/Invoke declarations and a `SafeHandle` for every handle type.
+///
+/// The entry points are prefixed with `symbol_prefix`, the `QUINN_FFI_SYMBOL_PREFIX` the library was built with.
+pub fn bindings(functions: &[Function], symbol_prefix: &str) -> String {
+    let mut out = String::from(HEADER);
+
+    out.push_str("    public static unsafe partial class Native\n    {\n");
+    out.push_str("        public const string Library = \"quinn_ffi\";\n");
+
+    for function in functions {
+        out.push('\n');
+        function_declaration(&mut out, function, symbol_prefix);
+    }
+
+    out.push_str("    }\n");
+
+    for handle in handle_types(functions) {
+        out.push('\n');
+        safe_handle(&mut out, &handle, functions);
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+fn function_declaration(out: &mut String, function: &Function, symbol_prefix: &str) {
+    docs(out, &function.docs, &function.feature);
+
+    let attributes = format!(
+        "        [DllImport(Library, CallingConvention = CallingConvention.Cdecl, EntryPoint = \"{}{}\")]\n",
+        symbol_prefix, function.name
+    );
+    let ret = return_type(&function.ret);
+
+    if !is_consuming(function) {
+        out.push_str(&attributes);
+        if function.ret == "bool" {
+            out.push_str("        [return: MarshalAs(UnmanagedType.U1)]\n");
+        }
+        let _ = writeln!(
+            out,
+            "        public static extern {} {}({});",
+            ret,
+            function.name,
+            parameters(&function.args, None)
+        );
+        return;
+    }
+
+    // Invalidates the `SafeHandle` that is freed by the call, such that it is not released twice.
+    let consumed = function
+        .args
+        .last()
+        .expect("consuming functions take a handle");
+
+    let _ = writeln!(
+        out,
+        "        public static {} {}({})\n        {{",
+        ret,
+        function.name,
+        parameters(&function.args, None)
+    );
+    let call_args: Vec<String> = function
+        .args
+        .iter()
+        .map(|arg| match arg.name == consumed.name {
+            true => format!("{}.DangerousGetHandle()", arg.name),
+            false => arg.name.clone(),
+        })
+        .collect();
+    let _ = writeln!(
+        out,
+        "            var result = {}_raw({});\n            {}.SetHandleAsInvalid();\n            return result;\n        }}\n",
+        function.name,
+        call_args.join(", "),
+        consumed.name
+    );
+
+    out.push_str(&attributes);
+    let _ = writeln!(
+        out,
+        "        internal static extern {} {}_raw({});",
+        ret,
+        function.name,
+        parameters(&function.args, Some(&consumed.name))
+    );
+}
+
+/// Declares a `SafeHandle` that releases the handle with the `free_*` function taking only that handle.
+fn safe_handle(out: &mut String, handle: &str, functions: &[Function]) {
+    let release = functions.iter().find(|function| {
+        function.name.starts_with("free_")
+            && function.args.len() == 1
+            && function.args[0].ty == handle
+    });
+
+    let _ = writeln!(
+        out,
+        "    public sealed class {handle} : SafeHandle
+    {{
+        public {handle}() : base(IntPtr.Zero, true)
+        {{
+        }}
+
+        public override bool IsInvalid => handle == IntPtr.Zero;
+
+        protected override bool ReleaseHandle()
+        {{",
+        handle = handle
+    );
+
+    match release {
+        Some(release) => {
+            let _ = writeln!(out, "            Native.{}_raw(handle);", release.name);
+        }
+        None => out.push_str("            // quinn-ffi has no function to free this handle.\n"),
+    }
+
+    out.push_str("            return true;\n        }\n    }\n");
+}
+
+fn docs(out: &mut String, docs: &[String], feature: &Option<String>) {
+    if docs.is_empty() && feature.is_none() {
+        return;
+    }
+
+    out.push_str("        /// <summary>\n");
+    for line in docs {
+        match line.trim_end() {
+            "" => out.push_str("        ///\n"),
+            line => {
+                let _ = writeln!(out, "        /// {}", xml_escape(line));
+            }
+        }
+    }
+    if let Some(feature) = feature {
+        let _ = writeln!(
+            out,
+            "        /// Only exported when quinn-ffi is built with the `{}` feature.",
+            feature
+        );
+    }
+    out.push_str("        /// </summary>\n");
+}
+
+/// Declares the parameters, the handle named `raw_handle` is passed as `IntPtr`.
+fn parameters(args: &[Arg], raw_handle: Option<&str>) -> String {
+    args.iter()
+        .enumerate()
+        .map(|(i, arg)| {
+            // An `Out` followed by a size is a buffer rather than a single value.
+            let is_buffer = args.get(i + 1).is_some_and(|next| next.ty == "size_t");
+            format!(
+                "{} {}",
+                parameter_type(&arg.ty, is_buffer, raw_handle == Some(arg.name.as_str())),
+                identifier(&arg.name)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn parameter_type(ty: &str, is_buffer: bool, raw_handle: bool) -> String {
+    // A byte buffer that may be null to query its length.
+    if ty == "OutBuffer" {
+        return "byte*".to_owned();
+    }
+
+    if let Some(inner) = generic_argument(ty, "Out") {
+        return if is_buffer {
+            format!("{}*", value_type(inner))
+        } else {
+            format!("out {}", handle_or_value_type(inner, false))
+        };
+    }
+
+    if let Some(inner) = generic_argument(ty, "Ref").or_else(|| generic_argument(ty, "RefMut")) {
+        return format!("{}*", value_type(inner));
+    }
+
+    if ty == "bool" {
+        return "[MarshalAs(UnmanagedType.U1)] bool".to_owned();
+    }
+
+    handle_or_value_type(ty, raw_handle)
+}
+
+fn return_type(ty: &str) -> String {
+    match ty {
+        // Marshaled with `[return: MarshalAs(UnmanagedType.U1)]`.
+        "bool" => "bool".to_owned(),
+        ty => value_type(ty),
+    }
+}
+
+fn handle_or_value_type(ty: &str, raw_handle: bool) -> String {
+    let ty = last_segment(ty);
+
+    if is_handle(ty) {
+        return match raw_handle {
+            true => "IntPtr".to_owned(),
+            false => ty.to_owned(),
+        };
+    }
+
+    value_type(ty)
+}
+
+/// Maps a type that is passed by value, in function pointers handles are passed as `IntPtr`.
+fn value_type(ty: &str) -> String {
+    let ty = ty.trim();
+
+    if let Some(pointee) = ty
+        .strip_prefix("*const ")
+        .or_else(|| ty.strip_prefix("*mut "))
+    {
+        return format!("{}*", value_type(pointee));
+    }
+
+    if let Some(callback) = ty.strip_prefix("extern \"C\" fn") {
+        return function_pointer(callback);
+    }
+
+    let ty = last_segment(ty);
+    match ty {
+        "()" => "void",
+        // `bool` is not blittable and can not be marshaled in function pointers.
+        "bool" => "byte",
+        "u8" => "byte",
+        "i8" => "sbyte",
+        "u16" => "ushort",
+        "i16" => "short",
+        "u32" => "uint",
+        "i32" => "int",
+        "u64" => "ulong",
+        "i64" => "long",
+        "size_t" | "usize" => "nuint",
+        ty if is_handle(ty) => "IntPtr",
+        ty => ty,
+    }
+    .to_owned()
+}
+
+/// Maps `(A, B) -> R` to a function pointer type that accepts `[UnmanagedCallersOnly]` methods.
+fn function_pointer(signature: &str) -> String {
+    let signature = signature.trim();
+    let close = signature.rfind(')').unwrap_or(signature.len());
+
+    let mut types: Vec<String> = split_top_level(&signature[1..close])
+        .into_iter()
+        .map(value_type)
+        .collect();
+
+    let ret = signature[close + 1..]
+        .trim()
+        .strip_prefix("->")
+        .map_or("()", str::trim);
+    types.push(value_type(ret));
+
+    format!("delegate* unmanaged[Cdecl]<{}>", types.join(", "))
+}
+
+fn handle_types(functions: &[Function]) -> BTreeSet<String> {
+    functions
+        .iter()
+        .flat_map(|function| &function.args)
+        .filter_map(|arg| {
+            let ty = generic_argument(&arg.ty, "Out").unwrap_or(&arg.ty);
+            let ty = last_segment(ty);
+            is_handle(ty).then(|| ty.to_owned())
+        })
+        .collect()
+}
+
+/// Returns whether the function frees the handle passed as its last argument.
+fn is_consuming(function: &Function) -> bool {
+    (function.name.starts_with("free_") || function.name.starts_with("dispose_"))
+        && function
+            .args
+            .last()
+            .is_some_and(|arg| is_handle(last_segment(&arg.ty)))
+}
+
+fn is_handle(ty: &str) -> bool {
+    ty.ends_with("Handle") && ty.chars().all(char::is_alphanumeric)
+}
+
+/// Returns `T` of `Wrapper<T>`.
+fn generic_argument<'a>(ty: &'a str, wrapper: &str) -> Option<&'a str> {
+    ty.strip_prefix(wrapper)?
+        .strip_prefix('<')?
+        .strip_suffix('>')
+        .map(str::trim)
+}
+
+/// Strips the module path, e.g. of `super::ConnectionHandle`.
+fn last_segment(ty: &str) -> &str {
+    if ty.contains('<') || ty.contains('(') {
+        return ty;
+    }
+
+    ty.rsplit("::").next().unwrap_or(ty)
+}
+
+/// Escapes argument names that are C# keywords.
+fn identifier(name: &str) -> String {
+    match name {
+        "event" | "object" | "string" | "params" | "ref" | "out" | "in" | "base" | "lock" => {
+            format!("@{}", name)
+        }
+        name => name.to_owned(),
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
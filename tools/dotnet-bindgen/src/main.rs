@@ -0,0 +1,125 @@
+//! Generates the C# P/Invoke bindings of quinn-ffi from its sources.
+//!
+//! Reads the functions declared in `ffi!` and `set_callbacks!` invocations and the hand-written `extern "cdecl"` functions,
+//! such that the bindings never drift from the Rust signatures.
+//!
+//! Usage: `dotnet-bindgen <quinn-ffi crate directory> [output file]`, writes to stdout without an output file.
+//! The entry points are prefixed with `quinn_`, set `QUINN_FFI_SYMBOL_PREFIX` to the prefix if the library is built with another.
+//!
+//! The `#[repr(C)]` structs and enums, e.g. `FFIResult` and `IpAddr`, are referenced by name and are expected to be
+//! declared in the `QuinnFfi` namespace of the consuming package.
+
+mod emit;
+mod parse;
+
+use std::{
+    env,
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process,
+};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let crate_dir = match args.first() {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            eprintln!("usage: dotnet-bindgen <quinn-ffi crate directory> [output file]");
+            process::exit(2);
+        }
+    };
+
+    let mut functions = Vec::new();
+    if let Err(e) = parse_dir(&crate_dir.join("src"), &mut functions) {
+        eprintln!("failed to read the quinn-ffi sources: {}", e);
+        process::exit(1);
+    }
+
+    // Sorted such that the output doesn't depend on the order of the files and definitions.
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let symbol_prefix = env::var("QUINN_FFI_SYMBOL_PREFIX").unwrap_or_else(|_| "quinn_".to_owned());
+    let bindings = emit::bindings(&functions, &symbol_prefix);
+
+    let result = match args.get(1) {
+        Some(output) => fs::write(output, bindings),
+        None => {
+            print!("{}", bindings);
+            Ok(())
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("failed to write the bindings: {}", e);
+        process::exit(1);
+    }
+}
+
+fn parse_dir(dir: &Path, functions: &mut Vec<parse::Function>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            parse_dir(&path, functions)?;
+        } else if path.extension().is_some_and(|extension| extension == "rs") {
+            functions.extend(parse::functions(&fs::read_to_string(&path)?));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the names of the functions declared in the `ffi!` blocks, found by counting braces.
+    fn ffi_block_functions(source: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut depth = None;
+
+        for line in source.lines() {
+            if line.starts_with("ffi! {") {
+                depth = Some(0);
+            }
+
+            if let Some(current) = depth {
+                if current == 1 {
+                    if let Some(rest) = line.trim().strip_prefix("fn ") {
+                        names.push(rest[..rest.find('(').unwrap()].to_owned());
+                    }
+                }
+
+                let opened = line.matches('{').count() as i32;
+                let closed = line.matches('}').count() as i32;
+                depth = Some(current + opened - closed).filter(|depth| *depth > 0);
+            }
+        }
+
+        names
+    }
+
+    #[test]
+    fn binds_every_ffi_function() {
+        let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+        let source = fs::read_to_string(crate_dir.join("src/ffi/bindings.rs")).unwrap();
+
+        let mut functions = Vec::new();
+        parse_dir(&crate_dir.join("src"), &mut functions).unwrap();
+        let bindings = emit::bindings(&functions, "");
+
+        let names = ffi_block_functions(&source);
+        assert!(names.iter().any(|name| name == "last_error"));
+
+        let missing: Vec<_> = names
+            .iter()
+            .filter(|name| !bindings.contains(&format!("EntryPoint = \"{}\"", name)))
+            .collect();
+        assert!(missing.is_empty(), "not bound: {:?}", missing);
+    }
+}
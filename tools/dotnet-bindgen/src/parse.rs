@@ -0,0 +1,222 @@
+//! Extracts the exported functions from the quinn-ffi sources.
+//!
+//! The parser is line based and relies on the formatting used in quinn-ffi:
+//! `ffi!` blocks start with `ffi! {` and end with `}` on their own lines, and each function starts on a new line.
+//! Functions of `ffi!` blocks are recognized regardless of their indentation, the bodies must not declare functions.
+
+/// An exported function.
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub docs: Vec<String>,
+    pub args: Vec<Arg>,
+    /// The return type, `()` if the function returns nothing.
+    pub ret: String,
+    /// The feature the function is only exported with.
+    pub feature: Option<String>,
+}
+
+/// An argument of an exported function.
+#[derive(Debug)]
+pub struct Arg {
+    pub name: String,
+    pub ty: String,
+}
+
+/// Returns the functions exported by the given source file.
+pub fn functions(source: &str) -> Vec<Function> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut functions = Vec::new();
+
+    let mut in_ffi_block = false;
+    let mut docs = Vec::new();
+    let mut feature = None;
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim();
+
+        if line == "ffi! {" {
+            in_ffi_block = true;
+        } else if line == "}" {
+            in_ffi_block = false;
+        } else if let Some(doc) = trimmed.strip_prefix("///") {
+            docs.push(doc.strip_prefix(' ').unwrap_or(doc).to_owned());
+            i += 1;
+            continue;
+        } else if let Some(cfg) = trimmed
+            .strip_prefix("#[cfg(")
+            .filter(|cfg| cfg.contains("feature"))
+        {
+            // Takes the feature of `any(test, feature = "...")` too, such functions are exported by test builds as well.
+            feature = cfg.split('"').nth(1).map(str::to_owned);
+            i += 1;
+            continue;
+        } else if trimmed.starts_with("#[") {
+            i += 1;
+            continue;
+        } else if trimmed.contains('$') {
+            // Skips macro definitions.
+        } else if let Some(mut setter) = callback_setter(trimmed) {
+            // The docs written above the entry follow the generated ones, like in the rustdoc of the setter.
+            setter.docs.extend(std::mem::take(&mut docs));
+            functions.push(setter);
+        } else if (in_ffi_block && trimmed.starts_with("fn "))
+            || trimmed.starts_with("pub extern \"cdecl\" fn ")
+        {
+            let (signature, end) = signature(&lines, i);
+
+            if let Some(mut function) = function(&signature) {
+                function.docs = std::mem::take(&mut docs);
+                function.feature = feature.take();
+                functions.push(function);
+            }
+
+            i = end;
+        }
+
+        docs.clear();
+        feature = None;
+        i += 1;
+    }
+
+    functions
+}
+
+/// Parses an entry of `set_callbacks!`, e.g. `fn set_on_connected(u32) set ON_CONNECTED`,
+/// optionally followed by the ABI version it requires, e.g. `requires (1, 0)`.
+fn callback_setter(line: &str) -> Option<Function> {
+    let rest = line.strip_prefix("fn ")?;
+    let (signature, slot) = rest.split_once(") set ")?;
+    let (name, arg_types) = signature.split_once('(')?;
+
+    let (slot, requires) = match slot.split_once(" requires ") {
+        Some((slot, version)) => (slot, Some(version)),
+        None => (slot, None),
+    };
+
+    let mut docs = vec![format!(
+        "Sets the callback stored in `{}`, can be called from any thread.",
+        slot.trim()
+    )];
+    if let Some(version) = requires {
+        let version = version.trim_matches(|c| c == '(' || c == ')' || c == ' ');
+        docs.push(format!(
+            "Requires ABI version {}, see `ffi_abi_require`.",
+            version.replace(", ", ".")
+        ));
+    }
+
+    Some(Function {
+        name: name.to_owned(),
+        docs,
+        args: vec![Arg {
+            name: "callback".to_owned(),
+            ty: format!("extern \"C\" fn({})", arg_types),
+        }],
+        ret: "FFIResult".to_owned(),
+        feature: None,
+    })
+}
+
+/// Joins the lines of the signature starting at `start` up to the opening brace of the body.
+///
+/// Returns the signature and the index of its last line.
+fn signature(lines: &[&str], start: usize) -> (String, usize) {
+    let mut signature = String::new();
+
+    for (i, line) in lines.iter().enumerate().skip(start) {
+        match line.find('{') {
+            Some(body) => {
+                signature.push_str(&line[..body]);
+                return (signature, i);
+            }
+            None => {
+                signature.push_str(line.trim());
+                signature.push(' ');
+            }
+        }
+    }
+
+    (signature, lines.len())
+}
+
+/// Parses a signature like `pub extern "cdecl" fn name(arg: Type) -> Return`.
+fn function(signature: &str) -> Option<Function> {
+    let rest = &signature[signature.find("fn ")? + 3..];
+    let open = rest.find('(')?;
+    let close = matching_paren(rest, open)?;
+
+    let args = split_top_level(&rest[open + 1..close])
+        .into_iter()
+        .filter_map(|arg| {
+            let (name, ty) = arg.split_once(':')?;
+            Some(Arg {
+                name: name.trim().trim_start_matches("mut ").to_owned(),
+                ty: ty.trim().to_owned(),
+            })
+        })
+        .collect();
+
+    let ret = rest[close + 1..]
+        .trim()
+        .strip_prefix("->")
+        .map_or("()", str::trim)
+        .to_owned();
+
+    Some(Function {
+        name: rest[..open].trim().to_owned(),
+        docs: Vec::new(),
+        args,
+        ret,
+        feature: None,
+    })
+}
+
+/// Returns the index of the parenthesis that closes the one at `open`.
+fn matching_paren(s: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+
+    for (i, c) in s.char_indices().skip_while(|(i, _)| *i < open) {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Splits a list at the commas that are not nested in parentheses or angle brackets.
+pub fn split_top_level(list: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    let mut previous = ' ';
+
+    for (i, c) in list.char_indices() {
+        match c {
+            '(' | '<' => depth += 1,
+            // The arrow of a return type is not a closing bracket.
+            '>' if previous == '-' => {}
+            ')' | '>' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(list[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+
+    items.push(list[start..].trim());
+    items.retain(|item| !item.is_empty());
+    items
+}